@@ -1,9 +1,67 @@
 extern crate winres;
 
+// Lets SendInput reach elevated windows and the secure desktop (`--features uiaccess`)
+const UIACCESS_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<assembly xmlns="urn:schemas-microsoft-com:asm.v1" manifestVersion="1.0">
+  <trustInfo xmlns="urn:schemas-microsoft-com:asm.v3">
+    <security>
+      <requestedPrivileges>
+        <requestedExecutionLevel level="asInvoker" uiAccess="true"/>
+      </requestedPrivileges>
+    </security>
+  </trustInfo>
+</assembly>
+"#;
+
+/// Generates `$OUT_DIR/hid_usages.rs` from hid_usages.txt: a `HID_USAGE_NAMES` table of
+/// (name, usage page, usage, canonical) for variable_maps.rs
+fn generate_usage_table() {
+    println!("cargo:rerun-if-changed=hid_usages.txt");
+    let source = std::fs::read_to_string("hid_usages.txt").expect("hid_usages.txt is missing");
+
+    let mut seen = std::collections::HashSet::new();
+    let mut table = String::from("pub static HID_USAGE_NAMES: &[(&str, u16, u16, bool)] = &[\n");
+    for (line_no, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fail = |why: &str| panic!("hid_usages.txt line {}: {} ({})", line_no + 1, why, line);
+
+        let mut fields = line.split_whitespace();
+        let mut hex = || fields.next().and_then(|f| u16::from_str_radix(f, 16).ok());
+        let (Some(page), Some(usage)) = (hex(), hex()) else { fail("expected PAGE USAGE NAME") };
+        let names: Vec<&str> = line.split_whitespace().skip(2).collect();
+        if names.is_empty() {
+            fail("no name");
+        }
+        for (i, name) in names.iter().enumerate() {
+            if !name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+                fail("names are upper case letters, digits and _");
+            }
+            if !seen.insert(*name) {
+                fail(&format!("{} is listed twice", name));
+            }
+            table.push_str(&format!("    (\"{}\", 0x{:02X}, 0x{:04X}, {}),\n", name, page, usage, i == 0));
+        }
+    }
+    table.push_str("];\n");
+
+    let out = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("hid_usages.rs");
+    std::fs::write(out, table).unwrap();
+}
+
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=RottenApple.ico");
+    generate_usage_table();
+
     if std::env::var("CARGO_CFG_TARGET_OS").unwrap() == "windows" {
         let mut res = winres::WindowsResource::new();
         res.set_icon("RottenApple.ico");
+        if std::env::var("CARGO_FEATURE_UIACCESS").is_ok() {
+            res.set_manifest(UIACCESS_MANIFEST);
+        }
         res.compile().unwrap();
     }
 }
@@ -0,0 +1,20 @@
+// --- START OF FILE fuzz/fuzz_targets/hid_report.rs ---
+// Feeds arbitrary report sequences to the HID parser: `cargo +nightly fuzz run hid_report`
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// The daemon is a binary crate, so the parser module is compiled in directly
+#[path = "../../src/hid_parser.rs"]
+#[allow(dead_code)]
+mod hid_parser;
+
+fuzz_target!(|data: &[u8]| {
+    // Each report is a length byte followed by that many bytes (shorter at the end of the input)
+    let mut rest = data;
+    while let Some((&len, tail)) = rest.split_first() {
+        let (report, tail) = tail.split_at((len as usize).min(tail.len()));
+        hid_parser::parse_a1314_hid_report(hid_parser::DeviceId::default(), report);
+        rest = tail;
+    }
+});
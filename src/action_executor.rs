@@ -1,101 +1,547 @@
 // --- START OF FILE src/action_executor.rs ---
 use windows::core::{PWSTR, PCWSTR};
-use windows::Win32::Foundation::{CloseHandle, WPARAM, LPARAM};
+use windows::Win32::Foundation::{CloseHandle, HWND, WPARAM, LPARAM};
 use windows::Win32::System::Threading::{
     CreateProcessW, PROCESS_INFORMATION, STARTUPINFOW,
 };
 use windows::Win32::UI::Input::KeyboardAndMouse::{
-    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
+    GetAsyncKeyState, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP,
+    KEYEVENTF_SCANCODE, KEYEVENTF_UNICODE,
     VIRTUAL_KEY, VK_CONTROL, VK_SHIFT, VK_MENU, VK_LWIN, VK_ESCAPE, VK_TAB,
+    VK_LCONTROL, VK_RCONTROL, VK_LSHIFT, VK_RSHIFT, VK_LMENU, VK_RMENU, VK_RWIN,
     VK_RETURN, VK_BACK, VK_SPACE,
     VK_F1, VK_F2, VK_F3, VK_F4, VK_F5, VK_F6, VK_F7, VK_F8, VK_F9, VK_F10, VK_F11, VK_F12,
     VK_DELETE, VK_HOME, VK_END, VK_PRIOR, VK_NEXT,
     VK_LEFT, VK_RIGHT, VK_UP, VK_DOWN,
 };
+use windows::Win32::UI::Shell::ShellExecuteW;
 use windows::Win32::UI::WindowsAndMessaging::{
-    GetForegroundWindow, PostMessageW, WM_APPCOMMAND,
+    AllowSetForegroundWindow, GetForegroundWindow, IsIconic, PostMessageW, SetForegroundWindow, SetWindowPos,
+    ShowWindow, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SW_RESTORE, SW_SHOWNORMAL, WM_APPCOMMAND,
 };
-use std::time::Duration;
+use std::cell::Cell;
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
-// Configurable delay between key events (in milliseconds)
-// Some applications need a small delay to properly register key combinations
-const KEY_EVENT_DELAY_MS: u64 = 1;
-pub const DAEMON_INJECTION_TAG: u32 = 0x1314DA00;
+use crate::clipboard;
+use crate::error::{A1314Error, Result};
+use crate::expand;
+use crate::focus_assist;
+use crate::foreground;
+use crate::hid_output;
+use crate::input_language;
+use crate::latency;
+use crate::magnifier::{self, Zoom};
+use crate::quit_app;
+use crate::launcher;
+use crate::scroll;
+use crate::settings;
+use crate::sound;
+use crate::suspend;
+use crate::toggles::{self, Toggle};
+use crate::variable_maps;
 
-#[derive(Debug, Clone)]
+// Set as dwExtraInfo on every INPUT we inject, so the keyboard hook can skip our own keystrokes
+const DAEMON_INJECTION_TAG: u32 = 0x1314DA00;
+
+// An unassigned virtual key, tapped so Windows doesn't take a lone Win or Alt press for a tap
+const MASK_KEY: VIRTUAL_KEY = VIRTUAL_KEY(0xE8);
+
+// Set 1 scan codes of Left Alt and of the numpad digits 0 to 9, for ALTCODE()
+const ALT_SCAN_CODE: u16 = 0x38;
+const NUMPAD_SCAN_CODES: [u16; 10] = [0x52, 0x4F, 0x50, 0x51, 0x4B, 0x4C, 0x4D, 0x47, 0x48, 0x49];
+
+// How long a paste leaves the text on the clipboard before putting back what was there
+const PASTE_SETTLE: Duration = Duration::from_millis(150);
+
+// Keys we've injected a press for but not yet a release, so shutdown can let go of them
+static HELD_KEYS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+// Modifiers the user is physically holding, as the virtual keys combos send (VK_CONTROL for
+// either Ctrl). Kept from the keystrokes the hook sees that aren't ours, so a combo never
+// releases a modifier the user pressed, only those the daemon pressed itself.
+static PHYSICAL_MODIFIERS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+
+// Runs sequences one after another off the main thread, so their waits can't stall the keyboard hook
+static SEQUENCE_WORKER: Mutex<Option<Sender<Action>>> = Mutex::new(None);
+
+thread_local! {
+    // Set on the sequence worker, where an action handed to it runs in place rather than being
+    // queued behind the sequence it is a step of
+    static ON_SEQUENCE_WORKER: Cell<bool> = const { Cell::new(false) };
+}
+
+// How often WAITWINDOW() and RUN(..., FOREGROUND) look for the window
+const WAIT_WINDOW_POLL: Duration = Duration::from_millis(50);
+// How long RUN(..., FOREGROUND) waits for the new program's main window
+const FOREGROUND_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Action {
     KeyCombo(String),
     Run(String),
+    RunForeground(String), // RUN("path", FOREGROUND): also bring the new program's window to the front
+    Open(String),    // OPEN("path"): a document, folder or URL in its associated application
+    Explore(String), // EXPLORE("folder"): a folder in File Explorer
+    LayoutNext,      // LAYOUT_NEXT: the next installed input language
+    Layout(String),  // LAYOUT("en-US"): the installed layout for that locale
+    ImeToggle,       // IME_TOGGLE: turn the IME on or off
+    FocusAssist(Option<bool>), // FOCUS_ASSIST(on|off|toggle), None for toggle
+    Launcher,        // LAUNCHER: the search tool picked by `launcher =` in [settings]
+    AppWindowNext,   // APP_WINDOW_NEXT: the foreground application's next window, like Cmd+`
+    AppWindowPrev,   // APP_WINDOW_PREV: back the other way
+    CloseWindow,     // CLOSE_WINDOW: WM_CLOSE to the foreground window
+    QuitApp { confirm: bool },      // QUIT_APP / QUIT_APP(CONFIRM): close all of the app's windows
+    ForceKillApp { confirm: bool }, // FORCE_KILL_APP / FORCE_KILL_APP(CONFIRM): end its process
+    Magnifier(Zoom), // MAGNIFIER_ZOOM_IN / _OUT / _TOGGLE
+    Sound(String),   // SOUND("click.wav")
+    // ACTION SOUND("click.wav"): the action, with a sound to confirm it fired
+    WithSound { sound: String, action: Box<Action> },
     AppCommand(u32), // Variant for APPCOMMANDs
+    Backlight(i32),  // Keyboard backlight step in percent
+    CapsLed(bool),
+    SendHid(Vec<u8>), // Raw output report, report ID first
+    Disable,          // NONE / DISABLE: swallow the key and do nothing
+    Toggle(Toggle),   // TOGGLE(name): flip a system state read back from Windows
+    Suspend(Duration), // SUSPEND(30s): leave keys alone for a while
+    NaturalScrollToggle, // NATURAL_SCROLL_TOGGLE: flip macOS-style scrolling on or off
+    Keys(Vec<KeyStep>), // KEYS(CTRL_DOWN, C, CTRL_UP): key events exactly in the order given
+    AltCode(String),    // ALTCODE(0233): the digits typed on the numpad while Alt is held
+    Text(String),       // TEXT("café"): typed as Unicode characters, whatever the keyboard layout
+    Paste(String),      // VIA=clipboard TEXT("café"): pasted with Ctrl+V, then the clipboard put back
+    // SEQUENCE(A, B, ...): actions one after another on the sequence worker thread
+    Sequence(Vec<Action>),
+    // WAITWINDOW("name.exe", 5000): in a sequence, wait until that application shows a window
+    WaitWindow { app: String, timeout: Duration },
+    // IF(app="name.exe", THEN, ELSE): picked by the foreground application when it runs
+    IfApp { app: String, then: Box<Action>, otherwise: Box<Action> },
+    // The action with held modifiers ("CTRL", "SHIFT", "ALT", "WIN") let go of while it runs; the
+    // key mapper wraps the action of a mapping that clears its modifiers in this. The `keep`
+    // ones the user holds go out with a key combo's keys and stay down after it, e.g. SHIFT so
+    // Cmd+Shift+Left still selects to Home.
+    LiftModifiers { modifiers: Vec<String>, keep: Vec<String>, action: Box<Action> },
+}
+
+/// One step of a KEYS(...) sequence
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyStep {
+    Press(String),   // NAME_DOWN
+    Release(String), // NAME_UP
+    Tap(String),     // NAME: down then up
+    Wait(Duration),  // WAIT(50ms)
+}
+
+impl Action {
+    /// Short name of the kind of action, for latency stats
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Action::KeyCombo(_) => "keys",
+            Action::Run(_) | Action::RunForeground(_) => "run",
+            Action::Open(_) | Action::Explore(_) => "open",
+            Action::LayoutNext | Action::Layout(_) | Action::ImeToggle => "layout",
+            Action::FocusAssist(_) => "focusassist",
+            Action::Launcher => "launcher",
+            Action::AppWindowNext | Action::AppWindowPrev => "window",
+            Action::CloseWindow | Action::QuitApp { .. } | Action::ForceKillApp { .. } => "quit",
+            Action::Magnifier(_) => "magnifier",
+            Action::Sound(_) => "sound",
+            Action::WithSound { action, .. } => action.kind(),
+            Action::AppCommand(_) => "appcommand",
+            Action::Backlight(_) => "backlight",
+            Action::CapsLed(_) => "capsled",
+            Action::SendHid(_) => "sendhid",
+            Action::Disable => "none",
+            Action::Toggle(_) => "toggle",
+            Action::Suspend(_) => "suspend",
+            Action::NaturalScrollToggle => "scroll",
+            Action::Keys(_) => "keys",
+            Action::AltCode(_) => "altcode",
+            Action::Text(_) | Action::Paste(_) => "text",
+            Action::Sequence(_) => "sequence",
+            Action::WaitWindow { .. } => "waitwindow",
+            Action::IfApp { .. } => "if",
+            Action::LiftModifiers { action, .. } => action.kind(),
+        }
+    }
+}
+
+/// Where the key mapper sends the actions it resolves
+pub trait ActionSink {
+    fn execute(&mut self, action: &Action) -> Result<()>;
+}
+
+/// Performs actions for real: SendInput, WM_APPCOMMAND and CreateProcess
+pub struct Win32Sink;
+
+impl ActionSink for Win32Sink {
+    fn execute(&mut self, action: &Action) -> Result<()> {
+        let result = execute_action(action);
+        latency::action_finished(action.kind());
+        result
+    }
+}
+
+/// Records actions instead of performing them, for tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct RecordingSink(std::rc::Rc<std::cell::RefCell<Vec<Action>>>);
+
+#[cfg(test)]
+impl RecordingSink {
+    /// Everything executed since the last call
+    pub fn take(&self) -> Vec<Action> {
+        self.0.take()
+    }
+}
+
+#[cfg(test)]
+impl ActionSink for RecordingSink {
+    fn execute(&mut self, action: &Action) -> Result<()> {
+        self.0.borrow_mut().push(action.clone());
+        Ok(())
+    }
 }
 
-pub fn execute_action(action: &Action) {
+pub fn execute_action(action: &Action) -> Result<()> {
     match action {
-        Action::KeyCombo(combo) => {
-            send_key_combo(combo);
+        // Spacing a combo's key events out sleeps, which would hold up the keyboard hook on this thread
+        Action::KeyCombo(_) | Action::Magnifier(_) | Action::LiftModifiers { .. }
+            if combo_waits(action, settings::current().key_delay_ms) => run_on_sequence_worker(action.clone()),
+        Action::KeyCombo(combo) => send_key_combo(combo),
+        Action::Run(path) => launch_program(path).map(|_| ()),
+        Action::RunForeground(path) => {
+            let pid = launch_program(path)?;
+            bring_to_front(pid);
+            Ok(())
+        }
+        Action::Open(target) => shell_execute("open", target),
+        Action::Explore(folder) => shell_execute("explore", folder),
+        Action::LayoutNext => input_language::next_layout(),
+        Action::Layout(locale) => input_language::set_layout(locale),
+        Action::ImeToggle => input_language::toggle_ime(),
+        Action::FocusAssist(on) => focus_assist::set(*on).map(|_| ()).or_else(|e| {
+            log::warn!("Could not switch Focus Assist ({}), opening its settings instead", e);
+            shell_execute("open", focus_assist::SETTINGS_URI)
+        }),
+        Action::Launcher => execute_action(&launcher::current().action()),
+        Action::AppWindowNext => cycle_app_windows(true),
+        Action::AppWindowPrev => cycle_app_windows(false),
+        Action::CloseWindow => quit_app::close_window(),
+        Action::QuitApp { confirm } => quit_app::quit_app(*confirm),
+        Action::ForceKillApp { confirm } => quit_app::force_kill_app(*confirm),
+        Action::Magnifier(zoom) => magnifier::combo(*zoom).map_or(Ok(()), send_key_combo),
+        Action::Sound(file) => sound::play(file),
+        Action::WithSound { sound, action } => {
+            if let Err(e) = sound::play(sound) {
+                log::warn!("{}", e);
+            }
+            execute_action(action)
+        }
+        Action::AppCommand(cmd) => send_app_command(*cmd),
+        Action::Backlight(step) => hid_output::adjust_backlight(*step),
+        Action::CapsLed(on) => hid_output::set_caps_led(*on),
+        Action::SendHid(report) => hid_output::send_output_report(report),
+        Action::Disable => Ok(()),
+        Action::Toggle(toggle) => toggles::toggle(*toggle).map(|_| ()),
+        Action::Suspend(duration) => {
+            suspend::suspend(*duration);
+            Ok(())
+        }
+        Action::NaturalScrollToggle => {
+            scroll::toggle();
+            Ok(())
+        }
+        Action::Keys(steps) => {
+            let key_delay_ms = settings::current().key_delay_ms;
+            if keys_wait(steps, key_delay_ms) {
+                // Sleeping here would hold up the keyboard hook, which runs on this thread
+                run_on_sequence_worker(action.clone())
+            } else {
+                send_key_steps(steps)
+            }
         }
-        Action::Run(path) => {
-            launch_program(path);
+        Action::AltCode(digits) => send_alt_code(digits),
+        Action::Text(text) => send_text(text),
+        // Pasting waits for the application to read the clipboard before putting it back
+        Action::Sequence(_) | Action::WaitWindow { .. } | Action::Paste(_) => run_on_sequence_worker(action.clone()),
+        Action::IfApp { app, then, otherwise } => {
+            let action = if foreground::is_app(app) { then } else { otherwise };
+            execute_action(action)
         }
-        Action::AppCommand(cmd) => {
-            send_app_command(*cmd);
+        Action::LiftModifiers { modifiers, keep, action } => lift_modifiers(modifiers, keep, action, execute_action),
+    }
+}
+
+/// Runs `action` with the held `modifiers` let up, apart from any combo's `keep`, using `run`
+/// (execute_action, or run_step on the sequence worker)
+fn lift_modifiers(modifiers: &[String], keep: &[String], action: &Action, run: fn(&Action) -> Result<()>) -> Result<()> {
+    let modifiers = modifier_keys(modifiers);
+    unsafe {
+        send_keys(&lift_events(&modifiers, true))?;
+    }
+    // Handed to the sequence worker, a sequence runs after the modifiers are back down
+    let result = match action {
+        Action::KeyCombo(combo) => send_key_combo_keeping(combo, &modifier_keys(keep)),
+        action => run(action),
+    };
+    // Only what the user still holds goes back down; one let go of meanwhile would stick
+    let held = physically_held();
+    let modifiers: Vec<VIRTUAL_KEY> = modifiers.into_iter().filter(|modifier| held.contains(modifier)).collect();
+    unsafe {
+        send_keys(&lift_events(&modifiers, false))?;
+    }
+    result
+}
+
+/// Hands a sequence to the worker thread, starting it on first use
+fn run_on_sequence_worker(action: Action) -> Result<()> {
+    if ON_SEQUENCE_WORKER.get() {
+        return run_step(&action);
+    }
+    let mut worker = SEQUENCE_WORKER.lock().unwrap_or_else(|p| p.into_inner());
+    let sender = worker.get_or_insert_with(|| {
+        let (sender, receiver) = std::sync::mpsc::channel::<Action>();
+        std::thread::spawn(move || {
+            ON_SEQUENCE_WORKER.set(true);
+            for action in receiver {
+                if let Err(e) = run_step(&action) {
+                    log::error!("Sequence stopped: {}", e);
+                }
+            }
+        });
+        sender
+    });
+    sender.send(action)
+        .map_err(|_| A1314Error::Injection("The sequence worker thread has stopped".to_string()))
+}
+
+/// Runs one step of a sequence on the worker thread, where waiting is fine
+fn run_step(action: &Action) -> Result<()> {
+    match action {
+        Action::Sequence(steps) => steps.iter().try_for_each(run_step),
+        Action::WaitWindow { app, timeout } => wait_for_window(app, *timeout),
+        Action::Paste(text) => paste_text(text),
+        Action::Keys(steps) => send_key_steps(steps),
+        Action::KeyCombo(combo) => send_key_combo(combo),
+        Action::Magnifier(zoom) => magnifier::combo(*zoom).map_or(Ok(()), send_key_combo),
+        Action::LiftModifiers { modifiers, keep, action } => lift_modifiers(modifiers, keep, action, run_step),
+        Action::IfApp { app, then, otherwise } => run_step(if foreground::is_app(app) { then } else { otherwise }),
+        action => execute_action(action),
+    }
+}
+
+fn wait_for_window(app: &str, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    while !foreground::has_window(app) {
+        if Instant::now() >= deadline {
+            return Err(A1314Error::Timeout(format!("no {} window within {}ms", app, timeout.as_millis())));
         }
+        std::thread::sleep(WAIT_WINDOW_POLL);
     }
+    Ok(())
 }
 
-fn send_key_combo(combo: &str) {
-    let parts: Vec<&str> = combo.split('+').map(|s| s.trim()).collect();
-    
+/// Pastes `text` with Ctrl+V through the clipboard, then puts back what the clipboard held
+fn paste_text(text: &str) -> Result<()> {
+    let saved = clipboard::save()?;
+    clipboard::set_transient_text(text)?;
+    let result = send_key_combo("CTRL+V");
+    // The application reads the clipboard when it gets to the keystroke, not when it is sent
+    std::thread::sleep(PASTE_SETTLE);
+    if let Err(e) = clipboard::restore(&saved) {
+        log::warn!("Failed to put the clipboard back after pasting: {}", e);
+    }
+    result
+}
+
+/// Splits a `MOD+KEY` combo into its modifiers and the keys tapped in turn while they're held
+/// (two or more for a chord like `CTRL+K+C`), or returns the first part that is neither a
+/// modifier nor a known key name
+fn combo_keys(combo: &str) -> std::result::Result<(Vec<VIRTUAL_KEY>, Vec<VIRTUAL_KEY>), String> {
     let mut modifiers = Vec::new();
-    let mut main_key = None;
-
-    for part in &parts {
-        match part.to_uppercase().as_str() {
-            "CTRL" | "CONTROL" => modifiers.push(VK_CONTROL),
-            "SHIFT" => modifiers.push(VK_SHIFT),
-            "ALT" | "MENU" => modifiers.push(VK_MENU),
-            "WIN" | "GUI" => modifiers.push(VK_LWIN),
-            key => main_key = Some(parse_key(key)),
+    let mut keys = Vec::new();
+
+    for part in combo.split('+') {
+        let key = variable_maps::normalize_name(part);
+        if let Some(modifier) = modifier_key(&key) {
+            modifiers.push(modifier);
+        } else {
+            match parse_key(&key) {
+                VIRTUAL_KEY(0) => return Err(part.trim().to_string()),
+                vk => keys.push(vk),
+            }
         }
     }
+    Ok((modifiers, keys))
+}
+
+/// Virtual key for a modifier name already passed through variable_maps::normalize_name
+fn modifier_key(key: &str) -> Option<VIRTUAL_KEY> {
+    match key {
+        "CTRL" => Some(VK_CONTROL),
+        "SHIFT" => Some(VK_SHIFT),
+        "ALT" | "MENU" => Some(VK_MENU),
+        "WIN" => Some(VK_LWIN),
+        _ => None,
+    }
+}
 
+/// Virtual key for any key or modifier name already passed through variable_maps::normalize_name
+fn any_key(key: &str) -> Option<VIRTUAL_KEY> {
+    modifier_key(key).or(Some(parse_key(key)).filter(|vk| vk.0 != 0))
+}
+
+/// Whether SendInput can send the (normalized) key or modifier name on its own, for KEYS()
+pub fn is_key_name(key: &str) -> bool {
+    any_key(key).is_some()
+}
+
+/// The first part of a key combo that SendInput can't send, so the parser can reject it up front
+pub fn unknown_combo_part(combo: &str) -> Option<String> {
+    combo_keys(combo).err()
+}
+
+/// Virtual keys of modifier names
+fn modifier_keys(names: &[String]) -> Vec<VIRTUAL_KEY> {
+    names.iter().filter_map(|name| modifier_key(&variable_maps::normalize_name(name))).collect()
+}
+
+fn send_key_combo(combo: &str) -> Result<()> {
+    send_key_combo_keeping(combo, &[])
+}
+
+/// Sends a combo with the `kept` modifiers held as well, leaving them down since the user is
+/// holding them, as are any of the combo's own the user holds
+fn send_key_combo_keeping(combo: &str, kept: &[VIRTUAL_KEY]) -> Result<()> {
+    let (modifiers, keys) = combo_keys(combo).map_err(|part| {
+        A1314Error::Injection(format!("'{}' contains an unknown key name '{}'", combo, part))
+    })?;
+    let kept = kept_modifiers(&modifiers, kept, &physically_held());
+
+    // key_delay_ms from [settings]: with 0 a combo goes out in one SendInput call, otherwise
+    // each key event is sent on its own this far apart, for applications that miss fast combos
+    let events = kept_combo_events(&modifiers, &keys, &kept);
+    let delay = settings::current().key_delay_ms;
     unsafe {
-        // Press modifiers
-        for &modifier in &modifiers {
-            send_key(modifier, false);
-            if KEY_EVENT_DELAY_MS > 0 {
-                std::thread::sleep(Duration::from_millis(KEY_EVENT_DELAY_MS));
-            }
+        if delay == 0 {
+            // One call, so the user's own typing can't land in the middle of the combo
+            return send_keys(&events);
         }
 
-        // Press and release main key (if present)
-        if let Some(key) = main_key {
-            send_key(key, false);
-            if KEY_EVENT_DELAY_MS > 0 {
-                std::thread::sleep(Duration::from_millis(KEY_EVENT_DELAY_MS));
+        // Releases still go out if a press is refused, so nothing is left held down
+        let mut result = Ok(());
+        for (index, &event) in events.iter().enumerate() {
+            if index > 0 {
+                std::thread::sleep(Duration::from_millis(delay));
             }
-            send_key(key, true);
-            if KEY_EVENT_DELAY_MS > 0 {
-                std::thread::sleep(Duration::from_millis(KEY_EVENT_DELAY_MS));
+            let sent = send_keys(&[event]);
+            if result.is_ok() && !event.1 {
+                result = sent;
             }
         }
+        result
+    }
+}
 
-        // Release modifiers (in reverse order)
-        for &modifier in modifiers.iter().rev() {
-            send_key(modifier, true);
-            if KEY_EVENT_DELAY_MS > 0 && modifier != *modifiers.last().unwrap() {
-                std::thread::sleep(Duration::from_millis(KEY_EVENT_DELAY_MS));
+/// Whether `action` sends a combo with key_delay_ms between its key events
+fn combo_waits(action: &Action, key_delay_ms: u64) -> bool {
+    key_delay_ms > 0 && match action {
+        Action::KeyCombo(_) | Action::Magnifier(_) => true,
+        Action::LiftModifiers { action, .. } => combo_waits(action, key_delay_ms),
+        _ => false,
+    }
+}
+
+/// Whether KEYS() `steps` pause anywhere, with a WAIT() or key_delay_ms between key events
+fn keys_wait(steps: &[KeyStep], key_delay_ms: u64) -> bool {
+    key_delay_ms > 0 || steps.iter().any(|step| matches!(step, KeyStep::Wait(wait) if !wait.is_zero()))
+}
+
+/// Sends KEYS() steps in order. Consecutive key events go out in one SendInput call unless
+/// key_delay_ms spaces them out; WAIT() steps pause between them.
+fn send_key_steps(steps: &[KeyStep]) -> Result<()> {
+    let key = |name: &str| any_key(name)
+        .ok_or_else(|| A1314Error::Injection(format!("KEYS() contains an unknown key name '{}'", name)));
+    let delay = Duration::from_millis(settings::current().key_delay_ms);
+
+    let mut batch = Vec::new();
+    for step in steps {
+        let pause = match step {
+            KeyStep::Press(name) => {
+                batch.push((key(name)?, false));
+                delay
+            }
+            KeyStep::Release(name) => {
+                batch.push((key(name)?, true));
+                delay
+            }
+            KeyStep::Tap(name) => {
+                let vk = key(name)?;
+                batch.extend([(vk, false), (vk, true)]);
+                delay
+            }
+            KeyStep::Wait(wait) => *wait,
+        };
+        if !pause.is_zero() {
+            unsafe {
+                send_keys(&batch)?;
             }
+            batch.clear();
+            std::thread::sleep(pause);
         }
     }
+    unsafe { send_keys(&batch) }
+}
+
+/// Modifiers down, the key down and up, then the modifiers up in reverse order
+fn combo_events(modifiers: &[VIRTUAL_KEY], keys: &[VIRTUAL_KEY]) -> Vec<(VIRTUAL_KEY, bool)> {
+    let mut events: Vec<(VIRTUAL_KEY, bool)> = modifiers.iter().map(|&modifier| (modifier, false)).collect();
+    for &key in keys {
+        events.push((key, false));
+        events.push((key, true));
+    }
+    events.extend(modifiers.iter().rev().map(|&modifier| (modifier, true)));
+    events
+}
+
+/// combo_events with the `kept` modifiers pressed first and never released
+fn kept_combo_events(modifiers: &[VIRTUAL_KEY], keys: &[VIRTUAL_KEY], kept: &[VIRTUAL_KEY]) -> Vec<(VIRTUAL_KEY, bool)> {
+    let mut all = kept.to_vec();
+    all.extend(modifiers.iter().filter(|modifier| !kept.contains(modifier)));
+    let mut events = combo_events(&all, keys);
+    events.retain(|&(vk, is_up)| !(is_up && kept.contains(&vk)));
+    events
+}
+
+/// `kept` plus the `modifiers` of a combo that are in `held`, the ones the user holds
+fn kept_modifiers(modifiers: &[VIRTUAL_KEY], kept: &[VIRTUAL_KEY], held: &[VIRTUAL_KEY]) -> Vec<VIRTUAL_KEY> {
+    let mut all = kept.to_vec();
+    all.extend(modifiers.iter().filter(|modifier| held.contains(modifier) && !kept.contains(modifier)));
+    all
+}
+
+/// Releases (`up`) or presses again the held `modifiers`, with a tap of MASK_KEY where Win or
+/// Alt would otherwise go down and up with nothing in between, which opens the Start menu or
+/// the menu bar
+fn lift_events(modifiers: &[VIRTUAL_KEY], up: bool) -> Vec<(VIRTUAL_KEY, bool)> {
+    let mask = modifiers.iter().any(|&modifier| modifier == VK_LWIN || modifier == VK_MENU);
+    let mut events: Vec<(VIRTUAL_KEY, bool)> = modifiers.iter().map(|&modifier| (modifier, up)).collect();
+    if mask && up {
+        events.splice(0..0, [(MASK_KEY, false), (MASK_KEY, true)]);
+    } else if mask {
+        events.extend([(MASK_KEY, false), (MASK_KEY, true)]);
+    }
+    events
 }
 
+/// Virtual key for a name already passed through variable_maps::normalize_name
 fn parse_key(key: &str) -> VIRTUAL_KEY {
     match key {
         // Special keys
-        "ESC" | "ESCAPE" => VK_ESCAPE,
+        "ESCAPE" => VK_ESCAPE,
         "TAB" => VK_TAB,
-        "ENTER" | "RETURN" => VK_RETURN,
+        "ENTER" => VK_RETURN,
         "BACKSPACE" => VK_BACK,
         "SPACE" => VK_SPACE,
         "DELETE" => VK_DELETE,
@@ -103,12 +549,12 @@ fn parse_key(key: &str) -> VIRTUAL_KEY {
         // Navigation
         "HOME" => VK_HOME,
         "END" => VK_END,
-        "PAGE_UP" | "PAGEUP" => VK_PRIOR,
-        "PAGE_DOWN" | "PAGEDOWN" => VK_NEXT,
-        "LEFT_ARROW" | "LEFT" => VK_LEFT,
-        "RIGHT_ARROW" | "RIGHT" => VK_RIGHT,
-        "UP_ARROW" | "UP" => VK_UP,
-        "DOWN_ARROW" | "DOWN" => VK_DOWN,
+        "PAGE_UP" => VK_PRIOR,
+        "PAGE_DOWN" => VK_NEXT,
+        "LEFT_ARROW" => VK_LEFT,
+        "RIGHT_ARROW" => VK_RIGHT,
+        "UP_ARROW" => VK_UP,
+        "DOWN_ARROW" => VK_DOWN,
         
         // Function keys
         "F1" => VK_F1,
@@ -187,36 +633,267 @@ fn parse_key(key: &str) -> VIRTUAL_KEY {
         "COMMA" | "," | "<" => VIRTUAL_KEY(0xBC),
         "PERIOD" | "." | ">" => VIRTUAL_KEY(0xBE),
         "SLASH" | "/" | "?" => VIRTUAL_KEY(0xBF),
+        "NON_US_BACKSLASH" | "SECTION" => VIRTUAL_KEY(0xE2), // VK_OEM_102, beside Left Shift on ISO
         
-        _ => {
-            log::warn!("Unknown key name: '{}', mapping will not work", key);
-            VIRTUAL_KEY(0)
+        _ => VIRTUAL_KEY(0),
+    }
+}
+
+unsafe fn send_key(vk: VIRTUAL_KEY, is_up: bool) -> Result<()> {
+    send_keys(&[(vk, is_up)])
+}
+
+/// Sends key events (virtual key, is_up) in a single SendInput call, skipping invalid keys
+unsafe fn send_keys(events: &[(VIRTUAL_KEY, bool)]) -> Result<()> {
+    let events: Vec<(VIRTUAL_KEY, bool)> = events.iter().copied().filter(|(vk, _)| vk.0 != 0).collect();
+    if events.is_empty() {
+        return Ok(());
+    }
+
+    let inputs = key_inputs(&events);
+
+    // Fewer than asked for means the input was blocked, e.g. by UIPI when an elevated window has focus
+    let sent = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) as usize;
+
+    // A press of a modifier the user holds isn't ours to let go of at shutdown
+    let physical = physically_held();
+    let mut held = HELD_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    for &(vk, is_up) in &events[..sent.min(events.len())] {
+        if is_up {
+            held.retain(|&key| key != vk.0);
+        } else if !held.contains(&vk.0) && !physical.contains(&vk) {
+            held.push(vk.0);
         }
     }
+
+    if sent < events.len() {
+        let (vk, _) = events[sent];
+        return Err(A1314Error::Injection(format!(
+            "SendInput rejected virtual key 0x{:02X}: {}", vk.0, windows::core::Error::from_win32())));
+    }
+    Ok(())
+}
+
+/// Keyboard INPUTs for key events, each tagged as ours
+fn key_inputs(events: &[(VIRTUAL_KEY, bool)]) -> Vec<INPUT> {
+    events.iter()
+        .map(|&(vk, is_up)| INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: vk,
+                    wScan: 0,
+                    dwFlags: if is_up { KEYEVENTF_KEYUP } else { Default::default() },
+                    time: 0,
+                    dwExtraInfo: DAEMON_INJECTION_TAG as usize,
+                },
+            },
+        })
+        .collect()
+}
+
+/// ALTCODE(): holds Alt and types the digits on the numpad as scan codes, which applications
+/// that ignore injected Unicode still turn into a character when Alt goes up
+fn send_alt_code(digits: &str) -> Result<()> {
+    let events = alt_code_events(digits);
+    unsafe {
+        let sent = SendInput(&scan_code_inputs(&events), std::mem::size_of::<INPUT>() as i32) as usize;
+        if sent < events.len() {
+            // Alt is never left down, or the next key the user types would come out as a shortcut
+            if sent > 0 {
+                SendInput(&scan_code_inputs(&[(ALT_SCAN_CODE, true)]), std::mem::size_of::<INPUT>() as i32);
+            }
+            return Err(A1314Error::Injection(format!(
+                "SendInput rejected ALTCODE({}): {}", digits, windows::core::Error::from_win32())));
+        }
+    }
+    Ok(())
+}
+
+/// Types `text` as Unicode characters in one SendInput call, so no keyboard layout gets in the way
+fn send_text(text: &str) -> Result<()> {
+    let inputs = text_inputs(text);
+    unsafe {
+        let sent = SendInput(&inputs, std::mem::size_of::<INPUT>() as i32) as usize;
+        if sent < inputs.len() {
+            return Err(A1314Error::Injection(format!(
+                "SendInput rejected TEXT(\"{}\"): {}", text, windows::core::Error::from_win32())));
+        }
+    }
+    Ok(())
+}
+
+/// A press and a release per UTF-16 unit, so characters beyond U+FFFF go out as surrogate pairs
+fn text_inputs(text: &str) -> Vec<INPUT> {
+    text.encode_utf16()
+        .flat_map(|unit| [(unit, false), (unit, true)])
+        .map(|(unit, is_up)| INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: unit,
+                    dwFlags: if is_up { KEYEVENTF_UNICODE | KEYEVENTF_KEYUP } else { KEYEVENTF_UNICODE },
+                    time: 0,
+                    dwExtraInfo: DAEMON_INJECTION_TAG as usize,
+                },
+            },
+        })
+        .collect()
+}
+
+/// Alt down, each digit's numpad key down and up, then Alt up, as (scan code, is_up)
+fn alt_code_events(digits: &str) -> Vec<(u16, bool)> {
+    let mut events = vec![(ALT_SCAN_CODE, false)];
+    for digit in digits.chars().filter_map(|c| c.to_digit(10)) {
+        let scan_code = NUMPAD_SCAN_CODES[digit as usize];
+        events.push((scan_code, false));
+        events.push((scan_code, true));
+    }
+    events.push((ALT_SCAN_CODE, true));
+    events
+}
+
+fn scan_code_inputs(events: &[(u16, bool)]) -> Vec<INPUT> {
+    events.iter()
+        .map(|&(scan_code, is_up)| INPUT {
+            r#type: INPUT_KEYBOARD,
+            Anonymous: INPUT_0 {
+                ki: KEYBDINPUT {
+                    wVk: VIRTUAL_KEY(0),
+                    wScan: scan_code,
+                    dwFlags: if is_up { KEYEVENTF_SCANCODE | KEYEVENTF_KEYUP } else { KEYEVENTF_SCANCODE },
+                    time: 0,
+                    dwExtraInfo: DAEMON_INJECTION_TAG as usize,
+                },
+            },
+        })
+        .collect()
 }
 
-unsafe fn send_key(vk: VIRTUAL_KEY, is_up: bool) {
-    if vk.0 == 0 {
-        return; // Skip invalid keys
+/// Set 1 scan codes (all E0-extended) of the consumer keys a PC keyboard also has
+fn consumer_scan_code(usage: u16) -> Option<u16> {
+    match usage {
+        0xE2 => Some(0x20), // Mute
+        0xE9 => Some(0x30), // Volume up
+        0xEA => Some(0x2E), // Volume down
+        0xCD => Some(0x22), // Play/pause
+        0xB7 => Some(0x24), // Stop
+        0xB5 => Some(0x19), // Next track
+        0xB6 => Some(0x10), // Previous track
+        _ => None,
+    }
+}
+
+/// Passes a consumer key (usage page 0x0C) on as its scan code, which remote desktop and VM
+/// clients forward to the guest. Keys without one (e.g. Eject) are dropped.
+pub fn forward_consumer_key(usage: u16, pressed: bool) -> Result<()> {
+    let Some(scan_code) = consumer_scan_code(usage) else {
+        log::trace!("Consumer key 0x{:04X} has no scan code to pass through", usage);
+        return Ok(());
+    };
+    let mut flags = KEYEVENTF_SCANCODE | KEYEVENTF_EXTENDEDKEY;
+    if !pressed {
+        flags |= KEYEVENTF_KEYUP;
     }
-    
     let input = INPUT {
         r#type: INPUT_KEYBOARD,
         Anonymous: INPUT_0 {
             ki: KEYBDINPUT {
-                wVk: vk,
-                wScan: 0,
-                dwFlags: if is_up { KEYEVENTF_KEYUP } else { Default::default() },
+                wVk: VIRTUAL_KEY(0),
+                wScan: scan_code,
+                dwFlags: flags,
                 time: 0,
                 dwExtraInfo: DAEMON_INJECTION_TAG as usize,
             },
         },
     };
+    unsafe {
+        if SendInput(&[input], std::mem::size_of::<INPUT>() as i32) == 0 {
+            return Err(A1314Error::Injection(format!(
+                "SendInput rejected scan code E0 {:02X}: {}", scan_code, windows::core::Error::from_win32())));
+        }
+    }
+    Ok(())
+}
+
+/// The dwExtraInfo that marks input as injected by the daemon, for other modules' SendInput calls
+pub fn injection_tag() -> usize {
+    DAEMON_INJECTION_TAG as usize
+}
 
-    SendInput(&[input], std::mem::size_of::<INPUT>() as i32);
+/// Whether a hooked keystroke's dwExtraInfo marks it as injected by the daemon
+pub fn is_daemon_injection(extra_info: usize) -> bool {
+    extra_info == DAEMON_INJECTION_TAG as usize
+}
+
+/// Notes a keystroke of the user's own (not injected), to keep track of the modifiers they
+/// physically hold
+pub fn note_physical_key(vk: u32, is_up: bool) {
+    let Some(modifier) = combo_modifier(vk) else {
+        return;
+    };
+    let mut held = PHYSICAL_MODIFIERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if is_up {
+        held.retain(|&key| key != modifier.0);
+    } else if !held.contains(&modifier.0) {
+        held.push(modifier.0);
+    }
+}
+
+/// Forgets the modifiers the user was holding, when the session is locked or unlocked: the
+/// hook doesn't see keys released on the secure desktop
+pub fn forget_physical_keys() {
+    PHYSICAL_MODIFIERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
+}
+
+/// Modifiers the user is physically holding
+fn physically_held() -> Vec<VIRTUAL_KEY> {
+    still_held(|vk| unsafe { GetAsyncKeyState(vk.0 as i32) } < 0)
+}
+
+/// The modifiers noted as held, first dropping any whose left and right keys `is_down` says are
+/// both up, for a release the hook never saw
+fn still_held(is_down: impl Fn(VIRTUAL_KEY) -> bool) -> Vec<VIRTUAL_KEY> {
+    let mut held = PHYSICAL_MODIFIERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    held.retain(|&vk| sides(VIRTUAL_KEY(vk)).iter().any(|&side| is_down(side)));
+    held.iter().map(|&vk| VIRTUAL_KEY(vk)).collect()
+}
+
+/// The left and right keys behind a modifier as `combo_modifier` names it
+fn sides(modifier: VIRTUAL_KEY) -> [VIRTUAL_KEY; 2] {
+    match modifier {
+        VK_CONTROL => [VK_LCONTROL, VK_RCONTROL],
+        VK_SHIFT => [VK_LSHIFT, VK_RSHIFT],
+        VK_MENU => [VK_LMENU, VK_RMENU],
+        _ => [VK_LWIN, VK_RWIN],
+    }
+}
+
+/// The virtual key combos send for a modifier key: either Ctrl, Shift or Alt as the generic
+/// key, and either Win as VK_LWIN
+fn combo_modifier(vk: u32) -> Option<VIRTUAL_KEY> {
+    match vk {
+        0x10 | 0xA0 | 0xA1 => Some(VK_SHIFT),
+        0x11 | 0xA2 | 0xA3 => Some(VK_CONTROL),
+        0x12 | 0xA4 | 0xA5 => Some(VK_MENU),
+        0x5B | 0x5C => Some(VK_LWIN),
+        _ => None,
+    }
 }
 
-fn send_app_command(app_cmd: u32) {
+/// Sends a release for every key still held down by an interrupted action
+pub fn release_held_keys() {
+    let held: Vec<u16> = std::mem::take(&mut *HELD_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()));
+    for &key in held.iter().rev() {
+        log::info!("Releasing injected key 0x{:02X}", key);
+        unsafe {
+            let _ = send_key(VIRTUAL_KEY(key), true);
+        }
+    }
+}
+
+fn send_app_command(app_cmd: u32) -> Result<()> {
     unsafe {
         let hwnd_fg = GetForegroundWindow();
         if !hwnd_fg.is_invalid() {
@@ -229,20 +906,25 @@ fn send_app_command(app_cmd: u32) {
                 Ok(_) => {
                     log::info!("Sent APPCOMMAND {} to foreground window", app_cmd);
                     log::debug!("Note: Success only means the message was posted, not that it was processed");
+                    Ok(())
                 }
                 Err(e) => {
-                    log::error!("Failed to send APPCOMMAND {}: {:?}", app_cmd, e);
                     log::warn!("The foreground application may not support this command, or there may be a permissions issue");
+                    Err(A1314Error::win32(format!("send APPCOMMAND {}", app_cmd), e))
                 }
             }
         } else {
-            log::error!("No foreground window found for APPCOMMAND {}", app_cmd);
             log::info!("Hint: Ensure an application window is focused before triggering this command");
+            Err(A1314Error::Injection(format!("No foreground window found for APPCOMMAND {}", app_cmd)))
         }
     }
 }
 
-fn launch_program(path: &str) {
+/// Starts a program, after expanding variables like %APPDATA% in its command line, and returns
+/// its process ID
+fn launch_program(path: &str) -> Result<u32> {
+    let expanded = expand::expand(path);
+    let path = expanded.as_str();
     unsafe {
         let mut cmd_line = widestring(path);
         
@@ -269,16 +951,103 @@ fn launch_program(path: &str) {
                 // Close handles to avoid leaks
                 let _ = CloseHandle(pi.hProcess);
                 let _ = CloseHandle(pi.hThread);
+                Ok(pi.dwProcessId)
             }
             Err(e) => {
-                log::error!("Failed to launch '{}': {}", path, e);
                 log::debug!("Error code: {:?}", e.code());
                 log::info!("Hint: Ensure the program path is correct and accessible");
+                Err(A1314Error::win32(format!("launch '{}'", path), e))
             }
         }
     }
 }
 
+/// Hands `target` to the shell with `verb`, after expanding variables like %USERPROFILE%, so it
+/// opens the way double-clicking it would
+fn shell_execute(verb: &str, target: &str) -> Result<()> {
+    let target = expand::expand(target);
+    let (wide_verb, wide_target) = (widestring(verb), widestring(&target));
+    let result = unsafe {
+        ShellExecuteW(None, PCWSTR(wide_verb.as_ptr()), PCWSTR(wide_target.as_ptr()), None, None, SW_SHOWNORMAL)
+    };
+    // Values up to 32 are error codes
+    if result.0 as usize <= 32 {
+        return Err(A1314Error::win32(format!("{} '{}'", verb, target), windows::core::Error::from_win32()));
+    }
+    log::info!("Shell {}: {}", verb, target);
+    Ok(())
+}
+
+/// Brings process `pid`'s main window to the front once it appears. Windows only lets the
+/// process that got the last input take the foreground, so a window that opens a moment after
+/// launch would otherwise just flash in the taskbar.
+fn bring_to_front(pid: u32) {
+    unsafe {
+        // Lets the program take the foreground itself, which many do when their window opens
+        let _ = AllowSetForegroundWindow(pid);
+    }
+    std::thread::spawn(move || {
+        let deadline = Instant::now() + FOREGROUND_WAIT;
+        let hwnd = loop {
+            if let Some(hwnd) = foreground::process_window(pid) {
+                break hwnd;
+            }
+            if Instant::now() >= deadline {
+                // e.g. a launcher that hands over to another process and exits
+                log::info!("Process {} showed no window within {}s to bring to the front", pid, FOREGROUND_WAIT.as_secs());
+                return;
+            }
+            std::thread::sleep(WAIT_WINDOW_POLL);
+        };
+        if !focus_window(hwnd) {
+            log::warn!("Windows refused to bring process {}'s window to the front", pid);
+        }
+    });
+}
+
+/// Restores `hwnd` if it is minimized and makes it the foreground window
+fn focus_window(hwnd: HWND) -> bool {
+    unsafe {
+        if IsIconic(hwnd).as_bool() {
+            let _ = ShowWindow(hwnd, SW_RESTORE);
+        }
+        if SetForegroundWindow(hwnd).as_bool() {
+            return true;
+        }
+        // Holding Alt counts as our own input, which lifts the foreground lock. An Alt the user
+        // holds is theirs, so it is neither pressed nor let go of here.
+        let alt: &[VIRTUAL_KEY] = if physically_held().contains(&VK_MENU) { &[] } else { &[VK_MENU] };
+        let _ = send_keys(&alt.iter().map(|&vk| (vk, false)).collect::<Vec<_>>());
+        let focused = SetForegroundWindow(hwnd).as_bool();
+        // Masked, or the Alt tap alone would open the new window's menu bar
+        let _ = send_keys(&lift_events(alt, true));
+        focused
+    }
+}
+
+/// APP_WINDOW_NEXT / APP_WINDOW_PREV: brings another window of the foreground application to the
+/// front, like Cmd+` on a Mac. Next sends the current window behind the application's others so
+/// repeated presses visit every window; previous undoes that.
+fn cycle_app_windows(forward: bool) -> Result<()> {
+    let pid = foreground::foreground_process_id()
+        .ok_or_else(|| A1314Error::Injection("No foreground window to switch away from".to_string()))?;
+    let windows = foreground::app_windows(pid);
+    if windows.len() < 2 {
+        return Ok(());
+    }
+    let (front, back) = (windows[0], windows[windows.len() - 1]);
+    let target = if forward { windows[1] } else { back };
+    if !focus_window(target) {
+        return Err(A1314Error::Injection("Windows refused to switch to the application's next window".to_string()));
+    }
+    if forward {
+        unsafe {
+            let _ = SetWindowPos(front, back, 0, 0, 0, 0, SWP_NOMOVE | SWP_NOSIZE | SWP_NOACTIVATE);
+        }
+    }
+    Ok(())
+}
+
 fn widestring(s: &str) -> Vec<u16> {
     use std::os::windows::ffi::OsStrExt;
     std::ffi::OsStr::new(s)
@@ -286,3 +1055,134 @@ fn widestring(s: &str) -> Vec<u16> {
         .chain(std::iter::once(0))
         .collect()
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn injected_keys_are_tagged_so_the_hook_skips_them() {
+        let (modifiers, keys) = combo_keys("CTRL+SHIFT+ESCAPE").unwrap();
+        let inputs = key_inputs(&combo_events(&modifiers, &keys));
+        let sent: Vec<(u16, bool, usize)> = inputs.iter()
+            .map(|input| unsafe {
+                let ki = input.Anonymous.ki;
+                (ki.wVk.0, ki.dwFlags.contains(KEYEVENTF_KEYUP), ki.dwExtraInfo)
+            })
+            .collect();
+
+        let tag = DAEMON_INJECTION_TAG as usize;
+        assert_eq!(sent, vec![
+            (VK_CONTROL.0, false, tag), (VK_SHIFT.0, false, tag), (VK_ESCAPE.0, false, tag),
+            (VK_ESCAPE.0, true, tag), (VK_SHIFT.0, true, tag), (VK_CONTROL.0, true, tag),
+        ]);
+        assert!(sent.iter().all(|&(_, _, extra_info)| is_daemon_injection(extra_info)));
+        assert!(!is_daemon_injection(0));
+    }
+
+    #[test]
+    fn chords_tap_each_key_with_the_modifiers_held() {
+        let (modifiers, keys) = combo_keys("CTRL+K+C").unwrap();
+        let (k, c) = (VIRTUAL_KEY(0x4B), VIRTUAL_KEY(0x43));
+        assert_eq!(combo_events(&modifiers, &keys), vec![
+            (VK_CONTROL, false), (k, false), (k, true), (c, false), (c, true), (VK_CONTROL, true),
+        ]);
+        assert_eq!(combo_keys("CTRL+K+NOPE"), Err("NOPE".to_string()));
+    }
+
+    #[test]
+    fn keys_that_pause_go_to_the_sequence_worker() {
+        let tap = KeyStep::Tap("A".to_string());
+        assert!(!keys_wait(&[tap.clone(), tap.clone()], 0));
+        assert!(keys_wait(&[tap.clone()], 5));
+        assert!(keys_wait(&[tap.clone(), KeyStep::Wait(Duration::from_secs(5)), tap.clone()], 0));
+        assert!(!keys_wait(&[tap, KeyStep::Wait(Duration::ZERO)], 0));
+    }
+
+    #[test]
+    fn spaced_out_combos_go_to_the_sequence_worker() {
+        let combo = Action::KeyCombo("CTRL+C".to_string());
+        let lifted = |action: Action| Action::LiftModifiers {
+            modifiers: vec!["ALT".to_string()], keep: Vec::new(), action: Box::new(action),
+        };
+        assert!(!combo_waits(&combo, 0));
+        assert!(combo_waits(&combo, 5));
+        assert!(combo_waits(&lifted(combo.clone()), 5));
+        assert!(!combo_waits(&lifted(combo), 0));
+        assert!(!combo_waits(&lifted(Action::Run("calc.exe".to_string())), 5));
+        assert!(!combo_waits(&Action::Run("calc.exe".to_string()), 5));
+    }
+
+    #[test]
+    fn alt_codes_type_the_digits_on_the_numpad() {
+        assert_eq!(alt_code_events("0233"), vec![
+            (0x38, false),
+            (0x52, false), (0x52, true), (0x50, false), (0x50, true),
+            (0x51, false), (0x51, true), (0x51, false), (0x51, true),
+            (0x38, true),
+        ]);
+        let inputs = scan_code_inputs(&alt_code_events("7"));
+        assert!(inputs.iter().all(|input| unsafe { input.Anonymous.ki.dwFlags.contains(KEYEVENTF_SCANCODE) }));
+    }
+
+    #[test]
+    fn lifted_modifiers_are_masked_from_windows() {
+        assert_eq!(lift_events(&[VK_LWIN], true), vec![(MASK_KEY, false), (MASK_KEY, true), (VK_LWIN, true)]);
+        assert_eq!(lift_events(&[VK_LWIN], false), vec![(VK_LWIN, false), (MASK_KEY, false), (MASK_KEY, true)]);
+        assert_eq!(lift_events(&[VK_CONTROL], true), vec![(VK_CONTROL, true)]);
+        assert!(lift_events(&[], false).is_empty());
+    }
+
+    #[test]
+    fn modifiers_the_user_holds_are_never_released() {
+        // Right Ctrl and Left Shift down, as the hook reports them
+        let down = |vk: VIRTUAL_KEY| vk == VK_RCONTROL || vk == VK_LSHIFT;
+        note_physical_key(0xA3, false);
+        note_physical_key(0xA0, false);
+        note_physical_key(0x41, false);
+        assert_eq!(still_held(down), vec![VK_CONTROL, VK_SHIFT]);
+
+        // CTRL+ALT+END leaves the held Ctrl down and only lets go of the Alt it pressed
+        let kept = kept_modifiers(&[VK_CONTROL, VK_MENU], &[], &still_held(down));
+        assert_eq!(kept, vec![VK_CONTROL]);
+        assert_eq!(kept_combo_events(&[VK_CONTROL, VK_MENU], &[VK_END], &kept), vec![
+            (VK_CONTROL, false), (VK_MENU, false), (VK_END, false), (VK_END, true), (VK_MENU, true),
+        ]);
+        assert_eq!(kept_modifiers(&[VK_MENU], &[VK_SHIFT], &still_held(down)), vec![VK_SHIFT]);
+
+        note_physical_key(0xA3, true);
+        note_physical_key(0xA0, true);
+        assert!(still_held(down).is_empty());
+
+        // A Shift released where the hook couldn't see it is dropped once Windows says it's up
+        note_physical_key(0xA3, false);
+        note_physical_key(0xA1, false);
+        assert_eq!(still_held(|vk| vk == VK_RCONTROL), vec![VK_CONTROL]);
+
+        // Locking the session forgets them all
+        forget_physical_keys();
+        assert!(still_held(down).is_empty());
+    }
+
+    #[test]
+    fn kept_modifiers_stay_down_after_the_combo() {
+        assert_eq!(kept_combo_events(&[VK_CONTROL], &[VK_LEFT], &[VK_SHIFT]), vec![
+            (VK_SHIFT, false), (VK_CONTROL, false), (VK_LEFT, false), (VK_LEFT, true), (VK_CONTROL, true),
+        ]);
+        assert_eq!(kept_combo_events(&[VK_SHIFT], &[VK_HOME], &[VK_SHIFT]), vec![
+            (VK_SHIFT, false), (VK_HOME, false), (VK_HOME, true),
+        ]);
+        assert_eq!(kept_combo_events(&[VK_CONTROL], &[VK_END], &[]), combo_events(&[VK_CONTROL], &[VK_END]));
+    }
+
+    #[test]
+    fn text_is_typed_as_utf16_units() {
+        let inputs = text_inputs("\u{e9}\u{1F600}");
+        let events: Vec<(u16, bool)> = inputs.iter()
+            .map(|input| unsafe { (input.Anonymous.ki.wScan, input.Anonymous.ki.dwFlags.contains(KEYEVENTF_KEYUP)) })
+            .collect();
+        assert_eq!(events, vec![(0xE9, false), (0xE9, true), (0xD83D, false), (0xD83D, true), (0xDE00, false), (0xDE00, true)]);
+        assert!(inputs.iter().all(|input| unsafe { input.Anonymous.ki.dwFlags.contains(KEYEVENTF_UNICODE) }));
+    }
+}
@@ -0,0 +1,85 @@
+// --- START OF FILE src/attribution.rs ---
+// Which keyboard a low-level hook event came from. The hook sees the keys of every keyboard
+// without saying which one; the raw HID path sees only the Apple keyboard's, with their device.
+// A hook key-down is put down to the device whose report pressed the same key just before, and
+// one no report accounts for (the laptop's own keyboard, say) is left alone.
+//
+// The match also keeps a press from acting twice: the report already ran it through the key
+// mapper, so the hook only needs that verdict on whether to suppress the original key.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::hid_parser::DeviceId;
+
+// How far apart the report and the hook event of one press may arrive
+const MATCH_WINDOW: Duration = Duration::from_millis(250);
+
+// Presses remembered; older ones were matched or missed long ago
+const MAX_PRESSES: usize = 32;
+
+struct Press {
+    usage_page: u16,
+    usage: u16,
+    at: Instant,
+    attribution: Attribution,
+}
+
+/// Where a hook key-down came from, and what the raw HID path already did with it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Attribution {
+    pub device: DeviceId,
+    pub mapped: Option<bool>, // Whether the mapper wanted the key suppressed; None if it never saw it
+}
+
+thread_local! {
+    // Key-downs from raw HID reports not yet matched with a hook event, oldest first
+    static PRESSES: RefCell<VecDeque<Press>> = const { RefCell::new(VecDeque::new()) };
+}
+
+/// Notes a key event parsed from a raw HID report, with the mapper's verdict if it was mapped;
+/// only presses are kept
+pub fn record(device: DeviceId, usage_page: u16, usage: u16, value: i32, mapped: Option<bool>) {
+    if value == 0 {
+        return;
+    }
+    PRESSES.with(|presses| {
+        let mut presses = presses.borrow_mut();
+        if presses.len() == MAX_PRESSES {
+            presses.pop_front();
+        }
+        presses.push_back(Press { usage_page, usage, at: Instant::now(), attribution: Attribution { device, mapped } });
+    });
+}
+
+/// The report that pressed this key within the last MATCH_WINDOW, if any. The press is used up,
+/// so one report accounts for one hook event.
+pub fn take(usage_page: u16, usage: u16) -> Option<Attribution> {
+    take_at(usage_page, usage, Instant::now())
+}
+
+fn take_at(usage_page: u16, usage: u16, now: Instant) -> Option<Attribution> {
+    PRESSES.with(|presses| {
+        let mut presses = presses.borrow_mut();
+        presses.retain(|press| now.saturating_duration_since(press.at) <= MATCH_WINDOW);
+        let index = presses.iter().rposition(|press| press.usage_page == usage_page && press.usage == usage)?;
+        presses.remove(index).map(|press| press.attribution)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_report_accounts_for_one_recent_hook_event() {
+        record(DeviceId(7), 0x07, 0x3A, 1, Some(true));
+        record(DeviceId(7), 0x07, 0x3A, 0, Some(false));
+        assert_eq!(take(0x07, 0x3B), None);
+        assert_eq!(take(0x07, 0x3A), Some(Attribution { device: DeviceId(7), mapped: Some(true) }));
+        assert_eq!(take(0x07, 0x3A), None);
+
+        record(DeviceId(7), 0x07, 0x3A, 1, None);
+        assert_eq!(take_at(0x07, 0x3A, Instant::now() + MATCH_WINDOW * 2), None);
+    }
+}
@@ -0,0 +1,128 @@
+// --- START OF FILE src/backups.rs ---
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+// Oldest backups beyond this are deleted
+const MAX_BACKUPS: usize = 20;
+
+/// `backups\` next to the mapping file
+pub fn backup_dir(mapping_path: &Path) -> PathBuf {
+    mapping_path.parent().unwrap_or(Path::new(".")).join("backups")
+}
+
+/// Saves `content` (a previous version of the mapping file) as a timestamped backup.
+/// Skipped if it matches the newest backup, so a reset followed by its reload isn't saved twice.
+pub fn save(mapping_path: &Path, content: &str, reason: &str) -> Option<PathBuf> {
+    let existing = list(mapping_path);
+    if let Some(newest) = existing.first() {
+        if fs::read_to_string(newest).is_ok_and(|newest| newest == content) {
+            return None;
+        }
+    }
+
+    let dir = backup_dir(mapping_path);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log::error!("Failed to create backup folder {}: {}", dir.display(), e);
+        return None;
+    }
+
+    let now = unsafe { GetLocalTime() };
+    let stem = mapping_path.file_stem().and_then(|s| s.to_str()).unwrap_or("A1314_mapping");
+    let path = dir.join(format!("{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{}.txt",
+                                stem, now.wYear, now.wMonth, now.wDay,
+                                now.wHour, now.wMinute, now.wSecond, reason));
+    if let Err(e) = fs::write(&path, content) {
+        log::error!("Failed to write backup {}: {}", path.display(), e);
+        return None;
+    }
+    log::info!("Backed up previous mapping file to {}", path.display());
+
+    for old in list(mapping_path).iter().skip(MAX_BACKUPS) {
+        if let Err(e) = fs::remove_file(old) {
+            log::warn!("Failed to remove old backup {}: {}", old.display(), e);
+        }
+    }
+    Some(path)
+}
+
+/// Backups of `mapping_path`, newest first
+pub fn list(mapping_path: &Path) -> Vec<PathBuf> {
+    let stem = mapping_path.file_stem().and_then(|s| s.to_str()).unwrap_or("A1314_mapping");
+    let prefix = format!("{}-", stem);
+
+    let mut backups: Vec<PathBuf> = match fs::read_dir(backup_dir(mapping_path)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".txt")))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    // The timestamp in the name sorts chronologically
+    backups.sort();
+    backups.reverse();
+    backups
+}
+
+/// Menu text for a backup, e.g. "2026-10-16 09:30:12 (reset)"
+pub fn label(backup: &Path) -> String {
+    let name = backup.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let parts: Vec<&str> = name.rsplitn(3, '-').collect();
+    let digits = |text: &str, len: usize| text.len() == len && text.bytes().all(|b| b.is_ascii_digit());
+    match parts.as_slice() {
+        // Slicing by byte is only safe once both parts are known to be ASCII digits
+        [reason, time, date] if digits(time, 6) => {
+            match date.len().checked_sub(8).and_then(|start| date.get(start..)) {
+                Some(date) if digits(date, 8) => format!("{}-{}-{} {}:{}:{} ({})",
+                                                         &date[..4], &date[4..6], &date[6..],
+                                                         &time[..2], &time[2..4], &time[4..], reason),
+                _ => name.to_string(),
+            }
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Replaces the mapping file with a backup; the reload that follows backs up what it replaced
+pub fn restore(mapping_path: &Path, backup: &Path) -> std::io::Result<()> {
+    fs::copy(backup, mapping_path)?;
+    log::info!("Restored {} from {}", mapping_path.display(), backup.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_show_the_time_and_reason() {
+        let backup = Path::new(r"C:\A1314\backups\A1314_mapping-20261016-093012-reset.txt");
+        assert_eq!(label(backup), "2026-10-16 09:30:12 (reset)");
+
+        // Names that don't follow the pattern are shown as-is
+        assert_eq!(label(Path::new("notes.txt")), "notes");
+        assert_eq!(label(Path::new("A1314_mapping-x-€€-reset.txt")), "A1314_mapping-x-€€-reset");
+        assert_eq!(label(Path::new("A1314_mapping-€€€-093012-reset.txt")), "A1314_mapping-€€€-093012-reset");
+    }
+
+    #[test]
+    fn backups_are_listed_newest_first() {
+        let dir = std::env::temp_dir().join(format!("a1314_backups_{}", std::process::id()));
+        let mapping = dir.join("A1314_mapping.txt");
+        fs::create_dir_all(backup_dir(&mapping)).unwrap();
+        for name in ["A1314_mapping-20261016-093012-edit.txt",
+                     "A1314_mapping-20261101-080000-reset.txt",
+                     "A1314_mapping-20260930-235959-edit.txt",
+                     "other-20261201-000000-edit.txt"] {
+            fs::write(backup_dir(&mapping).join(name), "").unwrap();
+        }
+
+        let names: Vec<String> = list(&mapping).iter().map(|path| label(path)).collect();
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(names, ["2026-11-01 08:00:00 (reset)", "2026-10-16 09:30:12 (edit)", "2026-09-30 23:59:59 (edit)"]);
+    }
+}
@@ -0,0 +1,86 @@
+// --- START OF FILE src/bench.rs ---
+// --bench: replays a canned A1314 report stream through the parser and mapper and times it,
+// so regressions in the pipeline show up as numbers. Actions are counted, never performed.
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::action_executor::{Action, ActionSink};
+use crate::error::Result;
+use crate::input_backend::{dispatch, ReplayBackend, Routing};
+use crate::key_mapper::KeyMapper;
+use crate::latency;
+
+pub const DEFAULT_ITERATIONS: usize = 10_000;
+
+// Typing, Fn and Eject layers over USB, then the same over Bluetooth
+const STREAM: &[&[u8]] = &[
+    &[0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00], // A
+    &[0x01, 0x00, 0x00, 0x04, 0x05, 0x00, 0x00, 0x00], // A + B
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x01, 0x00, 0x00, 0x3A, 0x00, 0x00, 0x00, 0x00], // F1
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x05, 0x01],                                     // USB Fn down
+    &[0x01, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x00, 0x00], // Backspace
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x05, 0x00],
+    &[0x02, 0xB8, 0x00],                               // USB Eject down
+    &[0x01, 0x00, 0x00, 0x1E, 0x00, 0x00, 0x00, 0x00], // 1
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x02, 0x00, 0x00],
+    &[0x11, 0x10],                                     // Bluetooth Fn down
+    &[0x01, 0x00, 0x00, 0x3A, 0x00, 0x00, 0x00, 0x00], // F1
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x11, 0x08],                                     // Bluetooth Eject down
+    &[0x01, 0x00, 0x00, 0x1E, 0x00, 0x00, 0x00, 0x00], // 1
+    &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    &[0x11, 0x00],
+];
+
+/// Counts the actions the mapper resolves instead of performing them
+struct CountingSink(Rc<Cell<usize>>);
+
+impl ActionSink for CountingSink {
+    fn execute(&mut self, _action: &Action) -> Result<()> {
+        self.0.set(self.0.get() + 1);
+        Ok(())
+    }
+}
+
+/// Replays the stream `iterations` times against `mapping_path` and reports the timings
+pub fn run(mapping_path: &Path, iterations: usize) -> Result<String> {
+    let actions = Rc::new(Cell::new(0));
+    let mut mapper = KeyMapper::with_sink(Box::new(CountingSink(actions.clone())));
+    mapper.load_mapping_file(mapping_path)?;
+
+    let mut timings = Vec::with_capacity(STREAM.len() * iterations);
+    let started = Instant::now();
+    for _ in 0..iterations {
+        for report in STREAM {
+            let mut backend = ReplayBackend::new(&[report]);
+            let at = Instant::now();
+            dispatch(&mut backend, &mut mapper, Routing::Map);
+            timings.push(at.elapsed());
+        }
+    }
+    let total = started.elapsed();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "Mapping file: {}", mapping_path.display());
+    let _ = writeln!(out, "Replayed {} reports ({} x {}), {} actions resolved",
+                     timings.len(), iterations, STREAM.len(), actions.get());
+    let _ = writeln!(out, "Total:      {:.1}ms ({:.0} reports/s)", latency::millis(total),
+                     timings.len() as f64 / total.as_secs_f64().max(f64::EPSILON));
+    if let Some((p50, p95)) = latency::percentiles(timings.iter().copied()) {
+        let max = timings.iter().max().copied().unwrap_or(Duration::ZERO);
+        let _ = writeln!(out, "Per report: p50 {:.1}us, p95 {:.1}us, max {:.1}us",
+                         micros(p50), micros(p95), micros(max));
+    }
+    Ok(out)
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
@@ -0,0 +1,155 @@
+// --- START OF FILE src/bluetooth.rs ---
+// The A1314 drops its Bluetooth link when it is switched off or goes to sleep, and the daemon then
+// just stops getting reports. Raw input tells the window when an Apple Bluetooth keyboard comes
+// or goes (RIDEV_DEVNOTIFY). While none is connected the tray says so, and the keyboard's HID
+// service is restarted every so often, which has Windows take the link up again as soon as the
+// keyboard wakes instead of waiting for it to be reconnected by hand.
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use windows::core::GUID;
+use windows::Win32::Devices::Bluetooth::{
+    BluetoothFindDeviceClose, BluetoothFindFirstDevice, BluetoothFindNextDevice,
+    BluetoothGetDeviceInfo, BluetoothSetServiceState, BLUETOOTH_DEVICE_INFO,
+    BLUETOOTH_DEVICE_SEARCH_PARAMS, BLUETOOTH_SERVICE_DISABLE, BLUETOOTH_SERVICE_ENABLE,
+};
+use windows::Win32::Foundation::{BOOL, HANDLE};
+
+use crate::devices::{self, Transport};
+
+// HumanInterfaceDeviceServiceClass_UUID
+const HID_SERVICE: GUID = GUID::from_u128(0x00001124_0000_1000_8000_00805F9B34FB);
+
+// Class of device: the peripheral major class, and the keyboard bit of its minor class
+const MAJOR_CLASS_PERIPHERAL: u32 = 0x05;
+const MINOR_CLASS_KEYBOARD: u32 = 0x40;
+
+/// How often a reconnect is tried while the keyboard is away, and how many times
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+const RECONNECT_ATTEMPTS: u32 = 10;
+
+thread_local! {
+    // Apple Bluetooth keyboards connected now, by raw input device handle
+    static CONNECTED: RefCell<HashSet<isize>> = RefCell::new(HashSet::new());
+    // Paired Bluetooth keyboards that were connected along with an Apple keyboard, by address
+    static PAIRED: RefCell<HashMap<u64, BLUETOOTH_DEVICE_INFO>> = RefCell::new(HashMap::new());
+    // When the last Apple Bluetooth keyboard went away, and how many reconnects were tried since
+    static LOST: Cell<Option<(Instant, u32)>> = const { Cell::new(None) };
+}
+
+/// What a raw input device coming or going means for the Apple Bluetooth keyboards
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Disconnected,
+    Reconnected,
+    Unchanged,
+}
+
+/// Notes a raw input device that arrived (WM_INPUT_DEVICE_CHANGE with GIDC_ARRIVAL)
+pub unsafe fn device_arrived(device: HANDLE) -> Change {
+    let Some(info) = devices::describe(device) else {
+        return Change::Unchanged;
+    };
+    let bluetooth = matches!(info.transport, Transport::Bluetooth | Transport::BluetoothLe);
+    if !info.is_apple() || info.kind != "keyboard" || !bluetooth {
+        return Change::Unchanged;
+    }
+    log::info!("Apple Bluetooth keyboard connected ({:04X}:{:04X})", info.vendor_id, info.product_id);
+    CONNECTED.with(|connected| connected.borrow_mut().insert(device.0 as isize));
+    remember_paired();
+    match LOST.take() {
+        Some(_) => Change::Reconnected,
+        None => Change::Unchanged,
+    }
+}
+
+/// Notes a raw input device that went away (GIDC_REMOVAL); its handle can't be asked about
+/// any more, so only keyboards seen arriving are recognized
+pub fn device_removed(device: HANDLE) -> Change {
+    let (removed, none_left) = CONNECTED.with(|connected| {
+        let mut connected = connected.borrow_mut();
+        (connected.remove(&(device.0 as isize)), connected.is_empty())
+    });
+    if !removed || !none_left {
+        return Change::Unchanged;
+    }
+    log::warn!("Apple Bluetooth keyboard disconnected");
+    LOST.set(Some((Instant::now(), 0)));
+    Change::Disconnected
+}
+
+/// Restarts the HID service of each remembered keyboard that isn't connected, so Windows
+/// reconnects it once it is awake. Returns whether to try again after RECONNECT_INTERVAL.
+pub fn reconnect() -> bool {
+    let Some((since, attempts)) = LOST.get() else {
+        return false;
+    };
+    if attempts >= RECONNECT_ATTEMPTS {
+        return false;
+    }
+    LOST.set(Some((since, attempts + 1)));
+    PAIRED.with(|paired| {
+        for known in paired.borrow().values() {
+            let mut info = *known;
+            if unsafe { BluetoothGetDeviceInfo(None, &mut info) } != 0 || info.fConnected.as_bool() {
+                continue;
+            }
+            let name = String::from_utf16_lossy(&info.szName[..info.szName.iter().position(|&c| c == 0).unwrap_or(0)]);
+            log::info!("Reconnecting Bluetooth keyboard '{}' (attempt {} of {})", name, attempts + 1, RECONNECT_ATTEMPTS);
+            unsafe {
+                let disabled = BluetoothSetServiceState(None, &info, &HID_SERVICE, BLUETOOTH_SERVICE_DISABLE);
+                let enabled = BluetoothSetServiceState(None, &info, &HID_SERVICE, BLUETOOTH_SERVICE_ENABLE);
+                if disabled != 0 || enabled != 0 {
+                    log::warn!("Failed to restart the HID service of '{}' (errors {}, {})", name, disabled, enabled);
+                }
+            }
+        }
+    });
+    attempts + 1 < RECONNECT_ATTEMPTS
+}
+
+/// How long the Apple Bluetooth keyboard has been away, for the tray and `--status`; None
+/// while it is connected (or was never seen)
+pub fn disconnected_for() -> Option<Duration> {
+    LOST.get().map(|(since, _)| since.elapsed())
+}
+
+/// Whether reconnecting was tried as many times as it will be
+pub fn gave_up() -> bool {
+    LOST.get().is_some_and(|(_, attempts)| attempts >= RECONNECT_ATTEMPTS)
+}
+
+/// Remembers the paired Bluetooth keyboards connected right now, one of which is the Apple
+/// keyboard that just arrived (the Bluetooth API doesn't give vendor IDs to tell which)
+fn remember_paired() {
+    let params = BLUETOOTH_DEVICE_SEARCH_PARAMS {
+        dwSize: std::mem::size_of::<BLUETOOTH_DEVICE_SEARCH_PARAMS>() as u32,
+        fReturnAuthenticated: BOOL(1),
+        fReturnRemembered: BOOL(1),
+        fReturnConnected: BOOL(1),
+        ..Default::default()
+    };
+    let mut info = BLUETOOTH_DEVICE_INFO { dwSize: std::mem::size_of::<BLUETOOTH_DEVICE_INFO>() as u32, ..Default::default() };
+    let Ok(find) = (unsafe { BluetoothFindFirstDevice(&params, &mut info) }) else {
+        return;
+    };
+    PAIRED.with(|paired| {
+        let mut paired = paired.borrow_mut();
+        loop {
+            if info.fConnected.as_bool() && is_keyboard(info.ulClassofDevice) {
+                paired.insert(unsafe { info.Address.Anonymous.ullLong }, info);
+            }
+            if unsafe { BluetoothFindNextDevice(find, &mut info) }.is_err() {
+                break;
+            }
+        }
+    });
+    unsafe {
+        let _ = BluetoothFindDeviceClose(find);
+    }
+}
+
+fn is_keyboard(class_of_device: u32) -> bool {
+    (class_of_device >> 8) & 0x1F == MAJOR_CLASS_PERIPHERAL && class_of_device & MINOR_CLASS_KEYBOARD != 0
+}
@@ -0,0 +1,223 @@
+// --- START OF FILE src/cheatsheet.rs ---
+// --export-cheatsheet html|md: the configuration in use as a printable cheat sheet, one section
+// per layer. It is drawn from the parsed mappings (the key, layer, hold tier and action each line
+// became), so it shows what the daemon does, not how the file happens to spell it. The HTML
+// version draws each layer as the A1314's keys in their places; keys that aren't on the
+// keyboard (media keys, mouse buttons, gestures) are listed under it.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::key_mapper::{HidKey, KeyMapper, Layer};
+use crate::name_list;
+use crate::variable_maps::STRING_TO_HID_KEY;
+
+/// The output format of a cheat sheet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Markdown,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "html" | "htm" => Some(Format::Html),
+            "md" | "markdown" => Some(Format::Markdown),
+            _ => None,
+        }
+    }
+}
+
+// The A1314's keys by row, as (key name, label on the key, width in key units); an empty name
+// is a key that can't be mapped (Fn, which is a layer)
+const ROWS: [&[(&str, &str, f32)]; 6] = [
+    &[("ESCAPE", "esc", 1.5), ("F1", "F1", 1.0), ("F2", "F2", 1.0), ("F3", "F3", 1.0),
+      ("F4", "F4", 1.0), ("F5", "F5", 1.0), ("F6", "F6", 1.0), ("F7", "F7", 1.0), ("F8", "F8", 1.0),
+      ("F9", "F9", 1.0), ("F10", "F10", 1.0), ("F11", "F11", 1.0), ("F12", "F12", 1.0),
+      ("EJECT", "\u{23CF}", 1.5)],
+    &[("GRAVE", "`", 1.0), ("KEY_1", "1", 1.0), ("KEY_2", "2", 1.0), ("KEY_3", "3", 1.0),
+      ("KEY_4", "4", 1.0), ("KEY_5", "5", 1.0), ("KEY_6", "6", 1.0), ("KEY_7", "7", 1.0),
+      ("KEY_8", "8", 1.0), ("KEY_9", "9", 1.0), ("KEY_0", "0", 1.0), ("MINUS", "-", 1.0),
+      ("EQUALS", "=", 1.0), ("BACKSPACE", "delete", 1.5)],
+    &[("TAB", "tab", 1.5), ("KEY_Q", "Q", 1.0), ("KEY_W", "W", 1.0), ("KEY_E", "E", 1.0),
+      ("KEY_R", "R", 1.0), ("KEY_T", "T", 1.0), ("KEY_Y", "Y", 1.0), ("KEY_U", "U", 1.0),
+      ("KEY_I", "I", 1.0), ("KEY_O", "O", 1.0), ("KEY_P", "P", 1.0), ("LEFT_BRACKET", "[", 1.0),
+      ("RIGHT_BRACKET", "]", 1.0), ("BACKSLASH", "\\", 1.0)],
+    &[("CAPS_LOCK", "caps lock", 1.75), ("KEY_A", "A", 1.0), ("KEY_S", "S", 1.0),
+      ("KEY_D", "D", 1.0), ("KEY_F", "F", 1.0), ("KEY_G", "G", 1.0), ("KEY_H", "H", 1.0),
+      ("KEY_J", "J", 1.0), ("KEY_K", "K", 1.0), ("KEY_L", "L", 1.0), ("SEMICOLON", ";", 1.0),
+      ("APOSTROPHE", "'", 1.0), ("ENTER", "return", 1.75)],
+    &[("LEFT_SHIFT", "shift", 2.25), ("KEY_Z", "Z", 1.0), ("KEY_X", "X", 1.0), ("KEY_C", "C", 1.0),
+      ("KEY_V", "V", 1.0), ("KEY_B", "B", 1.0), ("KEY_N", "N", 1.0), ("KEY_M", "M", 1.0),
+      ("COMMA", ",", 1.0), ("PERIOD", ".", 1.0), ("SLASH", "/", 1.0), ("RIGHT_SHIFT", "shift", 2.25)],
+    &[("", "fn", 1.0), ("LEFT_CTRL", "control", 1.0), ("LEFT_ALT", "option", 1.0),
+      ("LEFT_GUI", "command", 1.25), ("SPACE", "", 5.0), ("RIGHT_GUI", "command", 1.25),
+      ("RIGHT_ALT", "option", 1.0), ("LEFT_ARROW", "\u{2190}", 1.0), ("UP_ARROW", "\u{2191}", 1.0),
+      ("DOWN_ARROW", "\u{2193}", 1.0), ("RIGHT_ARROW", "\u{2192}", 1.0)],
+];
+
+/// What one key does in one layer: the action of each hold tier mapped, tap first
+type KeyActions = Vec<(&'static str, String)>;
+
+/// The mapped keys of each layer, most general layer first
+type Layers = BTreeMap<((u32, u8), Layer), HashMap<HidKey, KeyActions>>;
+
+/// The configuration `mapper` loaded last as a cheat sheet in `format`
+pub fn export(mapper: &KeyMapper, format: Format, title: &str) -> String {
+    let mut layers = Layers::new();
+    for origin in mapper.effective_mappings() {
+        let keys = layers.entry((origin.layer.specificity(), origin.layer)).or_default();
+        let mut description = name_list::describe(&origin.action);
+        if origin.passthrough {
+            description.push_str(" (key also passes through)");
+        }
+        keys.entry(origin.key).or_default().push((origin.tier_name(), description));
+    }
+    for keys in layers.values_mut() {
+        for actions in keys.values_mut() {
+            actions.sort_by_key(|(tier, _)| ["tap", "hold", "longhold", "longpress"].iter().position(|t| t == tier));
+        }
+    }
+
+    let mut out = String::new();
+    match format {
+        Format::Html => html(&mut out, title, &layers),
+        Format::Markdown => markdown(&mut out, title, &layers),
+    }
+    out
+}
+
+/// Keys of a layer that aren't on the keyboard drawing, by name
+fn off_keyboard(keys: &HashMap<HidKey, KeyActions>) -> Vec<(String, &KeyActions)> {
+    let on_keyboard: Vec<HidKey> = ROWS.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|(name, ..)| STRING_TO_HID_KEY.get(name).copied())
+        .collect();
+    let mut others: Vec<(String, &KeyActions)> = keys.iter()
+        .filter(|(key, _)| !on_keyboard.contains(key))
+        .map(|(key, actions)| (name_list::key_name(*key), actions))
+        .collect();
+    others.sort_by(|a, b| a.0.cmp(&b.0));
+    others
+}
+
+/// Every mapped key of a layer, the keyboard's in row order and then the others by name
+fn in_order(keys: &HashMap<HidKey, KeyActions>) -> Vec<(String, &KeyActions)> {
+    let mut ordered: Vec<(String, &KeyActions)> = ROWS.iter()
+        .flat_map(|row| row.iter())
+        .filter_map(|(name, ..)| {
+            let key = STRING_TO_HID_KEY.get(name)?;
+            keys.get(key).map(|actions| (name_list::key_name(*key), actions))
+        })
+        .collect();
+    ordered.extend(off_keyboard(keys));
+    ordered
+}
+
+fn tiered(tier: &str, description: &str) -> String {
+    if tier == "tap" { description.to_string() } else { format!("{}: {}", tier, description) }
+}
+
+fn layer_heading(layer: Layer) -> String {
+    if layer == Layer::NORMAL { "No modifiers".to_string() } else { format!("{} held", layer) }
+}
+
+fn markdown(out: &mut String, title: &str, layers: &Layers) {
+    let cell = |text: &str| text.replace('|', "\\|");
+    let _ = writeln!(out, "# {}", title);
+    if layers.is_empty() {
+        let _ = writeln!(out, "\nNo keys are mapped.");
+    }
+    for ((_, layer), keys) in layers {
+        let _ = writeln!(out, "\n## {}\n", layer_heading(*layer));
+        let _ = writeln!(out, "| Key | Action |");
+        let _ = writeln!(out, "| --- | --- |");
+        for (name, actions) in in_order(keys) {
+            let actions: Vec<String> = actions.iter().map(|(tier, description)| cell(&tiered(tier, description))).collect();
+            let _ = writeln!(out, "| `{}` | {} |", name, actions.join("<br>"));
+        }
+    }
+}
+
+fn html(out: &mut String, title: &str, layers: &Layers) {
+    let _ = writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>", escape(title));
+    let _ = writeln!(out, "<style>\n\
+        body {{ font-family: 'Segoe UI', sans-serif; margin: 24px; color: #222; }}\n\
+        section {{ break-inside: avoid; page-break-inside: avoid; margin-bottom: 28px; }}\n\
+        .row {{ display: flex; gap: 4px; margin-bottom: 4px; }}\n\
+        .key {{ box-sizing: border-box; height: 64px; padding: 3px 4px; border: 1px solid #bbb;\n\
+                border-radius: 6px; font-size: 9px; overflow: hidden; color: #999; }}\n\
+        .key b {{ display: block; font-size: 11px; font-weight: 600; color: #555; }}\n\
+        .key.mapped {{ background: #eef4ff; border-color: #4a7bd0; color: #123; }}\n\
+        table {{ border-collapse: collapse; margin-top: 10px; font-size: 12px; }}\n\
+        td, th {{ border: 1px solid #ccc; padding: 3px 8px; text-align: left; }}\n\
+        @media print {{ body {{ margin: 0; }} }}\n\
+        </style>\n</head>\n<body>");
+    let _ = writeln!(out, "<h1>{}</h1>", escape(title));
+    if layers.is_empty() {
+        let _ = writeln!(out, "<p>No keys are mapped.</p>");
+    }
+    for ((_, layer), keys) in layers {
+        let _ = writeln!(out, "<section>\n<h2>{}</h2>", escape(&layer_heading(*layer)));
+        for row in ROWS {
+            let _ = write!(out, "<div class=\"row\">");
+            for &(name, label, width) in row {
+                let actions = STRING_TO_HID_KEY.get(name).and_then(|key| keys.get(key));
+                let text: Vec<String> = actions.into_iter().flatten()
+                    .map(|(tier, description)| escape(&tiered(tier, description)))
+                    .collect();
+                let _ = write!(out, "<div class=\"key{}\" style=\"width: {}px\"><b>{}</b>{}</div>",
+                               if text.is_empty() { "" } else { " mapped" },
+                               (width * 64.0) as u32 + ((width - 1.0) * 4.0) as u32,
+                               escape(label), text.join("<br>"));
+            }
+            let _ = writeln!(out, "</div>");
+        }
+        let others = off_keyboard(keys);
+        if !others.is_empty() {
+            let _ = writeln!(out, "<table>\n<tr><th>Key</th><th>Action</th></tr>");
+            for (name, actions) in others {
+                let text: Vec<String> = actions.iter().map(|(tier, description)| escape(&tiered(tier, description))).collect();
+                let _ = writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", escape(&name), text.join("<br>"));
+            }
+            let _ = writeln!(out, "</table>");
+        }
+        let _ = writeln!(out, "</section>");
+    }
+    let _ = writeln!(out, "</body>\n</html>");
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_mapper::load_for_test;
+
+    #[test]
+    fn cheatsheet_groups_mappings_by_layer() {
+        let (mapper, _, result) = load_for_test("cheatsheet", "VERSION=2\n\
+            [mappings]\n\
+            F1 = MUTE\n\
+            F1 = BRIGHTNESS_DOWN\n\
+            HOLD+F1 = MUTE\n\
+            FN+KEY_T = RUN(\"wt.exe\")\n\
+            MOUSE4 = ALT+LEFT_ARROW\n");
+        result.unwrap();
+
+        let md = export(&mapper, Format::Markdown, "A1314");
+        let normal = md.find("## No modifiers").unwrap();
+        let fn_layer = md.find("## Fn held").unwrap();
+        assert!(normal < fn_layer);
+        let f1 = md.lines().find(|line| line.starts_with("| `F1`")).unwrap();
+        assert!(f1.contains("<br>hold: "), "{}", f1);
+        assert!(md.find("`F1`").unwrap() < md.find("`MOUSE4`").unwrap(), "keyboard keys come first");
+
+        let html = export(&mapper, Format::Html, "A1314");
+        assert_eq!(html.matches("<section>").count(), 2);
+        assert!(html.contains("<td>MOUSE4</td>"));
+        assert!(html.contains("run wt.exe</div>"));
+    }
+}
@@ -0,0 +1,163 @@
+// --- START OF FILE src/clipboard.rs ---
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use windows::Win32::Foundation::{GlobalFree, HANDLE, HGLOBAL, HWND};
+use windows::core::HSTRING;
+use windows::Win32::System::DataExchange::{
+    CloseClipboard, EmptyClipboard, EnumClipboardFormats, GetClipboardData, OpenClipboard,
+    RegisterClipboardFormatW, SetClipboardData,
+};
+use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalSize, GlobalUnlock, GMEM_MOVEABLE};
+
+use crate::error::{A1314Error, Result};
+
+const CF_TEXT: u32 = 1;
+const CF_OEMTEXT: u32 = 7;
+const CF_DIB: u32 = 8;
+const CF_UNICODETEXT: u32 = 13;
+const CF_HDROP: u32 = 15;
+const CF_LOCALE: u32 = 16;
+const CF_DIBV5: u32 = 17;
+
+// Formats `save` keeps: text, pictures and copied files. Reading any other format can make the
+// application that copied it render it on the spot (delayed rendering), which is slow for big
+// private formats and can fail if that application has moved on.
+const SAVED_FORMATS: &[u32] = &[CF_TEXT, CF_OEMTEXT, CF_DIB, CF_UNICODETEXT, CF_HDROP, CF_LOCALE, CF_DIBV5];
+const SAVED_REGISTERED_FORMATS: &[&str] = &["HTML Format", "Rich Text Format", "PNG"];
+
+// Registered formats that keep clipboard contents out of Win+V history, cloud sync and other
+// clipboard monitors
+const EXCLUDE_FROM_MONITORS: &str = "ExcludeClipboardContentFromMonitorProcessing";
+const CAN_INCLUDE_IN_HISTORY: &str = "CanIncludeInClipboardHistory";
+const CAN_UPLOAD_TO_CLOUD: &str = "CanUploadToCloudClipboard";
+
+// The window that owns what we put on the clipboard; Windows only accepts data from an owner
+static OWNER: AtomicIsize = AtomicIsize::new(0);
+
+/// Closes the clipboard on drop, so every early return releases it
+struct OpenedClipboard;
+
+impl Drop for OpenedClipboard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseClipboard();
+        }
+    }
+}
+
+/// What the clipboard held, to put back after a paste: the bytes of its text, picture and file
+/// formats. Bitmaps and metafiles are GDI handles rather than memory and are left out, though a
+/// picture usually survives anyway as CF_DIB.
+pub struct Contents(Vec<(u32, Vec<u8>)>);
+
+/// Sets the window that owns the text put on the clipboard
+pub fn set_owner(hwnd: HWND) {
+    OWNER.store(hwnd.0 as isize, Ordering::Relaxed);
+}
+
+/// Replaces the clipboard contents with `text`
+pub fn set_text(text: &str) -> Result<()> {
+    replace_with_text(text, false)
+}
+
+/// Replaces the clipboard contents with `text` just long enough to paste it, keeping it out of
+/// clipboard history
+pub fn set_transient_text(text: &str) -> Result<()> {
+    replace_with_text(text, true)
+}
+
+fn replace_with_text(text: &str, transient: bool) -> Result<()> {
+    let wide: Vec<u16> = text.encode_utf16().chain(std::iter::once(0)).collect();
+    let bytes: Vec<u8> = wide.iter().flat_map(|unit| unit.to_ne_bytes()).collect();
+
+    let _clipboard = open()?;
+    unsafe {
+        EmptyClipboard().map_err(|e| A1314Error::win32("empty the clipboard", e))?;
+        if transient {
+            exclude_from_monitors();
+        }
+        set_data(CF_UNICODETEXT, &bytes).map_err(|e| A1314Error::win32("set the clipboard text", e))
+    }
+}
+
+/// Copies the text, picture and file formats the clipboard holds now
+pub fn save() -> Result<Contents> {
+    let registered: Vec<u32> = SAVED_REGISTERED_FORMATS.iter().map(|name| registered_format(name)).collect();
+    let _clipboard = open()?;
+    let mut formats = Vec::new();
+    unsafe {
+        let mut format = EnumClipboardFormats(0);
+        while format != 0 {
+            if SAVED_FORMATS.contains(&format) || registered.contains(&format) {
+                if let Ok(handle) = GetClipboardData(format) {
+                    let memory = HGLOBAL(handle.0);
+                    let source = GlobalLock(memory) as *const u8;
+                    if !source.is_null() {
+                        formats.push((format, std::slice::from_raw_parts(source, GlobalSize(memory)).to_vec()));
+                        let _ = GlobalUnlock(memory);
+                    }
+                }
+            }
+            format = EnumClipboardFormats(format);
+        }
+    }
+    Ok(Contents(formats))
+}
+
+/// Puts back what `save` copied, emptying the clipboard if it held nothing. It was in clipboard
+/// history already, so it isn't added again.
+pub fn restore(contents: &Contents) -> Result<()> {
+    let _clipboard = open()?;
+    unsafe {
+        EmptyClipboard().map_err(|e| A1314Error::win32("empty the clipboard", e))?;
+        exclude_from_monitors();
+        for (format, bytes) in &contents.0 {
+            if let Err(e) = set_data(*format, bytes) {
+                log::debug!("Failed to put clipboard format {} back: {}", format, e);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn open() -> Result<OpenedClipboard> {
+    let owner = HWND(OWNER.load(Ordering::Relaxed) as *mut std::ffi::c_void);
+    unsafe {
+        OpenClipboard(owner).map_err(|e| A1314Error::win32("open the clipboard", e))?;
+    }
+    Ok(OpenedClipboard)
+}
+
+/// Puts `bytes` on the open clipboard as `format`
+unsafe fn set_data(format: u32, bytes: &[u8]) -> windows::core::Result<()> {
+    let memory = GlobalAlloc(GMEM_MOVEABLE, bytes.len())?;
+    let target = GlobalLock(memory) as *mut u8;
+    if target.is_null() {
+        let error = windows::core::Error::from_win32();
+        let _ = GlobalFree(memory);
+        return Err(error);
+    }
+    std::ptr::copy_nonoverlapping(bytes.as_ptr(), target, bytes.len());
+    let _ = GlobalUnlock(memory);
+
+    // On success the clipboard owns the memory
+    SetClipboardData(format, HANDLE(memory.0)).map(|_| ()).inspect_err(|_| {
+        let _ = GlobalFree(memory);
+    })
+}
+
+/// Marks what is being put on the open clipboard as not for clipboard history, cloud sync or
+/// other monitors
+unsafe fn exclude_from_monitors() {
+    // The history and cloud formats take a DWORD 0; the monitor one only has to be present
+    let no = 0u32.to_ne_bytes();
+    for name in [EXCLUDE_FROM_MONITORS, CAN_INCLUDE_IN_HISTORY, CAN_UPLOAD_TO_CLOUD] {
+        if let Err(e) = set_data(registered_format(name), &no) {
+            log::debug!("Failed to set clipboard format {}: {}", name, e);
+        }
+    }
+}
+
+fn registered_format(name: &str) -> u32 {
+    unsafe { RegisterClipboardFormatW(&HSTRING::from(name)) }
+}
@@ -0,0 +1,1174 @@
+// --- START OF FILE src/config.rs ---
+// The mapping file's line syntax: `[options] [HOLD+|LONGHOLD+][modifiers+]KEY = ACTION` lines and
+// `MODIFIER KEY` declarations, parsed into Mapping values. Nothing here reads files or touches the
+// mapper's state, so every rule of the syntax can be tested on a line of text; KeyMapper applies
+// the results, file by file.
+use std::time::Duration;
+
+use crate::action_executor::{self, Action, KeyStep};
+use crate::key_mapper::{self, Backend, HidKey, Layer};
+use crate::settings;
+use crate::toggles::Toggle;
+use crate::variable_maps::{self, STRING_TO_ACTION, STRING_TO_HID_KEY};
+
+/// A single `KEY = ACTION` line after parsing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    /// The modifiers held for it, i.e. its layer
+    pub modifiers: Layer,
+    pub key: HidKey,
+    pub action: Action,
+    /// Line number in its file; 0 for a line that came from no file, e.g. `map add`
+    pub line: usize,
+    pub hold_tier: Option<HoldTier>,
+    pub passthrough: bool,
+    pub backend: Backend,
+    /// CLEARMODS: whether the Ctrl, Shift, Alt and Win of its own layer are let go of while its
+    /// action runs, e.g. Win for the macOS navigation preset's CMD+LEFT_ARROW = HOME. KEEPMODS,
+    /// the default, leaves them held.
+    pub clear_modifiers: bool,
+}
+
+
+/// Why a mapping line was rejected, plus a hint on the expected format
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Where the problem starts in the text parsed (the line, or the action for parse_action),
+    /// counted in characters from 1
+    pub column: usize,
+    pub message: String,
+    pub hint: &'static str,
+}
+
+impl ParseError {
+    /// The same error, found in text that starts at `column` of a longer text
+    fn at(mut self, column: usize) -> Self {
+        self.column += column - 1;
+        self
+    }
+}
+
+/// The column `part`, a slice of `text`, starts at
+fn column_of(text: &str, part: &str) -> usize {
+    let offset = (part.as_ptr() as usize).saturating_sub(text.as_ptr() as usize).min(text.len());
+    text[..offset].chars().count() + 1
+}
+
+/// The part of a line a ParseError is about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseErrorKind {
+    /// Not `KEY = ACTION` at all
+    Syntax,
+    /// A BACKEND=, VIA=, PASSTHRU, KEEPMODS/CLEARMODS or hold tier option that can't be used
+    Option,
+    /// A key name that isn't known
+    UnknownKey,
+    /// HID() or LONGPRESS() around a key, written wrong
+    MalformedKey,
+    /// An action or key combination name that isn't known
+    UnknownAction,
+    /// RUN(), KEYS() or another action form, written wrong
+    MalformedAction,
+    /// A MODIFIER declaration of a key that doesn't select a layer
+    NotAModifier,
+}
+
+// Options that may start a line, ahead of the key and each followed by whitespace
+const LINE_OPTIONS: [&str; 2] = ["BACKEND=", "VIA="];
+
+// Words that may follow the options, in any order and each followed by whitespace
+const MAPPING_WORDS: [&str; 3] = ["PASSTHRU", "KEEPMODS", "CLEARMODS"];
+
+// LHS prefixes selecting a layer, in any order and combination
+const MODIFIER_PREFIXES: [(&str, Layer); 12] = [
+    ("EJECT+", Layer::EJECT),
+    ("FN+", Layer::FN),
+    ("LEFT_SHIFT+", Layer::SHIFT),
+    ("RIGHT_SHIFT+", Layer::SHIFT),
+    ("SHIFT+", Layer::SHIFT),
+    ("CTRL+", Layer::CTRL),
+    ("CONTROL+", Layer::CTRL),
+    ("ALT+", Layer::ALT),
+    ("OPTION+", Layer::ALT),
+    ("GUI+", Layer::GUI),
+    ("WIN+", Layer::GUI),
+    ("CMD+", Layer::GUI),
+];
+
+/// HOLD+, LONGHOLD+ or LONGPRESS(): the action for a key held down rather than tapped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HoldTier {
+    Medium,
+    Long,
+    LongPress(Option<u64>), // Threshold in ms, or the long_press_ms setting
+}
+
+/// Whether two hold tiers are the same slot of a binding; a key has one long press, whatever its
+/// threshold
+pub fn same_tier(a: Option<HoldTier>, b: Option<HoldTier>) -> bool {
+    match (a, b) {
+        (Some(HoldTier::LongPress(_)), Some(HoldTier::LongPress(_))) => true,
+        _ => a == b,
+    }
+}
+
+/// Parses the left-hand side of a mapping line on its own, e.g. for `map remove`
+pub fn parse_lhs(lhs: &str) -> Result<Mapping, ParseError> {
+    parse_mapping(&format!("{} = NONE", lhs), 0)
+}
+
+/// Whether mapping `line` sets what `lhs` names: the same key, layer and hold tier
+pub fn line_maps(line: &str, lhs: &str) -> bool {
+    match (parse_mapping(line, 0), parse_lhs(lhs)) {
+        (Ok(mapping), Ok(target)) => {
+            mapping.key == target.key && mapping.modifiers == target.modifiers && same_tier(mapping.hold_tier, target.hold_tier)
+        }
+        _ => false,
+    }
+}
+
+/// Length of the leading `BACKEND=` and `VIA=` options, up to the whitespace after the last
+fn options_len(line: &str) -> usize {
+    let mut len = 0;
+    loop {
+        let trimmed = line[len..].trim_start();
+        if !LINE_OPTIONS.iter().any(|option| strip_prefix_ignore_case(trimmed, option).is_some()) {
+            return len;
+        }
+        len = line.len() - trimmed.len() + trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    }
+}
+
+/// Splits a mapping line at the '=' between key and action: the first one after any leading
+/// `BACKEND=` or `VIA=` options, as the action may contain more (IF(app=...))
+pub fn split_mapping_line(line: &str) -> Option<(&str, &str)> {
+    let option_len = options_len(line);
+    let (lhs, rhs) = line[option_len..].split_once('=')?;
+    Some((&line[..option_len + lhs.len()], rhs))
+}
+
+/// Parses one mapping line (`[BACKEND=raw|hook ][VIA=keys|clipboard ][PASSTHRU ][KEEPMODS |CLEARMODS ][HOLD+|LONGHOLD+][EJECT+][FN+][SHIFT+][CTRL+][ALT+][GUI+]KEY = ACTION`,
+/// with the modifiers in any order, where KEY may also be `LONGPRESS(KEY[, 800ms])`); `line_no`
+/// is where it is in its file, or 0
+pub fn parse_mapping(line: &str, line_no: usize) -> Result<Mapping, ParseError> {
+    let Some((lhs, rhs_str)) = split_mapping_line(line).map(|(lhs, rhs)| (lhs.trim(), rhs.trim())) else {
+        return Err(ParseError {
+            kind: ParseErrorKind::Syntax,
+            column: 1,
+            message: format!("Invalid mapping syntax: {}", line),
+            hint: "Expected format: KEY = ACTION",
+        });
+    };
+
+    // Key names and prefixes are case-insensitive; the RHS keeps its case for RUN() paths
+    let lhs_upper = lhs.to_ascii_uppercase();
+    let lhs_str = lhs_upper.as_str();
+    // Upper-casing keeps every character where it was, so parts of it have a column in the line
+    let lhs_column = |part: &str| column_of(line, lhs) + column_of(&lhs_upper, part) - 1;
+
+    // BACKEND= (outermost) picks the input path that acts on the key, and VIA=clipboard pastes
+    // the mapping's TEXT() instead of typing it, for applications that ignore typed Unicode text
+    let mut backend = Backend::Both;
+    let mut via_clipboard = None;
+    let mut lhs_str = lhs_str;
+    while let Some((option, rest)) = LINE_OPTIONS.iter()
+        .find_map(|option| lhs_str.strip_prefix(option).map(|rest| (*option, rest))) {
+        let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        if option == "BACKEND=" {
+            backend = Backend::parse(name).ok_or_else(|| ParseError {
+                kind: ParseErrorKind::Option,
+                column: lhs_column(name),
+                message: format!("Unknown BACKEND '{}'", name),
+                hint: "Expected BACKEND=raw, BACKEND=hook or BACKEND=both before the key",
+            })?;
+        } else {
+            via_clipboard = match name {
+                "CLIPBOARD" => Some(lhs_column(name)),
+                "KEYS" => None,
+                _ => return Err(ParseError {
+                    kind: ParseErrorKind::Option,
+                    column: lhs_column(name),
+                    message: format!("Unknown VIA '{}'", name),
+                    hint: "Expected VIA=keys or VIA=clipboard before the key",
+                }),
+            };
+        }
+        lhs_str = rest.trim_start();
+    }
+
+    // PASSTHRU (followed by a space) also lets the original key through, and CLEARMODS lets go
+    // of the modifiers held for the mapping while its action runs, where KEEPMODS leaves them
+    let mut passthrough = false;
+    let mut modifier_policy: Option<&str> = None;
+    while let Some((word, rest)) = MAPPING_WORDS.iter().find_map(|word| {
+        lhs_str.strip_prefix(word).filter(|rest| rest.starts_with(char::is_whitespace)).map(|rest| (*word, rest))
+    }) {
+        if word == "PASSTHRU" {
+            passthrough = true;
+        } else if modifier_policy.replace(word).is_some_and(|policy| policy != word) {
+            return Err(ParseError {
+                kind: ParseErrorKind::Option,
+                column: lhs_column(lhs_str),
+                message: "KEEPMODS and CLEARMODS on the same mapping".to_string(),
+                hint: "Use KEEPMODS to leave the held modifiers down, or CLEARMODS to let go of them",
+            });
+        }
+        lhs_str = rest.trim_start();
+    }
+    let clear_modifiers = modifier_policy == Some("CLEARMODS");
+
+    // Check for HOLD+/LONGHOLD+ tier prefix
+    let (hold_tier, rest_after_hold) = if let Some(rest) = lhs_str.strip_prefix("HOLD+") {
+        (Some(HoldTier::Medium), rest.trim())
+    } else if let Some(rest) = lhs_str.strip_prefix("LONGHOLD+") {
+        (Some(HoldTier::Long), rest.trim())
+    } else {
+        (None, lhs_str)
+    };
+
+    // Modifier prefixes, in any order
+    let mut layer = Layer::NORMAL;
+    let mut key_name = rest_after_hold;
+    while let Some((rest, modifier)) = MODIFIER_PREFIXES.iter()
+        .find_map(|(prefix, modifier)| key_name.strip_prefix(prefix).map(|rest| (rest.trim(), *modifier))) {
+        layer = layer.with(modifier);
+        key_name = rest;
+    }
+
+    // LONGPRESS(KEY[, duration]) fires while the key is still held
+    let (hold_tier, key_name) = match key_name.strip_prefix("LONGPRESS(") {
+        Some(args) => {
+            let error = |message: String| ParseError {
+                kind: ParseErrorKind::MalformedKey,
+                column: lhs_column(key_name),
+                message,
+                hint: "Expected format: LONGPRESS(KEY) or LONGPRESS(KEY, 800ms)",
+            };
+            if hold_tier.is_some() {
+                return Err(error(format!("LONGPRESS() can't be combined with HOLD+ or LONGHOLD+: {}", lhs_str)));
+            }
+            let args = args.strip_suffix(')')
+                .ok_or_else(|| error(format!("Malformed LONGPRESS() syntax: '{}'", key_name)))?;
+            match args.split_once(',') {
+                Some((key, duration)) => {
+                    let threshold_ms = settings::parse_ms(&duration.to_ascii_lowercase()).map_err(error)?;
+                    (Some(HoldTier::LongPress(Some(threshold_ms))), key.trim())
+                }
+                None => (Some(HoldTier::LongPress(None)), args.trim()),
+            }
+        }
+        None => (hold_tier, key_name),
+    };
+
+    let key = parse_key_name(key_name).map_err(|e| e.at(lhs_column(key_name)))?;
+
+    let action = match split_sound(rhs_str) {
+        Some((action, sound)) => parse_action(action).map(|action| Action::WithSound { sound, action: Box::new(action) }),
+        None => parse_action(rhs_str),
+    };
+    let mut action = action.map_err(|e| e.at(column_of(line, rhs_str)))?;
+    if let Some(column) = via_clipboard {
+        let (pasted, has_text) = paste_text(action);
+        if !has_text {
+            return Err(ParseError {
+                kind: ParseErrorKind::Option,
+                column,
+                message: "VIA=clipboard on a mapping without TEXT()".to_string(),
+                hint: "VIA=clipboard pastes the mapping's TEXT(\"...\"), e.g. VIA=clipboard F1 = TEXT(\"\u{e9}\")",
+            });
+        }
+        action = pasted;
+    }
+    Ok(Mapping { modifiers: layer, key, action, line: line_no, hold_tier, passthrough, backend, clear_modifiers })
+}
+
+/// `action` with its TEXT() pasted through the clipboard rather than typed, wherever it is in
+/// sequences and IF() branches, and whether it had any
+fn paste_text(action: Action) -> (Action, bool) {
+    match action {
+        Action::Text(text) => (Action::Paste(text), true),
+        Action::Sequence(steps) => {
+            let (steps, has_text): (Vec<Action>, Vec<bool>) = steps.into_iter().map(paste_text).unzip();
+            (Action::Sequence(steps), has_text.contains(&true))
+        }
+        Action::IfApp { app, then, otherwise } => {
+            let (then, then_has_text) = paste_text(*then);
+            let (otherwise, otherwise_has_text) = paste_text(*otherwise);
+            (Action::IfApp { app, then: Box::new(then), otherwise: Box::new(otherwise) }, then_has_text || otherwise_has_text)
+        }
+        Action::WithSound { sound, action } => {
+            let (action, has_text) = paste_text(*action);
+            (Action::WithSound { sound, action: Box::new(action) }, has_text)
+        }
+        action => (action, false),
+    }
+}
+
+/// Looks up an upper-case key name in the hardcoded map, or takes a raw HID(page,usage)
+pub fn parse_key_name(key_name: &str) -> Result<HidKey, ParseError> {
+    match STRING_TO_HID_KEY.get(key_name)
+        .or_else(|| STRING_TO_HID_KEY.get(variable_maps::normalize_name(key_name).as_str())) {
+        Some(key) => Ok(*key),
+        None if key_name.starts_with("HID(") => parse_hid_usage(key_name),
+        None => Err(ParseError {
+            kind: ParseErrorKind::UnknownKey,
+            column: 1,
+            message: format!("Unknown key name '{}'{}", key_name,
+                             variable_maps::did_you_mean(key_name, STRING_TO_HID_KEY.keys().copied())),
+            hint: "Run with --list-keys to see the valid key names",
+        }),
+    }
+}
+
+/// Splits a trailing ` SOUND("file.wav")` off an action, e.g. `SUSPEND(5m) SOUND("click.wav")`
+fn split_sound(rhs_str: &str) -> Option<(&str, String)> {
+    let start = rhs_str.to_ascii_uppercase().rfind(" SOUND(\"")?;
+    let (action, attribute) = rhs_str.split_at(start);
+    let (sound, tail) = unquote(&attribute.trim_start()[7..])?;
+    // After `;`, SOUND() is an action of its own
+    let action = action.trim_end();
+    let own_action = split_actions(action).last() == Some(&"");
+    (tail.trim() == ")" && !sound.is_empty() && !action.is_empty() && !own_action).then_some((action, sound))
+}
+
+/// Parses `MODIFIER EJECT`, which makes a layer key act only in combination with other keys;
+/// None if the line isn't a MODIFIER declaration
+pub fn parse_modifier_declaration(line: &str) -> Option<Result<HidKey, ParseError>> {
+    let key_name = strip_prefix_ignore_case(line, "MODIFIER")
+        .filter(|rest| rest.starts_with(char::is_whitespace) && !rest.contains('='))?
+        .trim()
+        .to_ascii_uppercase();
+    let key = STRING_TO_HID_KEY.get(key_name.as_str())
+        .or_else(|| STRING_TO_HID_KEY.get(variable_maps::normalize_name(&key_name).as_str()))
+        .copied()
+        .filter(|key| key_mapper::is_modifier_key(*key));
+    Some(key.ok_or_else(|| ParseError {
+        kind: ParseErrorKind::NotAModifier,
+        column: 1,
+        message: format!("'{}' isn't a layer modifier", key_name),
+        hint: "Expected a layer key like MODIFIER EJECT, MODIFIER FN or MODIFIER LEFT_CTRL",
+    }))
+}
+
+/// Parses `HID(0xFF00,0x0005)`, a usage page and usage for keys without a friendly name
+fn parse_hid_usage(key_name: &str) -> Result<HidKey, ParseError> {
+    let error = || ParseError {
+        kind: ParseErrorKind::MalformedKey,
+        column: 1,
+        message: format!("Malformed HID() key: '{}'", key_name),
+        hint: "Expected format: HID(usage_page,usage), e.g. HID(0xFF00,0x0005)",
+    };
+    let args = key_name.strip_prefix("HID(").and_then(|rest| rest.strip_suffix(')')).ok_or_else(error)?;
+    match parse_numbers(args).as_deref() {
+        Some(&[usage_page, usage]) => Ok(HidKey {
+            usage_page: u16::try_from(usage_page).map_err(|_| error())?,
+            usage: u16::try_from(usage).map_err(|_| error())?,
+        }),
+        _ => Err(error()),
+    }
+}
+
+/// Parses a comma separated list of hex (0x1F) or decimal numbers
+fn parse_numbers(args: &str) -> Option<Vec<u32>> {
+    args.split(',')
+        .map(|arg| {
+            let arg = arg.trim();
+            match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => arg.parse().ok(),
+            }
+        })
+        .collect()
+}
+
+/// Splits function arguments at the commas that aren't inside quotes or parentheses
+fn split_args(args: &str) -> Vec<&str> {
+    split_outside_quotes(args, ',', |_| true)
+}
+
+/// Splits `A; B; C` into its actions. A `;` where a key name goes (`CTRL+;`, or `;` alone) is the
+/// semicolon key.
+fn split_actions(rhs_str: &str) -> Vec<&str> {
+    split_outside_quotes(rhs_str, ';', |before| !matches!(before.trim_end().chars().last(), None | Some('+')))
+}
+
+/// Splits `text` at the `separator`s outside quotes and parentheses that `separates` accepts,
+/// given the part before them
+fn split_outside_quotes(text: &str, separator: char, separates: impl Fn(&str) -> bool) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut start, mut i) = (0usize, 0, 0);
+    while let Some(c) = text[i..].chars().next() {
+        match c {
+            // A quoted string is skipped whole, to its end if it has no closing quote
+            '"' => i = closing_quote(&text[i + 1..]).map_or(text.len() - 1, |end| i + 1 + end),
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            c if c == separator && depth == 0 && separates(&text[start..i]) => {
+                parts.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    parts.push(text[start..].trim());
+    parts
+}
+
+/// Reads a quoted argument from just after its opening quote, giving the string (with `\"` and
+/// `\\` unescaped) and the text after its closing quote
+pub fn unquote(text: &str) -> Option<(String, &str)> {
+    let end = closing_quote(text)?;
+    let mut string = String::with_capacity(end);
+    let mut chars = text[..end].chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&next @ ('\\' | '"'))) => {
+                string.push(next);
+                chars.next();
+            }
+            _ => string.push(c),
+        }
+    }
+    Some((string, &text[end + 1..]))
+}
+
+/// Where a quoted string ends in `text`, the text after its opening quote. A quote only ends it
+/// where an argument can end, before a comma, a closing parenthesis or the end of the text, so
+/// files written before escapes existed keep working: other quotes are part of the string, as is
+/// the backslash in `C:\Folder\")`. Any other backslash is just a backslash, as in `C:\Tools`.
+fn closing_quote(text: &str) -> Option<usize> {
+    let ends_argument = |rest: &str| matches!(rest.trim_start().chars().next(), None | Some(',' | ')'));
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if text[i + 1..].starts_with('\\') => {
+                chars.next();
+            }
+            '\\' if text[i + 1..].starts_with('"') && !ends_argument(&text[i + 2..]) => {
+                chars.next();
+            }
+            '"' if ends_argument(&text[i + 1..]) => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+// WAITWINDOW() timeout when the mapping doesn't give one (in milliseconds)
+const DEFAULT_WAIT_WINDOW_MS: u64 = 5000;
+
+/// Action forms parse_action recognizes besides the names in STRING_TO_ACTION, as (syntax, description)
+pub const ACTION_SYNTAX: &[(&str, &str)] = &[
+    ("RUN(\"path\")", "Start a program, optionally with arguments"),
+    ("RUN(\"path\", FOREGROUND)", "Start a program and bring its window to the front"),
+    ("OPEN(\"path\")", "Open a document, folder or URL with its associated application"),
+    ("EXPLORE(\"folder\")", "Open a folder in File Explorer"),
+    ("LAYOUT(\"en-US\")", "Switch to the installed keyboard layout for a locale"),
+    ("SOUND(\"click.wav\")", "Play a .wav file; after another action, plays it along with that action"),
+    ("QUIT_APP(CONFIRM)", "Close every window of the app in front after asking; also FORCE_KILL_APP(CONFIRM)"),
+    ("FOCUS_ASSIST(toggle)", "Turn Focus Assist (Do Not Disturb) on, off or toggle it"),
+    ("APPCOMMAND(n)", "Send a WM_APPCOMMAND, e.g. APPCOMMAND(46)"),
+    ("TEXT(\"caf\u{e9}\")", "Type the text as it is, whatever the keyboard layout; VIA=clipboard before the key pastes it"),
+    ("ALTCODE(0233)", "Type a character by its Alt code, for older applications that ignore other typed text"),
+    ("SENDHID(id, byte, ...)", "Send a raw output report to the keyboard, report ID first"),
+    ("TOGGLE(MIC_MUTE)", "Mute or unmute the microphone (MUTE: the speakers) from its real state"),
+    ("KEYS(CTRL_DOWN, C, CTRL_UP)", "Key events in exactly this order: NAME_DOWN, NAME_UP, NAME to tap, WAIT(50ms)"),
+    ("SEQUENCE(A, B, ...)", "Actions one after another, e.g. RUN() then WAITWINDOW() then KEYS()"),
+    ("A; B; ...", "The same as SEQUENCE(A, B, ...), e.g. WIN+D; RUN(\"notepad.exe\")"),
+    ("WAITWINDOW(\"name.exe\", 5000)", "In a SEQUENCE(), wait up to 5000ms for that application's window"),
+    ("SUSPEND(30s)", "Leave every key alone for a while (s, m or ms; seconds by default)"),
+    ("IF(app=\"name.exe\", A, B)", "Action A while that application is in front, otherwise B"),
+    ("MOD+KEY", "Any other text is sent as a key combination, e.g. CTRL+SHIFT+ESC"),
+];
+
+pub fn parse_action(rhs_str: &str) -> Result<Action, ParseError> {
+    // `A; B` is shorthand for SEQUENCE(A, B)
+    let actions = split_actions(rhs_str);
+    if actions.len() > 1 {
+        let steps = actions.into_iter()
+            .map(|action| match action {
+                "" => Err(ParseError {
+                    kind: ParseErrorKind::MalformedAction,
+                    column: 1,
+                    message: format!("Missing an action between the ';'s of '{}'", rhs_str),
+                    hint: "Expected actions separated by ';', e.g. WIN+D; RUN(\"notepad.exe\")",
+                }),
+                _ => parse_action(action),
+            }.map_err(|e| e.at(column_of(rhs_str, action))))
+            .collect::<Result<_, _>>()?;
+        return Ok(Action::Sequence(steps));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "RUN(\"") {
+        let error = || ParseError {
+            kind: ParseErrorKind::MalformedAction,
+            column: 1,
+            message: format!("Malformed RUN() syntax: '{}'", rhs_str),
+            hint: "Expected format: RUN(\"path/to/program.exe\") or RUN(\"program.exe\", FOREGROUND)",
+        };
+        let (command, tail) = unquote(rest).ok_or_else(error)?;
+        let tail = tail.trim();
+        // RUN("path", FOREGROUND) also brings the program's window to the front
+        let option = tail.strip_suffix(')').ok_or_else(error)?.trim();
+        return match option.strip_prefix(',').map(str::trim) {
+            None if option.is_empty() => Ok(Action::Run(command)),
+            Some(option) if option.eq_ignore_ascii_case("FOREGROUND") => Ok(Action::RunForeground(command)),
+            _ => Err(error()),
+        };
+    }
+
+    for prefix in ["OPEN(", "EXPLORE("] {
+        if let Some(rest) = strip_prefix_ignore_case(rhs_str, prefix) {
+            let target = rest.strip_suffix(')')
+                .and_then(|arg| unquote(arg.trim().strip_prefix('"')?))
+                .filter(|(target, tail)| !target.is_empty() && tail.is_empty())
+                .map(|(target, _)| target)
+                .ok_or_else(|| ParseError {
+                    kind: ParseErrorKind::MalformedAction,
+                    column: 1,
+                    message: format!("Malformed {}) syntax: '{}'", prefix, rhs_str),
+                    hint: "Expected a quoted path, e.g. OPEN(\"C:\\Reports\\q3.xlsx\") or EXPLORE(\"D:\\Music\")",
+                })?;
+            return Ok(if prefix == "OPEN(" { Action::Open(target) } else { Action::Explore(target) });
+        }
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "TEXT(") {
+        let text = rest.strip_suffix(')')
+            .and_then(|arg| unquote(arg.trim().strip_prefix('"')?))
+            .filter(|(text, tail)| !text.is_empty() && tail.is_empty())
+            .map(|(text, _)| text)
+            .ok_or_else(|| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed TEXT() syntax: '{}'", rhs_str),
+                hint: "Expected quoted text, e.g. TEXT(\"caf\u{e9}\") or TEXT(\"\u{2014}\")",
+            })?;
+        return Ok(Action::Text(text));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "LAYOUT(") {
+        let locale = rest.strip_suffix(')')
+            .map(|arg| arg.trim().trim_matches('"').trim())
+            .filter(|locale| !locale.is_empty() && locale.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            .ok_or_else(|| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed LAYOUT() syntax: '{}'", rhs_str),
+                hint: "Expected a locale name, e.g. LAYOUT(\"en-US\") or LAYOUT(\"ja-JP\")",
+            })?;
+        return Ok(Action::Layout(locale.to_string()));
+    }
+
+    for (prefix, force) in [("QUIT_APP(", false), ("FORCE_KILL_APP(", true)] {
+        if let Some(rest) = strip_prefix_ignore_case(rhs_str, prefix) {
+            if !rest.strip_suffix(')').is_some_and(|arg| arg.trim().eq_ignore_ascii_case("CONFIRM")) {
+                return Err(ParseError {
+                    kind: ParseErrorKind::MalformedAction,
+                    column: 1,
+                    message: format!("Malformed {}) syntax: '{}'", prefix, rhs_str),
+                    hint: "Expected QUIT_APP, QUIT_APP(CONFIRM), FORCE_KILL_APP or FORCE_KILL_APP(CONFIRM)",
+                });
+            }
+            return Ok(if force { Action::ForceKillApp { confirm: true } } else { Action::QuitApp { confirm: true } });
+        }
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "SOUND(") {
+        let file = rest.strip_suffix(')')
+            .and_then(|arg| unquote(arg.trim().strip_prefix('"')?))
+            .filter(|(file, tail)| !file.is_empty() && tail.is_empty());
+        return match file {
+            Some((file, _)) => Ok(Action::Sound(file)),
+            None => Err(ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed SOUND() syntax: '{}'", rhs_str),
+                hint: "Expected SOUND(\"file.wav\"), or ACTION SOUND(\"file.wav\") to play it along with an action",
+            }),
+        };
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "FOCUS_ASSIST(") {
+        let state = match rest.strip_suffix(')').map(|arg| arg.trim().to_ascii_lowercase()).as_deref() {
+            Some("on") => Some(true),
+            Some("off") => Some(false),
+            Some("toggle") => None,
+            _ => return Err(ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed FOCUS_ASSIST() syntax: '{}'", rhs_str),
+                hint: "Expected FOCUS_ASSIST(on), FOCUS_ASSIST(off) or FOCUS_ASSIST(toggle)",
+            }),
+        };
+        return Ok(Action::FocusAssist(state));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "APPCOMMAND(") {
+        let end = match rest.find(')') {
+            Some(end) => end,
+            None => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::MalformedAction,
+                    column: 1,
+                    message: format!("Malformed APPCOMMAND syntax: '{}'", rhs_str),
+                    hint: "Expected format: APPCOMMAND(number)",
+                });
+            }
+        };
+        return rest[..end].parse::<u32>()
+            .map(Action::AppCommand)
+            .map_err(|_| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: column_of(rhs_str, rest),
+                message: format!("Invalid APPCOMMAND value: '{}'", rhs_str),
+                hint: "Expected a number, e.g., APPCOMMAND(46)",
+            });
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "ALTCODE(") {
+        // The digits stay text: a leading 0 picks the Windows code page rather than the OEM one
+        return rest.strip_suffix(')')
+            .map(str::trim)
+            .filter(|digits| (1..=5).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit()))
+            .map(|digits| Action::AltCode(digits.to_string()))
+            .ok_or_else(|| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed ALTCODE() syntax: '{}'", rhs_str),
+                hint: "Expected the code's digits, e.g. ALTCODE(0233) for \u{e9}",
+            });
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "SENDHID(") {
+        let error = || ParseError {
+            kind: ParseErrorKind::MalformedAction,
+            column: 1,
+            message: format!("Malformed SENDHID() syntax: '{}'", rhs_str),
+            hint: "Expected the report bytes, report ID first, e.g. SENDHID(0x01, 0x02)",
+        };
+        let bytes = rest.strip_suffix(')')
+            .and_then(parse_numbers)
+            .and_then(|numbers| numbers.into_iter().map(|n| u8::try_from(n).ok()).collect::<Option<Vec<u8>>>())
+            .ok_or_else(error)?;
+        return Ok(Action::SendHid(bytes));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "TOGGLE(") {
+        return rest.strip_suffix(')')
+            .and_then(Toggle::parse)
+            .map(Action::Toggle)
+            .ok_or_else(|| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Unknown TOGGLE() state: '{}'", rhs_str),
+                hint: "Expected TOGGLE(MUTE) or TOGGLE(MIC_MUTE)",
+            });
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "KEYS(") {
+        let error = |message: String| ParseError {
+            kind: ParseErrorKind::MalformedAction,
+            column: 1,
+            message,
+            hint: "Expected format: KEYS(CTRL_DOWN, C, CTRL_UP, WAIT(50ms))",
+        };
+        let args = rest.strip_suffix(')')
+            .map(split_args)
+            .filter(|args| args.iter().all(|arg| !arg.trim().is_empty()))
+            .ok_or_else(|| error(format!("Malformed KEYS() syntax: '{}'", rhs_str)))?;
+        let steps = args.iter()
+            .map(|step| parse_key_step(step).map_err(|message| error(message).at(column_of(rhs_str, step))))
+            .collect::<Result<_, _>>()?;
+        return Ok(Action::Keys(steps));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "SEQUENCE(") {
+        let args = rest.strip_suffix(')')
+            .map(split_args)
+            .ok_or_else(|| ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column: 1,
+                message: format!("Malformed SEQUENCE() syntax: '{}'", rhs_str),
+                hint: "Expected format: SEQUENCE(ACTION, ACTION, ...)",
+            })?;
+        let steps = args.into_iter()
+            .map(|step| parse_action(step).map_err(|e| e.at(column_of(rhs_str, step))))
+            .collect::<Result<_, _>>()?;
+        return Ok(Action::Sequence(steps));
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "WAITWINDOW(") {
+        let error = |message: String| ParseError {
+            kind: ParseErrorKind::MalformedAction,
+            column: 1,
+            message,
+            hint: "Expected format: WAITWINDOW(\"name.exe\", 5000), the timeout in milliseconds",
+        };
+        let args = rest.strip_suffix(')')
+            .map(split_args)
+            .ok_or_else(|| error(format!("Malformed WAITWINDOW() syntax: '{}'", rhs_str)))?;
+        let (app, timeout) = match args.as_slice() {
+            [app] => (app, DEFAULT_WAIT_WINDOW_MS),
+            [app, timeout] => (app, settings::parse_ms(timeout).map_err(error)?),
+            _ => return Err(error(format!("WAITWINDOW() takes an application and a timeout: '{}'", rhs_str))),
+        };
+        let app = app.trim().strip_prefix('"').and_then(unquote)
+            .filter(|(app, tail)| !app.is_empty() && tail.is_empty())
+            .map(|(app, _)| app)
+            .ok_or_else(|| error(format!("WAITWINDOW() needs a quoted application name: '{}'", rhs_str)))?;
+        return Ok(Action::WaitWindow { app, timeout: Duration::from_millis(timeout) });
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "SUSPEND(") {
+        let hint = "Expected format: SUSPEND(30s) or SUSPEND(5m)";
+        let duration = rest.strip_suffix(')')
+            .ok_or_else(|| ParseError { kind: ParseErrorKind::MalformedAction, column: 1, message: format!("Malformed SUSPEND() syntax: '{}'", rhs_str), hint })?;
+        return settings::parse_duration(duration)
+            .map(Action::Suspend)
+            .map_err(|message| ParseError { kind: ParseErrorKind::MalformedAction, column: 1, message, hint });
+    }
+
+    if let Some(rest) = strip_prefix_ignore_case(rhs_str, "IF(") {
+        let error = |message: String| ParseError {
+            kind: ParseErrorKind::MalformedAction,
+            column: 1,
+            message,
+            hint: "Expected format: IF(app=\"name.exe\", ACTION, OTHERWISE)",
+        };
+        let args = rest.strip_suffix(')')
+            .map(split_args)
+            .ok_or_else(|| error(format!("Malformed IF() syntax: '{}'", rhs_str)))?;
+        let [condition, then, otherwise] = args.as_slice() else {
+            return Err(error(format!("IF() takes a condition and two actions: '{}'", rhs_str)));
+        };
+        let app = condition.split_once('=')
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("app"))
+            .and_then(|(_, app)| unquote(app.trim().strip_prefix('"')?))
+            .filter(|(app, tail)| !app.is_empty() && tail.is_empty())
+            .map(|(app, _)| app)
+            .ok_or_else(|| error(format!("Invalid IF() condition '{}'", condition)).at(column_of(rhs_str, condition)))?;
+        let branch = |action: &str| parse_action(action).map_err(|e| e.at(column_of(rhs_str, action)));
+        return Ok(Action::IfApp {
+            app,
+            then: Box::new(branch(then)?),
+            otherwise: Box::new(branch(otherwise)?),
+        });
+    }
+
+    // For direct string actions like "MUTE", "WIN+TAB", look them up (exactly, for symbols like
+    // "!", then normalized), falling back to KeyCombo if not a recognized explicit action
+    let normalized = variable_maps::normalize_combo(rhs_str);
+    if let Some(action) = STRING_TO_ACTION.get(rhs_str).or_else(|| STRING_TO_ACTION.get(normalized.as_str())) {
+        return Ok(action.clone());
+    }
+
+    // Anything else is sent as keys, so every part has to be a key SendInput knows
+    if let Some(part) = action_executor::unknown_combo_part(&normalized) {
+        // Normalizing keeps the parts in order, so the part is found where it was written
+        let column = normalized.split('+').position(|p| p.trim() == part)
+            .and_then(|index| rhs_str.split('+').nth(index))
+            .map_or(1, |written| column_of(rhs_str, written.trim_start()));
+        if part.is_empty() {
+            return Err(ParseError {
+                kind: ParseErrorKind::MalformedAction,
+                column,
+                message: format!("Missing key name in '{}'", rhs_str),
+                hint: "Expected keys joined by '+', e.g. CTRL+SHIFT+ESC",
+            });
+        }
+        const MODIFIERS: [&str; 4] = ["CTRL", "SHIFT", "ALT", "WIN"];
+        let candidates = STRING_TO_ACTION.keys().copied().chain(MODIFIERS);
+        // A misspelled part of a combo is suggested in the context of the whole combo
+        let suggestions: Vec<String> = variable_maps::closest_names(&part, candidates).into_iter()
+            .map(|name| normalized.split('+').map(|p| if p == part { name } else { p }).collect::<Vec<_>>().join("+"))
+            .collect();
+        let suggestion = if suggestions.is_empty() {
+            String::new()
+        } else {
+            format!(" (did you mean {}?)", suggestions.join(" or "))
+        };
+        return Err(ParseError {
+            kind: ParseErrorKind::UnknownAction,
+            column,
+            message: format!("Unknown action or key name '{}'{}", part, suggestion),
+            hint: "Run with --list-actions to see the valid action names and syntax",
+        });
+    }
+    Ok(Action::KeyCombo(normalized))
+}
+
+/// One KEYS() step: WAIT(duration), NAME_DOWN, NAME_UP, or NAME for a tap
+fn parse_key_step(step: &str) -> Result<KeyStep, String> {
+    let step = step.trim();
+    if let Some(duration) = strip_prefix_ignore_case(step, "WAIT(").and_then(|rest| rest.strip_suffix(')')) {
+        return settings::parse_duration(duration).map(KeyStep::Wait);
+    }
+
+    // Names like PAGE_UP are keys in their own right, so a whole name wins over a suffix
+    let name = variable_maps::normalize_name(step);
+    if action_executor::is_key_name(&name) {
+        return Ok(KeyStep::Tap(name));
+    }
+    let upper = step.to_ascii_uppercase();
+    let key = |suffix: &str| upper.strip_suffix(suffix)
+        .map(variable_maps::normalize_name)
+        .filter(|key| action_executor::is_key_name(key));
+    if let Some(key) = key("_DOWN") {
+        return Ok(KeyStep::Press(key));
+    }
+    if let Some(key) = key("_UP") {
+        return Ok(KeyStep::Release(key));
+    }
+    Err(format!("Unknown key '{}' in KEYS()", step))
+}
+
+pub fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|head| head.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Mapping, ParseError> {
+        parse_mapping(line, 1)
+    }
+
+    fn kind(line: &str) -> ParseErrorKind {
+        parse(line).expect_err("line should be rejected").kind
+    }
+
+    #[test]
+    fn mapping_lines_split_into_modifiers_key_and_action() {
+        for line in ["KEY_A = A", "KEY_A=A", "  KEY_A  =  A  "] {
+            let mapping = parse(line).unwrap();
+            assert_eq!((mapping.modifiers, mapping.key), (Layer::NORMAL, HidKey { usage_page: 0x07, usage: 0x0004 }), "{}", line);
+            assert_eq!(mapping.action, Action::KeyCombo("A".to_string()));
+        }
+        let mapping = parse_mapping("EJECT+FN+KEY_1 = RUN(\"calc.exe\")", 12).unwrap();
+        assert_eq!(mapping, Mapping {
+            modifiers: Layer::EJECT.with(Layer::FN),
+            key: HidKey { usage_page: 0x07, usage: 0x001E },
+            action: Action::Run("calc.exe".to_string()),
+            line: 12,
+            hold_tier: None,
+            passthrough: false,
+            backend: Backend::Both,
+            clear_modifiers: false,
+        });
+        assert_eq!(parse("LEFT_SHIFT+KEY_1 = !").unwrap().modifiers, Layer::SHIFT);
+        assert_eq!(parse("FN+KEY_A = F1").unwrap().modifiers, Layer::FN);
+        assert_eq!(parse("HOLD+CMD+KEY_A = F1").unwrap().modifiers, Layer::GUI);
+        assert_eq!(parse("HOLD+CMD+KEY_A = F1").unwrap().hold_tier, Some(HoldTier::Medium));
+
+        assert_eq!(kind("KEY_A A"), ParseErrorKind::Syntax);
+        assert_eq!(kind("BACKEND=driver KEY_A = A"), ParseErrorKind::Option);
+        assert_eq!(kind("FN+KEY_Ö = A"), ParseErrorKind::UnknownKey);
+        assert_eq!(kind("HID(0xFF00) = A"), ParseErrorKind::MalformedKey);
+        assert_eq!(kind("KEY_A = NOT_AN_ACTION"), ParseErrorKind::UnknownAction);
+        assert_eq!(kind("KEY_A = RUN(\"calc.exe\""), ParseErrorKind::MalformedAction);
+    }
+
+    #[test]
+    fn actions_parse_by_their_form() {
+        let action = |rhs: &str| parse_action(rhs).map_err(|e| e.kind);
+        assert_eq!(action("WIN+TAB"), Ok(Action::KeyCombo("WIN+TAB".to_string())));
+        assert_eq!(action("ctrl+shift+esc"), Ok(Action::KeyCombo("CTRL+SHIFT+ESCAPE".to_string())));
+        assert_eq!(action("F1"), Ok(Action::KeyCombo("F1".to_string())));
+        assert_eq!(action("RUN(\"C:\\Windows\\System32\\notepad.exe\")"),
+                   Ok(Action::Run("C:\\Windows\\System32\\notepad.exe".to_string())));
+        assert!(action("RUN(calc.exe)").is_err());
+        assert_eq!(action("APPCOMMAND(8)"), Ok(Action::AppCommand(8)));
+        assert_eq!(action("APPCOMMAND(46)"), Ok(Action::AppCommand(46)));
+        assert_eq!(action("APPCOMMAND(abc)"), Err(ParseErrorKind::MalformedAction));
+        assert_eq!(action("altcode( 0233 )"), Ok(Action::AltCode("0233".to_string())));
+        assert_eq!(action("ALTCODE(0x41)"), Err(ParseErrorKind::MalformedAction));
+        assert_eq!(action("ALTCODE()"), Err(ParseErrorKind::MalformedAction));
+        assert_eq!(action("APPCOMMAND("), Err(ParseErrorKind::MalformedAction));
+    }
+
+    #[test]
+    fn action_errors_point_at_the_problem() {
+        let error = |line: &str| parse(line).map(|_| ()).map_err(|e| (e.kind, e.column));
+        assert_eq!(error("F1 = CTRL+ALT+DELTE"), Err((ParseErrorKind::UnknownAction, 15)));
+        assert_eq!(error("F1 = CTRL+"), Err((ParseErrorKind::MalformedAction, 11)));
+        assert_eq!(error("F1 = SEQUENCE(F2, NOT_A_KEY)"), Err((ParseErrorKind::UnknownAction, 19)));
+        assert_eq!(error("  F1 = KEYS(CTRL_DOWN, NOPE)"), Err((ParseErrorKind::MalformedAction, 24)));
+        assert_eq!(error("F1 = IF(app=\"a.exe\", F2, RUN(x))"), Err((ParseErrorKind::UnknownAction, 26)));
+        assert_eq!(error("F1 = APPCOMMAND(loud)"), Err((ParseErrorKind::MalformedAction, 17)));
+        assert_eq!(error("FN+SEMICOLN = F1"), Err((ParseErrorKind::UnknownKey, 4)));
+        assert_eq!(error("F1 = MUTE SOUND(\"a.wav\")"), Ok(()));
+        assert_eq!(error("F1 = NOPE SOUND(\"a.wav\")"), Err((ParseErrorKind::UnknownAction, 6)));
+    }
+
+    #[test]
+    fn quoted_arguments_take_escapes() {
+        let action = |rhs: &str| parse_action(rhs).map_err(|e| e.message);
+        assert_eq!(action(r#"RUN("C:\\Program Files\\My \"App\"\\app.exe")"#),
+                   Ok(Action::Run(r#"C:\Program Files\My "App"\app.exe"#.to_string())));
+        assert_eq!(action(r#"RUN("cmd /c echo \"a, b)\"", FOREGROUND)"#),
+                   Ok(Action::RunForeground(r#"cmd /c echo "a, b)""#.to_string())));
+        // Written before escapes: single backslashes, a backslash before the closing quote
+        assert_eq!(action(r#"EXPLORE("C:\Tools\")"#), Ok(Action::Explore(r"C:\Tools\".to_string())));
+        assert_eq!(action(r#"SEQUENCE(OPEN("\\\\server\share"), RUN("say \"x, y\""))"#), Ok(Action::Sequence(vec![
+            Action::Open(r"\\server\share".to_string()),
+            Action::Run(r#"say "x, y""#.to_string()),
+        ])));
+        assert!(action(r#"RUN("unterminated)"#).is_err());
+        // The same escapes in every quoted argument
+        assert_eq!(action(r#"SOUND("C:\\a\"b.wav")"#), Ok(Action::Sound(r#"C:\a"b.wav"#.to_string())));
+        assert_eq!(action(r#"WAITWINDOW("my \"app\"\\x.exe", 100)"#), Ok(Action::WaitWindow {
+            app: r#"my "app"\x.exe"#.to_string(),
+            timeout: Duration::from_millis(100),
+        }));
+        assert_eq!(parse(r#"F1 = MUTE SOUND("a\"b.wav")"#).map(|mapping| mapping.action).map_err(|e| e.message),
+                   Ok(Action::WithSound { sound: r#"a"b.wav"#.to_string(), action: Box::new(Action::KeyCombo("MUTE".to_string())) }));
+        assert_eq!(action(r#"IF(app="my \"app\".exe", F1, F2)"#), Ok(Action::IfApp {
+            app: r#"my "app".exe"#.to_string(),
+            then: Box::new(Action::KeyCombo("F1".to_string())),
+            otherwise: Box::new(Action::KeyCombo("F2".to_string())),
+        }));
+        assert!(action(r#"IF(app="a.exe" x, F1, F2)"#).is_err());
+        assert_eq!(unquote(r#"a\"b" , rest"#), Some((r#"a"b"#.to_string(), " , rest")));
+    }
+
+    #[test]
+    fn semicolons_separate_actions() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action).map_err(|e| (e.kind, e.column));
+        assert_eq!(action("EJECT+KEY_D = WIN+D; RUN(\"notepad.exe\")"), Ok(Action::Sequence(vec![
+            Action::KeyCombo("WIN+D".to_string()),
+            Action::Run("notepad.exe".to_string()),
+        ])));
+        assert_eq!(action("F1 = MUTE;SOUND(\"a;b.wav\") ; IF(app=\"code\", CTRL+;, F2; F3)"), Ok(Action::Sequence(vec![
+            Action::KeyCombo("MUTE".to_string()),
+            Action::Sound("a;b.wav".to_string()),
+            Action::IfApp {
+                app: "code".to_string(),
+                then: Box::new(Action::KeyCombo("CTRL+;".to_string())),
+                otherwise: Box::new(Action::Sequence(vec![
+                    Action::KeyCombo("F2".to_string()),
+                    Action::KeyCombo("F3".to_string()),
+                ])),
+            },
+        ])));
+
+        // The semicolon key itself
+        assert_eq!(action("F1 = ;"), Ok(Action::KeyCombo(";".to_string())));
+        assert!(matches!(action("F1 = CTRL+; SOUND(\"a.wav\")"), Ok(Action::WithSound { .. })));
+        assert_eq!(action("F1 = WIN+D;"), Err((ParseErrorKind::MalformedAction, 12)));
+        assert_eq!(action("F1 = WIN+D; NOPE"), Err((ParseErrorKind::UnknownAction, 13)));
+    }
+
+    #[test]
+    fn text_can_be_pasted_through_the_clipboard() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action).map_err(|e| (e.kind, e.column));
+        assert_eq!(action("F1 = TEXT(\"caf\u{e9}, \\\"ol\u{e9}\\\"\")"), Ok(Action::Text("caf\u{e9}, \"ol\u{e9}\"".to_string())));
+        assert_eq!(action("via=keys F1 = text(\"\u{2014}\")"), Ok(Action::Text("\u{2014}".to_string())));
+        assert_eq!(action("VIA=clipboard F1 = TEXT(\"\u{e9}\")"), Ok(Action::Paste("\u{e9}".to_string())));
+        assert_eq!(action("VIA=clipboard BACKEND=hook F1 = IF(app=\"java.exe\", TEXT(\"\u{e9}\"), MUTE); TEXT(\"!\")"), Ok(Action::Sequence(vec![
+            Action::IfApp {
+                app: "java.exe".to_string(),
+                then: Box::new(Action::Paste("\u{e9}".to_string())),
+                otherwise: Box::new(Action::KeyCombo("MUTE".to_string())),
+            },
+            Action::Paste("!".to_string()),
+        ])));
+        assert_eq!(split_mapping_line("VIA=clipboard F1 = TEXT(\"=\")"), Some(("VIA=clipboard F1 ", " TEXT(\"=\")")));
+
+        assert_eq!(action("F1 = TEXT()"), Err((ParseErrorKind::MalformedAction, 6)));
+        assert_eq!(action("F1 = TEXT(\"\")"), Err((ParseErrorKind::MalformedAction, 6)));
+        assert_eq!(action("VIA=clipboard F1 = MUTE"), Err((ParseErrorKind::Option, 5)));
+        assert_eq!(action("VIA=paste F1 = TEXT(\"a\")"), Err((ParseErrorKind::Option, 5)));
+    }
+
+    #[test]
+    fn modifier_declarations_name_a_modifier_key() {
+        assert!(parse_modifier_declaration("MODIFIER EJECT").unwrap().is_ok());
+        assert_eq!(parse_modifier_declaration("MODIFIER KEY_A").unwrap().unwrap_err().kind, ParseErrorKind::NotAModifier);
+        assert!(parse_modifier_declaration("KEY_A = A").is_none());
+        assert!(line_maps("EJECT+FN+KEY_1 = F1", "fn+eject+key_1"));
+    }
+
+    #[test]
+    fn raw_hid_keys_and_reports_parse() {
+        let mapping = parse("FN+HID(0xFF00,0x0005) = SENDHID(0x01, 0x02)").unwrap();
+        assert_eq!(mapping.key, HidKey { usage_page: 0xFF00, usage: 0x0005 });
+        assert!(matches!(mapping.modifiers, Layer::FN));
+        assert_eq!(mapping.action, Action::SendHid(vec![0x01, 0x02]));
+
+        assert!(parse("HID(0xFF00) = F1").is_err());
+        assert!(parse("HID(0x1FF00,0x05) = F1").is_err());
+        assert!(parse("F1 = SENDHID(0x01, 0x100)").is_err());
+        assert!(parse("F1 = SENDHID()").is_err());
+    }
+
+    #[test]
+    fn standard_usage_names_and_aliases_parse() {
+        let caps = parse("CAPS_LOCK = ESCAPE").unwrap();
+        assert_eq!(caps.key, HidKey { usage_page: 0x07, usage: 0x0039 });
+        assert_eq!(parse("ESC = F1").unwrap().key, parse("ESCAPE = F1").unwrap().key);
+        assert_eq!(parse("FN+AC_BACK = HOME").unwrap().key, HidKey { usage_page: 0x0C, usage: 0x0224 });
+        assert_eq!(parse("EJECT = F1").unwrap().key, HidKey { usage_page: 0x0C, usage: 0x00B8 });
+    }
+
+    #[test]
+    fn names_are_case_insensitive_and_accept_aliases() {
+        let mapping = parse("fn+Esc = cmd+opt+esc").unwrap();
+        assert_eq!(mapping.key, HidKey { usage_page: 0x07, usage: 0x0029 });
+        assert!(matches!(mapping.modifiers, Layer::FN));
+        assert_eq!(mapping.action, Action::KeyCombo("WIN+ALT+ESCAPE".to_string()));
+
+        assert_eq!(parse("eject+pgup = backlight_up").unwrap().action, Action::Backlight(10));
+        assert_eq!(parse("F1 = run(\"C:\\Tools\\App.exe\")").unwrap().action,
+                   Action::Run("C:\\Tools\\App.exe".to_string()));
+        assert_eq!(parse("F1 = control+Return").unwrap().action,
+                   Action::KeyCombo("CTRL+ENTER".to_string()));
+        assert_eq!(parse("F1 = !").unwrap().action, Action::KeyCombo("SHIFT+1".to_string()));
+    }
+
+    #[test]
+    fn typos_get_suggestions() {
+        let message = |line: &str| parse(line).expect_err("line should be rejected").message;
+        assert_eq!(message("SEMICOLN = F1"), "Unknown key name 'SEMICOLN' (did you mean SEMICOLON?)");
+        assert_eq!(message("F1 = BACKLIGHT_UPP"), "Unknown action or key name 'BACKLIGHT_UPP' (did you mean BACKLIGHT_UP?)");
+        assert_eq!(message("F1 = CTRL+ALT+DELTE"), "Unknown action or key name 'DELTE' (did you mean CTRL+ALT+DELETE?)");
+        assert_eq!(message("F1 = XYZZY_PLUGH"), "Unknown action or key name 'XYZZY_PLUGH'");
+    }
+
+    #[test]
+    fn if_app_actions_parse_both_branches() {
+        let mapping = parse("F1 = IF(app=\"Photoshop.exe\", CTRL+ALT+Z, ctrl+z)").unwrap();
+        assert_eq!(mapping.action, Action::IfApp {
+            app: "Photoshop.exe".to_string(),
+            then: Box::new(Action::KeyCombo("CTRL+ALT+Z".to_string())),
+            otherwise: Box::new(Action::KeyCombo("CTRL+Z".to_string())),
+        });
+        let nested = parse("F2 = if(app=\"code\", RUN(\"a.exe, b\"), IF(app=\"x\", NONE, MUTE))").unwrap();
+        assert!(matches!(nested.action, Action::IfApp { ref then, .. } if **then == Action::Run("a.exe, b".to_string())));
+
+        assert!(parse("F1 = IF(app=\"a.exe\", F2)").is_err());
+        assert!(parse("F1 = IF(title=\"a\", F2, F3)").is_err());
+        assert!(parse("F1 = IF(app=\"a.exe\", F2, NOT_A_KEY)").is_err());
+        assert!(crate::foreground::matches("photoshop", "Photoshop.exe"));
+        assert!(!crate::foreground::matches("photo", "Photoshop.exe"));
+    }
+
+    #[test]
+    fn keys_actions_keep_the_given_order() {
+        let mapping = parse("F1 = KEYS(ctrl_down, C, CTRL_UP, WAIT(50ms), ALT_DOWN, TAB, PAGE_UP, ALT_UP)").unwrap();
+        assert_eq!(mapping.action, Action::Keys(vec![
+            KeyStep::Press("CTRL".to_string()),
+            KeyStep::Tap("C".to_string()),
+            KeyStep::Release("CTRL".to_string()),
+            KeyStep::Wait(Duration::from_millis(50)),
+            KeyStep::Press("ALT".to_string()),
+            KeyStep::Tap("TAB".to_string()),
+            KeyStep::Tap("PAGE_UP".to_string()),
+            KeyStep::Release("ALT".to_string()),
+        ]));
+        assert_eq!(parse("F1 = KEYS(PAGE_UP_DOWN, OPT_UP)").unwrap().action,
+                   Action::Keys(vec![KeyStep::Press("PAGE_UP".to_string()), KeyStep::Release("ALT".to_string())]));
+        assert!(parse("F1 = KEYS(CTRL_DOWN, NOPE, CTRL_UP)").is_err());
+        assert!(parse("F1 = KEYS(CTRL_DOWN, , CTRL_UP)").is_err());
+        assert!(parse("F1 = KEYS(WAIT(soon))").is_err());
+    }
+
+    #[test]
+    fn run_can_bring_the_program_to_the_front() {
+        let run = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(run("F1 = RUN(\"notepad.exe C:\\notes.txt\")").unwrap(), Action::Run("notepad.exe C:\\notes.txt".to_string()));
+        assert_eq!(run("F1 = RUN(\"notepad.exe\", foreground)").unwrap(), Action::RunForeground("notepad.exe".to_string()));
+        assert_eq!(run("F1 = RUN(\"\"C:\\My App\\app.exe\" -x\" , FOREGROUND )").unwrap(),
+                   Action::RunForeground("\"C:\\My App\\app.exe\" -x".to_string()));
+        assert!(run("F1 = RUN(\"notepad.exe\", BACKGROUND)").is_err());
+        assert!(run("F1 = RUN(\"notepad.exe\"").is_err());
+    }
+
+    #[test]
+    fn open_and_explore_take_a_quoted_path() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(action("F1 = OPEN(\"%USERPROFILE%\\q3.xlsx\")").unwrap(), Action::Open("%USERPROFILE%\\q3.xlsx".to_string()));
+        assert_eq!(action("F2 = open(\"shell:RecycleBinFolder\")").unwrap(), Action::Open("shell:RecycleBinFolder".to_string()));
+        assert_eq!(action("F3 = EXPLORE( \"D:\\Music\" )").unwrap(), Action::Explore("D:\\Music".to_string()));
+        assert!(action("F1 = OPEN(C:\\file.txt)").is_err());
+        assert!(action("F1 = EXPLORE(\"\")").is_err());
+    }
+
+    #[test]
+    fn layout_actions_name_a_locale() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(action("EJECT = LAYOUT(\"en-US\")").unwrap(), Action::Layout("en-US".to_string()));
+        assert_eq!(action("FN+SPACE = layout_next").unwrap(), Action::LayoutNext);
+        assert_eq!(action("EJECT+SPACE = IME_TOGGLE").unwrap(), Action::ImeToggle);
+        assert!(action("EJECT = LAYOUT()").is_err());
+        assert!(action("EJECT = LAYOUT(\"en US\")").is_err());
+    }
+
+    #[test]
+    fn app_window_actions_go_both_ways() {
+        assert_eq!(parse("EJECT+GRAVE = APP_WINDOW_NEXT").unwrap().action, Action::AppWindowNext);
+        assert_eq!(parse("EJECT+FN+GRAVE = app_window_prev").unwrap().action, Action::AppWindowPrev);
+    }
+
+    #[test]
+    fn quit_actions_can_ask_first() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(action("EJECT+KEY_W = CLOSE_WINDOW").unwrap(), Action::CloseWindow);
+        assert_eq!(action("EJECT+KEY_Q = QUIT_APP").unwrap(), Action::QuitApp { confirm: false });
+        assert_eq!(action("EJECT+KEY_Q = quit_app( confirm )").unwrap(), Action::QuitApp { confirm: true });
+        assert_eq!(action("EJECT+FN+KEY_Q = FORCE_KILL_APP(CONFIRM)").unwrap(), Action::ForceKillApp { confirm: true });
+        assert!(action("EJECT+KEY_Q = QUIT_APP(NOW)").is_err());
+    }
+
+    #[test]
+    fn focus_assist_takes_on_off_or_toggle() {
+        let action = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(action("F6 = FOCUS_ASSIST(toggle)").unwrap(), Action::FocusAssist(None));
+        assert_eq!(action("FN+F6 = focus_assist( ON )").unwrap(), Action::FocusAssist(Some(true)));
+        assert_eq!(action("EJECT+F6 = FOCUS_ASSIST(off)").unwrap(), Action::FocusAssist(Some(false)));
+        assert!(action("F6 = FOCUS_ASSIST(alarms)").is_err());
+    }
+
+    #[test]
+    fn sequences_can_wait_for_a_window() {
+        let mapping = parse(
+            "EJECT+KEY_N = SEQUENCE(RUN(\"notepad.exe\"), WAITWINDOW(\"notepad.exe\", 5000), KEYS(H, I))").unwrap();
+        assert_eq!(mapping.action, Action::Sequence(vec![
+            Action::Run("notepad.exe".to_string()),
+            Action::WaitWindow { app: "notepad.exe".to_string(), timeout: Duration::from_secs(5) },
+            Action::Keys(vec![KeyStep::Tap("H".to_string()), KeyStep::Tap("I".to_string())]),
+        ]));
+        assert_eq!(parse("F1 = SEQUENCE(WAITWINDOW(\"code\"), F2)").unwrap().action, Action::Sequence(vec![
+            Action::WaitWindow { app: "code".to_string(), timeout: Duration::from_millis(DEFAULT_WAIT_WINDOW_MS) },
+            Action::KeyCombo("F2".to_string()),
+        ]));
+        assert!(parse("F1 = SEQUENCE(F2, NOT_A_KEY)").is_err());
+        assert!(parse("F1 = WAITWINDOW(notepad.exe, 5000)").is_err());
+        assert!(parse("F1 = WAITWINDOW(\"notepad.exe\", soon)").is_err());
+    }
+
+    #[test]
+    fn suspend_actions_take_a_duration() {
+        let suspend = |line: &str| parse(line).map(|mapping| mapping.action);
+        assert_eq!(suspend("EJECT+KEY_S = SUSPEND(30s)").unwrap(), Action::Suspend(Duration::from_secs(30)));
+        assert_eq!(suspend("EJECT+KEY_S = suspend(5M)").unwrap(), Action::Suspend(Duration::from_secs(300)));
+        assert_eq!(suspend("EJECT+KEY_S = SUSPEND(90)").unwrap(), Action::Suspend(Duration::from_secs(90)));
+        assert_eq!(suspend("EJECT+KEY_S = SUSPEND(1500ms)").unwrap(), Action::Suspend(Duration::from_millis(1500)));
+        assert!(suspend("EJECT+KEY_S = SUSPEND(soon)").is_err());
+        assert!(suspend("EJECT+KEY_S = SUSPEND(30s").is_err());
+        assert_eq!(crate::suspend::countdown(Duration::from_millis(299_001)), "5:00");
+    }
+
+    #[test]
+    fn toggle_actions_name_a_known_state() {
+        assert_eq!(parse("F10 = TOGGLE(MIC_MUTE)").unwrap().action, Action::Toggle(Toggle::MicMute));
+        assert_eq!(parse("F11 = toggle(mute)").unwrap().action, Action::Toggle(Toggle::Mute));
+        assert!(parse("F12 = TOGGLE(CAPS)").is_err());
+        assert!(parse("F12 = TOGGLE(MUTE").is_err());
+    }
+}
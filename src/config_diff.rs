@@ -0,0 +1,128 @@
+// --- START OF FILE src/config_diff.rs ---
+// What a reload changed: the mappings in effect before and after, compared by key, layer and hold
+// tier, so the log and a notification say whether an edit took and what it did, e.g.
+// "+3 mappings, -1, 2 changed: FN+F1, HOLD+F2".
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::action_executor::Action;
+use crate::key_mapper::{Backend, KeyMapper, Layer, MappingOrigin};
+use crate::name_list;
+
+// Mappings named in the one-line summary before it is cut short
+const SUMMARY_NAMES: usize = 3;
+
+/// The mappings in effect after a load, by the left-hand side they were written with (spelled
+/// the same way whatever the file did)
+#[derive(Debug, Default, Clone)]
+pub struct Snapshot(BTreeMap<String, (Action, bool, bool, Backend)>);
+
+impl Snapshot {
+    pub fn of(mapper: &KeyMapper) -> Snapshot {
+        Snapshot(mapper.effective_mappings().into_iter()
+            .map(|origin| (lhs(origin), (origin.action.clone(), origin.passthrough, origin.clear_modifiers, origin.backend)))
+            .collect())
+    }
+}
+
+/// Mappings a load added, removed and changed, by left-hand side
+#[derive(Debug, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Diff {
+    let mut diff = Diff::default();
+    for (lhs, mapping) in &after.0 {
+        match before.0.get(lhs) {
+            None => diff.added.push(lhs.clone()),
+            Some(old) if old != mapping => diff.changed.push(lhs.clone()),
+            Some(_) => {}
+        }
+    }
+    diff.removed = before.0.keys().filter(|lhs| !after.0.contains_key(*lhs)).cloned().collect();
+    diff
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// One line per mapping, e.g. "+ FN+F1 = mute", for the log
+    pub fn details(&self, before: &Snapshot, after: &Snapshot) -> Vec<String> {
+        let action = |snapshot: &Snapshot, lhs: &str| {
+            snapshot.0.get(lhs).map(|(action, ..)| name_list::describe(action)).unwrap_or_default()
+        };
+        let mut lines = Vec::new();
+        lines.extend(self.added.iter().map(|lhs| format!("+ {} = {}", lhs, action(after, lhs))));
+        lines.extend(self.removed.iter().map(|lhs| format!("- {} = {}", lhs, action(before, lhs))));
+        lines.extend(self.changed.iter()
+            .map(|lhs| format!("~ {} = {} (was {})", lhs, action(after, lhs), action(before, lhs))));
+        lines
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no mappings changed");
+        }
+        let mut counts = Vec::new();
+        if !self.added.is_empty() {
+            counts.push(format!("+{} mapping{}", self.added.len(), if self.added.len() == 1 { "" } else { "s" }));
+        }
+        if !self.removed.is_empty() {
+            counts.push(format!("\u{2212}{}", self.removed.len()));
+        }
+        if !self.changed.is_empty() {
+            counts.push(format!("{} changed", self.changed.len()));
+        }
+        let names: Vec<&String> = self.changed.iter().chain(&self.added).chain(&self.removed).collect();
+        let mut shown: Vec<&str> = names.iter().take(SUMMARY_NAMES).map(|name| name.as_str()).collect();
+        if names.len() > SUMMARY_NAMES {
+            shown.push("\u{2026}");
+        }
+        write!(f, "{}: {}", counts.join(", "), shown.join(", "))
+    }
+}
+
+/// The left-hand side of a mapping as the daemon understood it, e.g. "HOLD+FN+SHIFT+F1"
+fn lhs(origin: &MappingOrigin) -> String {
+    let key = name_list::key_name(origin.key);
+    let key = if origin.layer == Layer::NORMAL { key } else { format!("{}+{}", origin.layer.to_string().to_uppercase(), key) };
+    match origin.tier_name() {
+        "hold" => format!("HOLD+{}", key),
+        "longhold" => format!("LONGHOLD+{}", key),
+        "longpress" => format!("LONGPRESS({})", key),
+        _ => key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_mapper::{load_for_test, reload_for_test};
+
+    #[test]
+    fn reload_diff_names_what_changed() {
+        let (mut mapper, _, result) = load_for_test("diff", "VERSION=2\n[mappings]\nF1 = MUTE\nF2 = VOLUME_DOWN\nFN+F3 = VOLUME_UP\n");
+        result.unwrap();
+        let before = Snapshot::of(&mapper);
+
+        reload_for_test(&mut mapper, "diff", "VERSION=2\n[mappings]\nF1 = MUTE\nF2 = VOLUME_UP\nHOLD+F4 = MUTE\nPASSTHRU F5 = MUTE\n").unwrap();
+        let after = Snapshot::of(&mapper);
+
+        let diff = diff(&before, &after);
+        assert_eq!(diff, Diff {
+            added: vec!["F5".to_string(), "HOLD+F4".to_string()],
+            removed: vec!["FN+F3".to_string()],
+            changed: vec!["F2".to_string()],
+        });
+        assert_eq!(diff.to_string(), "+2 mappings, \u{2212}1, 1 changed: F2, F5, HOLD+F4, \u{2026}");
+        assert_eq!(diff.details(&before, &after).len(), 4);
+        assert!(super::diff(&after, &after).is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+// --- START OF FILE src/config_json.rs ---
+// --export-config json: the parsed configuration as JSON for GUIs and scripts, and the way back.
+// A mapping file whose content is such a JSON document is turned into mapping lines (from each
+// mapping's "lhs" and "action") before parsing, so an edited export can be used directly.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::config;
+use crate::key_mapper::{KeyMapper, Layer};
+use crate::migration;
+use crate::name_list::{self, json_string};
+
+/// The configuration `mapper` loaded last, with one entry per mapping in effect, grouped by layer
+pub fn export(mapper: &KeyMapper) -> String {
+    let written = mapper.written_config();
+    let object = |pairs: &[(String, String)]| {
+        let entries: Vec<String> = pairs.iter()
+            .map(|(name, value)| format!("    {}: {}", json_string(name), json_string(value)))
+            .collect();
+        if entries.is_empty() { "{}".to_string() } else { format!("{{\n{}\n  }}", entries.join(",\n")) }
+    };
+
+    let mut layers: BTreeMap<((u32, u8), Layer), Vec<String>> = BTreeMap::new();
+    for origin in mapper.effective_mappings() {
+        let (lhs, rhs) = config::split_mapping_line(&origin.text).unwrap_or((&origin.text, ""));
+        let entry = format!(
+            "        {{\"lhs\": {}, \"action\": {}, \"key\": {}, \"usage_page\": {}, \"usage\": {}, \
+             \"tier\": \"{}\", \"passthrough\": {}, \"backend\": \"{}\", \"description\": {}, \"source\": {}}}",
+            json_string(lhs.trim()), json_string(rhs.trim()), json_string(&name_list::key_name(origin.key)),
+            origin.key.usage_page, origin.key.usage, origin.tier_name(), origin.passthrough, origin.backend.name(),
+            json_string(&name_list::describe(&origin.action)),
+            json_string(&format!("{}:{}", origin.file.display(), origin.line)));
+        layers.entry((origin.layer.specificity(), origin.layer)).or_default().push(entry);
+    }
+    let layers: Vec<String> = layers.into_iter()
+        .map(|((_, layer), entries)| format!("    {{\"layer\": {}, \"mappings\": [\n{}\n    ]}}",
+                                             json_string(&layer.to_string()), entries.join(",\n")))
+        .collect();
+    let modifiers: Vec<String> = written.modifiers.iter()
+        .map(|line| json_string(line.split_whitespace().nth(1).unwrap_or_default()))
+        .collect();
+
+    let mut out = String::new();
+    let _ = writeln!(out, "{{");
+    let _ = writeln!(out, "  \"format\": \"a1314_mapping\",");
+    let _ = writeln!(out, "  \"version\": {},", migration::CURRENT_VERSION);
+    let _ = writeln!(out, "  \"settings\": {},", object(&written.settings));
+    let _ = writeln!(out, "  \"vendor_reports\": {},", object(&written.vendor_reports));
+    let _ = writeln!(out, "  \"modifiers\": [{}],", modifiers.join(", "));
+    let _ = writeln!(out, "  \"layers\": [\n{}\n  ]", layers.join(",\n"));
+    let _ = writeln!(out, "}}");
+    out
+}
+
+/// Whether a mapping file holds an exported JSON document rather than mapping lines
+pub fn is_json(text: &str) -> bool {
+    text.trim_start().starts_with('{')
+}
+
+/// Mapping file text for an exported (and possibly edited) JSON document. Only "version",
+/// "settings", "vendor_reports", "modifiers" and each mapping's "lhs" and "action" are read;
+/// the other fields describe the mapping and are ignored.
+pub fn to_mapping_text(text: &str) -> Result<String, String> {
+    let json = Parser { text, pos: 0 }.document()?;
+    let version = match json.get("version") {
+        Some(Json::Number(n)) if *n >= 1.0 => *n as u32,
+        Some(_) => return Err("\"version\" must be a format version number".to_string()),
+        None => migration::CURRENT_VERSION,
+    };
+
+    let mut out = format!("VERSION={}\n", version);
+    for (section, field) in [("settings", "settings"), ("vendor_reports", "vendor_reports")] {
+        let Some(entries) = json.get(field) else { continue };
+        let Json::Object(entries) = entries else {
+            return Err(format!("\"{}\" must be an object of name: value pairs", field));
+        };
+        let _ = writeln!(out, "[{}]", section);
+        for (name, value) in entries {
+            let _ = writeln!(out, "{} = {}", name, value.scalar(name)?);
+        }
+    }
+
+    let _ = writeln!(out, "[mappings]");
+    for modifier in json.get("modifiers").map(|m| m.array("modifiers")).transpose()?.unwrap_or_default() {
+        let _ = writeln!(out, "MODIFIER {}", modifier.scalar("modifiers")?);
+    }
+    for layer in json.get("layers").map(|l| l.array("layers")).transpose()?.unwrap_or_default() {
+        for mapping in layer.get("mappings").map(|m| m.array("mappings")).transpose()?.unwrap_or_default() {
+            match (mapping.get("lhs"), mapping.get("action")) {
+                (Some(Json::String(lhs)), Some(Json::String(action))) => {
+                    let _ = writeln!(out, "{} = {}", lhs, action);
+                }
+                _ => return Err("every mapping needs \"lhs\" and \"action\" strings".to_string()),
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, field: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == field).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn array(&self, field: &str) -> Result<Vec<&Json>, String> {
+        match self {
+            Json::Array(items) => Ok(items.iter().collect()),
+            _ => Err(format!("\"{}\" must be an array", field)),
+        }
+    }
+
+    /// A setting value as it would be written in the file
+    fn scalar(&self, field: &str) -> Result<String, String> {
+        match self {
+            Json::String(s) => Ok(s.clone()),
+            Json::Number(n) => Ok(n.to_string()),
+            Json::Bool(b) => Ok(b.to_string()),
+            _ => Err(format!("\"{}\" must be a string, number or boolean", field)),
+        }
+    }
+}
+
+/// Just enough of a JSON parser for exported configurations
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn document(mut self) -> Result<Json, String> {
+        let value = self.value()?;
+        self.skip_whitespace();
+        if self.pos < self.text.len() {
+            return Err(self.error("unexpected text after the document"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("Invalid JSON at line {}: {}", line, message)
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() != Some(c) {
+            return Err(self.error(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.list(),
+            Some('"') => self.string().map(Json::String),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('n') => self.literal("null", Json::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.text[self.pos..].starts_with(word) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let rest = &self.text[self.pos..];
+        let len = rest.find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c))).unwrap_or(rest.len());
+        let number = rest[..len].parse().map_err(|_| self.error("malformed number"))?;
+        self.pos += len;
+        Ok(Json::Number(number))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut out = String::new();
+        let mut chars = self.text[self.pos..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('u') => {
+                        let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                        let c = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                            .ok_or_else(|| self.error("malformed \\u escape"))?;
+                        out.push(c);
+                    }
+                    Some(c) => out.push(c),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    fn list(&mut self) -> Result<Json, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some('}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_mapper::load_for_test;
+
+    #[test]
+    fn export_round_trips_through_a_mapping_file() {
+        let (mapper, _, result) = load_for_test("export", "VERSION=2\n\
+            [settings]\n\
+            hold_medium_ms = 400\n\
+            [mappings]\n\
+            MODIFIER EJECT\n\
+            F1 = MUTE\n\
+            F1 = BRIGHTNESS_DOWN\n\
+            FN+F1 = RUN(\"C:\\Tools\\app.exe\")\n\
+            PASSTHRU HOLD+EJECT+KEY_A = WIN+A\n");
+        result.unwrap();
+        let json = export(&mapper);
+        assert!(json.contains("\"hold_medium_ms\": \"400\""));
+        assert!(json.contains("\"layer\": \"Eject\""));
+        assert!(json.contains("\"tier\": \"hold\", \"passthrough\": true"));
+        assert!(!json.contains("MUTE"), "replaced mappings aren't exported");
+
+        // The exported JSON loads like a mapping file
+        let (reloaded, _, result) = load_for_test("export_json", &json);
+        result.unwrap();
+        assert_eq!(reloaded.written_config().settings, vec![("hold_medium_ms".to_string(), "400".to_string())]);
+        let actions = |mapper: &KeyMapper| mapper.effective_mappings().iter()
+            .map(|origin| (origin.text.clone(), origin.action.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(actions(&reloaded), actions(&mapper));
+        assert_eq!(export(&reloaded).lines().count(), json.lines().count());
+
+        assert!(to_mapping_text("{\"layers\": [{\"mappings\": [{\"lhs\": \"F1\"}]}]}").is_err());
+        assert!(to_mapping_text("{\"layers\": [").unwrap_err().contains("line 1"));
+    }
+}
@@ -0,0 +1,104 @@
+// --- START OF FILE src/config_watch.rs ---
+// Hot reload: watches every file the configuration is read from. Editors like VS Code and
+// Notepad++ save by writing a temp file and renaming it over the original, which replaces the file
+// a watch would be on, so the folders are watched and events picked out for the config names.
+// Which files make up the configuration can change from one load to the next, so the watches are
+// rebuilt to match after every load rather than fixed at startup.
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+
+use notify::event::{EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+// The files watched now; read by the watcher's callback and the reload thread
+static FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // The watcher and the folders it watches, kept on the window thread
+    static WATCHER: RefCell<Option<(RecommendedWatcher, Vec<PathBuf>)>> = const { RefCell::new(None) };
+}
+
+/// Starts the watcher; from then on `tx` gets a message for every change to a watched file
+pub fn start(tx: Sender<()>) -> notify::Result<()> {
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res {
+            let relevant = matches!(event.kind,
+                EventKind::Create(_) | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any));
+            if relevant && event.paths.iter().any(|path| is_watched_file(path, &files())) {
+                let _ = tx.send(());
+            }
+        }
+    })?;
+    WATCHER.with(|w| *w.borrow_mut() = Some((watcher, Vec::new())));
+    Ok(())
+}
+
+/// Watches exactly `files` from now on, watching folders newly needed and dropping the ones no
+/// file is in any more
+pub fn watch(files: Vec<PathBuf>) {
+    let mut folders: Vec<PathBuf> = Vec::new();
+    for folder in files.iter().filter_map(|file| file.parent()) {
+        if !folders.iter().any(|known| same_path(known, folder)) {
+            folders.push(folder.to_path_buf());
+        }
+    }
+    *FILES.lock().unwrap_or_else(|p| p.into_inner()) = files;
+
+    WATCHER.with(|w| {
+        let mut w = w.borrow_mut();
+        let Some((watcher, watched)) = w.as_mut() else {
+            return;
+        };
+        for folder in watched.iter().filter(|old| !folders.iter().any(|new| same_path(old, new))) {
+            match watcher.unwatch(folder) {
+                Ok(()) => log::debug!("Stopped watching {}", folder.display()),
+                Err(e) => log::debug!("Failed to stop watching {}: {}", folder.display(), e),
+            }
+        }
+        for folder in folders.iter().filter(|new| !watched.iter().any(|old| same_path(old, new))) {
+            match watcher.watch(folder, RecursiveMode::NonRecursive) {
+                Ok(()) => log::debug!("Watching {} for config changes", folder.display()),
+                Err(e) => log::warn!("Failed to watch {}: {}", folder.display(), e),
+            }
+        }
+        *watched = folders;
+    });
+}
+
+/// The files watched now
+pub fn files() -> Vec<PathBuf> {
+    FILES.lock().unwrap_or_else(|p| p.into_inner()).clone()
+}
+
+/// Stops watching, at shutdown
+pub fn stop() {
+    WATCHER.with(|w| w.borrow_mut().take());
+}
+
+/// Windows paths are case-insensitive, and watcher events may not use the same case we do
+fn is_watched_file(path: &Path, targets: &[PathBuf]) -> bool {
+    targets.iter().any(|target| same_path(target, path))
+}
+
+fn same_path(a: &Path, b: &Path) -> bool {
+    a.to_string_lossy().eq_ignore_ascii_case(&b.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watched_files_follow_each_load() {
+        let mapping = PathBuf::from(r"C:\Users\me\AppData\Roaming\A1314Daemon\A1314_mapping.txt");
+        let shared = PathBuf::from(r"C:\ProgramData\A1314Daemon\A1314_mapping.txt");
+        watch(vec![mapping.clone()]);
+        assert!(is_watched_file(Path::new(r"c:\users\ME\appdata\roaming\a1314daemon\a1314_MAPPING.txt"), &files()));
+        assert!(!is_watched_file(&shared, &files()));
+
+        watch(vec![mapping, shared.clone()]);
+        assert!(is_watched_file(&shared, &files()));
+    }
+}
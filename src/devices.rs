@@ -0,0 +1,241 @@
+// --- START OF FILE src/devices.rs ---
+use std::ffi::c_void;
+
+use windows::core::PCWSTR;
+use windows::Win32::Devices::HumanInterfaceDevice::HidD_GetProductString;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+use crate::error::{A1314Error, Result};
+use windows::Win32::UI::Input::{
+    GetRawInputDeviceInfoW, GetRawInputDeviceList, RAWINPUTDEVICELIST, RID_DEVICE_INFO,
+    RIDI_DEVICEINFO, RIDI_DEVICENAME, RIM_TYPEHID, RIM_TYPEKEYBOARD,
+};
+
+// Apple's USB/Bluetooth vendor ID
+pub const APPLE_VENDOR_ID: u16 = 0x05AC;
+
+/// How a device is connected, read from its device path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Usb,
+    Bluetooth,   // Classic Bluetooth HID profile
+    BluetoothLe, // HID over GATT (HOGP), e.g. through a BLE dongle or bridge
+    Unknown,
+}
+
+// Service UUIDs that appear in Bluetooth HID device paths
+const BT_HID_SERVICE: &str = "{00001124-0000-1000-8000-00805F9B34FB}";
+const BLE_HID_SERVICE: &str = "{00001812-0000-1000-8000-00805F9B34FB}";
+
+impl Transport {
+    pub fn from_path(path: &str) -> Self {
+        let upper = path.to_uppercase();
+        if upper.contains(BLE_HID_SERVICE) {
+            Transport::BluetoothLe
+        } else if upper.contains(BT_HID_SERVICE) {
+            Transport::Bluetooth
+        } else if upper.contains("VID_") {
+            Transport::Usb
+        } else {
+            Transport::Unknown
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Transport::Usb => "USB",
+            Transport::Bluetooth => "BT",
+            Transport::BluetoothLe => "BLE",
+            Transport::Unknown => "-",
+        }
+    }
+}
+
+/// A keyboard-like raw input device (the keyboard itself, its consumer control or vendor collections)
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub path: String,
+    pub kind: &'static str,
+    pub transport: Transport,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub usage_page: u16,
+    pub usage: u16,
+    pub product: Option<String>,
+}
+
+impl DeviceInfo {
+    pub fn is_apple(&self) -> bool {
+        self.vendor_id == APPLE_VENDOR_ID
+    }
+}
+
+/// Enumerates raw input keyboards plus the consumer/vendor HID collections the A1314 exposes
+pub fn list_keyboards() -> Result<Vec<DeviceInfo>> {
+    let mut devices = Vec::new();
+
+    unsafe {
+        let mut count: u32 = 0;
+        let entry_size = std::mem::size_of::<RAWINPUTDEVICELIST>() as u32;
+        if GetRawInputDeviceList(None, &mut count, entry_size) == u32::MAX {
+            return Err(A1314Error::Device(format!("Failed to count raw input devices: {}",
+                                                  windows::core::Error::from_win32())));
+        }
+        if count == 0 {
+            return Ok(devices);
+        }
+
+        let mut list = vec![RAWINPUTDEVICELIST::default(); count as usize];
+        let found = GetRawInputDeviceList(Some(list.as_mut_ptr()), &mut count, entry_size);
+        if found == u32::MAX {
+            return Err(A1314Error::Device(format!("Failed to list raw input devices: {}",
+                                                  windows::core::Error::from_win32())));
+        }
+
+        for entry in list.iter().take(found as usize) {
+            if let Some(mut device) = describe(entry.hDevice) {
+                device.product = product_string(&device.path);
+                devices.push(device);
+            }
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Kind, IDs and usage of a raw input device; None for devices the daemon doesn't read.
+/// The product string is left out since reading it opens the device.
+pub unsafe fn describe(device: HANDLE) -> Option<DeviceInfo> {
+    let mut info = RID_DEVICE_INFO {
+        cbSize: std::mem::size_of::<RID_DEVICE_INFO>() as u32,
+        ..Default::default()
+    };
+    let mut size = info.cbSize;
+    if GetRawInputDeviceInfoW(device, RIDI_DEVICEINFO,
+                              Some(&mut info as *mut _ as *mut c_void), &mut size) == u32::MAX {
+        return None;
+    }
+
+    let (kind, usage_page, usage) = if info.dwType == RIM_TYPEKEYBOARD {
+        ("keyboard", 0x01, 0x06)
+    } else if info.dwType == RIM_TYPEHID {
+        let hid = info.Anonymous.hid;
+        match hid.usUsagePage {
+            0x0C => ("consumer", hid.usUsagePage, hid.usUsage),
+            page if page >= 0xFF00 => ("vendor", hid.usUsagePage, hid.usUsage),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    let path = device_path(device);
+    // HID collections report their IDs directly; keyboards only have them in the path
+    let (vendor_id, product_id) = if info.dwType == RIM_TYPEHID {
+        let hid = info.Anonymous.hid;
+        (hid.dwVendorId as u16, hid.dwProductId as u16)
+    } else {
+        parse_vid_pid(&path).unwrap_or((0, 0))
+    };
+    Some(DeviceInfo {
+        transport: Transport::from_path(&path),
+        product: None,
+        path,
+        kind,
+        vendor_id,
+        product_id,
+        usage_page,
+        usage,
+    })
+}
+
+/// Vendor and product IDs of any raw input device (mice included), read from its device path
+pub unsafe fn vid_pid(device: HANDLE) -> Option<(u16, u16)> {
+    parse_vid_pid(&device_path(device))
+}
+
+unsafe fn device_path(device: HANDLE) -> String {
+    let mut len: u32 = 0;
+    GetRawInputDeviceInfoW(device, RIDI_DEVICENAME, None, &mut len);
+    if len == 0 {
+        return String::new();
+    }
+
+    let mut buf = vec![0u16; len as usize];
+    let written = GetRawInputDeviceInfoW(device, RIDI_DEVICENAME, Some(buf.as_mut_ptr() as *mut c_void), &mut len);
+    if written == u32::MAX {
+        return String::new();
+    }
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    String::from_utf16_lossy(&buf[..end])
+}
+
+/// Reads the HID product string; the device is opened without read/write access so it never conflicts
+unsafe fn product_string(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+
+    let wide: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+    let handle = CreateFileW(
+        PCWSTR(wide.as_ptr()),
+        0,
+        FILE_SHARE_READ | FILE_SHARE_WRITE,
+        None,
+        OPEN_EXISTING,
+        FILE_FLAGS_AND_ATTRIBUTES(0),
+        None,
+    ).ok()?;
+
+    let mut buf = [0u16; 127];
+    let ok = HidD_GetProductString(handle, buf.as_mut_ptr() as *mut c_void, std::mem::size_of_val(&buf) as u32);
+    let _ = CloseHandle(handle);
+
+    if !ok.as_bool() {
+        return None;
+    }
+    let end = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Some(String::from_utf16_lossy(&buf[..end]))
+}
+
+/// Extracts the vendor and product IDs from a device path like `\\?\HID#VID_05AC&PID_0255#...`
+/// (Bluetooth paths use `_VID&0205ac_PID&0255` instead)
+pub fn parse_vid_pid(path: &str) -> Option<(u16, u16)> {
+    let upper = path.to_uppercase();
+
+    if let (Some(v), Some(p)) = (upper.find("VID_"), upper.find("PID_")) {
+        let vid = u16::from_str_radix(upper.get(v + 4..v + 8)?, 16).ok()?;
+        let pid = u16::from_str_radix(upper.get(p + 4..p + 8)?, 16).ok()?;
+        return Some((vid, pid));
+    }
+
+    if let (Some(v), Some(p)) = (upper.find("_VID&"), upper.find("_PID&")) {
+        // The first two digits are the vendor ID source (01 = Bluetooth SIG, 02 = USB-IF)
+        let vid = u16::from_str_radix(upper.get(v + 7..v + 11)?, 16).ok()?;
+        let pid = u16::from_str_radix(upper.get(p + 5..p + 9)?, 16).ok()?;
+        return Some((vid, pid));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vid_and_pid_come_from_usb_and_bluetooth_paths() {
+        // USB style path
+        let usb = r"\\?\HID#VID_05AC&PID_0250&MI_00#7&2a7e3f1&0&0000#{884b96c3-56ef-11d1-bc8c-00a0c91405dd}";
+        assert_eq!(parse_vid_pid(usb), Some((0x05AC, 0x0250)));
+
+        // Bluetooth style path, as reported for the A1314
+        let bt = r"\\?\HID#{00001124-0000-1000-8000-00805f9b34fb}_VID&0205ac_PID&0255&Col01#8&1b5c2d3&0&0000#{884b96c3}";
+        assert_eq!(parse_vid_pid(bt), Some((0x05AC, 0x0255)));
+
+        // Virtual devices have no IDs
+        assert_eq!(parse_vid_pid(r"\\?\Root#RDP_KBD#0000#{884b96c3}"), None);
+    }
+}
@@ -0,0 +1,75 @@
+// --- START OF FILE src/error.rs ---
+use std::fmt;
+use std::path::PathBuf;
+
+/// One bad line (or an unreadable file, with `line` 0) found while loading a mapping file
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    pub line: usize,
+    /// Where in the line the problem starts, or 0 for the line as a whole
+    pub column: usize,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    /// "line 12" or "line 12, column 9", for reports
+    pub fn location(&self) -> String {
+        match self.column {
+            0 => format!("line {}", self.line),
+            column => format!("line {}, column {}", self.line, column),
+        }
+    }
+}
+
+/// Failures the daemon can report, grouped by where they come from
+#[derive(Debug)]
+pub enum A1314Error {
+    /// The mapping file couldn't be read or some of its lines were rejected
+    Config { path: PathBuf, problems: Vec<ConfigProblem> },
+    /// A Windows API call failed
+    Win32 { context: String, source: windows::core::Error },
+    /// Synthesized input couldn't be sent (unknown key, or blocked by Windows)
+    Injection(String),
+    /// A keyboard or HID collection couldn't be found or opened
+    Device(String),
+    /// A wait in an action sequence ran out before its condition was met
+    Timeout(String),
+}
+
+pub type Result<T> = std::result::Result<T, A1314Error>;
+
+impl A1314Error {
+    pub fn win32(context: impl Into<String>, source: windows::core::Error) -> Self {
+        A1314Error::Win32 { context: context.into(), source }
+    }
+}
+
+impl fmt::Display for A1314Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            A1314Error::Config { path, problems } => {
+                write!(f, "{}: {} problem(s)", path.display(), problems.len())?;
+                if let Some(first) = problems.first() {
+                    match first.line {
+                        0 => write!(f, "; {}", first.message)?,
+                        _ => write!(f, "; {}: {}", first.location(), first.message)?,
+                    }
+                }
+                Ok(())
+            }
+            A1314Error::Win32 { context, source } => write!(f, "Failed to {}: {}", context, source),
+            A1314Error::Injection(message) => write!(f, "Input injection failed: {}", message),
+            A1314Error::Device(message) => write!(f, "Device error: {}", message),
+            A1314Error::Timeout(message) => write!(f, "Timed out: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for A1314Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            A1314Error::Win32 { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// --- START OF FILE src/expand.rs ---
+// %VAR%, ${VAR} and ~ in RUN() commands, expanded when the action runs so one mapping file works
+// for every user account and machine
+use windows::core::GUID;
+use windows::Win32::System::Com::CoTaskMemFree;
+use windows::Win32::UI::Shell::{
+    SHGetKnownFolderPath, FOLDERID_Desktop, FOLDERID_Documents, FOLDERID_Downloads, FOLDERID_Music,
+    FOLDERID_Pictures, FOLDERID_Videos, KF_FLAG_DEFAULT,
+};
+
+// Folders without an environment variable of their own, by the name used as a variable
+const KNOWN_FOLDERS: &[(&str, GUID)] = &[
+    ("DESKTOP", FOLDERID_Desktop),
+    ("DOCUMENTS", FOLDERID_Documents),
+    ("DOWNLOADS", FOLDERID_Downloads),
+    ("MUSIC", FOLDERID_Music),
+    ("PICTURES", FOLDERID_Pictures),
+    ("VIDEOS", FOLDERID_Videos),
+];
+
+/// Expands environment variables (`%APPDATA%`, `${LOCALAPPDATA}`), known folders
+/// (`%DOWNLOADS%`) and a leading `~` (the user profile). Unknown variables are left as written.
+pub fn expand(text: &str) -> String {
+    expand_with(text, |name| std::env::var(name).ok().or_else(|| known_folder(name)))
+}
+
+fn expand_with(text: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(['%', '$', '~']) {
+        let (before, from) = rest.split_at(start);
+        out.push_str(before);
+
+        let variable = if let Some(after) = from.strip_prefix('%') {
+            after.find('%').map(|end| (&after[..end], end + 2))
+        } else if let Some(after) = from.strip_prefix("${") {
+            after.find('}').map(|end| (&after[..end], end + 3))
+        } else {
+            None
+        };
+        let valid = |name: &str| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        match variable {
+            Some((name, len)) if valid(name) => match lookup(name) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&from[..len]),
+            },
+            // ~ only at the start of a path: after a space or quote, and followed by a separator
+            _ if from.starts_with('~') && at_path_start(&out) && ends_path_part(&from[1..]) => {
+                match lookup("USERPROFILE") {
+                    Some(profile) => out.push_str(&profile),
+                    None => out.push('~'),
+                }
+                rest = &from[1..];
+                continue;
+            }
+            _ => {
+                out.push_str(&from[..1]);
+                rest = &from[1..];
+                continue;
+            }
+        }
+        rest = &from[variable.map_or(1, |(_, len)| len)..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn at_path_start(before: &str) -> bool {
+    before.is_empty() || before.ends_with([' ', '"'])
+}
+
+fn ends_path_part(after: &str) -> bool {
+    after.is_empty() || after.starts_with(['\\', '/', ' ', '"'])
+}
+
+fn known_folder(name: &str) -> Option<String> {
+    let (_, id) = KNOWN_FOLDERS.iter().find(|(folder, _)| folder.eq_ignore_ascii_case(name))?;
+    unsafe {
+        let path = SHGetKnownFolderPath(id, KF_FLAG_DEFAULT, None).ok()?;
+        let value = path.to_string().ok();
+        CoTaskMemFree(Some(path.0 as *const _));
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables_and_home_are_expanded() {
+        let lookup = |name: &str| match name {
+            "APPDATA" => Some("C:\\Users\\me\\AppData\\Roaming".to_string()),
+            "USERPROFILE" => Some("C:\\Users\\me".to_string()),
+            _ => None,
+        };
+        assert_eq!(expand_with("%APPDATA%\\app.exe", lookup), "C:\\Users\\me\\AppData\\Roaming\\app.exe");
+        assert_eq!(expand_with("${USERPROFILE}\\a.exe ~/notes.txt", lookup), "C:\\Users\\me\\a.exe C:\\Users\\me/notes.txt");
+        assert_eq!(expand_with("\"~\\My App\\app.exe\"", lookup), "\"C:\\Users\\me\\My App\\app.exe\"");
+        assert_eq!(expand_with("cmd /c echo 50% done %NOPE% a~b ${ x}", lookup), "cmd /c echo 50% done %NOPE% a~b ${ x}");
+        assert_eq!(expand_with("$5 and 100%", lookup), "$5 and 100%");
+    }
+}
@@ -0,0 +1,69 @@
+// --- START OF FILE src/focus_assist.rs ---
+// FOCUS_ASSIST(on|off|toggle): Focus Assist / Do Not Disturb. Windows has no public API for it,
+// so the state is read and set through the shell's WNF state name, the same one the Action
+// Center uses. If that fails, the caller opens SETTINGS_URI so the user can flip it themselves.
+use windows::core::{s, w};
+use windows::Win32::System::LibraryLoader::{GetModuleHandleW, GetProcAddress};
+
+use crate::error::{A1314Error, Result};
+
+// WNF_SHEL_QUIETHOURS_ACTIVE_PROFILE_CHANGED; its data is the active profile
+const QUIET_HOURS_STATE: u64 = 0x0D83_063E_A3BF_1C75;
+const PROFILE_OFF: u32 = 0;
+const PROFILE_PRIORITY_ONLY: u32 = 1;
+pub const SETTINGS_URI: &str = "ms-settings:quiethours";
+
+type NtQueryWnfStateData = unsafe extern "system" fn(
+    state: *const u64, type_id: *const u8, scope: *const u8, change_stamp: *mut u32,
+    buffer: *mut u8, size: *mut u32,
+) -> i32;
+type NtUpdateWnfStateData = unsafe extern "system" fn(
+    state: *const u64, buffer: *const u8, size: u32, type_id: *const u8, scope: *const u8,
+    matching_stamp: u32, check_stamp: u32,
+) -> i32;
+
+/// Turns Focus Assist on (priority only) or off, or flips it with None, and returns the new state
+pub fn set(on: Option<bool>) -> Result<bool> {
+    let on = match on {
+        Some(on) => on,
+        None => !read()?,
+    };
+    write(on)?;
+    log::info!("Focus Assist {}", if on { "on" } else { "off" });
+    Ok(on)
+}
+
+/// Whether Focus Assist is on in any profile
+fn read() -> Result<bool> {
+    let query: NtQueryWnfStateData = unsafe { std::mem::transmute(ntdll_function(s!("NtQueryWnfStateData"))?) };
+    let (mut profile, mut size, mut stamp) = (0u32, 4u32, 0u32);
+    let status = unsafe {
+        query(&QUIET_HOURS_STATE, std::ptr::null(), std::ptr::null(), &mut stamp,
+              &mut profile as *mut u32 as *mut u8, &mut size)
+    };
+    if status < 0 {
+        return Err(A1314Error::Injection(format!("reading the Focus Assist state failed (NTSTATUS 0x{:08X})", status)));
+    }
+    Ok(profile != PROFILE_OFF)
+}
+
+fn write(on: bool) -> Result<()> {
+    let update: NtUpdateWnfStateData = unsafe { std::mem::transmute(ntdll_function(s!("NtUpdateWnfStateData"))?) };
+    let profile = if on { PROFILE_PRIORITY_ONLY } else { PROFILE_OFF };
+    let status = unsafe {
+        update(&QUIET_HOURS_STATE, &profile as *const u32 as *const u8, 4, std::ptr::null(), std::ptr::null(), 0, 0)
+    };
+    if status < 0 {
+        return Err(A1314Error::Injection(format!("setting the Focus Assist state failed (NTSTATUS 0x{:08X})", status)));
+    }
+    Ok(())
+}
+
+/// An export of ntdll, looked up at run time since these aren't in any import library
+fn ntdll_function(name: windows::core::PCSTR) -> Result<unsafe extern "system" fn() -> isize> {
+    unsafe {
+        let ntdll = GetModuleHandleW(w!("ntdll.dll")).map_err(|e| A1314Error::win32("find ntdll.dll", e))?;
+        GetProcAddress(ntdll, name)
+            .ok_or_else(|| A1314Error::Injection(format!("ntdll.dll has no {}", name.display())))
+    }
+}
@@ -0,0 +1,263 @@
+// --- START OF FILE src/foreground.rs ---
+// The application in front, for actions that depend on it and for game mode
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, BOOL, HWND, LPARAM, RECT};
+use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED};
+use windows::Win32::Graphics::Gdi::{GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONULL};
+use windows::Win32::System::Diagnostics::ToolHelp::{
+    CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS,
+};
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_RUNNING_D3D_FULL_SCREEN};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetDesktopWindow, GetForegroundWindow, GetShellWindow, GetWindow, GetWindowLongW,
+    GetWindowRect, GetWindowThreadProcessId, IsWindowVisible, GWL_EXSTYLE, GWL_STYLE, GW_OWNER, WS_CAPTION,
+    WS_EX_TOOLWINDOW,
+};
+
+// game_mode from [settings]: leave keys alone while a fullscreen game is in front
+static GAME_MODE: AtomicBool = AtomicBool::new(true);
+// Whether a game was in front at the last check, so only the changes are logged
+static GAME_IN_FRONT: AtomicBool = AtomicBool::new(false);
+// exclude_apps / include_apps / passthrough_apps from [settings]
+static APP_LISTS: Mutex<AppLists> = Mutex::new(AppLists::new());
+// Mode at the last check, so only the changes are logged
+static LAST_APP_MODE: Mutex<AppMode> = Mutex::new(AppMode::Remap);
+
+/// How the daemon treats keys while a given application is in front
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppMode {
+    Remap,
+    Excluded,    // Left alone
+    Passthrough, // Left alone, and keys Windows doesn't type itself are passed on as scan codes
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AppLists {
+    pub exclude: Vec<String>,
+    pub include: Vec<String>,
+    pub passthrough: Vec<String>,
+}
+
+impl AppLists {
+    pub const fn new() -> Self {
+        Self { exclude: Vec::new(), include: Vec::new(), passthrough: Vec::new() }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.exclude.is_empty() && self.include.is_empty() && self.passthrough.is_empty()
+    }
+
+    /// The mode for `process`; listed for passthrough wins over excluded, and a non-empty
+    /// include list excludes everything not on it
+    pub fn mode(&self, process: Option<&str>) -> AppMode {
+        let listed = |apps: &[String]| process.is_some_and(|process| apps.iter().any(|app| matches(app, process)));
+        if listed(&self.passthrough) {
+            AppMode::Passthrough
+        } else if listed(&self.exclude) || (!self.include.is_empty() && !listed(&self.include)) {
+            AppMode::Excluded
+        } else {
+            AppMode::Remap
+        }
+    }
+}
+
+/// File name of the foreground window's executable (e.g. "photoshop.exe"), or None if there is
+/// no foreground window or its process can't be queried
+pub fn process_name() -> Option<String> {
+    window_process_name(unsafe { GetForegroundWindow() })
+}
+
+/// File name of the executable that owns `hwnd`
+fn window_process_name(hwnd: HWND) -> Option<String> {
+    unsafe {
+        if hwnd.0.is_null() {
+            return None;
+        }
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        if pid == 0 {
+            return None;
+        }
+
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buffer = [0u16; 1024];
+        let mut len = buffer.len() as u32;
+        let result = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut len);
+        let _ = CloseHandle(process);
+        result.ok()?;
+
+        let path = String::from_utf16_lossy(&buffer[..len as usize]);
+        path.rsplit(['\\', '/']).next().map(str::to_string)
+    }
+}
+
+/// Visible top-level windows, front to back
+fn visible_windows() -> Vec<HWND> {
+    unsafe extern "system" fn collect(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        if IsWindowVisible(hwnd).as_bool() {
+            let windows = &mut *(lparam.0 as *mut Vec<HWND>);
+            windows.push(hwnd);
+        }
+        true.into()
+    }
+
+    let mut windows = Vec::new();
+    unsafe {
+        let _ = EnumWindows(Some(collect), LPARAM(&mut windows as *mut Vec<HWND> as isize));
+    }
+    windows
+}
+
+/// The first visible, unowned top-level window of process `pid`, i.e. its main window
+pub fn process_window(pid: u32) -> Option<HWND> {
+    process_windows(pid).into_iter().next()
+}
+
+/// Visible, unowned top-level windows of process `pid`, front to back
+pub fn process_windows(pid: u32) -> Vec<HWND> {
+    visible_windows().into_iter().filter(|&hwnd| unsafe {
+        let mut owner_pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut owner_pid));
+        owner_pid == pid && GetWindow(hwnd, GW_OWNER).map_or(true, |owner| owner.0.is_null())
+    }).collect()
+}
+
+/// Process ID of the foreground window, or None if there is no foreground window
+pub fn foreground_process_id() -> Option<u32> {
+    unsafe {
+        let hwnd = GetForegroundWindow();
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(hwnd, Some(&mut pid));
+        (!hwnd.0.is_null() && pid != 0).then_some(pid)
+    }
+}
+
+/// The windows of process `pid` that Alt+Tab would show, front to back: unowned, not tool
+/// windows, and not cloaked (e.g. on another virtual desktop)
+pub fn app_windows(pid: u32) -> Vec<HWND> {
+    process_windows(pid).into_iter().filter(|&hwnd| unsafe {
+        let mut cloaked = 0u32;
+        let _ = DwmGetWindowAttribute(hwnd, DWMWA_CLOAKED, &mut cloaked as *mut u32 as *mut _, 4);
+        GetWindowLongW(hwnd, GWL_EXSTYLE) as u32 & WS_EX_TOOLWINDOW.0 == 0 && cloaked == 0
+    }).collect()
+}
+
+/// Whether `app` has a visible top-level window
+pub fn has_window(app: &str) -> bool {
+    visible_windows().into_iter()
+        .any(|hwnd| window_process_name(hwnd).is_some_and(|process| matches(app, &process)))
+}
+
+/// Whether a process of `app` is running, with or without a window
+pub fn is_running(app: &str) -> bool {
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+            return false;
+        };
+        let mut entry = PROCESSENTRY32W { dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+        let mut found = false;
+        let mut more = Process32FirstW(snapshot, &mut entry).is_ok();
+        while more && !found {
+            let len = entry.szExeFile.iter().position(|&c| c == 0).unwrap_or(entry.szExeFile.len());
+            found = matches(app, &String::from_utf16_lossy(&entry.szExeFile[..len]));
+            more = Process32NextW(snapshot, &mut entry).is_ok();
+        }
+        let _ = CloseHandle(snapshot);
+        found
+    }
+}
+
+/// Whether `app` names the executable `process`; case doesn't matter and ".exe" is optional
+pub fn matches(app: &str, process: &str) -> bool {
+    let strip = |name: &str| {
+        let name = name.trim().to_ascii_lowercase();
+        name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+    };
+    strip(app) == strip(process)
+}
+
+/// Whether the foreground application is `app`
+pub fn is_app(app: &str) -> bool {
+    process_name().is_some_and(|process| matches(app, &process))
+}
+
+pub fn set_game_mode(enabled: bool) {
+    GAME_MODE.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether game mode is on and a fullscreen game is in front, so remapping should pause
+pub fn game_in_front() -> bool {
+    let in_front = GAME_MODE.load(Ordering::Relaxed) && is_fullscreen_game();
+    if GAME_IN_FRONT.swap(in_front, Ordering::Relaxed) != in_front {
+        if in_front {
+            log::info!("Fullscreen game in front ({}), remapping paused",
+                       process_name().as_deref().unwrap_or("unknown"));
+        } else {
+            log::info!("Fullscreen game no longer in front, remapping resumed");
+        }
+    }
+    in_front
+}
+
+/// Whether the foreground window is a game in exclusive fullscreen, or a window without a
+/// title bar covering its whole monitor (borderless fullscreen)
+fn is_fullscreen_game() -> bool {
+    unsafe {
+        if SHQueryUserNotificationState().is_ok_and(|state| state == QUNS_RUNNING_D3D_FULL_SCREEN) {
+            return true;
+        }
+
+        let hwnd = GetForegroundWindow();
+        if hwnd.0.is_null() || hwnd == GetDesktopWindow() || hwnd == GetShellWindow() {
+            return false;
+        }
+        if GetWindowLongW(hwnd, GWL_STYLE) as u32 & WS_CAPTION.0 == WS_CAPTION.0 {
+            return false;
+        }
+
+        let mut window = RECT::default();
+        if GetWindowRect(hwnd, &mut window).is_err() {
+            return false;
+        }
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONULL);
+        let mut info = MONITORINFO { cbSize: std::mem::size_of::<MONITORINFO>() as u32, ..Default::default() };
+        if monitor.is_invalid() || !GetMonitorInfoW(monitor, &mut info).as_bool() {
+            return false;
+        }
+        let screen = info.rcMonitor;
+        window.left <= screen.left && window.top <= screen.top
+            && window.right >= screen.right && window.bottom >= screen.bottom
+    }
+}
+
+pub fn set_app_lists(lists: &AppLists) {
+    *APP_LISTS.lock().unwrap_or_else(|p| p.into_inner()) = lists.clone();
+}
+
+/// What exclude_apps / include_apps / passthrough_apps say about the application in front
+pub fn app_mode() -> AppMode {
+    let mode = {
+        let lists = APP_LISTS.lock().unwrap_or_else(|p| p.into_inner());
+        if lists.is_empty() {
+            AppMode::Remap
+        } else {
+            lists.mode(process_name().as_deref())
+        }
+    };
+    let previous = std::mem::replace(&mut *LAST_APP_MODE.lock().unwrap_or_else(|p| p.into_inner()), mode);
+    if previous != mode {
+        let app = process_name().unwrap_or_else(|| "Unknown application".to_string());
+        match mode {
+            AppMode::Remap => log::info!("{} in front, remapping resumed", app),
+            AppMode::Excluded => log::info!("{} in front, remapping paused", app),
+            AppMode::Passthrough => log::info!("{} in front, passing keys through untouched", app),
+        }
+    }
+    mode
+}
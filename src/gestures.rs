@@ -0,0 +1,139 @@
+// --- START OF FILE src/gestures.rs ---
+// GESTURE_* keys: two-finger swipes on an Apple trackpad, read from its raw input while a mapping
+// uses them. Windows only sees the fingers when the trackpad presents itself as a precision
+// touchpad (HID digitizer page, touch pad usage), which Apple's Boot Camp driver and the open
+// precision touchpad drivers do; each finger is a link collection with a tip switch and X/Y.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::c_void;
+
+use windows::Win32::Devices::HumanInterfaceDevice::{
+    HidP_GetCaps, HidP_GetUsageValue, HidP_GetUsages, HidP_GetValueCaps, HidP_Input, HIDP_CAPS,
+    HIDP_STATUS_SUCCESS, HIDP_VALUE_CAPS, PHIDP_PREPARSED_DATA,
+};
+use windows::Win32::Foundation::HANDLE;
+use windows::Win32::UI::Input::{GetRawInputDeviceInfoW, RIDI_PREPARSEDDATA};
+
+use crate::devices;
+use crate::hid_parser::SwipeTracker;
+use crate::key_mapper::HidKey;
+use crate::variable_maps::GESTURE_PAGE;
+
+// The precision touchpad top-level collection, registered for raw input while gestures are mapped
+pub const DIGITIZER_PAGE: u16 = 0x0D;
+pub const TOUCH_PAD: u16 = 0x05;
+const TIP_SWITCH: u16 = 0x42;
+const GENERIC_DESKTOP_PAGE: u16 = 0x01;
+const X: u16 = 0x30;
+const Y: u16 = 0x31;
+
+// Fingers a precision touchpad report can hold at most
+const MAX_FINGERS: usize = 5;
+
+/// How to read the fingers out of one trackpad's reports
+struct Touchpad {
+    preparsed: Vec<u8>,
+    // The link collection of each finger, in report order
+    fingers: Vec<u16>,
+    width: i32,
+    tracker: SwipeTracker,
+}
+
+thread_local! {
+    // Every HID device raw input came from, by device handle; None for the ones that aren't
+    // Apple trackpads with readable fingers
+    static TOUCHPADS: RefCell<HashMap<isize, Option<Touchpad>>> = RefCell::new(HashMap::new());
+}
+
+/// Whether raw input from `device` comes from an Apple trackpad, whose reports are gestures
+/// rather than keys
+pub unsafe fn is_trackpad(device: HANDLE) -> bool {
+    TOUCHPADS.with(|touchpads| {
+        touchpads.borrow_mut().entry(device.0 as isize).or_insert_with(|| open(device)).is_some()
+    })
+}
+
+/// Follows the fingers through one report from a trackpad, and returns the gesture key once a
+/// swipe completes
+pub unsafe fn report(device: HANDLE, report: &[u8]) -> Option<HidKey> {
+    TOUCHPADS.with(|touchpads| {
+        let mut touchpads = touchpads.borrow_mut();
+        let touchpad = touchpads.get_mut(&(device.0 as isize))?.as_mut()?;
+        let fingers = touchpad.fingers_down(report);
+        let usage = touchpad.tracker.update(&fingers, touchpad.width)?;
+        Some(HidKey { usage_page: GESTURE_PAGE, usage })
+    })
+}
+
+/// Reads the report layout of an Apple precision touchpad; None for any other device
+unsafe fn open(device: HANDLE) -> Option<Touchpad> {
+    let (vendor_id, _) = devices::vid_pid(device)?;
+    if vendor_id != devices::APPLE_VENDOR_ID {
+        return None;
+    }
+
+    let mut size = 0u32;
+    GetRawInputDeviceInfoW(device, RIDI_PREPARSEDDATA, None, &mut size);
+    if size == 0 {
+        return None;
+    }
+    let mut preparsed = vec![0u8; size as usize];
+    if GetRawInputDeviceInfoW(device, RIDI_PREPARSEDDATA, Some(preparsed.as_mut_ptr() as *mut c_void), &mut size) == u32::MAX {
+        return None;
+    }
+    let data = PHIDP_PREPARSED_DATA(preparsed.as_ptr() as isize);
+
+    let mut caps = HIDP_CAPS::default();
+    if HidP_GetCaps(data, &mut caps) != HIDP_STATUS_SUCCESS || caps.UsagePage != DIGITIZER_PAGE || caps.Usage != TOUCH_PAD {
+        return None;
+    }
+    let mut count = caps.NumberInputValueCaps;
+    let mut value_caps = vec![HIDP_VALUE_CAPS::default(); count as usize];
+    if HidP_GetValueCaps(HidP_Input, value_caps.as_mut_ptr(), &mut count, data) != HIDP_STATUS_SUCCESS {
+        return None;
+    }
+    value_caps.truncate(count as usize);
+
+    // Every finger collection has an X; the width is the largest X range among them
+    let mut fingers = Vec::new();
+    let mut width = 0;
+    for value in value_caps.iter().filter(|value| value.UsagePage == GENERIC_DESKTOP_PAGE) {
+        let usage = if value.IsRange.as_bool() { value.Anonymous.Range.UsageMin } else { value.Anonymous.NotRange.Usage };
+        if usage == X && !fingers.contains(&value.LinkCollection) {
+            fingers.push(value.LinkCollection);
+            width = width.max(value.LogicalMax - value.LogicalMin);
+        }
+    }
+    fingers.truncate(MAX_FINGERS);
+    if fingers.is_empty() || width <= 0 {
+        log::debug!("Apple touch pad {:?} has no finger positions, so it gives no gestures", device);
+        return None;
+    }
+    log::info!("Reading gestures from an Apple trackpad ({} fingers)", fingers.len());
+    Some(Touchpad { preparsed, fingers, width, tracker: SwipeTracker::default() })
+}
+
+impl Touchpad {
+    /// The position of each finger touching the pad in `report`
+    unsafe fn fingers_down(&self, report: &[u8]) -> Vec<(i32, i32)> {
+        let data = PHIDP_PREPARSED_DATA(self.preparsed.as_ptr() as isize);
+        // HidP_GetUsages wants the report writable, though it only reads it
+        let mut report = report.to_vec();
+        let mut down = Vec::new();
+        for &link in &self.fingers {
+            let mut usages = [0u16; 8];
+            let mut count = usages.len() as u32;
+            let status = HidP_GetUsages(HidP_Input, DIGITIZER_PAGE, link, usages.as_mut_ptr(), &mut count, data, &mut report);
+            if status != HIDP_STATUS_SUCCESS || !usages[..count as usize].contains(&TIP_SWITCH) {
+                continue;
+            }
+            let (mut x, mut y) = (0u32, 0u32);
+            if HidP_GetUsageValue(HidP_Input, GENERIC_DESKTOP_PAGE, link, X, &mut x, data, &report) == HIDP_STATUS_SUCCESS
+                && HidP_GetUsageValue(HidP_Input, GENERIC_DESKTOP_PAGE, link, Y, &mut y, data, &report) == HIDP_STATUS_SUCCESS
+            {
+                down.push((x as i32, y as i32));
+            }
+        }
+        down
+    }
+}
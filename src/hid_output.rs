@@ -0,0 +1,185 @@
+// --- START OF FILE src/hid_output.rs ---
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use windows::core::PCWSTR;
+use windows::Win32::Devices::HumanInterfaceDevice::{
+    HidD_FreePreparsedData, HidD_GetFeature, HidD_GetPreparsedData, HidD_SetFeature,
+    HidD_SetOutputReport, HidP_GetCaps, HIDP_CAPS, PHIDP_PREPARSED_DATA,
+};
+use windows::Win32::Foundation::{CloseHandle, GENERIC_READ, GENERIC_WRITE, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    CreateFileW, FILE_FLAGS_AND_ATTRIBUTES, FILE_SHARE_READ, FILE_SHARE_WRITE, OPEN_EXISTING,
+};
+
+use crate::devices::{self, DeviceInfo};
+use crate::error::{A1314Error, Result};
+use crate::osd;
+
+// Keyboard LED output report (report ID 0x01, LED bits in byte 1)
+const LED_REPORT_ID: u8 = 0x01;
+const LED_CAPS_LOCK: u8 = 0x02;
+
+// Apple backlight feature reports, as used by backlit Apple keyboards:
+// 0xBF reads [id, version, off, min, max] (u16 LE), 0xB0 sets [id, version, level, fade rate]
+const BACKLIGHT_CONFIG_REPORT_ID: u8 = 0xBF;
+const BACKLIGHT_SET_REPORT_ID: u8 = 0xB0;
+const BACKLIGHT_FADE_MS: u16 = 250;
+
+// Last backlight level set, as a percentage; the keyboard can't be asked for it
+static BACKLIGHT_PERCENT: AtomicI32 = AtomicI32::new(50);
+
+/// An opened HID collection, closed on drop
+struct HidHandle(HANDLE);
+
+impl Drop for HidHandle {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = CloseHandle(self.0);
+        }
+    }
+}
+
+impl HidHandle {
+    /// Opens for writing when Windows allows it (it doesn't for keyboards in use), otherwise without access
+    fn open(path: &str) -> Result<Self> {
+        let wide: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+        let open = |access: u32| unsafe {
+            CreateFileW(PCWSTR(wide.as_ptr()), access, FILE_SHARE_READ | FILE_SHARE_WRITE,
+                        None, OPEN_EXISTING, FILE_FLAGS_AND_ATTRIBUTES(0), None)
+        };
+        open(GENERIC_READ.0 | GENERIC_WRITE.0)
+            .or_else(|_| open(0))
+            .map(HidHandle)
+            .map_err(|e| A1314Error::win32(format!("open {}", path), e))
+    }
+
+    fn caps(&self) -> Option<HIDP_CAPS> {
+        unsafe {
+            let mut preparsed = PHIDP_PREPARSED_DATA::default();
+            if !HidD_GetPreparsedData(self.0, &mut preparsed).as_bool() {
+                return None;
+            }
+            let mut caps = HIDP_CAPS::default();
+            let status = HidP_GetCaps(preparsed, &mut caps);
+            let _ = HidD_FreePreparsedData(preparsed);
+            status.is_ok().then_some(caps)
+        }
+    }
+}
+
+/// Apple keyboard collections, keyboard first
+fn apple_collections() -> Result<Vec<DeviceInfo>> {
+    let mut collections: Vec<DeviceInfo> = devices::list_keyboards()?
+        .into_iter()
+        .filter(|d| d.is_apple() && !d.path.is_empty())
+        .collect();
+    collections.sort_by_key(|d| d.kind != "keyboard");
+    Ok(collections)
+}
+
+/// Turns the Caps Lock LED of the Apple keyboard on or off
+pub fn set_caps_led(on: bool) -> Result<()> {
+    let keyboard = apple_collections()?
+        .into_iter()
+        .find(|d| d.kind == "keyboard")
+        .ok_or_else(|| A1314Error::Device("No Apple keyboard found for the Caps Lock LED".to_string()))?;
+
+    let handle = HidHandle::open(&keyboard.path)?;
+    let len = handle.caps().map(|caps| caps.OutputReportByteLength as usize).unwrap_or(2).max(2);
+    let mut report = vec![0u8; len];
+    report[0] = LED_REPORT_ID;
+    report[1] = if on { LED_CAPS_LOCK } else { 0 };
+
+    let ok = unsafe { HidD_SetOutputReport(handle.0, report.as_ptr() as *const c_void, len as u32) };
+    if !ok.as_bool() {
+        return Err(A1314Error::win32("set the Caps Lock LED", windows::core::Error::from_win32()));
+    }
+    log::debug!("Caps Lock LED {}", if on { "on" } else { "off" });
+    Ok(())
+}
+
+/// Sends a raw output report (report ID first) to the first Apple collection that accepts it
+pub fn send_output_report(report: &[u8]) -> Result<()> {
+    let mut last_error = None;
+    for collection in apple_collections()? {
+        let handle = match HidHandle::open(&collection.path) {
+            Ok(handle) => handle,
+            Err(e) => {
+                last_error = Some(e);
+                continue;
+            }
+        };
+        let len = match handle.caps() {
+            Some(caps) if caps.OutputReportByteLength as usize >= report.len() => caps.OutputReportByteLength as usize,
+            _ => continue,
+        };
+
+        let mut padded = report.to_vec();
+        padded.resize(len, 0);
+        if unsafe { HidD_SetOutputReport(handle.0, padded.as_ptr() as *const c_void, len as u32) }.as_bool() {
+            log::debug!("Sent output report {:02X?} to {}", report, collection.path);
+            return Ok(());
+        }
+        last_error = Some(A1314Error::win32(format!("send an output report to {}", collection.path),
+                                            windows::core::Error::from_win32()));
+    }
+    Err(last_error.unwrap_or_else(|| A1314Error::Device(
+        format!("No Apple HID collection accepts output report {:02X?}", report))))
+}
+
+/// Backlight range of a collection that answers the backlight config report
+struct Backlight {
+    handle: HidHandle,
+    feature_len: usize,
+    min: u16,
+    max: u16,
+}
+
+fn find_backlight() -> Result<Backlight> {
+    for collection in apple_collections()? {
+        let Ok(handle) = HidHandle::open(&collection.path) else { continue };
+        let feature_len = match handle.caps() {
+            Some(caps) if caps.FeatureReportByteLength >= 8 => caps.FeatureReportByteLength as usize,
+            _ => continue,
+        };
+
+        let mut config = vec![0u8; feature_len];
+        config[0] = BACKLIGHT_CONFIG_REPORT_ID;
+        if !unsafe { HidD_GetFeature(handle.0, config.as_mut_ptr() as *mut c_void, feature_len as u32) }.as_bool() {
+            continue;
+        }
+        let min = u16::from_le_bytes([config[4], config[5]]);
+        let max = u16::from_le_bytes([config[6], config[7]]);
+        if max > min {
+            return Ok(Backlight { handle, feature_len, min, max });
+        }
+    }
+    Err(A1314Error::Device("This keyboard has no adjustable backlight".to_string()))
+}
+
+/// Raises or lowers the keyboard backlight by `step` percent
+pub fn adjust_backlight(step: i32) -> Result<()> {
+    let backlight = find_backlight()?;
+    let percent = (BACKLIGHT_PERCENT.load(Ordering::Relaxed) + step).clamp(0, 100);
+    let level = if percent == 0 {
+        0
+    } else {
+        backlight.min + ((backlight.max - backlight.min) as i32 * percent / 100) as u16
+    };
+
+    let mut report = vec![0u8; backlight.feature_len];
+    report[0] = BACKLIGHT_SET_REPORT_ID;
+    report[1] = 1; // Report version
+    report[2..4].copy_from_slice(&level.to_le_bytes());
+    report[4..6].copy_from_slice(&BACKLIGHT_FADE_MS.to_le_bytes());
+
+    let ok = unsafe { HidD_SetFeature(backlight.handle.0, report.as_ptr() as *const c_void, report.len() as u32) };
+    if !ok.as_bool() {
+        return Err(A1314Error::win32("set the keyboard backlight", windows::core::Error::from_win32()));
+    }
+    BACKLIGHT_PERCENT.store(percent, Ordering::Relaxed);
+    log::debug!("Keyboard backlight {}% (level {})", percent, level);
+    osd::level("Keyboard backlight", percent as u32);
+    Ok(())
+}
@@ -1,29 +1,237 @@
 // --- src/hid_parser.rs ---
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::sync::Mutex;
 
 // Constants for HID report values
 const NO_KEY: u8 = 0;
 const ERROR_ROLLOVER: u8 = 1;
 
-// Global state to track previously pressed keys for detecting releases
-static PREVIOUS_KEYS: Mutex<Option<HashSet<(u16, u16)>>> = Mutex::new(None);
+// Key codes kept from one keyboard report; the A1314 sends 6 (6KRO), longer reports are cut here
+const MAX_REPORT_KEYS: usize = 64;
+
+// Non-keyboard usages (consumer keys, Fn) one report group can hold at once
+const MAX_OTHER_KEYS: usize = 32;
+
+/// A fixed-capacity list stored inline, so parsing a report doesn't touch the heap
+#[derive(Debug, Clone, Copy)]
+struct SlotList<T: Copy + Default, const N: usize> {
+    items: [T; N],
+    len: usize,
+}
+
+impl<T: Copy + Default, const N: usize> Default for SlotList<T, N> {
+    fn default() -> Self {
+        Self { items: [T::default(); N], len: 0 }
+    }
+}
+
+impl<T: Copy + Default, const N: usize> SlotList<T, N> {
+    /// Appends `item`, or returns false if the list is full
+    fn push(&mut self, item: T) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.items[self.len] = item;
+        self.len += 1;
+        true
+    }
+
+    fn as_slice(&self) -> &[T] {
+        &self.items[..self.len]
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+type KeyCodes = SlotList<u8, MAX_REPORT_KEYS>;
+
+/// Keys held in one report group: keyboard usages as a bit set, anything else in a short list
+#[derive(Debug, Clone, Copy, Default)]
+struct KeyState {
+    keyboard: [u64; 4],
+    others: SlotList<(u16, u16), MAX_OTHER_KEYS>,
+}
+
+impl KeyState {
+    fn insert(&mut self, key: (u16, u16)) {
+        match key {
+            (0x07, usage @ 0..=0xFF) => self.keyboard[usage as usize / 64] |= 1 << (usage % 64),
+            _ if self.contains(key) => {}
+            _ => {
+                if !self.others.push(key) {
+                    log::debug!("Too many keys held in one report, ignoring {:04X}:{:04X}", key.0, key.1);
+                }
+            }
+        }
+    }
+
+    fn contains(&self, key: (u16, u16)) -> bool {
+        match key {
+            (0x07, usage @ 0..=0xFF) => self.keyboard[usage as usize / 64] & (1 << (usage % 64)) != 0,
+            _ => self.others.as_slice().contains(&key),
+        }
+    }
+
+    /// Keyboard usages in usage order, then the others in the order they were seen
+    fn iter(&self) -> impl Iterator<Item = (u16, u16)> + '_ {
+        (0..=0xFFu16)
+            .filter(|&usage| self.contains((0x07, usage)))
+            .map(|usage| (0x07, usage))
+            .chain(self.others.as_slice().iter().copied())
+    }
+}
+
+// Global state to track previously pressed keys for detecting releases, per report group.
+// Each report only describes its own keys (e.g. the Fn report says nothing about letters),
+// so a report is only compared against the previous report of the same group.
+// Each device has its own state too, so one keyboard's report can't release another's keys.
+static PREVIOUS_KEYS: Mutex<Option<HashMap<(DeviceId, u8), KeyState>>> = Mutex::new(None);
+
+/// The raw input device a report came from (its device handle); the default is "unknown device"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct DeviceId(pub isize);
+
+/// Reports that describe the same set of keys share a group; None for momentary reports
+fn report_group(report_id: u8, vendor_reports: &[VendorReport]) -> Option<u8> {
+    match report_id {
+        0x01 => Some(0x01),        // Keyboard
+        0x02 | 0x03 => Some(0x02), // Consumer control
+        // Apple vendor reports (Fn / Eject), each tracked on its own
+        id if vendor_reports.iter().any(|vendor| vendor.report_id == id) => Some(id),
+        _ => None,
+    }
+}
+
+/// Which bits of byte 1 of a vendor-specific report carry the Fn and Eject state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VendorReport {
+    pub report_id: u8,
+    pub fn_mask: u8,
+    pub eject_mask: u8,
+}
+
+/// Bit positions found in logs: report 0x05 (typically USB) and 0x11 (typically Bluetooth)
+pub const DEFAULT_VENDOR_REPORTS: [VendorReport; 2] = [
+    VendorReport { report_id: 0x05, fn_mask: 0x01, eject_mask: 0x00 },
+    VendorReport { report_id: 0x11, fn_mask: 0x10, eject_mask: 0x08 },
+];
+
+impl VendorReport {
+    /// Parses a `[vendor_reports]` line, e.g. `0x11 = FN:0x10, EJECT:0x08`
+    pub fn parse(report_id: &str, masks: &str) -> Result<Self, String> {
+        let report_id = parse_byte(report_id)
+            .ok_or_else(|| format!("Invalid report ID '{}', expected a byte like 0x11", report_id.trim()))?;
+        if matches!(report_id, 0x01..=0x03) {
+            return Err(format!("Report 0x{:02X} is a standard keyboard/consumer report, not a vendor report", report_id));
+        }
+
+        let mut vendor = VendorReport { report_id, fn_mask: 0, eject_mask: 0 };
+        for part in masks.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (name, mask) = part.split_once(':')
+                .ok_or_else(|| format!("Invalid mask '{}', expected FN:<bits> or EJECT:<bits>", part))?;
+            let mask = parse_byte(mask)
+                .ok_or_else(|| format!("Invalid bit mask '{}', expected a byte like 0x10", mask.trim()))?;
+            match name.trim().to_uppercase().as_str() {
+                "FN" => vendor.fn_mask = mask,
+                "EJECT" => vendor.eject_mask = mask,
+                other => return Err(format!("Unknown vendor key '{}', expected FN or EJECT", other)),
+            }
+        }
+        Ok(vendor)
+    }
+}
+
+/// Accepts hex (0x10) or decimal (16)
+fn parse_byte(value: &str) -> Option<u8> {
+    let value = value.trim();
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+// Vendor report layouts, updated whenever the mapping file is (re)loaded; the built-in ones
+// until then, or when none are configured
+static VENDOR_REPORTS: Mutex<Cow<'static, [VendorReport]>> = Mutex::new(Cow::Borrowed(&DEFAULT_VENDOR_REPORTS));
+
+pub fn set_vendor_reports(vendor_reports: &[VendorReport]) {
+    let mut lock = VENDOR_REPORTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *lock = if vendor_reports.is_empty() {
+        Cow::Borrowed(&DEFAULT_VENDOR_REPORTS)
+    } else {
+        Cow::Owned(vendor_reports.to_vec())
+    };
+}
+
+/// Whether `report_id` is one of the configured vendor reports
+pub fn is_vendor_report(report_id: u8) -> bool {
+    let vendor_reports = VENDOR_REPORTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    vendor_reports.iter().any(|vendor| vendor.report_id == report_id)
+}
+
+/// What to do with keyboard reports the A1314 matrix can't represent (ghost/phantom chords)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GhostPolicy {
+    Off,           // Take every report at face value
+    Ignore,        // Drop the key array of impossible chords, keeping the previous state
+    PreferEarlier, // Keep keys that were already held, then the earliest new keys up to the limit
+}
+
+impl GhostPolicy {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "off" => Some(GhostPolicy::Off),
+            "ignore" => Some(GhostPolicy::Ignore),
+            "prefer_earlier" => Some(GhostPolicy::PreferEarlier),
+            _ => None,
+        }
+    }
+}
+
+// Ghost chord handling, updated whenever the mapping file is (re)loaded
+static GHOST_HANDLING: Mutex<(GhostPolicy, usize)> = Mutex::new((GhostPolicy::Off, 3));
+
+pub fn set_ghost_handling(policy: GhostPolicy, max_chord_keys: usize) {
+    let mut lock = GHOST_HANDLING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *lock = (policy, max_chord_keys);
+}
 
 /// Parses Apple A1314 HID reports and extracts usage page, usage, and value tuples
 /// Returns key-down (value=1) and key-up (value=0) events.
-pub fn parse_a1314_hid_report(report: &[u8]) -> Vec<(u16, u16, i32)> {
+pub fn parse_a1314_hid_report(device: DeviceId, report: &[u8]) -> Vec<(u16, u16, i32)> {
     let mut events = Vec::new();
+    parse_report_into(device, report, &mut events);
+    events
+}
+
+/// parse_a1314_hid_report into `events`, which is cleared first, so the raw input path can
+/// reuse one buffer for every report
+pub fn parse_report_into(device: DeviceId, report: &[u8], events: &mut Vec<(u16, u16, i32)>) {
+    events.clear();
 
     if report.len() < 2 {
         log::warn!("HID report too short: {} bytes (expected at least 2)", report.len());
-        return events;
+        return;
     }
 
     // Debug: log raw report (controlled by log level)
     log::debug!("HID Report (ID={:02X}, len={}): {:02X?}", report[0], report.len(), report);
 
     let report_id = report[0];
-    let mut current_stateful_keys = HashSet::new(); // Keys that maintain a "pressed" state
+    let vendor_reports = VENDOR_REPORTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let group = report_group(report_id, &vendor_reports);
+    let mut current_stateful_keys = KeyState::default(); // Keys that maintain a "pressed" state
+
+    // Handle lock poisoning by recovering the inner data
+    let mut prev_state_lock = PREVIOUS_KEYS.lock().unwrap_or_else(|poisoned| {
+        log::error!("PREVIOUS_KEYS mutex was poisoned, recovering...");
+        poisoned.into_inner()
+    });
+    let states = prev_state_lock.get_or_insert_with(HashMap::new);
+    let previous_stateful_keys = group.and_then(|group| states.get(&(device, group)).copied()).unwrap_or_default();
 
     // --- Process Report based on Report ID ---
     match report_id {
@@ -52,59 +260,56 @@ pub fn parse_a1314_hid_report(report: &[u8]) -> Vec<(u16, u16, i32)> {
 
                 // Key codes in bytes 3 onwards (Usage Page 0x07)
                 // Standard 6-key rollover reports are 8 bytes total
-                for i in 3..report.len() {
-                    if report[i] != NO_KEY && report[i] != ERROR_ROLLOVER {
-                        let key_tuple = (0x07, report[i] as u16);
-                        current_stateful_keys.insert(key_tuple);
+                let mut pressed = KeyCodes::default();
+                let mut rollover = false;
+                for &code in &report[3..] {
+                    if code == ERROR_ROLLOVER {
+                        rollover = true;
+                    } else if (0xE0..=0xE7).contains(&code) {
+                        // Modifiers only come from byte 1; in the key array they're malformed
+                        log::debug!("Ignoring modifier usage {:02X} in the key array", code);
+                    } else if code != NO_KEY && !pressed.push(code) {
+                        log::debug!("Ignoring key {:02X} past the first {} in a keyboard report", code, MAX_REPORT_KEYS);
                     }
                 }
+
+                let (policy, max_chord_keys) = *GHOST_HANDLING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                let pressed = filter_ghost_chord(pressed, rollover, Some(&previous_stateful_keys), policy, max_chord_keys);
+                for &code in pressed.as_slice() {
+                    current_stateful_keys.insert((0x07, code as u16));
+                }
             } else {
                 log::warn!("Standard keyboard report too short: {} bytes (expected 8)", report.len());
             }
         }
         
         // Consumer control report (0x02 or 0x03) (Usage Page 0x0C)
-        // Now adding these to stateful keys if they represent a toggle/hold.
+        // Usually a single 16-bit usage, but some firmwares send an array of them when several
+        // consumer keys are held; each usage is tracked (pressed/released) on its own.
         // EJECT (0C:00B8) is handled here.
         0x02 | 0x03 => {
             if report.len() >= 3 {
-                let usage = u16::from_le_bytes([report[1], report[2]]);
-                if usage != 0 {
-                    let key_tuple = (0x0C, usage);
-                    // Add consumer control keys to stateful tracking,
-                    // so we can detect their press and release like other keys.
-                    current_stateful_keys.insert(key_tuple);
+                for pair in report[1..].chunks_exact(2) {
+                    let usage = u16::from_le_bytes([pair[0], pair[1]]);
+                    if usage != 0 {
+                        current_stateful_keys.insert((0x0C, usage));
+                    }
                 }
             } else {
                 log::warn!("Consumer control report too short: {} bytes (expected 3)", report.len());
             }
         }
         
-        // Apple vendor-specific (Fn key state) (Usage Page 0xFF00)
-        // Report 0x05 (typically USB) or 0x11 (typically Bluetooth)
-        0x05 | 0x11 => {
-            if report.len() >= 2 {
-                // Heuristic: check bit 0 (0x01) for report 0x05, 
-                // and bit 4 (0x10) for report 0x11 as discovered in logs.
-                let mut fn_state = false;
-                if report_id == 0x05 {
-                    fn_state = (report[1] & 0x01) != 0;
-                } else if report_id == 0x11 {
-                    fn_state = (report[1] & 0x10) != 0;
-                    
-                    // Also check for Eject bit (0x08) in Bluetooth report 0x11
-                    let eject_state = (report[1] & 0x08) != 0;
-                    if eject_state {
-                        current_stateful_keys.insert((0x0C, 0x00B8)); // Standard Eject usage
-                    }
+        // Apple vendor-specific (Fn / Eject state) (Usage Page 0xFF00)
+        // Bit masks per report ID come from the [vendor_reports] section
+        id if group == Some(id) => {
+            if let Some(vendor) = vendor_reports.iter().find(|vendor| vendor.report_id == id) {
+                if report[1] & vendor.fn_mask != 0 {
+                    current_stateful_keys.insert((0xFF00, 0x0003)); // Specific Fn state usage
                 }
-
-                let key_tuple = (0xFF00, 0x0003); // Specific Fn state usage
-                if fn_state {
-                    current_stateful_keys.insert(key_tuple);
+                if report[1] & vendor.eject_mask != 0 {
+                    current_stateful_keys.insert((0x0C, 0x00B8)); // Standard Eject usage
                 }
-            } else {
-                log::warn!("Vendor-specific report too short: {} bytes", report.len());
             }
         }
         
@@ -122,37 +327,374 @@ pub fn parse_a1314_hid_report(report: &[u8]) -> Vec<(u16, u16, i32)> {
         }
     }
 
+    // Momentary reports don't take part in press/release tracking
+    let group = match group {
+        Some(group) => group,
+        None => return,
+    };
+
     // --- Compare Stateful Keys with Previous State to Detect Releases ---
-    // Handle lock poisoning by recovering the inner data
-    let mut prev_state_lock = PREVIOUS_KEYS.lock().unwrap_or_else(|poisoned| {
-        log::error!("PREVIOUS_KEYS mutex was poisoned, recovering...");
-        poisoned.into_inner()
-    });
-    
-    if let Some(ref previous_stateful_keys) = *prev_state_lock {
-        // Key-up events for stateful keys: keys that were pressed before but aren't now
-        for key in previous_stateful_keys.iter() {
-            if !current_stateful_keys.contains(key) {
-                events.push((key.0, key.1, 0));
+    // (the first report of a group compares against an empty set, so every key is a key-down)
+    // Key-up events for stateful keys: keys that were pressed before but aren't now
+    for key in previous_stateful_keys.iter() {
+        if !current_stateful_keys.contains(key) {
+            events.push((key.0, key.1, 0));
+        }
+    }
+
+    // Key-down events for stateful keys: keys that are pressed now but weren't before
+    for key in current_stateful_keys.iter() {
+        if !previous_stateful_keys.contains(key) {
+            log::debug!("Key-Down: {:04X}:{:04X}", key.0, key.1);
+            events.push((key.0, key.1, 1));
+        }
+    }
+
+    // Update previous state for stateful keys
+    states.insert((device, group), current_stateful_keys);
+}
+
+// The parser state is global, so tests that feed it reports or load mappings (which publish its
+// vendor reports and ghost handling) must not interleave
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Serializes a test's use of the parser and starts it with no keys held and ghost handling off
+#[cfg(test)]
+pub fn lock_for_test() -> std::sync::MutexGuard<'static, ()> {
+    let guard = TEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *PREVIOUS_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+    set_ghost_handling(GhostPolicy::Off, 3);
+    set_vendor_reports(&DEFAULT_VENDOR_REPORTS);
+    guard
+}
+
+/// Applies the ghost chord policy to the key codes of a standard keyboard report.
+/// `pressed` is in report order, which on the A1314 is the order the keys went down.
+fn filter_ghost_chord(
+    pressed: KeyCodes,
+    rollover: bool,
+    previous: Option<&KeyState>,
+    policy: GhostPolicy,
+    max_chord_keys: usize,
+) -> KeyCodes {
+    if policy == GhostPolicy::Off || (!rollover && pressed.len() <= max_chord_keys) {
+        return pressed;
+    }
+
+    let was_down = |code: &u8| previous.is_some_and(|prev| prev.contains((0x07, *code as u16)));
+    let previously_held = || -> KeyCodes {
+        let mut held = KeyCodes::default();
+        if let Some(prev) = previous {
+            for (_, usage) in prev.iter().filter(|(page, usage)| *page == 0x07 && !(0xE0..=0xE7).contains(usage)) {
+                held.push(usage as u8);
             }
         }
-        
-        // Key-down events for stateful keys: keys that are pressed now but weren't before
-        for key in current_stateful_keys.iter() {
-            if !previous_stateful_keys.contains(key) {
-                log::debug!("Key-Down: {:04X}:{:04X}", key.0, key.1);
-                events.push((key.0, key.1, 1));
+        held
+    };
+
+    // ErrorRollOver means the keyboard lost track of the matrix: keep what we had
+    if rollover {
+        log::debug!("Ghost chord: rollover report, keeping previous key state");
+        return previously_held();
+    }
+
+    match policy {
+        GhostPolicy::Off => pressed,
+        GhostPolicy::Ignore => {
+            log::debug!("Ghost chord: ignoring impossible {}-key chord {:02X?}", pressed.len(), pressed.as_slice());
+            previously_held()
+        }
+        GhostPolicy::PreferEarlier => {
+            let mut kept = KeyCodes::default();
+            for &code in pressed.as_slice().iter().filter(|code| was_down(code)) {
+                kept.push(code);
             }
+            for &code in pressed.as_slice().iter().filter(|code| !was_down(code)) {
+                if kept.len() >= max_chord_keys {
+                    break;
+                }
+                kept.push(code);
+            }
+            log::debug!("Ghost chord: kept {:02X?} of {:02X?}", kept.as_slice(), pressed.as_slice());
+            kept
         }
-    } else {
-        // First time initialization: all currently pressed stateful keys are new key-down events
-        for key in current_stateful_keys.iter() {
-            events.push((key.0, key.1, 1));
+    }
+}
+
+/// Spots two-finger swipes in the finger positions of one trackpad's reports. A swipe fires
+/// once the fingers have moved a quarter of the pad's width from where they landed, mostly
+/// sideways; lifting a finger or adding one starts over.
+#[derive(Debug, Default)]
+pub struct SwipeTracker {
+    // Where the two fingers' midpoint was when they landed, and whether this swipe has fired
+    start: Option<(i32, i32)>,
+    fired: bool,
+}
+
+impl SwipeTracker {
+    /// Takes the fingers touching the pad in one report, in the pad's logical units, and
+    /// returns the gesture usage (GESTURE_PAGE) when a swipe completes
+    pub fn update(&mut self, fingers: &[(i32, i32)], width: i32) -> Option<u16> {
+        let &[(x1, y1), (x2, y2)] = fingers else {
+            *self = Self::default();
+            return None;
+        };
+        let midpoint = ((x1 + x2) / 2, (y1 + y2) / 2);
+        let (start_x, start_y) = *self.start.get_or_insert(midpoint);
+        let (dx, dy) = (midpoint.0 - start_x, midpoint.1 - start_y);
+        if self.fired || dx.abs() < width / 4 || dx.abs() < 2 * dy.abs() {
+            return None;
         }
+        self.fired = true;
+        Some(if dx < 0 { 0x0001 } else { 0x0002 }) // GESTURE_SWIPE_LEFT / GESTURE_SWIPE_RIGHT
     }
+}
 
-    // Update previous state for stateful keys
-    *prev_state_lock = Some(current_stateful_keys);
+// Property tests: random report sequences, checked against invariants the rest of the
+// daemon relies on. The same parser is also a cargo-fuzz target (fuzz/fuzz_targets).
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    events
+    const CASES: usize = 2000;
+    const DEVICE: DeviceId = DeviceId(1);
+
+    // Reports that release everything a group can hold
+    const RELEASE_ALL: [&[u8]; 4] = [
+        &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        &[0x02, 0x00, 0x00],
+        &[0x05, 0x00],
+        &[0x11, 0x00],
+    ];
+
+    /// Small deterministic xorshift generator, so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn byte(&mut self) -> u8 {
+            self.next() as u8
+        }
+
+        /// Mostly well-formed reports of known IDs, with truncated, oversized and unknown ones mixed in
+        fn report(&mut self) -> Vec<u8> {
+            let id = [0x01, 0x02, 0x03, 0x05, 0x11, self.byte()][self.below(6)];
+            let len = match self.below(4) {
+                0 => self.below(4),
+                1 => 16 + self.below(48),
+                _ => match id { 0x01 => 8, 0x02 | 0x03 => 3, _ => 2 },
+            };
+            let mut report = vec![id];
+            // Key arrays drawn from a few codes so presses and releases actually overlap
+            report.extend((1..len).map(|_| if self.below(2) == 0 { self.byte() } else { self.below(8) as u8 }));
+            report.truncate(len);
+            report
+        }
+    }
+
+    fn is_known(report: &[u8]) -> bool {
+        report.first().is_some_and(|id| report_group(*id, &DEFAULT_VENDOR_REPORTS).is_some())
+    }
+
+    #[test]
+    fn arbitrary_reports_never_panic() {
+        let _parser = lock_for_test();
+        let mut rng = Rng(0x1314);
+        for _ in 0..CASES {
+            let len = rng.below(80);
+            let report: Vec<u8> = (0..len).map(|_| rng.byte()).collect();
+            parse_a1314_hid_report(DEVICE, &report);
+        }
+    }
+
+    #[test]
+    fn every_down_gets_an_up_after_release_reports() {
+        for policy in [GhostPolicy::Off, GhostPolicy::Ignore, GhostPolicy::PreferEarlier] {
+            let _parser = lock_for_test();
+            set_ghost_handling(policy, 3);
+            let mut rng = Rng(0xA1314);
+            let mut held: HashMap<(u16, u16), i32> = HashMap::new();
+
+            for _ in 0..CASES {
+                let report = rng.report();
+                let events = parse_a1314_hid_report(DEVICE, &report);
+                if !is_known(&report) {
+                    // Momentary events from unknown reports have no release
+                    continue;
+                }
+                for (page, usage, value) in events {
+                    let count = held.entry((page, usage)).or_default();
+                    *count += if value != 0 { 1 } else { -1 };
+                    assert!(*count >= 0, "{:04X}:{:04X} released while not held ({:?})", page, usage, policy);
+                }
+            }
+
+            for report in RELEASE_ALL {
+                for (page, usage, value) in parse_a1314_hid_report(DEVICE, report) {
+                    assert_eq!(value, 0, "release report pressed {:04X}:{:04X}", page, usage);
+                    *held.entry((page, usage)).or_default() -= 1;
+                }
+            }
+            held.retain(|_, count| *count != 0);
+            assert!(held.is_empty(), "keys left held with {:?}: {:04X?}", policy, held);
+        }
+    }
+
+    #[test]
+    fn rollover_reports_emit_nothing() {
+        for policy in [GhostPolicy::Ignore, GhostPolicy::PreferEarlier] {
+            let _parser = lock_for_test();
+            set_ghost_handling(policy, 3);
+            let mut rng = Rng(0xE1);
+
+            for _ in 0..CASES {
+                let mut report = rng.report();
+                if report.first() != Some(&0x01) || report.len() < 8 {
+                    parse_a1314_hid_report(DEVICE, &report);
+                    continue;
+                }
+
+                // Settle on this report, then repeat it with ErrorRollOver in the key array
+                parse_a1314_hid_report(DEVICE, &report);
+                let slot = 3 + rng.below(report.len() - 3);
+                report[slot] = ERROR_ROLLOVER;
+                let events = parse_a1314_hid_report(DEVICE, &report);
+                assert!(events.is_empty(), "rollover report {:02X?} produced {:04X?} ({:?})", report, events, policy);
+            }
+        }
+    }
+
+    #[test]
+    fn prefer_earlier_keeps_held_keys_then_new_ones_in_report_order() {
+        let codes = |list: &[u8]| {
+            let mut codes = KeyCodes::default();
+            for &code in list {
+                codes.push(code);
+            }
+            codes
+        };
+        let filter = |pressed: &[u8], previous: &KeyState, max_chord_keys| {
+            filter_ghost_chord(codes(pressed), false, Some(previous), GhostPolicy::PreferEarlier, max_chord_keys)
+                .as_slice().to_vec()
+        };
+
+        // A and B held, C pressed and a phantom D appears
+        let mut previous = KeyState::default();
+        previous.insert((0x07, 0x04));
+        previous.insert((0x07, 0x05));
+        let report_keys = [0x04, 0x05, 0x06, 0x07];
+        assert_eq!(filter(&report_keys, &previous, 3), vec![0x04, 0x05, 0x06]);
+        assert_eq!(filter(&report_keys, &previous, 2), vec![0x04, 0x05]);
+
+        // Within the limit nothing is dropped
+        assert_eq!(filter(&[0x04, 0x06], &previous, 3), vec![0x04, 0x06]);
+
+        // Ignore keeps what was held, as does a rollover report under either policy
+        let ignored = filter_ghost_chord(codes(&report_keys), false, Some(&previous), GhostPolicy::Ignore, 3);
+        assert_eq!(ignored.as_slice(), &[0x04, 0x05]);
+        let rollover = filter_ghost_chord(codes(&[ERROR_ROLLOVER; 6]), true, Some(&previous), GhostPolicy::PreferEarlier, 3);
+        assert_eq!(rollover.as_slice(), &[0x04, 0x05]);
+    }
+
+    #[test]
+    fn rollover_code_is_never_a_key() {
+        let _parser = lock_for_test();
+        let mut rng = Rng(0x0101);
+        for _ in 0..CASES {
+            let events = parse_a1314_hid_report(DEVICE, &rng.report());
+            assert!(!events.iter().any(|&(page, usage, _)| page == 0x07 && usage == ERROR_ROLLOVER as u16));
+        }
+    }
+
+    #[test]
+    fn vendor_report_lines_parse() {
+        assert_eq!(VendorReport::parse("0x11", "FN:0x10, EJECT:0x08"),
+                   Ok(VendorReport { report_id: 0x11, fn_mask: 0x10, eject_mask: 0x08 }));
+        assert_eq!(VendorReport::parse("18", "fn:2"),
+                   Ok(VendorReport { report_id: 0x12, fn_mask: 0x02, eject_mask: 0x00 }));
+        assert!(VendorReport::parse("0x01", "FN:0x01").is_err());
+        assert!(VendorReport::parse("0x11", "FN=0x10").is_err());
+        assert!(VendorReport::parse("0x11", "CAPS:0x01").is_err());
+        assert!(VendorReport::parse("0x11", "FN:0x100").is_err());
+    }
+
+    #[test]
+    fn configured_vendor_masks_replace_the_defaults() {
+        let _parser = lock_for_test();
+        set_vendor_reports(&[VendorReport { report_id: 0x11, fn_mask: 0x02, eject_mask: 0x04 }]);
+
+        // The old Fn bit means nothing now
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x11, 0x10]), vec![]);
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x11, 0x02]), vec![(0xFF00, 0x0003, 1)]);
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x11, 0x04]), vec![(0xFF00, 0x0003, 0), (0x0C, 0x00B8, 1)]);
+
+        // Report 0x05 is no longer a vendor report, so it is treated as momentary
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x05, 0x01]), vec![]);
+    }
+
+    #[test]
+    fn consumer_arrays_track_each_usage() {
+        let _parser = lock_for_test();
+        let sorted = |mut events: Vec<(u16, u16, i32)>| { events.sort(); events };
+
+        // Volume up (E9) and mute (E2) held together
+        assert_eq!(sorted(parse_a1314_hid_report(DEVICE, &[0x02, 0xE9, 0x00, 0xE2, 0x00])),
+                   vec![(0x0C, 0x00E2, 1), (0x0C, 0x00E9, 1)]);
+        // Volume up released while mute stays down, in a different slot
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x02, 0x00, 0x00, 0xE2, 0x00]), vec![(0x0C, 0x00E9, 0)]);
+        // The single-usage form still works and shares the same state
+        assert_eq!(sorted(parse_a1314_hid_report(DEVICE, &[0x02, 0xB8, 0x00])),
+                   vec![(0x0C, 0x00B8, 1), (0x0C, 0x00E2, 0)]);
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x03, 0x00, 0x00]), vec![(0x0C, 0x00B8, 0)]);
+    }
+
+    #[test]
+    fn devices_keep_separate_key_state() {
+        let _parser = lock_for_test();
+        let other = DeviceId(2);
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x11, 0x10]), vec![(0xFF00, 0x0003, 1)]);
+        // Another device's empty vendor report doesn't release this one's Fn
+        assert_eq!(parse_a1314_hid_report(other, &[0x11, 0x00]), vec![]);
+        assert_eq!(parse_a1314_hid_report(DEVICE, &[0x11, 0x00]), vec![(0xFF00, 0x0003, 0)]);
+    }
+
+    #[test]
+    fn a_reused_event_buffer_only_holds_the_latest_report() {
+        let _parser = lock_for_test();
+        let mut events = Vec::new();
+        parse_report_into(DEVICE, &[0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00], &mut events);
+        assert_eq!(events, vec![(0x07, 0x04, 1)]);
+        parse_report_into(DEVICE, &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], &mut events);
+        assert_eq!(events, vec![(0x07, 0x04, 0)]);
+        parse_report_into(DEVICE, &[0x01], &mut events);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn two_finger_swipes_fire_once() {
+        let mut tracker = SwipeTracker::default();
+        assert_eq!(tracker.update(&[(500, 500), (700, 500)], 1000), None);
+        assert_eq!(tracker.update(&[(400, 510), (600, 510)], 1000), None);
+        assert_eq!(tracker.update(&[(200, 520), (400, 520)], 1000), Some(0x0001));
+        assert_eq!(tracker.update(&[(100, 520), (300, 520)], 1000), None);
+
+        // One finger, or a mostly vertical move, is no swipe
+        assert_eq!(tracker.update(&[(100, 520)], 1000), None);
+        assert_eq!(tracker.update(&[(100, 100), (300, 100)], 1000), None);
+        assert_eq!(tracker.update(&[(400, 900), (600, 900)], 1000), None);
+        assert_eq!(tracker.update(&[], 1000), None);
+        assert_eq!(tracker.update(&[(100, 100), (300, 100)], 1000), None);
+        assert_eq!(tracker.update(&[(400, 120), (600, 120)], 1000), Some(0x0002));
+    }
 }
@@ -0,0 +1,216 @@
+// --- START OF FILE src/input_backend.rs ---
+use std::cell::Cell;
+use std::ffi::c_void;
+
+use windows::Win32::Foundation::{HANDLE, LPARAM};
+use windows::Win32::UI::Input::{GetRawInputData, HRAWINPUT, RAWINPUT, RAWINPUTHEADER, RID_INPUT};
+
+use crate::action_executor;
+use crate::attribution;
+use crate::gestures;
+use crate::hid_parser::{self, DeviceId};
+use crate::ipc;
+use crate::journal::{self, JournalEvent, ReportBytes};
+use crate::key_mapper::{HidKey, KeyMapper};
+use crate::report_profiles;
+use crate::scroll;
+
+const RIM_TYPEMOUSE: u32 = 0;
+const RIM_TYPEHID: u32 = 2;
+
+// RAWMOUSE button flags for a vertical or horizontal wheel turn
+const RI_MOUSE_WHEEL: u16 = 0x0400;
+const RI_MOUSE_HWHEEL: u16 = 0x0800;
+
+thread_local! {
+    // Buffers reused from one WM_INPUT to the next so the hot path doesn't allocate per report.
+    // They are taken out while in use, so a nested message loop just gets fresh ones.
+    static RAW_INPUT_BUFFER: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+    static REPORT_BUFFER: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+    static EVENT_BUFFER: Cell<Vec<(u16, u16, i32)>> = const { Cell::new(Vec::new()) };
+}
+
+/// A source of raw A1314 HID reports, so the parser → mapper pipeline can be fed
+/// from WM_INPUT or, in tests, from recorded report sequences
+pub trait InputBackend {
+    /// Writes the next report to `report` and returns the device it came from, or None once
+    /// this backend has nothing more to deliver
+    fn next_report(&mut self, report: &mut Vec<u8>) -> Option<DeviceId>;
+}
+
+/// The HID reports carried by a single WM_INPUT message
+pub struct RawInputBackend {
+    device: HANDLE,
+    buffer: Vec<u8>,
+    report_size: usize,
+    count: usize,
+    next: usize,
+}
+
+impl RawInputBackend {
+    /// Reads the RAWINPUT behind a WM_INPUT `lparam`; None if it isn't HID input
+    pub unsafe fn from_wm_input(lparam: LPARAM) -> Option<Self> {
+        let hrawinput = HRAWINPUT(lparam.0 as *mut c_void);
+
+        // First call: get the size of the RAWINPUT structure
+        let mut size = 0u32;
+        GetRawInputData(
+            hrawinput,
+            RID_INPUT,
+            None,
+            &mut size,
+            std::mem::size_of::<RAWINPUTHEADER>() as u32,
+        );
+
+        if size == 0 {
+            return None;
+        }
+
+        // Second call: get the actual RAWINPUT data
+        let mut buffer = RAW_INPUT_BUFFER.take();
+        buffer.clear();
+        buffer.resize(size as usize, 0);
+        let res = GetRawInputData(
+            hrawinput,
+            RID_INPUT,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            &mut size,
+            std::mem::size_of::<RAWINPUTHEADER>() as u32,
+        );
+
+        if res == u32::MAX {
+            log::error!("Failed to get raw input data");
+            RAW_INPUT_BUFFER.set(buffer);
+            return None;
+        }
+
+        let raw: &RAWINPUT = &*(buffer.as_ptr() as *const RAWINPUT);
+        // Mouse input is only registered for natural_scroll, to tell which mouse turned the wheel
+        if raw.header.dwType == RIM_TYPEMOUSE {
+            let button_flags = raw.data.mouse.Anonymous.Anonymous.usButtonFlags;
+            if button_flags & (RI_MOUSE_WHEEL | RI_MOUSE_HWHEEL) != 0 {
+                scroll::wheel_turned(raw.header.hDevice);
+            }
+        }
+        if raw.header.dwType != RIM_TYPEHID {
+            RAW_INPUT_BUFFER.set(buffer);
+            return None;
+        }
+
+        let device = raw.header.hDevice;
+        let hid = raw.data.hid;
+        let report_size = hid.dwSizeHid as usize;
+        let count = hid.dwCount as usize;
+        Some(Self { device, buffer, report_size, count, next: 0 })
+    }
+
+    /// The gestures in this message's reports if they come from a trackpad, in which case
+    /// none are left for the keyboard parser
+    pub fn take_gestures(&mut self) -> Vec<HidKey> {
+        if !unsafe { gestures::is_trackpad(self.device) } {
+            return Vec::new();
+        }
+        let mut keys = Vec::new();
+        while self.next < self.count {
+            keys.extend(unsafe { gestures::report(self.device, self.report(self.next)) });
+            self.next += 1;
+        }
+        keys
+    }
+
+    /// The `index`th report; they follow each other in bRawData at the end of the RAWINPUT
+    fn report(&self, index: usize) -> &[u8] {
+        let raw = self.buffer.as_ptr() as *const RAWINPUT;
+        unsafe {
+            let data_ptr = (*raw).data.hid.bRawData.as_ptr();
+            std::slice::from_raw_parts(data_ptr.add(index * self.report_size), self.report_size)
+        }
+    }
+}
+
+impl Drop for RawInputBackend {
+    fn drop(&mut self) {
+        RAW_INPUT_BUFFER.set(std::mem::take(&mut self.buffer));
+    }
+}
+
+impl InputBackend for RawInputBackend {
+    fn next_report(&mut self, out: &mut Vec<u8>) -> Option<DeviceId> {
+        while self.next < self.count {
+            self.next += 1;
+            let report = self.report(self.next - 1);
+
+            // Reports from devices that aren't on the allowlist are skipped; BLE devices may leave
+            // out the report ID the parser relies on
+            if report_profiles::frame(self.device, report, out) {
+                return Some(DeviceId(self.device.0 as isize));
+            }
+        }
+        None
+    }
+}
+
+/// Recorded reports replayed in order, standing in for the keyboard in tests and --bench
+pub struct ReplayBackend {
+    reports: std::collections::VecDeque<(DeviceId, Vec<u8>)>,
+}
+
+impl ReplayBackend {
+    /// Reports from a single keyboard
+    pub fn new(reports: &[&[u8]]) -> Self {
+        Self::from_devices(&reports.iter().map(|report| (DeviceId(1), *report)).collect::<Vec<_>>())
+    }
+
+    /// Reports interleaved from several devices
+    pub fn from_devices(reports: &[(DeviceId, &[u8])]) -> Self {
+        Self { reports: reports.iter().map(|(device, report)| (*device, report.to_vec())).collect() }
+    }
+}
+
+impl InputBackend for ReplayBackend {
+    fn next_report(&mut self, out: &mut Vec<u8>) -> Option<DeviceId> {
+        let (device, report) = self.reports.pop_front()?;
+        out.clear();
+        out.extend_from_slice(&report);
+        Some(device)
+    }
+}
+
+/// Where dispatch sends the parsed events; they are journaled and forwarded to IPC clients either way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Routing {
+    Map,         // Into the key mapper
+    CaptureOnly, // Nowhere else
+    Passthrough, // Consumer keys are passed on as scan codes, for a remote desktop or VM in front
+}
+
+/// Runs every report from `backend` through the parser and routes the events
+pub fn dispatch(backend: &mut dyn InputBackend, mapper: &mut KeyMapper, routing: Routing) {
+    let mut report = REPORT_BUFFER.take();
+    let mut events = EVENT_BUFFER.take();
+    while let Some(device) = backend.next_report(&mut report) {
+        journal::record(JournalEvent::Report(ReportBytes::new(&report)));
+        ipc::forward(format_args!("report {:02X?}", report));
+
+        hid_parser::parse_report_into(device, &report, &mut events);
+        for &(usage_page, usage, value) in &events {
+            journal::record(JournalEvent::Hid { usage_page, usage, value });
+            ipc::forward(format_args!("hid {:04X}:{:04X} {}", usage_page, usage, value));
+            let mapped = match routing {
+                Routing::Map => mapper.handle_hid_event(device, usage_page, usage, value),
+                Routing::CaptureOnly => None,
+                Routing::Passthrough if usage_page == 0x0C => {
+                    if let Err(e) = action_executor::forward_consumer_key(usage, value != 0) {
+                        log::warn!("Failed to pass consumer key 0x{:04X} through: {}", usage, e);
+                    }
+                    None
+                }
+                Routing::Passthrough => None,
+            };
+            // Kept for the keyboard hook, which sees the same press again and must not act on it twice
+            attribution::record(device, usage_page, usage, value, mapped);
+        }
+    }
+    REPORT_BUFFER.set(report);
+    EVENT_BUFFER.set(events);
+}
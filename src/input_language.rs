@@ -0,0 +1,83 @@
+// --- START OF FILE src/input_language.rs ---
+// LAYOUT_NEXT, LAYOUT("en-US") and IME_TOGGLE: switch the foreground window's input language the
+// way the language bar does, by asking the window rather than changing it behind its back
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Globalization::LocaleNameToLCID;
+use windows::Win32::UI::Input::Ime::{ImmGetDefaultIMEWnd, IMC_SETOPENSTATUS};
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetKeyboardLayoutList, HKL};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, PostMessageW, SendMessageTimeoutW, HKL_NEXT, INPUTLANGCHANGE_FORWARD, SMTO_ABORTIFHUNG,
+    WM_IME_CONTROL, WM_INPUTLANGCHANGEREQUEST,
+};
+
+use crate::error::{A1314Error, Result};
+
+const IMC_GETOPENSTATUS: u32 = 5;
+// How long to wait for the IME window to answer before giving up (in milliseconds)
+const IME_TIMEOUT_MS: u32 = 200;
+
+/// Switches the foreground window to the next installed input language
+pub fn next_layout() -> Result<()> {
+    request_layout(WPARAM(INPUTLANGCHANGE_FORWARD as usize), LPARAM(HKL_NEXT as isize))
+}
+
+/// Switches the foreground window to the installed layout for `locale`, e.g. "en-US" or "ja-JP"
+pub fn set_layout(locale: &str) -> Result<()> {
+    let name: Vec<u16> = locale.encode_utf16().chain(std::iter::once(0)).collect();
+    let language = unsafe { LocaleNameToLCID(PCWSTR(name.as_ptr()), 0) } & 0xFFFF;
+    if language == 0 {
+        return Err(A1314Error::Injection(format!("'{}' is not a locale name Windows knows", locale)));
+    }
+
+    let layouts = installed_layouts();
+    let layout = layouts.iter()
+        .find(|layout| (layout.0 as usize & 0xFFFF) as u32 == language)
+        .ok_or_else(|| A1314Error::Injection(format!("No keyboard layout for {} is installed", locale)))?;
+    request_layout(WPARAM(0), LPARAM(layout.0 as isize))
+}
+
+/// Turns the foreground window's IME on or off, e.g. between kana and direct input
+pub fn toggle_ime() -> Result<()> {
+    unsafe {
+        let ime = ImmGetDefaultIMEWnd(foreground_window()?);
+        if ime.0.is_null() {
+            return Err(A1314Error::Injection("The foreground window has no IME".to_string()));
+        }
+        let mut open = 0usize;
+        let answered = SendMessageTimeoutW(ime, WM_IME_CONTROL, WPARAM(IMC_GETOPENSTATUS as usize), LPARAM(0),
+                                           SMTO_ABORTIFHUNG, IME_TIMEOUT_MS, Some(&mut open));
+        if answered.0 == 0 {
+            return Err(A1314Error::win32("ask the IME whether it is on", windows::core::Error::from_win32()));
+        }
+        let _ = SendMessageTimeoutW(ime, WM_IME_CONTROL, WPARAM(IMC_SETOPENSTATUS as usize), LPARAM((open == 0) as isize),
+                                    SMTO_ABORTIFHUNG, IME_TIMEOUT_MS, None);
+        log::info!("IME turned {}", if open == 0 { "on" } else { "off" });
+    }
+    Ok(())
+}
+
+fn installed_layouts() -> Vec<HKL> {
+    unsafe {
+        let count = GetKeyboardLayoutList(None).max(0) as usize;
+        let mut layouts = vec![HKL::default(); count];
+        let written = GetKeyboardLayoutList(Some(&mut layouts)).max(0) as usize;
+        layouts.truncate(written);
+        layouts
+    }
+}
+
+fn request_layout(wparam: WPARAM, lparam: LPARAM) -> Result<()> {
+    unsafe {
+        PostMessageW(foreground_window()?, WM_INPUTLANGCHANGEREQUEST, wparam, lparam)
+            .map_err(|e| A1314Error::win32("ask the foreground window to change input language", e))
+    }
+}
+
+fn foreground_window() -> Result<HWND> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0.is_null() {
+        return Err(A1314Error::Injection("No foreground window to switch the input language of".to_string()));
+    }
+    Ok(hwnd)
+}
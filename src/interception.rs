@@ -0,0 +1,139 @@
+// --- START OF FILE src/interception.rs ---
+// input_driver = interception: keys are held back by the Interception filter driver instead of
+// the low-level keyboard hook. The driver sits below the Win32 input layer, so suppression works
+// where a hook can't (the secure desktop, games that reject hooked or injected input). The driver
+// and its interception.dll are installed separately; without them the daemon falls back to the hook.
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use windows::core::{s, w};
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryW};
+use windows::Win32::UI::Input::KeyboardAndMouse::{MapVirtualKeyW, MAPVK_VSC_TO_VK_EX};
+use windows::Win32::UI::WindowsAndMessaging::SendMessageW;
+
+use crate::error::{A1314Error, Result};
+
+// Keyboard stroke states (INTERCEPTION_KEY_*); DOWN is 0
+const KEY_UP: u16 = 0x01;
+const KEY_E0: u16 = 0x02;
+const KEY_E1: u16 = 0x04;
+
+// INTERCEPTION_FILTER_KEY_ALL: every down, up, E0 and E1 stroke
+const FILTER_KEY_ALL: u16 = 0xFFFF;
+
+// How often the driver thread looks up from waiting to see if it should stop (in milliseconds)
+const POLL_MS: u32 = 100;
+
+type Context = *mut c_void;
+type Device = i32;
+
+/// InterceptionStroke is sized for a mouse stroke; a keyboard stroke uses the start of it
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Stroke {
+    code: u16,
+    state: u16,
+    _information: u32,
+    _mouse: [u8; 12],
+}
+
+type CreateContext = unsafe extern "C" fn() -> Context;
+type DestroyContext = unsafe extern "C" fn(Context);
+type Predicate = unsafe extern "C" fn(Device) -> i32;
+type SetFilter = unsafe extern "C" fn(Context, Predicate, u16);
+type WaitWithTimeout = unsafe extern "C" fn(Context, u32) -> Device;
+type Receive = unsafe extern "C" fn(Context, Device, *mut Stroke, u32) -> i32;
+type SendStroke = unsafe extern "C" fn(Context, Device, *const Stroke, u32) -> i32;
+type Export = unsafe extern "system" fn() -> isize;
+
+/// The interception.dll exports the daemon uses
+struct Api {
+    create_context: CreateContext,
+    destroy_context: DestroyContext,
+    set_filter: SetFilter,
+    is_keyboard: Predicate,
+    wait_with_timeout: WaitWithTimeout,
+    receive: Receive,
+    send: SendStroke,
+}
+
+// Cleared by stop() to end the driver thread
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Opens the driver and starts a thread that asks the window thread about each keystroke with
+/// `message` (WPARAM the virtual key, LPARAM nonzero for a release). A nonzero reply drops the
+/// keystroke; anything else sends it on to Windows.
+pub fn start(hwnd: HWND, message: u32) -> Result<()> {
+    let api = load()?;
+    let context = unsafe { (api.create_context)() };
+    if context.is_null() {
+        return Err(A1314Error::Device("the Interception driver is not installed (or needs a reboot)".to_string()));
+    }
+    unsafe { (api.set_filter)(context, api.is_keyboard, FILTER_KEY_ALL) };
+    RUNNING.store(true, Ordering::SeqCst);
+
+    let (context_val, hwnd_val) = (context as usize, hwnd.0 as usize);
+    std::thread::spawn(move || {
+        let context = context_val as Context;
+        let hwnd = HWND(hwnd_val as *mut c_void);
+        let mut stroke = Stroke::default();
+        while RUNNING.load(Ordering::SeqCst) {
+            let device = unsafe { (api.wait_with_timeout)(context, POLL_MS) };
+            if device == 0 || unsafe { (api.receive)(context, device, &mut stroke, 1) } <= 0 {
+                continue;
+            }
+            let suppress = match virtual_key(&stroke) {
+                Some(vk) => unsafe {
+                    SendMessageW(hwnd, message, WPARAM(vk as usize), LPARAM((stroke.state & KEY_UP) as isize)).0 != 0
+                },
+                None => false,
+            };
+            if !suppress {
+                unsafe { (api.send)(context, device, &stroke, 1) };
+            }
+        }
+        unsafe { (api.destroy_context)(context) };
+        log::info!("Interception driver released");
+    });
+    Ok(())
+}
+
+/// Ends the driver thread, which lets keys through untouched from then on
+pub fn stop() {
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+/// The virtual key Windows will make of a keystroke, as the keyboard hook would see it
+fn virtual_key(stroke: &Stroke) -> Option<u32> {
+    // Only Pause sends E1 strokes, and the hook doesn't remap it
+    if stroke.state & KEY_E1 != 0 {
+        return None;
+    }
+    let scan_code = stroke.code as u32 | if stroke.state & KEY_E0 != 0 { 0xE000 } else { 0 };
+    match unsafe { MapVirtualKeyW(scan_code, MAPVK_VSC_TO_VK_EX) } {
+        0 => None,
+        vk => Some(vk),
+    }
+}
+
+/// Finds interception.dll beside the exe or on the DLL search path
+fn load() -> Result<Api> {
+    unsafe {
+        let dll = LoadLibraryW(w!("interception.dll"))
+            .map_err(|e| A1314Error::win32("load interception.dll", e))?;
+        let export = |name: windows::core::PCSTR| {
+            GetProcAddress(dll, name)
+                .ok_or_else(|| A1314Error::Device(format!("interception.dll has no {}", name.display())))
+        };
+        Ok(Api {
+            create_context: std::mem::transmute::<Export, CreateContext>(export(s!("interception_create_context"))?),
+            destroy_context: std::mem::transmute::<Export, DestroyContext>(export(s!("interception_destroy_context"))?),
+            set_filter: std::mem::transmute::<Export, SetFilter>(export(s!("interception_set_filter"))?),
+            is_keyboard: std::mem::transmute::<Export, Predicate>(export(s!("interception_is_keyboard"))?),
+            wait_with_timeout: std::mem::transmute::<Export, WaitWithTimeout>(export(s!("interception_wait_with_timeout"))?),
+            receive: std::mem::transmute::<Export, Receive>(export(s!("interception_receive"))?),
+            send: std::mem::transmute::<Export, SendStroke>(export(s!("interception_send"))?),
+        })
+    }
+}
@@ -0,0 +1,238 @@
+// --- START OF FILE src/ipc.rs ---
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_PIPE_CONNECTED, HANDLE, HWND, INVALID_HANDLE_VALUE, LPARAM, WPARAM};
+use windows::Win32::Storage::FileSystem::{
+    FlushFileBuffers, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+};
+use windows::Win32::UI::WindowsAndMessaging::SendMessageW;
+
+// Local-only control channel used by the CLI subcommands (e.g. --status)
+pub const PIPE_NAME: &str = r"\\.\pipe\A1314Daemon";
+
+// Requests are a single line; anything longer is a misbehaving client
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// A client streaming parsed events; `exclusive` clients also stop the daemon acting on them
+struct Subscriber {
+    tx: Sender<String>,
+    exclusive: bool,
+}
+
+static SUBSCRIBERS: Mutex<Vec<Subscriber>> = Mutex::new(Vec::new());
+
+// Forwarded events are timestamped in milliseconds since the server started
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+/// A request forwarded to the window thread, which owns all daemon state
+pub struct IpcRequest {
+    pub line: String,
+    pub response: String,
+}
+
+/// Starts the pipe server thread. Each request is handed to the window thread as
+/// `message` with a pointer to an `IpcRequest` in LPARAM, and answered synchronously.
+pub fn start_server(hwnd: HWND, message: u32) {
+    let hwnd_val = hwnd.0 as usize;
+    STARTED.get_or_init(Instant::now);
+    std::thread::spawn(move || {
+        let pipe_name: Vec<u16> = PIPE_NAME.encode_utf16().chain(std::iter::once(0)).collect();
+
+        // The first instance claims the name, so another process can't already be serving it
+        let mut open_mode = PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE;
+        loop {
+            unsafe {
+                let pipe = CreateNamedPipeW(
+                    PCWSTR(pipe_name.as_ptr()),
+                    open_mode,
+                    PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                    PIPE_UNLIMITED_INSTANCES,
+                    4096,
+                    4096,
+                    0,
+                    None,
+                );
+                if pipe == INVALID_HANDLE_VALUE {
+                    log::error!("Failed to create IPC pipe {}: {:?}", PIPE_NAME, GetLastError());
+                    return;
+                }
+                open_mode = PIPE_ACCESS_DUPLEX;
+
+                // A client may connect between CreateNamedPipe and ConnectNamedPipe
+                if ConnectNamedPipe(pipe, None).is_ok() || GetLastError() == ERROR_PIPE_CONNECTED {
+                    // Each client gets its own thread, so one that never finishes its request
+                    // can't hold up the others
+                    let pipe_val = pipe.0 as isize;
+                    std::thread::spawn(move || {
+                        serve(HWND(hwnd_val as *mut c_void), message, HANDLE(pipe_val as *mut c_void));
+                    });
+                } else {
+                    let _ = CloseHandle(pipe);
+                }
+            }
+        }
+    });
+}
+
+/// Answers one connected client, or streams events to it, then closes its pipe instance
+unsafe fn serve(hwnd: HWND, message: u32, pipe: HANDLE) {
+    match read_request(pipe) {
+        Some(line) if line == "subscribe" || line == "capture" => stream_events(pipe, line == "capture"),
+        Some(line) => {
+            log::debug!("IPC request: {}", line);
+            let mut request = IpcRequest { line, response: String::new() };
+            SendMessageW(hwnd, message, WPARAM(0), LPARAM(&mut request as *mut IpcRequest as isize));
+            let _ = WriteFile(pipe, Some(request.response.as_bytes()), None, None);
+            let _ = FlushFileBuffers(pipe);
+        }
+        None => {}
+    }
+    let _ = DisconnectNamedPipe(pipe);
+    let _ = CloseHandle(pipe);
+}
+
+/// Registers a streaming client and writes forwarded events to it until it disconnects
+unsafe fn stream_events(pipe: HANDLE, exclusive: bool) {
+    let (tx, rx) = channel::<String>();
+    SUBSCRIBERS.lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(Subscriber { tx, exclusive });
+    log::info!("IPC event stream connected ({})", if exclusive { "capture only" } else { "mirror" });
+
+    let mut connected = WriteFile(pipe, Some(b"OK\n"), None, None).is_ok();
+    while connected {
+        match rx.recv() {
+            Ok(line) => connected = WriteFile(pipe, Some(line.as_bytes()), None, None).is_ok(),
+            Err(_) => break,
+        }
+    }
+    log::info!("IPC event stream disconnected");
+}
+
+/// Sends one event line to every streaming client, dropping those that went away
+pub fn forward(event: std::fmt::Arguments) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if subscribers.is_empty() {
+        return;
+    }
+
+    let ms = STARTED.get().map_or(0, |started| started.elapsed().as_millis());
+    let line = format!("{} {}\n", ms, event);
+    subscribers.retain(|subscriber| subscriber.tx.send(line.clone()).is_ok());
+}
+
+/// True while a `--capture-only` client is connected; mappings are not applied then
+pub fn capture_exclusive() -> bool {
+    SUBSCRIBERS.lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .iter()
+        .any(|subscriber| subscriber.exclusive)
+}
+
+unsafe fn read_request(pipe: HANDLE) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 512];
+    while !request.contains(&b'\n') && request.len() < MAX_REQUEST_LEN {
+        let mut read: u32 = 0;
+        if ReadFile(pipe, Some(&mut buf), Some(&mut read), None).is_err() || read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read as usize]);
+    }
+
+    let line = String::from_utf8_lossy(&request);
+    let line = line.lines().next()?.trim().to_string();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/// Splits a request into words. Double quotes keep spaces in a word and take \" and \\ inside
+/// them, e.g. `map add "FN+KEY_T" "RUN(\"wt.exe\")"`
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else { return Ok(args) };
+        let mut arg = String::new();
+        if first == '"' {
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\')) => arg.push(c),
+                        Some(c) => { arg.push('\\'); arg.push(c); }
+                        None => return Err("Unterminated quote".to_string()),
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err("Unterminated quote".to_string()),
+                }
+            }
+        } else {
+            arg.push(first);
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+}
+
+/// Quotes `arg` so `split_args` gives it back as one word
+pub fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Sends one command to the running daemon and returns its reply
+pub fn query(command: &str) -> std::io::Result<String> {
+    let mut pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(PIPE_NAME)?;
+    pipe.write_all(command.as_bytes())?;
+    pipe.write_all(b"\n")?;
+
+    let mut response = String::new();
+    pipe.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+/// Subscribes to the daemon's event stream and passes each line to `on_line` until it disconnects
+pub fn stream(exclusive: bool, mut on_line: impl FnMut(&str)) -> std::io::Result<()> {
+    let mut pipe = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(PIPE_NAME)?;
+    pipe.write_all(if exclusive { b"capture\n" } else { b"subscribe\n" })?;
+
+    // The first line is the server's acknowledgement
+    for line in BufReader::new(pipe).lines().skip(1) {
+        on_line(&line?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_words_round_trip() {
+        let args = split_args(r#"map add "FN+KEY_T"  "RUN(\"C:\\Tools\\wt.exe\")" --save"#).unwrap();
+        assert_eq!(args, ["map", "add", "FN+KEY_T", r#"RUN("C:\Tools\wt.exe")"#, "--save"]);
+        assert_eq!(split_args(&quote(&args[3])).unwrap(), [args[3].clone()]);
+        assert!(split_args(r#"map add "FN+KEY_T"#).is_err());
+    }
+}
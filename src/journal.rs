@@ -0,0 +1,202 @@
+// --- START OF FILE src/journal.rs ---
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::hid_parser::DeviceId;
+
+// Hard cap so a stuck key or a flood of reports can't grow the journal without bound
+const MAX_ENTRIES: usize = 10_000;
+const DEFAULT_RETENTION_SECS: u64 = 30;
+
+// Events kept regardless of age, for "copy recent events" and `dump-events`
+const RECENT_EVENTS: usize = 500;
+
+// Bytes of a report kept in its journal entry; A1314 reports are far shorter
+const MAX_REPORT_BYTES: usize = 64;
+
+/// A raw report stored inline, so journaling one doesn't touch the heap
+#[derive(Clone, Copy)]
+pub struct ReportBytes {
+    bytes: [u8; MAX_REPORT_BYTES],
+    // Length of the whole report, which may be more than was kept
+    len: usize,
+}
+
+impl ReportBytes {
+    pub fn new(report: &[u8]) -> Self {
+        let mut bytes = [0; MAX_REPORT_BYTES];
+        let kept = report.len().min(MAX_REPORT_BYTES);
+        bytes[..kept].copy_from_slice(&report[..kept]);
+        Self { bytes, len: report.len() }
+    }
+
+    /// The bytes kept, the first MAX_REPORT_BYTES of a longer report
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len.min(MAX_REPORT_BYTES)]
+    }
+}
+
+impl std::fmt::Debug for ReportBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:02X?}", self.as_slice())?;
+        if self.len > MAX_REPORT_BYTES {
+            write!(f, " (+{} bytes)", self.len - MAX_REPORT_BYTES)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single thing worth knowing when reproducing a misbehavior
+#[derive(Debug, Clone)]
+pub enum JournalEvent {
+    Report(ReportBytes),
+    Hid { usage_page: u16, usage: u16, value: i32 },
+    Hook { vk: u32, is_up: bool, device: Option<DeviceId>, suppressed: bool },
+    Action { usage_page: u16, usage: u16, action: String },
+}
+
+struct Journal {
+    // Events within the retention window, plus at least the last RECENT_EVENTS however old
+    entries: VecDeque<(Instant, JournalEvent)>,
+    retention: Duration,
+}
+
+impl Journal {
+    fn prune(&mut self, now: Instant) {
+        while let Some((at, _)) = self.entries.front() {
+            let len = self.entries.len();
+            if len > MAX_ENTRIES || (len > RECENT_EVENTS && now.duration_since(*at) > self.retention) {
+                self.entries.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn within_retention(&self, now: Instant) -> impl Iterator<Item = &(Instant, JournalEvent)> {
+        self.entries.iter().filter(move |(at, _)| now.duration_since(*at) <= self.retention)
+    }
+
+    fn recent(&self) -> impl Iterator<Item = &(Instant, JournalEvent)> {
+        self.entries.iter().skip(self.entries.len().saturating_sub(RECENT_EVENTS))
+    }
+}
+
+// Global journal shared by the raw input handler, the keyboard hook and the mapper
+static JOURNAL: Mutex<Option<Journal>> = Mutex::new(None);
+
+fn with_journal<R>(f: impl FnOnce(&mut Journal) -> R) -> R {
+    let mut lock = JOURNAL.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let journal = lock.get_or_insert_with(|| Journal {
+        entries: VecDeque::new(),
+        retention: Duration::from_secs(DEFAULT_RETENTION_SECS),
+    });
+    f(journal)
+}
+
+/// Appends an event, dropping anything older than the retention window beyond the last
+/// RECENT_EVENTS
+pub fn record(event: JournalEvent) {
+    let now = Instant::now();
+    with_journal(|journal| {
+        journal.entries.push_back((now, event));
+        journal.prune(now);
+    });
+}
+
+pub fn set_retention(seconds: u64) {
+    with_journal(|journal| journal.retention = Duration::from_secs(seconds));
+}
+
+/// Renders the retained events, timestamped relative to now (e.g. "-1.250s")
+pub fn dump() -> String {
+    let now = Instant::now();
+    with_journal(|journal| {
+        journal.prune(now);
+        let entries: Vec<_> = journal.within_retention(now).collect();
+        render(&format!("last {}s", journal.retention.as_secs()), &entries, now)
+    })
+}
+
+/// Renders the last RECENT_EVENTS events however long ago they happened
+pub fn dump_recent() -> String {
+    let now = Instant::now();
+    with_journal(|journal| {
+        let entries: Vec<_> = journal.recent().collect();
+        render(&format!("last {} events", RECENT_EVENTS), &entries, now)
+    })
+}
+
+fn render(span: &str, entries: &[&(Instant, JournalEvent)], now: Instant) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "A1314 Daemon event journal ({}, {} events)", span, entries.len());
+    for (at, event) in entries.iter().copied() {
+        let age = now.duration_since(*at).as_secs_f64();
+        let _ = match event {
+            JournalEvent::Report(bytes) => writeln!(out, "-{:.3}s REPORT {:?}", age, bytes),
+            JournalEvent::Hid { usage_page, usage, value } => {
+                writeln!(out, "-{:.3}s HID    {:04X}:{:04X} {}", age, usage_page, usage,
+                         if *value != 0 { "DOWN" } else { "UP" })
+            }
+            JournalEvent::Hook { vk, is_up, device, suppressed } => {
+                writeln!(out, "-{:.3}s HOOK   VK 0x{:02X} {} {}{}", age, vk,
+                         if *is_up { "UP" } else { "DOWN" },
+                         device.map_or("(other keyboard)".to_string(), |device| format!("from 0x{:X}", device.0)),
+                         if *suppressed { " (suppressed)" } else { "" })
+            }
+            JournalEvent::Action { usage_page, usage, action } => {
+                writeln!(out, "-{:.3}s ACTION {:04X}:{:04X} -> {}", age, usage_page, usage, action)
+            }
+        };
+    }
+    out
+}
+
+/// Writes the current journal to `path`
+pub fn dump_to_file(path: &Path) -> std::io::Result<()> {
+    std::fs::write(path, dump())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_past_the_retention_are_pruned_beyond_the_recent_ones() {
+        let now = Instant::now();
+        let mut journal = Journal { entries: VecDeque::new(), retention: Duration::from_secs(30) };
+        let event = |usage| JournalEvent::Hid { usage_page: 0x07, usage, value: 1 };
+        for _ in 0..RECENT_EVENTS {
+            journal.entries.push_back((now - Duration::from_secs(45), event(1)));
+        }
+        journal.entries.push_back((now - Duration::from_secs(31), event(2)));
+        journal.entries.push_back((now - Duration::from_secs(5), event(3)));
+        journal.entries.push_back((now, event(4)));
+        journal.prune(now);
+
+        // The old events go until only RECENT_EVENTS are left, however old those are
+        assert_eq!(journal.entries.len(), RECENT_EVENTS);
+        let within: Vec<u16> = journal.within_retention(now)
+            .map(|(_, event)| match event { JournalEvent::Hid { usage, .. } => *usage, _ => 0 })
+            .collect();
+        assert_eq!(within, vec![3, 4]);
+        assert_eq!(journal.recent().count(), RECENT_EVENTS);
+
+        journal.retention = Duration::from_secs(60);
+        assert_eq!(journal.within_retention(now).count(), RECENT_EVENTS);
+    }
+
+    #[test]
+    fn reports_are_kept_inline_up_to_the_byte_limit() {
+        let report = ReportBytes::new(&[0x01, 0x00, 0x04]);
+        assert_eq!(report.as_slice(), &[0x01, 0x00, 0x04]);
+        assert_eq!(format!("{:?}", report), "[01, 00, 04]");
+
+        let long = ReportBytes::new(&[0xAB; MAX_REPORT_BYTES + 3]);
+        assert_eq!(long.as_slice().len(), MAX_REPORT_BYTES);
+        assert!(format!("{:?}", long).ends_with(" (+3 bytes)"));
+    }
+}
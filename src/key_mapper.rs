@@ -1,10 +1,25 @@
 // --- START OF FILE src/key_mapper.rs ---
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::action_executor::{Action, execute_action};
-use crate::variable_maps::{STRING_TO_HID_KEY, STRING_TO_ACTION};
+use crate::action_executor::{Action, ActionSink, Win32Sink};
+use crate::config::{self, HoldTier, Mapping, same_tier};
+use crate::error::{A1314Error, ConfigProblem};
+use crate::foreground;
+use crate::hid_parser::{self, DeviceId};
+use crate::journal::{self, JournalEvent};
+use crate::launcher;
+use crate::migration;
+use crate::name_list;
+use crate::osd;
+use crate::report_profiles;
+use crate::scroll;
+use crate::settings::{self, Fallthrough, Settings};
+use crate::sound;
+use crate::templates;
+use crate::variable_maps;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct HidKey {
@@ -12,269 +27,1695 @@ pub struct HidKey {
     pub usage: u16,
 }
 
+/// Everything bound to one key within a layer: the tap action plus optional hold tiers
+#[derive(Debug, Clone, Default)]
+pub struct Binding {
+    pub tap: Option<Action>,
+    pub holds: Vec<(u64, Action)>,  // (minimum hold in ms, action), sorted by threshold
+    pub long_press: Option<(u64, Action)>,  // Fires as soon as the key has been held this long
+    pub passthrough: bool,  // PASSTHRU: the original key still reaches Windows
+    pub backend: Backend,   // BACKEND=: which input path carries it out
+}
+
+impl Binding {
+    /// Picks the action for a press that was held for `held_ms` milliseconds.
+    /// The longest tier whose threshold was reached wins, falling back to the tap action.
+    pub fn select(&self, held_ms: u64) -> Option<&Action> {
+        self.holds.iter()
+            .rev()
+            .find(|(threshold, _)| held_ms >= *threshold)
+            .map(|(_, action)| action)
+            .or(self.tap.as_ref())
+    }
+
+    /// Whether a press has to wait (for release or the long press threshold) before acting
+    fn is_deferred(&self) -> bool {
+        !self.holds.is_empty() || self.long_press.is_some()
+    }
+
+    /// Whether the original key is held back from Windows. Only the keyboard hook can do that,
+    /// so a BACKEND=raw binding never suppresses.
+    fn suppresses(&self) -> bool {
+        !self.passthrough && self.backend != Backend::Raw
+    }
+
+    fn set_hold(&mut self, threshold_ms: u64, action: Action) {
+        self.holds.retain(|(t, _)| *t != threshold_ms);
+        self.holds.push((threshold_ms, action));
+        self.holds.sort_by_key(|(t, _)| *t);
+    }
+}
+
 #[derive(Default)]
 struct KeyMaps {
-    normal: HashMap<HidKey, Action>,
-    fn_map: HashMap<HidKey, Action>,
-    shift_map: HashMap<HidKey, Action>,      // Map for SHIFT as modifier
-    eject_map: HashMap<HidKey, Action>,      // Map for EJECT as modifier
-    eject_fn_map: HashMap<HidKey, Action>,   // Map for EJECT+FN as modifier
+    layers: HashMap<Layer, HashMap<HidKey, Binding>>, // One map per modifier combination in use
+    modifier_only: HashSet<HidKey>,                   // MODIFIER KEY: never acts when pressed alone
+}
+
+impl KeyMaps {
+    fn layer_mut(&mut self, layer: Layer) -> &mut HashMap<HidKey, Binding> {
+        self.layers.entry(layer).or_default()
+    }
+
+    /// Applies one mapping line over whatever the key had in that layer and hold tier
+    fn bind(&mut self, mapping: Mapping, settings: &Settings) {
+        let action = match mapping.modifiers.windows_modifiers() {
+            modifiers if mapping.clear_modifiers && !modifiers.is_empty() => {
+                Action::LiftModifiers { modifiers, keep: Vec::new(), action: Box::new(mapping.action) }
+            }
+            _ => mapping.action,
+        };
+        let binding = self.layer_mut(mapping.modifiers).entry(mapping.key).or_default();
+        binding.passthrough |= mapping.passthrough;
+        if mapping.backend != Backend::Both {
+            binding.backend = mapping.backend;
+        }
+        match mapping.hold_tier {
+            None => binding.tap = Some(action),
+            Some(HoldTier::LongPress(threshold_ms)) => {
+                binding.long_press = Some((threshold_ms.unwrap_or(settings.long_press_ms), action));
+            }
+            Some(HoldTier::Long) => binding.set_hold(settings.hold_long_ms, action),
+            Some(HoldTier::Medium) => binding.set_hold(settings.hold_medium_ms, action),
+        }
+    }
+
+    /// Clears what the key does in one layer and hold tier, dropping the binding once nothing is
+    /// left of it. False if there was nothing to clear.
+    fn unbind(&mut self, layer: Layer, key: HidKey, tier: Option<HoldTier>, settings: &Settings) -> bool {
+        let Some(bindings) = self.layers.get_mut(&layer) else { return false };
+        let Some(binding) = bindings.get_mut(&key) else { return false };
+        let cleared = match tier {
+            None => binding.tap.take().is_some(),
+            Some(HoldTier::LongPress(_)) => binding.long_press.take().is_some(),
+            Some(hold) => {
+                let threshold = if hold == HoldTier::Long { settings.hold_long_ms } else { settings.hold_medium_ms };
+                let before = binding.holds.len();
+                binding.holds.retain(|(t, _)| *t != threshold);
+                binding.holds.len() != before
+            }
+        };
+        if binding.tap.is_none() && binding.holds.is_empty() && binding.long_press.is_none() {
+            bindings.remove(&key);
+        }
+        cleared
+    }
+
+    fn binding(&self, layer: Layer, key: HidKey) -> Option<&Binding> {
+        self.layers.get(&layer)?.get(&key)
+    }
+
+    fn len(&self) -> usize {
+        self.layers.values().map(HashMap::len).sum()
+    }
+
+    /// The held modifiers that select layers: Fn, Shift and Eject always; Ctrl, Alt and Gui only
+    /// once a mapping uses them, so holding them doesn't change what other mappings do
+    fn effective(&self, held: Layer) -> Layer {
+        let used = self.layers.keys().fold(Layer::FN.with(Layer::SHIFT).with(Layer::EJECT), |all, layer| all.with(*layer));
+        held.intersect(used)
+    }
+
+    /// The binding for `key` in the most specific layer whose modifiers are all held. With any
+    /// modifier held the normal layer doesn't count; the layer's fallthrough decides that.
+    fn lookup(&self, held: Layer, key: HidKey) -> Option<&Binding> {
+        self.binding(self.winning_layer(held, key)?, key)
+    }
+
+    /// The layer `lookup` takes the binding from
+    fn winning_layer(&self, held: Layer, key: HidKey) -> Option<Layer> {
+        if held == Layer::NORMAL {
+            return self.binding(Layer::NORMAL, key).map(|_| Layer::NORMAL);
+        }
+        self.layers.iter()
+            .filter(|(layer, bindings)| **layer != Layer::NORMAL && held.contains(**layer) && bindings.contains_key(&key))
+            .map(|(layer, _)| *layer)
+            .max_by_key(|layer| layer.specificity())
+    }
+}
+
+/// Modifier combination a mapping applies to, selected by its LHS prefixes (e.g. `FN+SHIFT+`)
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+pub struct Layer(u8);
+
+impl Layer {
+    // Higher bits win between equally specific layers: Eject > Shift > Fn > Gui > Alt > Ctrl
+    pub const NORMAL: Layer = Layer(0);
+    pub const CTRL: Layer = Layer(1 << 0);
+    pub const ALT: Layer = Layer(1 << 1);
+    pub const GUI: Layer = Layer(1 << 2);
+    pub const FN: Layer = Layer(1 << 3);
+    pub const SHIFT: Layer = Layer(1 << 4);
+    pub const EJECT: Layer = Layer(1 << 5);
+
+    // In the order they are written, e.g. "Eject+Fn"
+    const NAMES: [(Layer, &'static str); 6] = [
+        (Layer::EJECT, "Eject"), (Layer::SHIFT, "Shift"), (Layer::FN, "Fn"),
+        (Layer::CTRL, "Ctrl"), (Layer::ALT, "Alt"), (Layer::GUI, "Gui"),
+    ];
+
+    pub const fn with(self, other: Layer) -> Layer {
+        Layer(self.0 | other.0)
+    }
+
+    fn without(self, other: Layer) -> Layer {
+        Layer(self.0 & !other.0)
+    }
+
+    fn intersect(self, other: Layer) -> Layer {
+        Layer(self.0 & other.0)
+    }
+
+    /// Whether every modifier of `other` is in this layer
+    pub fn contains(self, other: Layer) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The modifiers Windows itself sees held, by the names the action executor sends them as
+    fn windows_modifiers(self) -> Vec<String> {
+        [(Layer::CTRL, "CTRL"), (Layer::SHIFT, "SHIFT"), (Layer::ALT, "ALT"), (Layer::GUI, "WIN")].iter()
+            .filter(|(modifier, _)| self.contains(*modifier))
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
+
+    /// More modifiers first, then the higher-priority ones
+    pub fn specificity(self) -> (u32, u8) {
+        (self.0.count_ones(), self.0)
+    }
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if *self == Layer::NORMAL {
+            return f.write_str("Normal");
+        }
+        let names: Vec<&str> = Layer::NAMES.iter()
+            .filter(|(modifier, _)| self.contains(*modifier))
+            .map(|(_, name)| *name)
+            .collect();
+        f.write_str(&names.join("+"))
+    }
+}
+
+impl std::fmt::Debug for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+/// Which input path acts on a key: raw HID reports, the low-level keyboard hook, or both (the
+/// default), whichever sees the press first. Consumer keys, Fn and Eject only come as reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Both,
+    Raw,
+    Hook,
+}
+
+impl Backend {
+    /// `both`, `raw` or `hook`, as written after BACKEND=
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Both => "both",
+            Backend::Raw => "raw",
+            Backend::Hook => "hook",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "both" => Some(Backend::Both),
+            "raw" => Some(Backend::Raw),
+            "hook" => Some(Backend::Hook),
+            _ => None,
+        }
+    }
+}
+
+/// Where a loaded mapping line came from, for `--explain` and `--export-config`
+#[derive(Debug, Clone)]
+pub struct MappingOrigin {
+    pub file: PathBuf,
+    pub line: usize,
+    pub text: String,
+    pub layer: Layer,
+    hold_tier: Option<HoldTier>,
+    pub key: HidKey,
+    pub passthrough: bool,
+    pub clear_modifiers: bool,
+    pub backend: Backend,
+    pub action: Action,
+}
+
+/// `[settings]` and `[vendor_reports]` entries and MODIFIER declarations as written, in load order
+#[derive(Debug, Clone, Default)]
+pub struct WrittenConfig {
+    pub settings: Vec<(String, String)>,
+    pub vendor_reports: Vec<(String, String)>,
+    pub modifiers: Vec<String>,
+}
+
+impl MappingOrigin {
+    /// `tap`, `hold`, `longhold` or `longpress`
+    pub fn tier_name(&self) -> &'static str {
+        match self.hold_tier {
+            None => "tap",
+            Some(HoldTier::Medium) => "hold",
+            Some(HoldTier::Long) => "longhold",
+            Some(HoldTier::LongPress(_)) => "longpress",
+        }
+    }
+
+    /// Whether this line replaces what `earlier` mapped: same key, layer and hold tier (LONGPRESS
+    /// thresholds aside, as a key has one long press)
+    fn replaces(&self, earlier: &MappingOrigin) -> bool {
+        self.key == earlier.key && self.layer == earlier.layer && same_tier(self.hold_tier, earlier.hold_tier)
+    }
+}
+
+/// A key with hold tiers or a long press that is currently down; the action is chosen on
+/// release, unless the long press fired first
+struct PendingHold {
+    binding: Binding,
+    pressed_at: Instant,
+    long_press_fired: bool,
+}
+
+impl PendingHold {
+    fn new(binding: Binding) -> Self {
+        Self { binding, pressed_at: Instant::now(), long_press_fired: false }
+    }
+
+    /// When the long press fires if the key is still down
+    fn long_press_deadline(&self) -> Option<Instant> {
+        match &self.binding.long_press {
+            Some((threshold_ms, _)) if !self.long_press_fired => {
+                Some(self.pressed_at + std::time::Duration::from_millis(*threshold_ms))
+            }
+            _ => None,
+        }
+    }
 }
 
 pub struct KeyMapper {
     maps: KeyMaps,
-    fn_down: bool,
-    shift_down: bool,    // Field to track SHIFT state (either left or right)
-    eject_down: bool,    // Field to track EJECT state
+    settings: Settings,
+    modifiers: HashMap<DeviceId, Layer>, // Modifiers held on each device
+    pending_holds: HashMap<HidKey, PendingHold>,
+    origins: Vec<MappingOrigin>,    // Every mapping line in load order, overridden ones included
+    written: WrittenConfig,
+    warnings: Vec<ConfigProblem>,   // Lines that loaded but probably aren't what was meant
+    shared_defaults: Option<PathBuf>,
+    sink: Box<dyn ActionSink>,
 }
 
+// File name given to mappings added over IPC with `map add`, which come from no file
+const LIVE_SOURCE: &str = "(live)";
+// File name given to the built-in mappings of `mac_navigation = on`
+const MAC_NAVIGATION_SOURCE: &str = "(macOS navigation)";
+
 // Define the HID key for EJECT (from variable_maps)
 const EJECT_HID_KEY: HidKey = HidKey { usage_page: 0x0C, usage: 0x00B8 };
 
 // Define the HID key for FN_STATE (from variable_maps)
 const FN_STATE_HID_KEY: HidKey = HidKey { usage_page: 0xFF00, usage: 0x0003 };
 
-// Define the HID keys for SHIFT (from variable_maps)
-const LEFT_SHIFT_HID_KEY: HidKey = HidKey { usage_page: 0x07, usage: 0x00E1 };
-const RIGHT_SHIFT_HID_KEY: HidKey = HidKey { usage_page: 0x07, usage: 0x00E5 };
+// Keys that select layers, and the modifier each one holds; left and right count the same
+const MODIFIER_KEYS: [(HidKey, Layer); 10] = [
+    (FN_STATE_HID_KEY, Layer::FN),
+    (EJECT_HID_KEY, Layer::EJECT),
+    (HidKey { usage_page: 0x07, usage: 0x00E0 }, Layer::CTRL),
+    (HidKey { usage_page: 0x07, usage: 0x00E1 }, Layer::SHIFT),
+    (HidKey { usage_page: 0x07, usage: 0x00E2 }, Layer::ALT),
+    (HidKey { usage_page: 0x07, usage: 0x00E3 }, Layer::GUI),
+    (HidKey { usage_page: 0x07, usage: 0x00E4 }, Layer::CTRL),
+    (HidKey { usage_page: 0x07, usage: 0x00E5 }, Layer::SHIFT),
+    (HidKey { usage_page: 0x07, usage: 0x00E6 }, Layer::ALT),
+    (HidKey { usage_page: 0x07, usage: 0x00E7 }, Layer::GUI),
+];
 
 impl KeyMapper {
     pub fn new() -> Self {
+        Self::with_sink(Box::new(Win32Sink))
+    }
+
+    /// A mapper whose actions go to `sink` instead of being sent to Windows
+    pub fn with_sink(sink: Box<dyn ActionSink>) -> Self {
         Self {
             maps: KeyMaps::default(),
-            fn_down: false,
-            shift_down: false,
-            eject_down: false,
+            settings: Settings::default(),
+            modifiers: HashMap::new(),
+            pending_holds: HashMap::new(),
+            origins: Vec::new(),
+            written: WrittenConfig::default(),
+            warnings: Vec::new(),
+            shared_defaults: None,
+            sink,
         }
     }
 
-    pub fn load_mapping_file<P: AsRef<Path>>(&mut self, path: P) {
+    /// Machine-wide mapping file loaded underneath the user's file; user lines win
+    pub fn set_shared_defaults(&mut self, path: Option<PathBuf>) {
+        self.shared_defaults = path;
+    }
+
+    /// Loads the mapping file (over any shared defaults). Fails if it couldn't be read or had
+    /// invalid lines; whatever lines were valid are still applied.
+    pub fn load_mapping_file<P: AsRef<Path>>(&mut self, path: P) -> crate::error::Result<()> {
         let path_ref = path.as_ref();
-        let text = match fs::read_to_string(path_ref) {
-            Ok(t) => t,
-            Err(e) => {
-                log::error!("Failed to read mapping file '{}': {}", path_ref.display(), e);
-                return;
+        match fs::read_to_string(path_ref) {
+            Ok(text) => self.load_mapping_text(path_ref, text),
+            Err(e) => Err(read_failure(path_ref, e)),
+        }
+    }
+
+    /// Loads `text`, already read from the mapping file at `path`, as `load_mapping_file` does
+    pub fn load_mapping_text(&mut self, path: &Path, text: String) -> crate::error::Result<()> {
+        let path_ref = path;
+        let text = migration::upgrade(path_ref, text).map_err(|e| read_failure(path_ref, e))?;
+
+        // Shared defaults come first so the same key in the user's file overrides them
+        let mut sources = Vec::new();
+        if let Some(shared) = &self.shared_defaults {
+            match fs::read_to_string(shared) {
+                // The shared file is usually read-only to users, so it is only upgraded in memory
+                Ok(shared_text) => sources.push((shared.clone(), migration::upgrade_in_memory(&shared_text))),
+                Err(e) => log::warn!("Failed to read shared mapping file '{}': {}", shared.display(), e),
             }
-        };
+        }
+        sources.push((path_ref.to_path_buf(), text));
 
-        log::info!("Loading mappings from: {}", path_ref.display());
+        let mut maps = KeyMaps::default();
+        let mut settings = Settings::default();
+        let mut origins = Vec::new();
+        let mut written = WrittenConfig::default();
 
-        let mut normal = HashMap::new();
-        let mut fn_map = HashMap::new();
-        let mut shift_map = HashMap::new();
-        let mut eject_map = HashMap::new();
-        let mut eject_fn_map = HashMap::new();
+        // Hold mappings are resolved once the [settings] thresholds are known
+        let mut hold_entries = Vec::new();
+        // Mappings of Fn, Shift or Eject by themselves, rejected if a MODIFIER line (wherever it
+        // is) declares that key modifier-only
+        let mut own_bindings = Vec::new();
 
         let mut line_count = 0;
-        let mut error_count = 0;
+        let mut problems = Vec::new();
+        let mut section = Section::Mappings;
 
-        for (line_no, line) in text.lines().enumerate() {
+        let lines = sources.iter().flat_map(|(source, text)| {
+            text.lines().enumerate().map(move |(line_no, line)| (source, line_no, line))
+        });
+
+        for (source, line_no, line) in lines {
+            // Each file starts in the [mappings] section; errors below refer to this file
+            if line_no == 0 {
+                log::info!("Loading mappings from: {}", source.display());
+                section = Section::Mappings;
+            }
+
+            // Columns in errors count from the start of the line as written
+            let indent = line.chars().take_while(|c| c.is_whitespace()).count();
             let line = line.trim();
-            if line.is_empty() || line.starts_with('#') {
+            if migration::version_header(line) == Some(0) {
+                log::error!("Invalid format version at line {}: {}", line_no + 1, line);
+                problems.push(problem(source, path_ref, line_no, "There is no format version 0, reading the file as version 1".to_string()));
+            }
+            if line.is_empty() || line.starts_with('#') || migration::version_header(line).is_some() {
                 continue;
             }
 
-            line_count += 1;
-
-            let parts: Vec<&str> = line.split('=').map(|s| s.trim()).collect();
-            if parts.len() != 2 {
-                log::error!("Invalid mapping syntax at line {}: {}", line_no + 1, line);
-                log::info!("  Expected format: KEY = ACTION");
-                error_count += 1;
+            // Section headers switch between global settings and key mappings
+            if line.starts_with('[') && line.ends_with(']') {
+                match line[1..line.len() - 1].trim().to_lowercase().as_str() {
+                    "settings" => section = Section::Settings,
+                    "vendor_reports" => section = Section::VendorReports,
+                    "mappings" => section = Section::Mappings,
+                    other => {
+                        log::error!("Unknown section at line {}: [{}]", line_no + 1, other);
+                        log::info!("  Valid sections: [settings], [vendor_reports], [mappings]");
+                        problems.push(problem(source, path_ref, line_no, format!("Unknown section [{}]", other)));
+                    }
+                }
                 continue;
             }
 
-            let lhs_str = parts[0];
-            let rhs_str = parts[1].to_string(); // Keep as String for Action parsing
-
-            // Check for SHIFT+ prefix first (can be LEFT_SHIFT+ or RIGHT_SHIFT+)
-            let (is_shift, rest_after_shift) = if let Some(rest) = lhs_str.strip_prefix("LEFT_SHIFT+") {
-                (true, rest.trim())
-            } else if let Some(rest) = lhs_str.strip_prefix("RIGHT_SHIFT+") {
-                (true, rest.trim())
-            } else {
-                (false, lhs_str)
-            };
-
-            let (is_eject, rest_after_eject) = if let Some(rest) = rest_after_shift.strip_prefix("EJECT+") {
-                (true, rest.trim())
-            } else {
-                (false, rest_after_shift)
-            };
-
-            let (is_fn, key_name) = if let Some(rest) = rest_after_eject.strip_prefix("FN+") {
-                (true, rest.trim())
-            } else {
-                (false, rest_after_eject)
-            };
+            line_count += 1;
 
-            // Lookup the HidKey from the hardcoded map
-            let hid_key = match STRING_TO_HID_KEY.get(key_name) {
-                Some(key) => *key,
-                None => {
-                    log::error!("Unknown key name at line {}: '{}'", line_no + 1, key_name);
-                    log::info!("  Check src/variable_maps.rs for valid key names");
-                    error_count += 1;
-                    continue;
+            if section == Section::Settings {
+                let parts: Vec<&str> = line.split('=').map(|s| s.trim()).collect();
+                if parts.len() != 2 {
+                    log::error!("Invalid setting syntax at line {}: {}", line_no + 1, line);
+                    log::info!("  Expected format: name = value");
+                    problems.push(problem(source, path_ref, line_no, format!("Invalid setting syntax: {}", line)));
+                } else if let Err(e) = settings.apply(parts[0], parts[1]) {
+                    log::error!("Invalid setting at line {}: {}", line_no + 1, e);
+                    problems.push(problem(source, path_ref, line_no, e));
+                } else {
+                    written.settings.push((parts[0].to_string(), parts[1].to_string()));
                 }
-            };
+                continue;
+            }
 
-            // Parse the Action for the RHS
-            let action = if let Some(rest) = rhs_str.strip_prefix("RUN(\"") {
-                if let Some(end) = rest.rfind("\")") {
-                    let path = &rest[..end];
-                    Action::Run(path.to_string())
+            if section == Section::VendorReports {
+                let parts: Vec<&str> = line.split('=').map(|s| s.trim()).collect();
+                if parts.len() != 2 {
+                    log::error!("Invalid vendor report syntax at line {}: {}", line_no + 1, line);
+                    log::info!("  Expected format: 0x11 = FN:0x10, EJECT:0x08");
+                    problems.push(problem(source, path_ref, line_no, format!("Invalid vendor report syntax: {}", line)));
+                } else if let Err(e) = settings.apply_vendor_report(parts[0], parts[1]) {
+                    log::error!("Invalid vendor report at line {}: {}", line_no + 1, e);
+                    problems.push(problem(source, path_ref, line_no, e));
                 } else {
-                    log::error!("Malformed RUN() syntax at line {}: '{}'", line_no + 1, rhs_str);
-                    log::info!("  Expected format: RUN(\"path/to/program.exe\")");
-                    error_count += 1;
-                    Action::KeyCombo(rhs_str) // Fallback
+                    written.vendor_reports.push((parts[0].to_string(), parts[1].to_string()));
                 }
-            } else if let Some(rest) = rhs_str.strip_prefix("APPCOMMAND(") {
-                if let Some(end) = rest.find(')') {
-                    let cmd_str = &rest[..end];
-                    if let Ok(cmd_val) = cmd_str.parse::<u32>() {
-                        Action::AppCommand(cmd_val)
-                    } else {
-                        log::error!("Invalid APPCOMMAND value at line {}: '{}'", line_no + 1, rhs_str);
-                        log::info!("  Expected a number, e.g., APPCOMMAND(46)");
-                        error_count += 1;
-                        Action::KeyCombo(rhs_str) // Fallback
+                continue;
+            }
+
+            if let Some(declaration) = config::parse_modifier_declaration(line) {
+                match declaration {
+                    Ok(key) => {
+                        maps.modifier_only.insert(key);
+                        written.modifiers.push(line.to_string());
+                    }
+                    Err(e) => {
+                        log::error!("Invalid MODIFIER declaration at line {}: {}", line_no + 1, e.message);
+                        log::info!("  {}", e.hint);
+                        problems.push(problem(source, path_ref, line_no, e.message));
                     }
-                } else {
-                    log::error!("Malformed APPCOMMAND syntax at line {}: '{}'", line_no + 1, rhs_str);
-                    log::info!("  Expected format: APPCOMMAND(number)");
-                    error_count += 1;
-                    Action::KeyCombo(rhs_str) // Fallback
                 }
+                continue;
             }
-            else {
-                // For direct string actions like "MUTE", "WIN+TAB", look them up
-                match STRING_TO_ACTION.get(rhs_str.as_str()) {
-                    Some(action) => action.clone(),
-                    None => {
-                        // Fallback to KeyCombo if not a recognized explicit action
-                        Action::KeyCombo(rhs_str) 
-                    }
+
+            let mapping = match config::parse_mapping(line, line_no + 1) {
+                Ok(mapping) => mapping,
+                Err(e) => {
+                    let column = e.column + indent;
+                    log::error!("Invalid mapping at line {}, column {}: {}", line_no + 1, column, e.message);
+                    log::info!("  {}", e.hint);
+                    log::debug!("  ({:?})", e.kind);
+                    problems.push(ConfigProblem { column, ..problem(source, path_ref, line_no, e.message) });
+                    continue;
                 }
             };
 
-            if is_eject && is_fn {
-                eject_fn_map.insert(hid_key, action);
-            } else if is_eject {
-                eject_map.insert(hid_key, action);
-            } else if is_shift {
-                shift_map.insert(hid_key, action);
-            } else if is_fn {
-                fn_map.insert(hid_key, action);
-            } else {
-                normal.insert(hid_key, action);
+            if is_modifier_key(mapping.key) {
+                own_bindings.push((mapping.key, problem(source, path_ref, line_no,
+                    format!("'{}' is declared MODIFIER, so it can't have an action of its own", line))));
+            }
+            origins.push(MappingOrigin {
+                file: source.clone(),
+                line: mapping.line,
+                text: line.to_string(),
+                layer: mapping.modifiers,
+                hold_tier: mapping.hold_tier,
+                key: mapping.key,
+                passthrough: mapping.passthrough,
+                clear_modifiers: mapping.clear_modifiers,
+                backend: mapping.backend,
+                action: mapping.action.clone(),
+            });
+            match mapping.hold_tier {
+                Some(_) => hold_entries.push(mapping),
+                None => maps.bind(mapping, &settings),
             }
         }
 
-        self.maps = KeyMaps { normal, fn_map, shift_map, eject_map, eject_fn_map };
-        
-        log::info!("Loaded {} mappings from {} lines", 
-                   self.maps.normal.len() + self.maps.fn_map.len() + 
-                   self.maps.shift_map.len() + self.maps.eject_map.len() + 
-                   self.maps.eject_fn_map.len(),
-                   line_count);
-        log::info!("  Normal: {}, Fn: {}, Shift: {}, Eject: {}, Eject+Fn: {}", 
-                   self.maps.normal.len(), 
-                   self.maps.fn_map.len(), 
-                   self.maps.shift_map.len(),
-                   self.maps.eject_map.len(), 
-                   self.maps.eject_fn_map.len());
+        settings.validate();
+
+        // The macOS navigation preset goes under every file, and only what they leave unmapped
+        // takes effect
+        if settings.mac_navigation {
+            let preset = mac_navigation_mappings();
+            for (mapping, origin) in &preset {
+                if !origins.iter().any(|later| later.replaces(origin)) {
+                    maps.bind(mapping.clone(), &settings);
+                }
+            }
+            origins.splice(0..0, preset.into_iter().map(|(_, origin)| origin));
+        }
+
+        own_bindings.retain(|(key, _)| maps.modifier_only.contains(key));
+        for (key, own_binding) in own_bindings {
+            log::error!("Ignoring mapping at line {}: {}", own_binding.line, own_binding.message);
+            problems.push(own_binding);
+            hold_entries.retain(|mapping| mapping.key != key);
+            origins.retain(|origin| origin.key != key);
+            for bindings in maps.layers.values_mut() {
+                bindings.remove(&key);
+            }
+        }
+
+        for mapping in hold_entries {
+            maps.bind(mapping, &settings);
+        }
+
+        self.maps = maps;
+        self.warnings = duplicate_mappings(&origins, path_ref);
+        self.warnings.extend(unreachable_mappings(&origins, &settings, path_ref));
+        self.origins = origins;
+        self.written = written;
+        journal::set_retention(settings.journal_seconds);
+        hid_parser::set_ghost_handling(settings.ghost_policy, settings.max_chord_keys);
+        hid_parser::set_vendor_reports(&settings.vendor_reports);
+        report_profiles::set_allowed_vendors(settings.vendor_ids.as_deref());
+        foreground::set_game_mode(settings.game_mode);
+        scroll::configure(&settings.natural_scroll);
+        foreground::set_app_lists(&settings.apps);
+        launcher::set(settings.launcher.clone());
+        sound::set_base_dir(path_ref.parent());
+        settings::publish(&settings);
+        self.settings = settings;
+        self.pending_holds.clear();
         
-        if error_count > 0 {
-            log::warn!("{} errors encountered while loading mappings", error_count);
+        log::info!("Loaded {} mappings from {} lines", self.maps.len(), line_count);
+        let counts: Vec<String> = self.mapping_counts().iter().map(|(layer, n)| format!("{}: {}", layer, n)).collect();
+        log::info!("  {}", counts.join(", "));
+
+        for warning in &self.warnings {
+            log::warn!("Line {}: {}", warning.line, warning.message);
+        }
+        if !self.warnings.is_empty() {
+            log::warn!("{} warnings while loading mappings", self.warnings.len());
+        }
+        if !problems.is_empty() {
+            log::warn!("{} errors encountered while loading mappings", problems.len());
         }
         
-        if self.maps.normal.is_empty() && self.maps.fn_map.is_empty() && 
-           self.maps.shift_map.is_empty() && self.maps.eject_map.is_empty() && 
-           self.maps.eject_fn_map.is_empty() {
+        if self.maps.len() == 0 {
             log::warn!("No valid mappings loaded! Check your mapping file syntax");
         }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(A1314Error::Config { path: path_ref.to_path_buf(), problems })
+        }
     }
 
-    pub fn handle_hid_event(&mut self, usage_page: u16, usage: u16, value: i32) {
-        let key = HidKey { usage_page, usage };
+    /// The mapping lines from the last load that are in effect, i.e. not replaced by a later line
+    /// for the same key, layer and hold tier
+    pub fn effective_mappings(&self) -> Vec<&MappingOrigin> {
+        self.origins.iter().enumerate()
+            .filter(|(i, origin)| !self.origins[i + 1..].iter().any(|later| later.replaces(origin)))
+            .map(|(_, origin)| origin)
+            .collect()
+    }
 
-        // Update Fn state
-        if key == FN_STATE_HID_KEY {
-            self.fn_down = value != 0;
-            log::trace!("Fn key: {}", if self.fn_down { "DOWN" } else { "UP" });
-            return;
+    /// Settings, vendor reports and MODIFIER declarations from the last load, as written
+    pub fn written_config(&self) -> &WrittenConfig {
+        &self.written
+    }
+
+    /// Lines from the last load that were accepted but look like mistakes, e.g. a key mapped
+    /// twice in the same layer of one file
+    pub fn warnings(&self) -> &[ConfigProblem] {
+        &self.warnings
+    }
+
+    /// Number of keys bound in each layer, for status reporting: the normal layer, then every
+    /// modifier combination with mappings
+    pub fn mapping_counts(&self) -> Vec<(String, usize)> {
+        let mut layers: Vec<(&Layer, usize)> = self.maps.layers.iter()
+            .filter(|(layer, bindings)| **layer == Layer::NORMAL || !bindings.is_empty())
+            .map(|(layer, bindings)| (layer, bindings.len()))
+            .collect();
+        if !self.maps.layers.contains_key(&Layer::NORMAL) {
+            layers.push((&Layer::NORMAL, 0));
         }
+        layers.sort_by_key(|(layer, _)| layer.specificity());
+        layers.into_iter().map(|(layer, n)| (layer.to_string(), n)).collect()
+    }
 
-        // Update SHIFT state (either left or right)
-        if key == LEFT_SHIFT_HID_KEY || key == RIGHT_SHIFT_HID_KEY {
-            self.shift_down = value != 0;
-            log::trace!("Shift key: {}", if self.shift_down { "DOWN" } else { "UP" });
-            return;
+    /// Mapping lines from the last successful load, in file order, with their parsed actions
+    pub fn mappings(&self) -> Vec<(String, Action)> {
+        self.origins.iter().map(|origin| (origin.text.clone(), origin.action.clone())).collect()
+    }
+
+    /// Applies one mapping line on top of the loaded file, as if it was its last line. The change
+    /// only lives in memory, so the next reload of the file drops it unless it was saved there too.
+    pub fn add_mapping(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        let mapping = config::parse_mapping(line, 0).map_err(|e| e.message)?;
+        if is_modifier_key(mapping.key) && self.maps.modifier_only.contains(&mapping.key) {
+            return Err(format!("'{}' is declared MODIFIER, so it can't have an action of its own", line));
         }
+        self.origins.push(MappingOrigin {
+            file: PathBuf::from(LIVE_SOURCE),
+            line: 0,
+            text: line.to_string(),
+            layer: mapping.modifiers,
+            hold_tier: mapping.hold_tier,
+            key: mapping.key,
+            passthrough: mapping.passthrough,
+            clear_modifiers: mapping.clear_modifiers,
+            backend: mapping.backend,
+            action: mapping.action.clone(),
+        });
+        self.maps.bind(mapping, &self.settings);
+        self.pending_holds.clear();
+        log::info!("Mapped live: {}", line);
+        Ok(())
+    }
 
-        // Update EJECT state
-        if key == EJECT_HID_KEY {
-            self.eject_down = value != 0;
-            log::trace!("Eject key: {}", if self.eject_down { "DOWN" } else { "UP" });
-            return;
+    /// Removes what `lhs` (the part of a mapping line before '=') does, as `add_mapping` adds it:
+    /// in memory only. The key goes back to its fallthrough for that layer and hold tier.
+    pub fn remove_mapping(&mut self, lhs: &str) -> Result<(), String> {
+        let lhs = lhs.trim();
+        let target = config::parse_lhs(lhs).map_err(|e| e.message)?;
+        if !self.maps.unbind(target.modifiers, target.key, target.hold_tier, &self.settings) {
+            return Err(format!("Nothing is mapped to {}", lhs));
         }
+        self.origins.retain(|origin| {
+            !(origin.key == target.key && origin.layer == target.modifiers && same_tier(origin.hold_tier, target.hold_tier))
+        });
+        self.pending_holds.clear();
+        log::info!("Unmapped live: {}", lhs);
+        Ok(())
+    }
 
-        // Only act on key-down for triggering actions
-        if value == 0 {
-            return;
+    /// Explains what `key_name` does under each modifier state: the mapping lines for it in every
+    /// layer, which of them are overridden by a later line for the same key (e.g. a user file
+    /// over the shared defaults), and which layer or fallthrough decides each state
+    pub fn explain(&self, key_name: &str) -> Result<String, String> {
+        use std::fmt::Write;
+
+        let key_name = key_name.trim().to_ascii_uppercase();
+        let key = config::parse_key_name(&key_name).map_err(|e| e.message)?;
+        let mut out = String::new();
+        let _ = writeln!(out, "{} (usage page 0x{:02X}, usage 0x{:02X})", key_name, key.usage_page, key.usage);
+        if self.maps.modifier_only.contains(&key) {
+            let _ = writeln!(out, "Declared MODIFIER: it never acts when pressed alone");
         }
 
-        // Determine which map to use based on modifier states
-        // Priority: EJECT+FN > EJECT > SHIFT > FN > NORMAL
-        let action = if self.eject_down && self.fn_down {
-            self.maps.eject_fn_map.get(&key)
-        } else if self.eject_down {
-            self.maps.eject_map.get(&key)
-        } else if self.shift_down {
-            self.maps.shift_map.get(&key)
-        } else if self.fn_down {
-            self.maps.fn_map.get(&key)
-        } else {
-            self.maps.normal.get(&key)
-        };
+        // Lines for the key by layer and hold tier; the last line of each group is the one in effect
+        let origins: Vec<&MappingOrigin> = self.origins.iter().filter(|origin| origin.key == key).collect();
+        let mut layers: Vec<Layer> = origins.iter().map(|origin| origin.layer).collect();
+        layers.sort_by_key(|layer| layer.specificity());
+        layers.dedup();
+
+        let _ = writeln!(out, "\nMappings (the most specific layer whose modifiers are all held wins):");
+        if origins.is_empty() {
+            let _ = writeln!(out, "  none");
+        }
+        for layer in &layers {
+            for (i, origin) in origins.iter().enumerate().filter(|(_, origin)| origin.layer == *layer) {
+                let overridden_by = origins[i + 1..].iter().find(|later| later.replaces(origin));
+                let _ = write!(out, "  {:<16} {}  ({}:{})", layer.to_string(), origin.text, origin.file.display(), origin.line);
+                match overridden_by {
+                    Some(later) if later.file == origin.file => {
+                        let _ = writeln!(out, "  DUPLICATE, overridden by line {}", later.line);
+                    }
+                    Some(later) => {
+                        let _ = writeln!(out, "  SHADOWED by {}:{}", later.file.display(), later.line);
+                    }
+                    None => out.push('\n'),
+                }
+            }
+        }
+
+        // Every modifier state that can change the outcome: the layers in use, plus the modifiers
+        // that always select a layer
+        let mut states: Vec<Layer> = [Layer::NORMAL, Layer::FN, Layer::SHIFT, Layer::EJECT, Layer::EJECT.with(Layer::FN)]
+            .into_iter()
+            .chain(self.maps.layers.keys().copied())
+            .collect();
+        states.sort_by_key(|layer| layer.specificity());
+        states.dedup();
+
+        let _ = writeln!(out, "\nWhen pressed with:");
+        for state in states {
+            let outcome = match self.maps.winning_layer(state, key) {
+                Some(layer) => format!("{} mapping", layer),
+                None if state == Layer::NORMAL => "sent as-is (unmapped)".to_string(),
+                None => {
+                    let (setting, fallthrough) = self.fallthrough(state);
+                    match fallthrough {
+                        Fallthrough::None => format!("blocked ({} = none)", setting),
+                        Fallthrough::System => format!("sent as-is ({} = system)", setting),
+                        Fallthrough::Normal if self.maps.binding(Layer::NORMAL, key).is_some() => {
+                            format!("Normal mapping ({} = normal)", setting)
+                        }
+                        Fallthrough::Normal => format!("sent as-is ({} = normal, no Normal mapping)", setting),
+                    }
+                }
+            };
+            let _ = writeln!(out, "  {:<16} {}", state.to_string(), outcome);
+        }
+        Ok(out)
+    }
+
+    /// Handles a key event from a raw HID report of `device`; modifiers only affect later keys from
+    /// the same device. For a key-down, returns whether the keyboard hook should suppress the
+    /// original key, or None if the key is BACKEND=hook and left for the hook to carry out.
+    pub fn handle_hid_event(&mut self, device: DeviceId, usage_page: u16, usage: u16, value: i32) -> Option<bool> {
+        let key = HidKey { usage_page, usage };
+        let mods = self.modifiers.get(&device).copied().unwrap_or_default();
+
+        if let Some(modifier) = modifier_of(key) {
+            self.update_modifier_hold(key, value, mods);
+            let held = self.modifiers.entry(device).or_default();
+            *held = if value != 0 { held.with(modifier) } else { held.without(modifier) };
+            log::trace!("{} key: {}", modifier, if value != 0 { "DOWN" } else { "UP" });
+            self.layer_feedback(device, mods);
+            return Some(false);
+        }
 
-        if let Some(action) = action {
-            log::debug!("Executing action for key {:04X}:{:04X} (modifiers: Fn={}, Shift={}, Eject={}): {:?}",
-                       usage_page, usage, self.fn_down, self.shift_down, self.eject_down, action);
-            execute_action(action);
+        // Key-up only matters for keys with a pending hold decision
+        if value == 0 {
+            self.release_hold(key);
+            return Some(false);
         }
+
+        self.press(key, mods, Backend::Raw)
     }
 
-    /// Tries to trigger a mapping and returns true if an action was executed (should suppress original key)
-    pub fn try_trigger_mapping(&mut self, usage_page: u16, usage: u16, value: i32) -> bool {
+    /// Tries to trigger a mapping for a keyboard hook event and returns true if the original key
+    /// should be suppressed, i.e. the key has a binding that isn't PASSTHRU. `device` is the
+    /// keyboard the event was attributed to, whose held modifiers count.
+    pub fn try_trigger_mapping(&mut self, device: DeviceId, usage_page: u16, usage: u16, value: i32) -> bool {
         if value == 0 {
             return false; // Only trigger and suppress on key-down
         }
 
         let key = HidKey { usage_page, usage };
+        let mods = self.modifiers.get(&device).copied().unwrap_or(Layer::NORMAL);
+        let suppress = self.press(key, mods, Backend::Hook).unwrap_or(false);
+        if suppress {
+            log::debug!("Triggered mapping for {:04X}:{:04X}, suppressing original", usage_page, usage);
+        }
+        suppress
+    }
+
+    /// Handles a button or wheel event from the mouse hook and returns true if the original
+    /// should be suppressed. Modifiers held on any keyboard count, and a layer that blocks its
+    /// unmapped keys still lets unmapped mouse buttons through.
+    pub fn mouse_event(&mut self, key: HidKey, pressed: bool) -> bool {
+        if !pressed {
+            self.release_hold(key);
+            return false;
+        }
+        let mods = self.held_anywhere();
+        let held = self.maps.effective(mods);
+        if self.maps.lookup(held, key).is_none() && self.fallthrough(held).1 == Fallthrough::None {
+            return false;
+        }
+        self.press(key, mods, Backend::Hook).unwrap_or(false)
+    }
 
-        // Determine map based on current modifiers
-        let action = if self.eject_down && self.fn_down {
-            self.maps.eject_fn_map.get(&key)
-        } else if self.eject_down {
-            self.maps.eject_map.get(&key)
-        } else if self.shift_down {
-            self.maps.shift_map.get(&key)
-        } else if self.fn_down {
-            self.maps.fn_map.get(&key)
+    /// Handles a gesture spotted in trackpad raw input, with the modifiers held on any keyboard.
+    /// A gesture is over as soon as it is recognized, so it is pressed and released at once.
+    pub fn gesture(&mut self, key: HidKey) {
+        let mods = self.held_anywhere();
+        self.press(key, mods, Backend::Raw);
+        self.release_hold(key);
+    }
+
+    /// Whether any mapping is for a mouse button or wheel, which the mouse hook is only installed for
+    pub fn uses_mouse(&self) -> bool {
+        self.origins.iter().any(|origin| variable_maps::is_mouse_key(origin.key))
+    }
+
+    /// Whether any mapping is for a trackpad gesture, which trackpad input is only read for
+    pub fn uses_gestures(&self) -> bool {
+        self.origins.iter().any(|origin| variable_maps::is_gesture_key(origin.key))
+    }
+
+    /// The modifiers held on all keyboards together
+    fn held_anywhere(&self) -> Layer {
+        self.modifiers.values().fold(Layer::NORMAL, |all, held| all.with(*held))
+    }
+
+    /// The `*_fallthrough` setting (name and value) for keys unmapped under the held modifiers.
+    /// Eject+Fn, Eject, Shift and Fn have one, in that order; Ctrl, Alt and Gui on their own let
+    /// keys through.
+    fn fallthrough(&self, held: Layer) -> (&'static str, Fallthrough) {
+        if held.contains(Layer::EJECT.with(Layer::FN)) {
+            ("eject_fn_fallthrough", self.settings.eject_fn_fallthrough)
+        } else if held.contains(Layer::EJECT) {
+            ("eject_fallthrough", self.settings.eject_fallthrough)
+        } else if held.contains(Layer::SHIFT) {
+            ("shift_fallthrough", self.settings.shift_fallthrough)
+        } else if held.contains(Layer::FN) {
+            ("fn_fallthrough", self.settings.fn_fallthrough)
         } else {
-            self.maps.normal.get(&key)
+            ("", Fallthrough::System)
+        }
+    }
+
+    /// Handles a key-down for a non-modifier key arriving through the `via` path. Returns true if
+    /// the key has a binding in the active layer that suppresses the original key, or None if
+    /// the binding belongs to the other path, which then acts on the press instead.
+    fn press(&mut self, key: HidKey, mods: Layer, via: Backend) -> Option<bool> {
+        // Any other key pressed while a modifier is held means the modifier is being
+        // used as a modifier, so its own hold/tap binding must not fire on release
+        self.pending_holds.retain(|pending_key, _| !is_modifier_key(*pending_key));
+
+        // Auto-repeat of a key whose hold is already being timed
+        if let Some(pending) = self.pending_holds.get(&key) {
+            return Some(pending.binding.suppresses());
+        }
+        if osd::showing_keystrokes() {
+            let name = name_list::key_name(key);
+            let combo = if mods == Layer::NORMAL { name.clone() } else { format!("{}+{}", mods, name) };
+            osd::keystroke(&name, combo);
+        }
+
+        let held = self.maps.effective(mods);
+        let binding = match self.maps.lookup(held, key) {
+            Some(binding) => binding.clone(),
+            None if held == Layer::NORMAL => return Some(false),
+            None => match self.fallthrough(held).1 {
+                Fallthrough::System => return Some(false),
+                Fallthrough::None => {
+                    log::trace!("Key {:04X}:{:04X} unmapped in the {} layer, blocking", key.usage_page, key.usage, held);
+                    return Some(true);
+                }
+                Fallthrough::Normal => match self.maps.binding(Layer::NORMAL, key) {
+                    Some(binding) => binding.clone(),
+                    None => return Some(false),
+                },
+            },
         };
+        if binding.backend != Backend::Both && binding.backend != via {
+            log::trace!("Key {:04X}:{:04X} is BACKEND={:?}, not acting on it here", key.usage_page, key.usage, binding.backend);
+            return None;
+        }
 
-        if let Some(action) = action {
-            log::debug!("Triggered mapping for {:04X}:{:04X}, suppressing original", usage_page, usage);
-            execute_action(action);
-            true
+        if !binding.is_deferred() {
+            if let Some(action) = &binding.tap {
+                log::debug!("Executing action for key {:04X}:{:04X} (modifiers: {}): {:?}",
+                           key.usage_page, key.usage, held, action);
+                self.run_action(key, action);
+            }
         } else {
-            false
+            // Defer until release (or the long press) so the hold duration can select the action
+            log::trace!("Key {:04X}:{:04X} has hold actions, deferring", key.usage_page, key.usage);
+            let suppress = binding.suppresses();
+            self.pending_holds.insert(key, PendingHold::new(binding));
+            return Some(suppress);
+        }
+        Some(binding.suppresses())
+    }
+
+    /// Starts or resolves a hold decision for a modifier key that also has its own binding
+    fn update_modifier_hold(&mut self, key: HidKey, value: i32, mods: Layer) {
+        if value == 0 {
+            self.release_hold(key);
+            return;
+        }
+
+        // Look up the modifier in the layer selected by the *other* modifiers
+        if let Some(binding) = self.maps.lookup(self.maps.effective(mods), key).cloned() {
+            self.pending_holds.entry(key)
+                .or_insert_with(|| PendingHold::new(binding));
+        }
+    }
+
+    /// Plays layer_sound from [settings] if a modifier change on `device` switched the layer
+    fn layer_feedback(&mut self, device: DeviceId, before: Layer) {
+        let after = self.modifiers.get(&device).copied().unwrap_or_default();
+        if self.maps.effective(before) == self.maps.effective(after) {
+            return;
+        }
+        if let Some(sound) = self.settings.layer_sound.clone() {
+            if let Err(e) = self.sink.execute(&Action::Sound(sound)) {
+                log::error!("{}", e);
+            }
+        }
+    }
+
+    /// Executes an action and notes it in the event journal
+    fn run_action(&mut self, key: HidKey, action: &Action) {
+        // A key sent without its mapping's modifiers keeps the Shift the user holds, so a
+        // remapped Cmd+Shift+Left still selects to Home
+        let shifted = match action {
+            Action::LiftModifiers { modifiers, keep, action }
+                if self.held_anywhere().contains(Layer::SHIFT) && !modifiers.iter().chain(keep).any(|m| m == "SHIFT") => {
+                Some(Action::LiftModifiers { modifiers: modifiers.clone(), keep: vec!["SHIFT".to_string()], action: action.clone() })
+            }
+            _ => None,
+        };
+        let action = shifted.as_ref().unwrap_or(action);
+        journal::record(JournalEvent::Action {
+            usage_page: key.usage_page,
+            usage: key.usage,
+            action: format!("{:?}", action),
+        });
+        if osd::showing_keystrokes() {
+            osd::keystroke_action(&name_list::key_name(key), &name_list::describe(action));
+        }
+        if let Err(e) = self.sink.execute(action) {
+            log::error!("{}", e);
+        }
+    }
+
+    /// When the next long press fires, for the caller's timer; None if no long press is pending
+    pub fn next_long_press(&self) -> Option<Instant> {
+        self.pending_holds.values().filter_map(PendingHold::long_press_deadline).min()
+    }
+
+    /// Fires the long press of every key that has been held past its threshold by `now`.
+    /// The key's tap and hold actions are then skipped on release.
+    pub fn fire_long_presses(&mut self, now: Instant) {
+        let due: Vec<(HidKey, Action)> = self.pending_holds.iter_mut()
+            .filter(|(_, pending)| pending.long_press_deadline().is_some_and(|deadline| deadline <= now))
+            .filter_map(|(key, pending)| {
+                pending.long_press_fired = true;
+                pending.binding.long_press.as_ref().map(|(_, action)| (*key, action.clone()))
+            })
+            .collect();
+
+        for (key, action) in due {
+            log::debug!("Key {:04X}:{:04X} long press: {:?}", key.usage_page, key.usage, action);
+            self.run_action(key, &action);
+        }
+    }
+
+    /// Resolves a pending hold on key release, executing the tier matching the hold duration
+    fn release_hold(&mut self, key: HidKey) {
+        if let Some(pending) = self.pending_holds.remove(&key) {
+            if pending.long_press_fired {
+                return;
+            }
+            // A long press whose timer hasn't run yet still wins over the tap
+            let overdue = pending.long_press_deadline().is_some_and(|deadline| deadline <= Instant::now());
+            if let Some((_, action)) = pending.binding.long_press.as_ref().filter(|_| overdue) {
+                log::debug!("Key {:04X}:{:04X} long press: {:?}", key.usage_page, key.usage, action);
+                self.run_action(key, action);
+                return;
+            }
+            let held_ms = pending.pressed_at.elapsed().as_millis() as u64;
+            // A modifier held alone for a while was meant as a layer key after all (the other key
+            // just never came), unless its hold tiers say what long holds do
+            let tap_limit = self.settings.modifier_tap_ms;
+            if is_modifier_key(key) && pending.binding.holds.is_empty() && tap_limit > 0 && held_ms > tap_limit {
+                log::debug!("Key {:04X}:{:04X} held alone for {}ms, too long for a tap", key.usage_page, key.usage, held_ms);
+                return;
+            }
+            if let Some(action) = pending.binding.select(held_ms) {
+                log::debug!("Key {:04X}:{:04X} held for {}ms: {:?}", key.usage_page, key.usage, held_ms, action);
+                self.run_action(key, action);
+            }
+        }
+    }
+}
+
+/// The mappings of `mac_navigation = on`, each letting go of the modifiers it is for
+fn mac_navigation_mappings() -> Vec<(Mapping, MappingOrigin)> {
+    templates::MAC_NAVIGATION.lines().enumerate()
+        .filter_map(|(line_no, line)| {
+            let mapping = config::parse_mapping(line, line_no + 1).ok()?;
+            let origin = MappingOrigin {
+                file: PathBuf::from(MAC_NAVIGATION_SOURCE),
+                line: mapping.line,
+                text: line.to_string(),
+                layer: mapping.modifiers,
+                hold_tier: mapping.hold_tier,
+                key: mapping.key,
+                passthrough: mapping.passthrough,
+                clear_modifiers: mapping.clear_modifiers,
+                backend: mapping.backend,
+                action: mapping.action.clone(),
+            };
+            Some((mapping, origin))
+        })
+        .collect()
+}
+
+/// Section of the mapping file a line belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Section {
+    Mappings,
+    Settings,
+    VendorReports,
+}
+
+fn read_failure(path: &Path, e: std::io::Error) -> A1314Error {
+    log::error!("Failed to read mapping file '{}': {}", path.display(), e);
+    A1314Error::Config {
+        path: path.to_path_buf(),
+        problems: vec![ConfigProblem { line: 0, column: 0, message: format!("Failed to read file: {}", e) }],
+    }
+}
+
+/// A rejected line; lines from the shared defaults say so, since `path` is the user's file
+fn problem(source: &Path, path: &Path, line_no: usize, message: String) -> ConfigProblem {
+    let message = if source == path {
+        message
+    } else {
+        format!("{} (in {})", message, source.display())
+    };
+    ConfigProblem { line: line_no + 1, column: 0, message }
+}
+
+/// Lines mapping the same key in the same layer as an earlier line of the same file, which they
+/// silently replace. A user file replacing the shared defaults is what it's for, so that's fine.
+fn duplicate_mappings(origins: &[MappingOrigin], path: &Path) -> Vec<ConfigProblem> {
+    origins.iter().enumerate()
+        .filter_map(|(i, origin)| {
+            let earlier = origins[..i].iter().rev().find(|earlier| earlier.file == origin.file && origin.replaces(earlier))?;
+            Some(problem(&origin.file, path, origin.line - 1, format!(
+                "'{}' maps the same key as line {} ('{}'), which it replaces", origin.text, earlier.line, earlier.text)))
+        })
+        .collect()
+}
+
+/// Lines that load but can never fire: a layer key mapped in its own layer when no other key
+/// selects that layer (`FN+FN_STATE`), and hold tiers the key's long press always beats
+fn unreachable_mappings(origins: &[MappingOrigin], settings: &Settings, path: &Path) -> Vec<ConfigProblem> {
+    let in_effect = |i: usize| !origins[i + 1..].iter().any(|later| later.replaces(&origins[i]));
+    let mut warnings = Vec::new();
+    for (_, origin) in origins.iter().enumerate().filter(|(i, _)| in_effect(*i)) {
+        let mut warn = |reason: String| warnings.push(problem(&origin.file, path, origin.line - 1,
+            format!("'{}' can never fire: {}", origin.text, reason)));
+
+        if let Some(modifier) = modifier_of(origin.key).filter(|modifier| origin.layer.contains(*modifier)) {
+            let other_key = MODIFIER_KEYS.iter().any(|(key, m)| *key != origin.key && *m == modifier);
+            if !other_key {
+                warn(format!("the key itself selects the {} layer, so it is never pressed with it held", modifier));
+            }
+        }
+
+        if variable_maps::is_mouse_key(origin.key) {
+            if origin.backend == Backend::Raw {
+                warn("it is BACKEND=raw, and mouse buttons only come through the mouse hook".to_string());
+            }
+        } else if variable_maps::is_gesture_key(origin.key) {
+            if origin.backend == Backend::Hook {
+                warn("it is BACKEND=hook, and gestures only come through trackpad raw input".to_string());
+            }
+        } else if origin.backend == Backend::Hook && !variable_maps::hook_sees(origin.key) {
+            warn("it is BACKEND=hook, and the keyboard hook never sees this key".to_string());
+        }
+
+        let hold_ms = match origin.hold_tier {
+            Some(HoldTier::Medium) => settings.hold_medium_ms,
+            Some(HoldTier::Long) => settings.hold_long_ms,
+            _ => continue,
+        };
+        let long_press_ms = origins.iter().enumerate()
+            .filter(|(j, later)| in_effect(*j) && later.key == origin.key && later.layer == origin.layer)
+            .find_map(|(_, later)| match later.hold_tier {
+                Some(HoldTier::LongPress(threshold_ms)) => Some(threshold_ms.unwrap_or(settings.long_press_ms)),
+                _ => None,
+            });
+        if let Some(long_press_ms) = long_press_ms.filter(|long_press_ms| *long_press_ms <= hold_ms) {
+            warn(format!("the key's long press fires after {}ms, before this {}ms hold is reached", long_press_ms, hold_ms));
+        }
+    }
+    warnings
+}
+
+/// The modifier `key` holds, if it selects layers
+fn modifier_of(key: HidKey) -> Option<Layer> {
+    MODIFIER_KEYS.iter().find(|(modifier_key, _)| *modifier_key == key).map(|(_, modifier)| *modifier)
+}
+
+pub fn is_modifier_key(key: HidKey) -> bool {
+    modifier_of(key).is_some()
+}
+
+/// A mapper with `text` loaded as the mapping file `a1314_<name>.txt` (never written), its
+/// recorded actions and the result of the load, for tests here and in the modules that read a
+/// loaded mapper
+#[cfg(test)]
+pub fn load_for_test(name: &str, text: &str) -> (KeyMapper, crate::action_executor::RecordingSink, crate::error::Result<()>) {
+    let sink = crate::action_executor::RecordingSink::default();
+    let mut mapper = KeyMapper::with_sink(Box::new(sink.clone()));
+    let result = reload_for_test(&mut mapper, name, text);
+    (mapper, sink, result)
+}
+
+/// Loads `text` into `mapper` as `load_for_test` does. A load publishes the parser's vendor
+/// reports and ghost handling, so it holds the parser's test lock to stay out of parser tests.
+#[cfg(test)]
+pub fn reload_for_test(mapper: &mut KeyMapper, name: &str, text: &str) -> crate::error::Result<()> {
+    let _parser = hid_parser::lock_for_test();
+    let path = std::env::temp_dir().join(format!("a1314_{}.txt", name));
+    mapper.load_mapping_text(&path, text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::action_executor::RecordingSink;
+    use crate::foreground::{AppLists, AppMode};
+    use crate::launcher::Launcher;
+    use crate::magnifier::Zoom;
+    use crate::settings::NaturalScroll;
+    use crate::templates::Template;
+    use crate::variable_maps::STRING_TO_HID_KEY;
+
+    const F1: (u16, u16) = (0x07, 0x003A);
+    const KEY_1: (u16, u16) = (0x07, 0x001E);
+    const FN: (u16, u16) = (0xFF00, 0x0003);
+    const EJECT: (u16, u16) = (0x0C, 0x00B8);
+    const DEVICE: DeviceId = DeviceId(1);
+
+    fn default_mapper() -> (KeyMapper, RecordingSink) {
+        let sink = RecordingSink::default();
+        let mut mapper = KeyMapper::with_sink(Box::new(sink.clone()));
+        let _parser = hid_parser::lock_for_test();
+        mapper.load_mapping_file(concat!(env!("CARGO_MANIFEST_DIR"), "/A1314_mapping.txt"))
+            .expect("default mapping file should load cleanly");
+        assert!(mapper.warnings().is_empty(), "default mapping file has warnings: {:?}", mapper.warnings());
+        (mapper, sink)
+    }
+
+    /// A mapper loaded from `text`, which must load cleanly
+    fn mapper_from(name: &str, text: &str) -> (KeyMapper, RecordingSink) {
+        let (mapper, sink, result) = load_for_test(name, text);
+        result.expect("test mappings should load cleanly");
+        (mapper, sink)
+    }
+
+    fn tap(mapper: &mut KeyMapper, key: (u16, u16)) {
+        mapper.handle_hid_event(DEVICE, key.0, key.1, 1);
+        mapper.handle_hid_event(DEVICE, key.0, key.1, 0);
+    }
+
+    #[test]
+    fn f1_yields_brightness_down() {
+        let (mut mapper, sink) = default_mapper();
+        tap(&mut mapper, F1);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("BRIGHTNESS_DOWN".to_string())]);
+    }
+
+    #[test]
+    fn fn_f1_yields_f1() {
+        let (mut mapper, sink) = default_mapper();
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, F1);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F1".to_string())]);
+    }
+
+    #[test]
+    fn eject_fn_layer_wins_over_eject() {
+        let (mut mapper, sink) = default_mapper();
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, KEY_1);
+        assert_eq!(sink.take(), vec![Action::Run("powershell.exe".to_string())]);
+    }
+
+    #[test]
+    fn modifiers_alone_execute_nothing() {
+        let (mut mapper, sink) = default_mapper();
+        tap(&mut mapper, FN);
+        assert!(sink.take().is_empty());
+        // except Eject, which the default file maps to the launcher when tapped on its own
+        tap(&mut mapper, EJECT);
+        assert_eq!(sink.take(), vec![Action::Launcher]);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        tap(&mut mapper, KEY_1);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+        assert_eq!(sink.take(), vec![Action::Run("calc.exe".to_string())]);
+    }
+
+    #[test]
+    fn fn_on_one_device_does_not_shift_another() {
+        let (mut mapper, sink) = default_mapper();
+        mapper.handle_hid_event(DeviceId(2), FN.0, FN.1, 1);
+        tap(&mut mapper, F1);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("BRIGHTNESS_DOWN".to_string())]);
+
+        // A hook event goes by the modifiers of the keyboard it was attributed to
+        assert!(mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("BRIGHTNESS_DOWN".to_string())]);
+        assert!(mapper.try_trigger_mapping(DeviceId(2), F1.0, F1.1, 1));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F1".to_string())]);
+    }
+
+    #[test]
+    fn long_press_fires_at_the_threshold_instead_of_the_tap() {
+        let (mut mapper, sink) = mapper_from("long_press", "EJECT = DELETE\nLONGPRESS(EJECT, 800ms) = WIN+L\n");
+
+        tap(&mut mapper, EJECT);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("DELETE".to_string())]);
+
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        let deadline = mapper.next_long_press().expect("long press should be pending");
+        mapper.fire_long_presses(deadline - std::time::Duration::from_millis(1));
+        assert_eq!(sink.take(), Vec::new());
+        mapper.fire_long_presses(deadline);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("WIN+L".to_string())]);
+        assert!(mapper.next_long_press().is_none());
+
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+        assert_eq!(sink.take(), Vec::new());
+    }
+
+    #[test]
+    fn long_press_threshold_defaults_to_the_setting() {
+        let (mut mapper, _) = mapper_from("long_press_default",
+                                          "[settings]\nlong_press_ms = 1200\n[mappings]\nlongpress(key_1) = F1\n");
+        mapper.handle_hid_event(DEVICE, KEY_1.0, KEY_1.1, 1);
+        let pending = mapper.pending_holds.values().next().unwrap();
+        assert_eq!(pending.long_press_deadline(), Some(pending.pressed_at + std::time::Duration::from_millis(1200)));
+
+        assert!(config::parse_mapping("HOLD+LONGPRESS(F1) = F2", 0).is_err());
+        assert!(config::parse_mapping("LONGPRESS(F1, soon) = F2", 0).is_err());
+    }
+
+    #[test]
+    fn backend_picks_the_path_that_acts_on_a_key() {
+        let (mut mapper, sink) = mapper_from("backend", "VERSION=2\nBACKEND=hook F1 = F2\nbackend=RAW F3 = F4\nF5 = F6\n");
+        assert_eq!(mapper.handle_hid_event(DEVICE, F1.0, F1.1, 1), None);
+        assert!(sink.take().is_empty());
+        assert!(mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F2".to_string())]);
+
+        // Raw-only keys act on the report, and the hook lets the original key through
+        assert_eq!(mapper.handle_hid_event(DEVICE, 0x07, 0x3C, 1), Some(false));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F4".to_string())]);
+        assert!(!mapper.try_trigger_mapping(DEVICE, 0x07, 0x3C, 1));
+        assert!(sink.take().is_empty());
+        assert_eq!(mapper.handle_hid_event(DEVICE, 0x07, 0x3E, 1), Some(true));
+
+        assert_eq!(config::split_mapping_line("BACKEND=hook F1 = IF(app=code.exe, F2)"), Some(("BACKEND=hook F1 ", " IF(app=code.exe, F2)")));
+        assert!(config::parse_mapping("BACKEND=driver F1 = F2", 0).is_err());
+        let (mapper, _) = mapper_from("backend_lint", "VERSION=2\nBACKEND=hook MUTE = F2\n");
+        assert_eq!(mapper.warnings().len(), 1);
+    }
+
+    #[test]
+    fn mouse_buttons_map_with_keyboard_modifiers() {
+        let (mut mapper, sink) = mapper_from("mouse",
+            "VERSION=2\n[settings]\nfn_fallthrough = none\n[mappings]\nMOUSE4 = ALT+LEFT\nFN+WHEEL_UP = VOLUME_UP\nFN+KEY_1 = F1\n");
+        assert!(mapper.uses_mouse());
+        let back = HidKey { usage_page: 0x09, usage: 4 };
+        assert!(mapper.mouse_event(back, true));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("ALT+LEFT_ARROW".to_string())]);
+        assert!(!mapper.mouse_event(back, false));
+
+        // Fn held on the keyboard selects the layer, whose fallthrough doesn't block clicks
+        let wheel_up = STRING_TO_HID_KEY["WHEEL_UP"];
+        assert!(!mapper.mouse_event(wheel_up, true));
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        assert!(mapper.mouse_event(wheel_up, true));
+        assert_eq!(sink.take(), vec![Action::KeyCombo("VOLUME_UP".to_string())]);
+        assert!(!mapper.mouse_event(HidKey { usage_page: 0x09, usage: 1 }, true));
+
+        assert_eq!(variable_maps::mouse_key(0x020B, 0x0001_0000), Some((back, true)));
+        assert_eq!(variable_maps::mouse_key(0x020A, 0xFF88_0000), Some((STRING_TO_HID_KEY["WHEEL_DOWN"], true)));
+        let (mapper, _) = mapper_from("mouse_none", "VERSION=2\nF1 = F2\n");
+        assert!(!mapper.uses_mouse());
+
+        // Trackpad gestures come through raw input and fire on their own
+        let (mut mapper, sink) = mapper_from("gesture", "VERSION=2\nGESTURE_SWIPE_LEFT = MEDIA_PREV\n");
+        assert!(mapper.uses_gestures() && !mapper.uses_mouse());
+        mapper.gesture(STRING_TO_HID_KEY["GESTURE_SWIPE_LEFT"]);
+        assert_eq!(sink.take().len(), 1);
+        mapper.gesture(STRING_TO_HID_KEY["GESTURE_SWIPE_RIGHT"]);
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn passthru_mappings_fire_without_suppressing_the_key() {
+        let (mut mapper, sink) = mapper_from("passthru",
+                                             "PASSTHRU FN+KEY_1 = RUN(\"calc.exe\")\nF1 = F2\npassthru HOLD+F3 = F4\n");
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        assert!(!mapper.try_trigger_mapping(DEVICE, KEY_1.0, KEY_1.1, 1));
+        assert_eq!(sink.take(), vec![Action::Run("calc.exe".to_string())]);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+
+        assert!(mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1));
+        assert!(!mapper.try_trigger_mapping(DEVICE, 0x07, 0x3C, 1));
+        assert!(!mapper.try_trigger_mapping(DEVICE, 0x07, 0x3C, 1)); // Auto-repeat while the hold is timed
+
+        // Raw HID events give the same verdict, which the hook reuses instead of pressing again
+        assert_eq!(mapper.handle_hid_event(DEVICE, F1.0, F1.1, 1), Some(true));
+        assert_eq!(mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1), Some(false));
+        assert_eq!(mapper.handle_hid_event(DEVICE, KEY_1.0, KEY_1.1, 1), Some(false));
+
+        assert!(config::parse_mapping("PASSTHRUKEY_1 = F1", 0).is_err());
+    }
+
+    #[test]
+    fn clearmods_lets_go_of_the_held_modifiers() {
+        let (mut mapper, sink) = mapper_from("clearmods",
+                                             "CLEARMODS CTRL+KEY_J = LEFT_ARROW\nkeepmods PASSTHRU ALT+KEY_J = RUN(\"calc.exe\")\n");
+        let (ctrl, alt, j) = ((0x07, 0x00E0), (0x07, 0x00E2), (0x07, 0x000D));
+        mapper.handle_hid_event(DEVICE, ctrl.0, ctrl.1, 1);
+        tap(&mut mapper, j);
+        assert_eq!(sink.take(), vec![Action::LiftModifiers {
+            modifiers: vec!["CTRL".to_string()],
+            keep: Vec::new(),
+            action: Box::new(Action::KeyCombo("LEFT_ARROW".to_string())),
+        }]);
+        mapper.handle_hid_event(DEVICE, ctrl.0, ctrl.1, 0);
+
+        mapper.handle_hid_event(DEVICE, alt.0, alt.1, 1);
+        tap(&mut mapper, j);
+        assert_eq!(sink.take(), vec![Action::Run("calc.exe".to_string())]);
+
+        assert!(config::parse_mapping("CLEARMODS KEEPMODS F1 = F2", 0).is_err());
+        assert!(config::parse_mapping("CLEARMODSF1 = F2", 0).is_err());
+    }
+
+    #[test]
+    fn none_mappings_swallow_the_key() {
+        let (mut mapper, sink) = mapper_from("none", "CAPS_LOCK = NONE\nFN+F1 = disable\n");
+        assert!(mapper.try_trigger_mapping(DEVICE, 0x07, 0x39, 1));
+        assert_eq!(sink.take(), vec![Action::Disable]);
+
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        assert!(mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1));
+        assert_eq!(sink.take(), vec![Action::Disable]);
+    }
+
+    #[test]
+    fn fallthrough_decides_unmapped_keys_in_a_layer() {
+        let (mut mapper, sink) = mapper_from("fallthrough", "[settings]\nfn_fallthrough = none\neject_fallthrough = normal\n\
+                                                             [mappings]\nF1 = F2\nFN+KEY_1 = F3\n");
+        let key_2 = (0x07, 0x001F);
+
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        assert!(mapper.try_trigger_mapping(DEVICE, KEY_1.0, KEY_1.1, 1));
+        assert!(mapper.try_trigger_mapping(DEVICE, key_2.0, key_2.1, 1)); // Blocked
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F3".to_string())]);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        assert!(mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1)); // The normal mapping
+        assert!(!mapper.try_trigger_mapping(DEVICE, key_2.0, key_2.1, 1)); // Sent as-is
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F2".to_string())]);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+
+        // Shift keeps the default
+        mapper.handle_hid_event(DEVICE, 0x07, 0xE1, 1);
+        assert!(!mapper.try_trigger_mapping(DEVICE, F1.0, F1.1, 1));
+        assert!(sink.take().is_empty());
+    }
+
+    #[test]
+    fn app_lists_decide_where_remapping_pauses() {
+        let (mapper, _) = mapper_from("app_lists", "[settings]\nexclude_apps = [\"vmware.exe\", \"mstsc.exe\"]\n\
+                                                   passthrough_apps = mstsc\n");
+        let apps = &mapper.settings.apps;
+        assert_eq!(apps.exclude, vec!["vmware.exe".to_string(), "mstsc.exe".to_string()]);
+        assert_eq!(apps.mode(Some("VMWARE.EXE")), AppMode::Excluded);
+        assert_eq!(apps.mode(Some("mstsc.exe")), AppMode::Passthrough);
+        assert_eq!(apps.mode(Some("notepad.exe")), AppMode::Remap);
+        assert_eq!(apps.mode(None), AppMode::Remap);
+
+        let include = AppLists { include: vec!["code".to_string()], ..AppLists::new() };
+        assert_eq!(include.mode(Some("Code.exe")), AppMode::Remap);
+        assert_eq!(include.mode(Some("notepad.exe")), AppMode::Excluded);
+        assert_eq!(include.mode(None), AppMode::Excluded);
+    }
+
+    #[test]
+    fn launcher_backend_comes_from_settings() {
+        let (mapper, _) = mapper_from("launcher", "[settings]\nlauncher = RUN(\"%LOCALAPPDATA%\\Flow\\Flow.exe\")\n\
+                                                  [mappings]\nEJECT = LAUNCHER\n");
+        assert_eq!(mapper.settings.launcher, Launcher::Run("%LOCALAPPDATA%\\Flow\\Flow.exe".to_string()));
+        assert_eq!(mapper.settings.launcher.action(), Action::Run("%LOCALAPPDATA%\\Flow\\Flow.exe".to_string()));
+        assert_eq!(Launcher::parse(" PowerToys "), Some(Launcher::PowerToys));
+        assert_eq!(Launcher::Search.action(), Action::KeyCombo("WIN+S".to_string()));
+        assert_eq!(Launcher::parse("RUN(\"\")"), None);
+        assert_eq!(Launcher::parse("alfred"), None);
+        assert_eq!(config::parse_mapping("EJECT = launcher", 0).unwrap().action, Action::Launcher);
+    }
+
+    #[test]
+    fn logging_and_repeat_settings_parse() {
+        let (mapper, _) = mapper_from("repeat", "[settings]\nlog_level = DEBUG\nrepeat_delay_ms = off\n\
+                                                repeat_interval_ms = 50ms\n[mappings]\nF1 = MUTE\n");
+        assert_eq!(mapper.settings.log_level, Some(log::LevelFilter::Debug));
+        assert_eq!(mapper.settings.repeat_delay_ms, None);
+        assert_eq!(mapper.settings.repeat_interval_ms, 50);
+        assert_eq!(Settings::default().repeat_delay_ms, Some(0));
+        assert!(Settings::default().apply("log_level", "loud").is_err());
+    }
+
+    #[test]
+    fn natural_scroll_names_mice_by_id() {
+        let (mapper, _) = mapper_from("scroll", "[settings]\nnatural_scroll = 0x05AC:030D, 046d\n\
+                                                [mappings]\nEJECT+KEY_S = NATURAL_SCROLL_TOGGLE\n");
+        let NaturalScroll::Devices(devices) = &mapper.settings.natural_scroll else {
+            panic!("expected a device list, got {:?}", mapper.settings.natural_scroll);
+        };
+        assert!(scroll::matches_device(devices, 0x05AC, 0x030D));
+        assert!(!scroll::matches_device(devices, 0x05AC, 0x0269));
+        assert!(scroll::matches_device(devices, 0x046D, 0xC52B));
+        assert_eq!(NaturalScroll::parse(" On "), Ok(NaturalScroll::All));
+        assert!(NaturalScroll::parse("apple").is_err());
+        assert_eq!(config::parse_mapping("EJECT+KEY_S = natural_scroll_toggle", 0).unwrap().action, Action::NaturalScrollToggle);
+    }
+
+    #[test]
+    fn fn_layer_drives_the_magnifier() {
+        let (mut mapper, sink) = default_mapper();
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, (0x07, 0x002E));
+        tap(&mut mapper, (0x07, 0x002D));
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+        assert_eq!(sink.take(), vec![Action::Magnifier(Zoom::In), Action::Magnifier(Zoom::Out)]);
+    }
+
+    #[test]
+    fn sounds_confirm_actions_and_layer_changes() {
+        let action = |line: &str| config::parse_mapping(line, 0).map(|mapping| mapping.action);
+        assert_eq!(action("EJECT+KEY_S = SUSPEND(5m) sound(\"sounds\\click.wav\")").unwrap(), Action::WithSound {
+            sound: "sounds\\click.wav".to_string(),
+            action: Box::new(Action::Suspend(Duration::from_secs(300))),
+        });
+        assert_eq!(action("F5 = SOUND(\"ding.wav\")").unwrap(), Action::Sound("ding.wav".to_string()));
+        assert!(action("F5 = SOUND(ding.wav)").is_err());
+
+        let (mut mapper, sink) = mapper_from("layer_sound", "[settings]\nlayer_sound = \"layer.wav\"\n\
+                                                     [mappings]\nFN+KEY_1 = F1\n");
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, KEY_1);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+        assert_eq!(sink.take(), vec![
+            Action::Sound("layer.wav".to_string()),
+            Action::KeyCombo("F1".to_string()),
+            Action::Sound("layer.wav".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn modifier_only_keys_never_act_alone() {
+        let (mut mapper, sink, result) = load_for_test("modifier_only",
+            "VERSION=2\nEJECT = LAUNCHER\nHOLD+EJECT = WIN+L\nEJECT+KEY_1 = F1\nMODIFIER EJECT\nMODIFIER KEY_A\n");
+        let Err(A1314Error::Config { problems, .. }) = result else { panic!("expected config problems") };
+        assert_eq!(problems.iter().map(|problem| problem.line).collect::<Vec<_>>(), vec![6, 2, 3]);
+
+        tap(&mut mapper, EJECT);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        tap(&mut mapper, KEY_1);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("F1".to_string())]);
+    }
+
+    #[test]
+    fn rejected_mappings_are_reported_with_their_column() {
+        let (mapper, _, result) = load_for_test("column", "VERSION=2\nF1 = MUTE\n  F2 = CTRL+ALT+DELTE\n");
+        let Err(error @ A1314Error::Config { .. }) = result else { panic!("expected config problems") };
+        assert!(error.to_string().contains("; line 3, column 17: Unknown action or key name 'DELTE'"), "{}", error);
+        assert_eq!(mapper.mappings().len(), 1);
+    }
+
+    #[test]
+    fn format_version_zero_is_reported_and_read_as_version_one() {
+        let (mapper, _, result) = load_for_test("version_zero", "VERSION=0\nF1 = MUTE\n");
+        let Err(A1314Error::Config { problems, .. }) = result else { panic!("expected config problems") };
+        assert_eq!(problems.iter().map(|problem| problem.line).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(mapper.mappings().len(), 1);
+    }
+
+    #[test]
+    fn the_longest_hold_tier_reached_wins() {
+        let (mut mapper, sink) = mapper_from("hold_tiers",
+            "[settings]\nhold_medium_ms = 500\nhold_long_ms = 2000\n[mappings]\n\
+             F1 = DELETE\nHOLD+F1 = WIN+L\nLONGHOLD+F1 = VOLUME_UP\nLONGHOLD+KEY_1 = MUTE\n");
+        let mut hold = |key: (u16, u16), held_ms: u64| {
+            mapper.handle_hid_event(DEVICE, key.0, key.1, 1);
+            mapper.pending_holds.get_mut(&HidKey { usage_page: key.0, usage: key.1 }).unwrap().pressed_at -= Duration::from_millis(held_ms);
+            mapper.handle_hid_event(DEVICE, key.0, key.1, 0);
+            sink.take()
+        };
+        let combo = |combo: &str| vec![Action::KeyCombo(combo.to_string())];
+
+        assert_eq!(hold(F1, 120), combo("DELETE"));
+        assert_eq!(hold(F1, 500), combo("WIN+L"));
+        assert_eq!(hold(F1, 1999), combo("WIN+L"));
+        assert_eq!(hold(F1, 2500), combo("VOLUME_UP"));
+
+        // Only a long tier: a medium-length hold does nothing, as KEY_1 has no tap action
+        assert_eq!(hold(KEY_1, 800), Vec::new());
+        assert_eq!(hold(KEY_1, 2100), combo("MUTE"));
+    }
+
+    #[test]
+    fn modifiers_tap_only_when_released_quickly() {
+        let (mut mapper, sink) = default_mapper();
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        mapper.pending_holds.get_mut(&EJECT_HID_KEY).unwrap().pressed_at -= Duration::from_millis(600);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+        assert!(sink.take().is_empty());
+
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 1);
+        mapper.pending_holds.get_mut(&EJECT_HID_KEY).unwrap().pressed_at -= Duration::from_millis(400);
+        mapper.handle_hid_event(DEVICE, EJECT.0, EJECT.1, 0);
+        assert_eq!(sink.take(), vec![Action::Launcher]);
+    }
+
+    #[test]
+    fn most_specific_modifier_stack_wins() {
+        let (mut mapper, sink) = mapper_from("stacks", "FN+F11 = VOLUME_DOWN\nSHIFT+FN+F11 = MUTE\nCTRL+KEY_1 = F1\n");
+        let f11 = (0x07, 0x0044);
+        let (shift, ctrl, alt) = ((0x07, 0x00E1), (0x07, 0x00E0), (0x07, 0x00E2));
+
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, f11);
+        mapper.handle_hid_event(DEVICE, shift.0, shift.1, 1);
+        tap(&mut mapper, f11);
+        // Alt has no mappings of its own, so holding it changes nothing
+        mapper.handle_hid_event(DEVICE, alt.0, alt.1, 1);
+        tap(&mut mapper, f11);
+        for key in [alt, shift, FN] {
+            mapper.handle_hid_event(DEVICE, key.0, key.1, 0);
+        }
+        mapper.handle_hid_event(DEVICE, ctrl.0, ctrl.1, 1);
+        tap(&mut mapper, KEY_1);
+        assert_eq!(sink.take(), vec![
+            Action::KeyCombo("VOLUME_DOWN".to_string()),
+            Action::KeyCombo("MUTE".to_string()),
+            Action::KeyCombo("MUTE".to_string()),
+            Action::KeyCombo("F1".to_string()),
+        ]);
+        assert_eq!(config::parse_mapping("FN+EJECT+KEY_1 = F1", 0).unwrap().modifiers, Layer::EJECT.with(Layer::FN));
+        assert_eq!(Layer::EJECT.with(Layer::FN).with(Layer::GUI).to_string(), "Eject+Fn+Gui");
+    }
+
+    #[test]
+    fn explain_shows_each_modifier_state_and_overridden_lines() {
+        let (mapper, _) = mapper_from("explain", "VERSION=2\n\
+            [settings]\n\
+            eject_fallthrough = none\n\
+            [mappings]\n\
+            F1 = MUTE\n\
+            FN+F1 = VOLUME_UP\n\
+            FN+F1 = VOLUME_DOWN\n\
+            SHIFT+FN+F1 = F1\n");
+        let explanation = mapper.explain("f1").unwrap();
+        assert!(explanation.contains("FN+F1 = VOLUME_UP"));
+        assert!(explanation.contains("DUPLICATE, overridden by line 7"));
+        assert_eq!(mapper.warnings().len(), 1);
+        assert_eq!(mapper.warnings()[0].line, 7);
+        assert!(mapper.warnings()[0].message.contains("line 6"));
+        let outcome = |state: &str| explanation.lines()
+            .skip_while(|line| !line.starts_with("When pressed with"))
+            .find(|line| line.trim_start().starts_with(&format!("{} ", state)))
+            .map(|line| line.split_whitespace().skip(1).collect::<Vec<_>>().join(" "));
+        assert_eq!(outcome("Normal").as_deref(), Some("Normal mapping"));
+        assert_eq!(outcome("Shift+Fn").as_deref(), Some("Shift+Fn mapping"));
+        assert_eq!(outcome("Eject").as_deref(), Some("blocked (eject_fallthrough = none)"));
+        assert_eq!(outcome("Eject+Fn").as_deref(), Some("Fn mapping"));
+        assert!(mapper.explain("NOT_A_KEY").is_err());
+    }
+
+    #[test]
+    fn live_mappings_can_be_added_and_removed() {
+        let (mut mapper, sink) = mapper_from("live", "VERSION=2\nF1 = MUTE\n");
+        mapper.add_mapping("F1 = VOLUME_UP").unwrap();
+        mapper.add_mapping("LONGHOLD+F1 = VOLUME_DOWN").unwrap();
+        tap(&mut mapper, F1);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("VOLUME_UP".to_string())]);
+        assert!(mapper.explain("F1").unwrap().contains("F1 = VOLUME_UP  ((live):0)"));
+
+        // Removing the hold tier leaves the tap, and removing that leaves nothing to remove
+        mapper.remove_mapping("LONGHOLD+F1").unwrap();
+        assert!(mapper.maps.binding(Layer::NORMAL, HidKey { usage_page: F1.0, usage: F1.1 }).unwrap().holds.is_empty());
+        mapper.remove_mapping("F1").unwrap();
+        assert!(mapper.remove_mapping("F1").is_err());
+        assert!(mapper.mappings().is_empty());
+        assert!(mapper.add_mapping("F1 = NOT_AN_ACTION").is_err());
+
+        assert!(config::line_maps("FN+F1 = MUTE", "fn+F1"));
+        assert!(!config::line_maps("HOLD+FN+F1 = MUTE", "FN+F1"));
+    }
+
+    #[test]
+    fn lint_flags_mappings_that_can_never_fire() {
+        let (mapper, _) = mapper_from("lint", "VERSION=2\n\
+            FN+FN_STATE = MUTE\n\
+            SHIFT+LEFT_SHIFT = MUTE\n\
+            HOLD+F1 = MUTE\n\
+            LONGHOLD+F1 = VOLUME_UP\n\
+            LONGPRESS(F1, 1000ms) = VOLUME_DOWN\n");
+        let lines: Vec<usize> = mapper.warnings().iter().map(|warning| warning.line).collect();
+        assert_eq!(lines, vec![2, 5]);
+        assert!(mapper.warnings()[0].message.contains("Fn layer"));
+        assert!(mapper.warnings()[1].message.contains("1000ms, before this 2000ms hold"));
+    }
+
+    #[test]
+    fn every_template_loads_cleanly() {
+        for template in Template::ALL {
+            let (mapper, _) = mapper_from(template.name(), &template.render());
+            assert!(mapper.warnings().is_empty(), "{}: {:?}", template.name(), mapper.warnings());
         }
+
+        let (mut mapper, sink) = mapper_from("fkeys", &Template::WindowsFKeys.render());
+        tap(&mut mapper, F1);
+        assert!(sink.take().is_empty());
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, F1);
+        assert_eq!(sink.take(), vec![Action::KeyCombo("BRIGHTNESS_DOWN".to_string())]);
+        assert_eq!(Template::parse(" ISO "), Some(Template::Iso));
+    }
+
+    #[test]
+    fn mac_navigation_lets_go_of_cmd_and_option() {
+        let (mut mapper, sink) = mapper_from("mac_navigation", "[settings]\nmac_navigation = on\n[mappings]\nCMD+RIGHT_ARROW = F5\n");
+        let (gui, alt, backspace) = ((0x07, 0x00E3), (0x07, 0x00E2), (0x07, 0x002A));
+        let (left, right) = ((0x07, 0x0050), (0x07, 0x004F));
+        let lifted = |modifier: &str, combo: &str| Action::LiftModifiers {
+            modifiers: vec![modifier.to_string()],
+            keep: Vec::new(),
+            action: Box::new(Action::KeyCombo(combo.to_string())),
+        };
+
+        mapper.handle_hid_event(DEVICE, gui.0, gui.1, 1);
+        tap(&mut mapper, left);
+        tap(&mut mapper, right);
+        mapper.handle_hid_event(DEVICE, gui.0, gui.1, 0);
+        mapper.handle_hid_event(DEVICE, alt.0, alt.1, 1);
+        tap(&mut mapper, left);
+        mapper.handle_hid_event(DEVICE, alt.0, alt.1, 0);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 1);
+        tap(&mut mapper, backspace);
+        assert_eq!(sink.take(), vec![
+            lifted("WIN", "HOME"),
+            Action::KeyCombo("F5".to_string()),
+            lifted("ALT", "CTRL+LEFT_ARROW"),
+            Action::KeyCombo("DELETE".to_string()),
+        ]);
+        assert!(mapper.explain("LEFT_ARROW").unwrap().contains(MAC_NAVIGATION_SOURCE));
+
+        // Shift held as well goes out with the key, and stays down
+        let shift = (0x07, 0x00E1);
+        mapper.handle_hid_event(DEVICE, FN.0, FN.1, 0);
+        mapper.handle_hid_event(DEVICE, shift.0, shift.1, 1);
+        mapper.handle_hid_event(DEVICE, gui.0, gui.1, 1);
+        tap(&mut mapper, left);
+        assert_eq!(sink.take(), vec![Action::LiftModifiers {
+            modifiers: vec!["WIN".to_string()],
+            keep: vec!["SHIFT".to_string()],
+            action: Box::new(Action::KeyCombo("HOME".to_string())),
+        }]);
+
+        // Off by default
+        let (mut mapper, sink) = mapper_from("mac_navigation_off", "F1 = MUTE\n");
+        mapper.handle_hid_event(DEVICE, gui.0, gui.1, 1);
+        tap(&mut mapper, left);
+        assert!(sink.take().is_empty());
     }
+
 }
@@ -0,0 +1,100 @@
+// --- START OF FILE src/latency.rs ---
+// Time from WM_INPUT receipt to the end of each action it triggers, summarized as p50/p95
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Samples kept per kind of action; the percentiles cover these
+const MAX_SAMPLES: usize = 1000;
+
+// A summary is logged every this many actions of a kind
+const LOG_EVERY: u64 = 100;
+
+thread_local! {
+    // When the WM_INPUT being dispatched arrived; None outside raw input handling
+    static INPUT_RECEIVED: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+#[derive(Default)]
+struct Samples {
+    recent: VecDeque<Duration>,
+    total: u64,
+}
+
+// Latency samples by action kind (Action::kind)
+static SAMPLES: Mutex<Option<HashMap<&'static str, Samples>>> = Mutex::new(None);
+
+/// Marks the start (Some) or end (None) of handling a WM_INPUT message
+pub fn input_received(at: Option<Instant>) {
+    INPUT_RECEIVED.with(|received| received.set(at));
+}
+
+/// Records how long after its WM_INPUT an action of `kind` finished. Actions not caused by
+/// raw input (the keyboard hook, long press timers) aren't measured.
+pub fn action_finished(kind: &'static str) {
+    if let Some(received) = INPUT_RECEIVED.with(Cell::get) {
+        record(kind, received.elapsed());
+    }
+}
+
+fn record(kind: &'static str, elapsed: Duration) {
+    let mut lock = SAMPLES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let samples = lock.get_or_insert_with(HashMap::new).entry(kind).or_default();
+    if samples.recent.len() == MAX_SAMPLES {
+        samples.recent.pop_front();
+    }
+    samples.recent.push_back(elapsed);
+    samples.total += 1;
+
+    log::trace!("{} action finished {:.2}ms after WM_INPUT", kind, millis(elapsed));
+    if samples.total.is_multiple_of(LOG_EVERY) {
+        if let Some((p50, p95)) = percentiles(samples.recent.iter().copied()) {
+            log::debug!("{} latency over the last {}: p50 {:.2}ms, p95 {:.2}ms",
+                        kind, samples.recent.len(), millis(p50), millis(p95));
+        }
+    }
+}
+
+/// The 50th and 95th percentile of `samples`, or None if there are none
+pub fn percentiles(samples: impl Iterator<Item = Duration>) -> Option<(Duration, Duration)> {
+    let mut sorted: Vec<Duration> = samples.collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_unstable();
+    let at = |percent: usize| sorted[(sorted.len() - 1) * percent / 100];
+    Some((at(50), at(95)))
+}
+
+pub fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// One line per kind of action seen so far, for --status
+pub fn summary() -> Vec<String> {
+    let lock = SAMPLES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut lines: Vec<String> = lock.iter()
+        .flatten()
+        .filter_map(|(kind, samples)| {
+            let (p50, p95) = percentiles(samples.recent.iter().copied())?;
+            Some(format!("{} p50 {:.2}ms, p95 {:.2}ms ({} actions)", kind, millis(p50), millis(p95), samples.total))
+        })
+        .collect();
+    lines.sort();
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_pick_from_the_sorted_samples() {
+        let samples = (1..=100).rev().map(Duration::from_millis);
+        assert_eq!(percentiles(samples), Some((Duration::from_millis(50), Duration::from_millis(95))));
+        assert_eq!(percentiles(std::iter::once(Duration::from_millis(3))),
+                   Some((Duration::from_millis(3), Duration::from_millis(3))));
+        assert_eq!(percentiles(std::iter::empty()), None);
+    }
+}
@@ -0,0 +1,66 @@
+// --- START OF FILE src/launcher.rs ---
+// LAUNCHER: the Spotlight key. Which search tool it opens comes from `launcher = ...` in
+// [settings] and is read when the key is pressed, so a reload switches it without remapping
+use std::sync::Mutex;
+
+use crate::action_executor::Action;
+use crate::config;
+
+// Everything's default install location; -toggle-window shows its search window or hides it again
+const EVERYTHING_COMMAND: &str = "\"%ProgramFiles%\\Everything\\Everything.exe\" -toggle-window";
+
+static LAUNCHER: Mutex<Launcher> = Mutex::new(Launcher::Search);
+
+/// What the LAUNCHER action opens
+#[derive(Debug, Clone, PartialEq)]
+pub enum Launcher {
+    Search,      // Windows Search (Win+S)
+    PowerToys,   // PowerToys Run, on its default Alt+Space hotkey
+    Everything,  // voidtools Everything
+    Run(String), // RUN("command"): any other launcher
+}
+
+impl Launcher {
+    /// Parses `search`, `powertoys`, `everything` or `RUN("command")`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let lower = value.to_lowercase();
+        match lower.as_str() {
+            "search" | "windows" => Some(Launcher::Search),
+            "powertoys" => Some(Launcher::PowerToys),
+            "everything" => Some(Launcher::Everything),
+            _ => {
+                let (command, tail) = config::unquote(config::strip_prefix_ignore_case(value, "RUN(\"")?)?;
+                (tail.trim() == ")" && !command.trim().is_empty()).then_some(Launcher::Run(command))
+            }
+        }
+    }
+
+    /// The action that opens this launcher
+    pub fn action(&self) -> Action {
+        match self {
+            Launcher::Search => Action::KeyCombo("WIN+S".to_string()),
+            Launcher::PowerToys => Action::KeyCombo("ALT+SPACE".to_string()),
+            Launcher::Everything => Action::Run(EVERYTHING_COMMAND.to_string()),
+            Launcher::Run(command) => Action::Run(command.clone()),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Launcher::Search => "Windows Search".to_string(),
+            Launcher::PowerToys => "PowerToys Run".to_string(),
+            Launcher::Everything => "Everything".to_string(),
+            Launcher::Run(command) => format!("RUN(\"{}\")", command),
+        }
+    }
+}
+
+/// Replaces the launcher LAUNCHER opens from now on
+pub fn set(launcher: Launcher) {
+    *LAUNCHER.lock().unwrap_or_else(|p| p.into_inner()) = launcher;
+}
+
+pub fn current() -> Launcher {
+    LAUNCHER.lock().unwrap_or_else(|p| p.into_inner()).clone()
+}
@@ -0,0 +1,36 @@
+// --- START OF FILE src/magnifier.rs ---
+// MAGNIFIER_ZOOM_IN / _OUT / _TOGGLE: drives the Windows Magnifier through its own Win+Plus,
+// Win+Minus and Win+Esc shortcuts. A zoom set through the Magnification API ends with the process
+// that set it, so the built-in Magnifier (with its own settings and focus tracking) is used.
+use crate::foreground;
+
+const MAGNIFIER_PROCESS: &str = "magnify.exe";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zoom {
+    In,     // Starts Magnifier if needed
+    Out,
+    Toggle, // Starts Magnifier, or closes it if it is running
+}
+
+impl Zoom {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Zoom::In => "zoom in",
+            Zoom::Out => "zoom out",
+            Zoom::Toggle => "magnifier on/off",
+        }
+    }
+}
+
+/// The Magnifier shortcut that performs `zoom` right now, or None if there is nothing to do
+pub fn combo(zoom: Zoom) -> Option<&'static str> {
+    let running = foreground::is_running(MAGNIFIER_PROCESS);
+    match zoom {
+        Zoom::In => Some("WIN+EQUALS"),
+        // Magnifier isn't zoomed in while it isn't running
+        Zoom::Out => running.then_some("WIN+MINUS"),
+        Zoom::Toggle if running => Some("WIN+ESCAPE"),
+        Zoom::Toggle => Some("WIN+EQUALS"),
+    }
+}
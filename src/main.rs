@@ -1,39 +1,96 @@
-#![windows_subsystem = "windows"]
+// No console window at login; CLI commands and --console attach one when needed
+#![cfg_attr(not(feature = "console"), windows_subsystem = "windows")]
 // --- START OF FILE src/main.rs ---
 mod hid_parser;
 mod key_mapper;
+mod config;
 mod action_executor;
 mod variable_maps;
+mod settings;
+mod journal;
+mod test_bench;
+mod devices;
+mod ipc;
+mod updater;
+mod migration;
+mod backups;
+mod error;
+mod input_backend;
+mod report_profiles;
+mod hid_output;
+mod name_list;
+mod clipboard;
+mod latency;
+mod bench;
+mod foreground;
+mod toggles;
+mod suspend;
+mod expand;
+mod input_language;
+mod focus_assist;
+mod launcher;
+mod quit_app;
+mod magnifier;
+mod sound;
+mod templates;
+mod config_json;
+mod cheatsheet;
+mod policy;
+mod attribution;
+mod scroll;
+mod gestures;
+mod bluetooth;
+mod toast;
+mod config_watch;
+mod config_diff;
+mod safe_mode;
+mod osd;
+#[cfg(feature = "interception")]
+mod interception;
+
+#[cfg(test)]
+mod pipeline_tests;
 
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::ptr::null_mut;
 use std::ffi::c_void;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::{channel, Receiver};
 use std::time::Duration;
 
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, WPARAM};
 use windows::Win32::UI::Input::{
-    GetRawInputData, RegisterRawInputDevices, HRAWINPUT, RAWINPUT, RAWINPUTDEVICE, 
-    RAWINPUTHEADER, RAWINPUTDEVICE_FLAGS, RID_INPUT, RIDEV_INPUTSINK,
+    RegisterRawInputDevices, RAWINPUTDEVICE, RAWINPUTDEVICE_FLAGS, RIDEV_DEVNOTIFY, RIDEV_INPUTSINK,
+    RIDEV_REMOVE,
 };
 use windows::Win32::UI::WindowsAndMessaging::{
     CreateWindowExW, DefWindowProcW, DispatchMessageW, GetMessageW, PostQuitMessage,
-    RegisterClassW, TranslateMessage, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, MSG, WM_DESTROY,
+    RegisterClassW, TranslateMessage, CS_HREDRAW, CS_VREDRAW, CW_USEDEFAULT, MSG, WM_DESTROY, WM_ENDSESSION,
     WM_INPUT, WNDCLASSW, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_OVERLAPPEDWINDOW,
     PostMessageW, WM_USER,
     SetWindowsHookExW, CallNextHookEx, UnhookWindowsHookEx, WH_KEYBOARD_LL, KBDLLHOOKSTRUCT,
-    WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP,
+    WH_MOUSE_LL, MSLLHOOKSTRUCT, WM_MOUSEHWHEEL,
+    WM_KEYDOWN, WM_KEYUP, WM_SYSKEYDOWN, WM_SYSKEYUP, SW_SHOWNORMAL,
+    SetTimer, KillTimer, WM_TIMER, PeekMessageW, PM_REMOVE,
+    WM_INPUT_DEVICE_CHANGE, GIDC_ARRIVAL, GIDC_REMOVAL,
+    WM_WTSSESSION_CHANGE, WTS_SESSION_LOCK, WTS_SESSION_UNLOCK,
 };
+use windows::Win32::System::RemoteDesktop::{WTSRegisterSessionNotification, NOTIFY_FOR_THIS_SESSION};
+use windows::Win32::UI::Shell::ShellExecuteW;
 
-use notify::{Watcher, RecommendedWatcher, RecursiveMode};
-use notify::event::{EventKind, ModifyKind};
-use tray_icon::{TrayIconBuilder, menu::{Menu, MenuItem, PredefinedMenuItem}};
+use tray_icon::{TrayIcon, TrayIconBuilder, menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu}};
 use tray_icon::Icon;
 
-use key_mapper::KeyMapper;
+use key_mapper::{HidKey, KeyMapper};
+use hid_parser::DeviceId;
+use foreground::AppMode;
+use input_backend::{RawInputBackend, Routing};
+use journal::JournalEvent;
+use templates::Template;
+use settings::InputDriver;
 
 
 
@@ -42,6 +99,95 @@ use key_mapper::KeyMapper;
 const WM_RELOAD_CONFIG: u32 = WM_USER + 1;
 const WM_RESET_CONFIG: u32 = WM_USER + 2;
 const WM_EXIT_APP: u32 = WM_USER + 3;
+const WM_CAPTURE_JOURNAL: u32 = WM_USER + 4;
+const WM_TOGGLE_AUTOSTART: u32 = WM_USER + 5;
+const WM_OPEN_TEST_BENCH: u32 = WM_USER + 6;
+const WM_IPC_REQUEST: u32 = WM_USER + 7;
+const WM_CHECK_UPDATES: u32 = WM_USER + 8;
+const WM_RESTORE_BACKUP: u32 = WM_USER + 9;
+const WM_UNDO_RESET: u32 = WM_USER + 10;
+const WM_COPY_EVENTS: u32 = WM_USER + 11;
+const WM_TOGGLES_CHANGED: u32 = WM_USER + 12;
+const WM_SUSPEND: u32 = WM_USER + 13;
+const WM_SUSPEND_CHANGED: u32 = WM_USER + 14;
+const WM_INTERCEPTED_KEY: u32 = WM_USER + 15;
+const WM_SCROLL_CHANGED: u32 = WM_USER + 16;
+const WM_TOAST: u32 = WM_USER + 17;
+const WM_SHOW_KEYSTROKES: u32 = WM_USER + 18;
+const WM_SHOW_LEVEL: u32 = WM_USER + 19;
+
+const TRAY_TOOLTIP: &str = "A1314 Keyboard Daemon";
+
+// Timer that fires LONGPRESS() mappings while their key is still held
+const LONG_PRESS_TIMER_ID: usize = 1;
+// Timer that counts a suspension down in the tray, once a second
+const SUSPEND_TIMER_ID: usize = 2;
+// Timer that tries to bring a disconnected Bluetooth keyboard back
+const RECONNECT_TIMER_ID: usize = 3;
+
+// How long the tray's suspend item leaves keys alone
+const TRAY_SUSPEND_DURATION: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+// Registry location used for "start with Windows"
+const RUN_KEY_PATH: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+const RUN_VALUE_NAME: &str = "A1314Daemon";
+
+// Task Scheduler name used by --install-task
+const TASK_NAME: &str = "A1314Daemon";
+
+// Set once at startup from --portable or a portable.txt beside the exe
+static PORTABLE: AtomicBool = AtomicBool::new(false);
+
+// Set at startup if RUST_LOG chose the log level, which log_level in [settings] then leaves alone
+static LOG_LEVEL_FROM_ENV: AtomicBool = AtomicBool::new(false);
+
+// Content hash of the config files at the last load, so the watcher skips saves that change nothing
+static LOADED_HASH: AtomicU64 = AtomicU64::new(0);
+
+// Set by --silent so installers can run the CLI commands without console output
+static SILENT: AtomicBool = AtomicBool::new(false);
+
+// Console output for CLI commands, muted by --silent
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !SILENT.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Flags accepted after --install / --uninstall
+#[derive(Debug, Clone, Copy, Default)]
+struct InstallOptions {
+    all_users: bool,  // HKLM Run key instead of HKCU
+}
+
+impl InstallOptions {
+    /// Parses the trailing flags; --silent is global and recorded in SILENT
+    fn parse(flags: &[String]) -> Self {
+        let mut options = Self::default();
+        for flag in flags {
+            match flag.as_str() {
+                "--all-users" => options.all_users = true,
+                "--silent" => SILENT.store(true, Ordering::Relaxed),
+                other => log::warn!("Ignoring unknown install option: {}", other),
+            }
+        }
+        options
+    }
+
+    fn root_key(&self) -> windows::Win32::System::Registry::HKEY {
+        if self.all_users {
+            windows::Win32::System::Registry::HKEY_LOCAL_MACHINE
+        } else {
+            windows::Win32::System::Registry::HKEY_CURRENT_USER
+        }
+    }
+
+    fn scope_label(&self) -> &'static str {
+        if self.all_users { " for all users" } else { "" }
+    }
+}
 
 // Thread-local storage for the key mapper
 // IMPORTANT: This assumes all HID input processing happens on the window message thread.
@@ -51,9 +197,40 @@ const WM_EXIT_APP: u32 = WM_USER + 3;
 thread_local! {
     static GLOBAL_MAPPER: RefCell<Option<Rc<RefCell<KeyMapper>>>> = RefCell::new(None);
     static MAPPING_FILE_PATH: RefCell<Option<PathBuf>> = RefCell::new(None);
+    static SHARED_MAPPING_PATH: RefCell<Option<PathBuf>> = RefCell::new(None);
     static MAIN_WINDOW: RefCell<Option<HWND>> = RefCell::new(None);
-    static SUPPRESSED_KEYS: RefCell<std::collections::HashSet<u32>> = RefCell::new(std::collections::HashSet::new());
+    // Keys whose hook key-down was suppressed, with the keyboard it was attributed to
+    static SUPPRESSED_KEYS: RefCell<std::collections::HashMap<u32, DeviceId>> = RefCell::new(std::collections::HashMap::new());
+    // When each mapped key held down was pressed and last ran its action, for repeat_due()
+    static KEY_REPEATS: RefCell<std::collections::HashMap<u32, (std::time::Instant, std::time::Instant)>> = RefCell::new(std::collections::HashMap::new());
     static H_HOOK: RefCell<Option<windows::Win32::UI::WindowsAndMessaging::HHOOK>> = RefCell::new(None);
+    // Only installed while a mapping is for a mouse button or the wheel
+    static H_MOUSE_HOOK: RefCell<Option<windows::Win32::UI::WindowsAndMessaging::HHOOK>> = const { RefCell::new(None) };
+    // Raw input usages registered only while needed: mice, for natural_scroll to tell them apart,
+    // and trackpads, for gesture mappings
+    static ON_DEMAND_RAW_INPUT: RefCell<std::collections::HashSet<(u16, u16)>> = RefCell::new(std::collections::HashSet::new());
+    // Mouse buttons (Button page usages) whose press was suppressed, so their release is too
+    static SUPPRESSED_BUTTONS: RefCell<std::collections::HashSet<u16>> = RefCell::new(std::collections::HashSet::new());
+    static AUTOSTART_ITEM: RefCell<Option<CheckMenuItem>> = RefCell::new(None);
+    // Kept for the life of the program; its tooltip shows the TOGGLE() states
+    static TRAY_ICON: RefCell<Option<TrayIcon>> = const { RefCell::new(None) };
+    static RESTORE_MENU: RefCell<Option<Submenu>> = const { RefCell::new(None) };
+    static UNDO_RESET_ITEM: RefCell<Option<MenuItem>> = const { RefCell::new(None) };
+    // Reads "Resume Now" while suspended
+    static SUSPEND_ITEM: RefCell<Option<MenuItem>> = const { RefCell::new(None) };
+    static KEYSTROKES_ITEM: RefCell<Option<CheckMenuItem>> = const { RefCell::new(None) };
+    // Names the journal_seconds of the last load
+    static CAPTURE_ITEM: RefCell<Option<MenuItem>> = const { RefCell::new(None) };
+    // Backups listed in the restore submenu, indexed by the menu item's WPARAM
+    static RESTORE_CHOICES: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+    // Contents of the mapping file at the last clean load, backed up when an edit replaces it
+    static LOADED_TEXT: RefCell<Option<String>> = const { RefCell::new(None) };
+    // Why the last load of the mapping file wasn't clean, for --status
+    static LAST_LOAD_ERROR: RefCell<Option<error::A1314Error>> = const { RefCell::new(None) };
+    static STARTED_AT: std::time::Instant = std::time::Instant::now();
+    // input_driver at startup, and what holds keys back after falling back if that failed
+    static REQUESTED_DRIVER: std::cell::Cell<InputDriver> = const { std::cell::Cell::new(InputDriver::Hook) };
+    static INPUT_DRIVER: std::cell::Cell<InputDriver> = const { std::cell::Cell::new(InputDriver::Hook) };
 }
 
 fn main() -> windows::core::Result<()> {
@@ -61,21 +238,116 @@ fn main() -> windows::core::Result<()> {
     #[cfg(debug_assertions)]
     println!("--- A1314 Daemon DEBUG START (PID: {}) ---", std::process::id());
 
+    // Portable mode keeps everything next to the exe and never touches the registry
+    let exe_dir_for_mode = std::env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()));
+    let portable = std::env::args().any(|arg| arg == "--portable") ||
+        exe_dir_for_mode.as_ref().is_some_and(|dir| dir.join("portable.txt").exists());
+    PORTABLE.store(portable, Ordering::Relaxed);
+
+    // CLI commands print into the console they were started from; --console opens one if needed
+    let wants_console = std::env::args().any(|arg| arg == "--console");
+    let has_console = attach_console(std::env::args().skip(1).any(|arg| arg != "--portable"), wants_console);
+
     // Initialize logging - Default to INFO for release, DEBUG for dev
-    let default_log_level = if cfg!(debug_assertions) { "debug" } else { "info" };
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_log_level))
-        .format_timestamp(Some(env_logger::TimestampPrecision::Millis))
-        .init();
+    // Without RUST_LOG the logger passes everything and log::set_max_level filters, so log_level
+    // in [settings] can change the level on reload
+    let level_from_env = std::env::var_os("RUST_LOG").is_some();
+    LOG_LEVEL_FROM_ENV.store(level_from_env, Ordering::Relaxed);
+    let mut logger = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("trace"));
+    logger.format_timestamp(Some(env_logger::TimestampPrecision::Millis));
+    if portable && !has_console {
+        // There is no console in the GUI subsystem, so portable logs go to a file beside the exe
+        if let Some(dir) = &exe_dir_for_mode {
+            if let Ok(file) = std::fs::OpenOptions::new().create(true).append(true).open(dir.join("A1314_daemon.log")) {
+                logger.target(env_logger::Target::Pipe(Box::new(file)));
+            }
+        }
+    }
+    logger.init();
+    if !level_from_env {
+        log::set_max_level(default_log_level());
+    }
 
-    // Parse command line arguments
-    let args: Vec<String> = std::env::args().collect();
+    // Parse command line arguments (--portable and --console may appear anywhere and are handled above)
+    let args: Vec<String> = std::env::args().filter(|arg| arg != "--portable" && arg != "--console").collect();
     if args.len() > 1 {
         match args[1].as_str() {
             "--install" => {
-                return install_service();
+                return install_service(InstallOptions::parse(&args[2..]));
             }
             "--uninstall" => {
-                return uninstall_service();
+                return uninstall_service(InstallOptions::parse(&args[2..]));
+            }
+            "--install-task" => {
+                // Only --silent applies; the task is always created for the current user
+                let _ = InstallOptions::parse(&args[2..]);
+                return install_task();
+            }
+            "--uninstall-task" => {
+                let _ = InstallOptions::parse(&args[2..]);
+                return uninstall_task();
+            }
+            "--status" => {
+                print_status();
+                return Ok(());
+            }
+            "--dump-events" => {
+                print_recent_events();
+                return Ok(());
+            }
+            "--list-devices" => {
+                print_devices();
+                return Ok(());
+            }
+            "--list-keys" | "--list-actions" => {
+                let json = args[2..].iter().any(|arg| arg == "--json");
+                if args[1] == "--list-keys" {
+                    print!("{}", name_list::keys(json));
+                } else {
+                    print!("{}", name_list::actions(json));
+                }
+                return Ok(());
+            }
+            "--explain" => {
+                print_explanation(args.get(2).map(String::as_str));
+                return Ok(());
+            }
+            "--map" => {
+                send_map_command(&args[2..]);
+                return Ok(());
+            }
+            "--export-config" => {
+                export_config(args.get(2).map(String::as_str));
+                return Ok(());
+            }
+            "--export-cheatsheet" => {
+                export_cheatsheet(args.get(2).map(String::as_str));
+                return Ok(());
+            }
+            "--check" => {
+                check_mapping_file(args.get(2).map(PathBuf::from));
+                return Ok(());
+            }
+            "--template" => {
+                write_template(args.get(2).map(String::as_str));
+                return Ok(());
+            }
+            "--bench" => {
+                run_bench(args.get(2).map(String::as_str));
+                return Ok(());
+            }
+            "--update" => {
+                let result = updater::check_and_stage();
+                println!("{}", updater::describe(&result));
+                if result.is_err() {
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+            "--capture" | "--capture-only" => {
+                print_capture(args[1] == "--capture-only");
+                return Ok(());
             }
             "--help" | "-h" => {
                 print_help();
@@ -89,8 +361,28 @@ fn main() -> windows::core::Result<()> {
         }
     }
 
+    // A release downloaded by --update or the tray is swapped in here
+    if updater::apply_staged_update() {
+        return Ok(());
+    }
+
     log::info!("{} v{} starting...", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
     log::info!("Log level: {} (set RUST_LOG environment variable to change)", log::max_level());
+    if portable {
+        log::info!("Portable mode: state stays next to the executable, registry is not touched");
+    }
+
+    // Without UIAccess, Windows drops injected input aimed at elevated windows and UAC prompts
+    if has_ui_access() {
+        log::info!("Running with UIAccess, mapped keys will reach elevated windows");
+    } else if cfg!(feature = "uiaccess") {
+        // An unsigned uiaccess build, or one outside a secure location, doesn't get this far:
+        // Windows refuses to start it (ERROR_ELEVATION_REQUIRED). Running without the flag
+        // means UAC is turned off, which gives no process UIAccess.
+        log::warn!("Built with uiaccess but running without UIAccess; is UAC turned off?");
+    } else {
+        log::debug!("Running without UIAccess, mapped keys won't reach elevated windows");
+    }
 
     // Force initialization of lazy_static maps
     let _ = variable_maps::STRING_TO_HID_KEY.len();
@@ -101,24 +393,48 @@ fn main() -> windows::core::Result<()> {
         .expect("Failed to get executable path");
     let exe_dir = exe_path.parent()
         .expect("Failed to get executable directory");
-    let mapping_path = exe_dir.join("A1314_mapping.txt");
+    let (mapping_path, shared_mapping_path) = resolve_mapping_paths(exe_dir);
 
     log::info!("Executable location: {}", exe_path.display());
     log::info!("Looking for mapping file: {}", mapping_path.display());
+    if let Some(shared) = &shared_mapping_path {
+        log::info!("Using shared defaults from {}", shared.display());
+    }
 
-    // Create default mapping file if it doesn't exist
-    if !mapping_path.exists() {
+    // Create default mapping file if it doesn't exist; one fixed by policy is never written here
+    if !mapping_path.exists() && policy::is_locked() {
+        log::error!("The mapping file set by policy does not exist: {}", mapping_path.display());
+    } else if !mapping_path.exists() {
         log::warn!("Mapping file not found, creating default mapping file");
-        create_default_mapping_file(&mapping_path)?;
+        if shared_mapping_path.is_some() {
+            create_user_override_file(&mapping_path)?;
+        } else {
+            create_default_mapping_file(&mapping_path, Template::UsAnsi)?;
+        }
     }
 
-    // Store mapping path globally
+    // Store mapping paths globally
     MAPPING_FILE_PATH.with(|path| {
         *path.borrow_mut() = Some(mapping_path.clone());
     });
+    SHARED_MAPPING_PATH.with(|path| {
+        *path.borrow_mut() = shared_mapping_path.clone();
+    });
 
+    // After repeated crashes, run the default mappings and let every key through
+    let safe_mode = safe_mode::start(&state_dir(exe_dir));
     let mapper = Rc::new(RefCell::new(KeyMapper::new()));
-    mapper.borrow_mut().load_mapping_file(&mapping_path);
+    let result = if safe_mode {
+        log::warn!("Starting in safe mode after {}: default mappings, no keys held back", safe_mode::trigger());
+        load_safe_mode_mappings(&mut mapper.borrow_mut(), &state_dir(exe_dir))
+    } else {
+        mapper.borrow_mut().set_shared_defaults(shared_mapping_path.clone());
+        load_mappings(&mut mapper.borrow_mut(), &mapping_path)
+    };
+    LAST_LOAD_ERROR.with(|error| *error.borrow_mut() = result.err());
+    apply_log_level();
+    LOADED_HASH.store(content_hash(&config_files()), Ordering::Relaxed);
+    LOADED_TEXT.with(|text| *text.borrow_mut() = std::fs::read_to_string(&mapping_path).ok());
 
     GLOBAL_MAPPER.with(|gm| {
         *gm.borrow_mut() = Some(mapper.clone());
@@ -162,10 +478,26 @@ fn main() -> windows::core::Result<()> {
         register_raw_input(hwnd)?;
         log::info!("Raw input registered successfully");
 
-        // Install keyboard hook
-        let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook_proc), hinstance, 0)?;
-        H_HOOK.with(|h| *h.borrow_mut() = Some(hook));
-        log::info!("Low-level keyboard hook installed for key suppression");
+        // Cleans up on every way out of here, including early returns and panics
+        let _shutdown = ShutdownGuard;
+
+        // Uptime is measured from here
+        STARTED_AT.with(|_| {});
+
+        ipc::start_server(hwnd, WM_IPC_REQUEST);
+        log::info!("IPC server listening on {}", ipc::PIPE_NAME);
+
+        // Hold keys back with the Interception driver if configured and available, else the hook
+        REQUESTED_DRIVER.with(|driver| driver.set(settings::current().input_driver));
+        if start_interception(hwnd) {
+            INPUT_DRIVER.with(|driver| driver.set(InputDriver::Interception));
+        } else {
+            let hook = SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook_proc), hinstance, 0)?;
+            H_HOOK.with(|h| *h.borrow_mut() = Some(hook));
+            log::info!("Low-level keyboard hook installed for key suppression");
+        }
+        scroll::set_notify(hwnd, WM_SCROLL_CHANGED);
+        update_pointer_input();
 
         // Create system tray icon
         if let Err(e) = create_system_tray(&exe_dir, hwnd) {
@@ -174,22 +506,33 @@ fn main() -> windows::core::Result<()> {
             log::info!("System tray icon created");
         }
 
-        // Start file watcher for hot reload
-        let (tx, rx) = channel();
-        let mut watcher: RecommendedWatcher = notify::recommended_watcher(
-            move |res: Result<notify::Event, notify::Error>| {
-                if let Ok(event) = res {
-                    if matches!(event.kind, EventKind::Modify(ModifyKind::Data(_))) {
-                        let _ = tx.send(());
-                    }
-                }
-            }
-        ).expect("Failed to create file watcher");
+        if safe_mode {
+            let text = format!("It had {}, so the default mappings are used and every key reaches Windows. \
+                                Fix the mapping file, then exit and start the daemon again.", safe_mode::trigger());
+            toast::show(hwnd, WM_TOAST, "A1314 Daemon is in safe mode", &text, true);
+        }
 
-        watcher.watch(&mapping_path, RecursiveMode::NonRecursive)
-            .expect("Failed to watch mapping file");
+        // TOGGLE() states start from what Windows reports, and the tray shows them
+        toggles::set_notify(hwnd, WM_TOGGLES_CHANGED);
+        toggles::refresh();
+        suspend::set_notify(hwnd, WM_SUSPEND_CHANGED);
+        clipboard::set_owner(hwnd);
+        osd::set_notify(hwnd, WM_SHOW_LEVEL);
+
+        // Keys released while the session is locked never reach the hook
+        if let Err(e) = WTSRegisterSessionNotification(hwnd, NOTIFY_FOR_THIS_SESSION) {
+            log::warn!("Failed to register for session lock notifications: {}", e);
+        }
 
-        log::info!("File watcher started for hot reload");
+        // Start file watcher for hot reload; every load from here on rewires it to the files it read
+        let (tx, rx) = channel();
+        match config_watch::start(tx) {
+            Ok(()) => {
+                config_watch::watch(config_files());
+                log::info!("File watcher started for hot reload");
+            }
+            Err(e) => log::error!("Failed to start the file watcher, changes to the mapping file won't reload: {}", e),
+        }
         log::info!("Daemon is now running. Use system tray icon to control.");
 
         // Start a thread to handle file watch events
@@ -205,34 +548,133 @@ fn main() -> windows::core::Result<()> {
             DispatchMessageW(&msg);
         }
 
-        // Keep watcher alive until shutdown
-        drop(watcher);
+        config_watch::stop();
+    }
+
+    Ok(())
+}
+
+/// Runs `shutdown` when main's window and hook go out of scope
+struct ShutdownGuard;
+
+impl Drop for ShutdownGuard {
+    fn drop(&mut self) {
+        shutdown();
     }
+}
 
+/// Undoes everything that affects other applications' input. Safe to call more than once;
+/// it runs on exit from the tray, on WM_DESTROY, and when main unwinds.
+fn shutdown() {
+    static DONE: AtomicBool = AtomicBool::new(false);
+    if DONE.swap(true, Ordering::SeqCst) {
+        return;
+    }
     log::info!("Daemon shutting down");
 
-    // Cleanup hook
-    H_HOOK.with(|h| {
-        if let Some(hook) = *h.borrow() {
-            unsafe {
-                let _ = UnhookWindowsHookEx(hook);
-            }
-            log::info!("Low-level keyboard hook uninstalled");
+    // Keys pressed by an interrupted action would otherwise stay down system-wide
+    action_executor::release_held_keys();
+    SUPPRESSED_KEYS.with(|sk| sk.borrow_mut().clear());
+
+    unsafe {
+        if let Err(e) = unregister_raw_input() {
+            log::warn!("Failed to unregister raw input: {}", e);
         }
-    });
+    }
 
-    Ok(())
+    if let Some(hook) = H_HOOK.with(|h| h.borrow_mut().take()) {
+        unsafe {
+            let _ = UnhookWindowsHookEx(hook);
+        }
+        log::info!("Low-level keyboard hook uninstalled");
+    }
+    if let Some(hook) = H_MOUSE_HOOK.with(|h| h.borrow_mut().take()) {
+        unsafe {
+            let _ = UnhookWindowsHookEx(hook);
+        }
+        log::info!("Low-level mouse hook uninstalled");
+    }
+    set_raw_input(MOUSE_USAGE, false);
+    set_raw_input(TOUCH_PAD_USAGE, false);
+    if let Some(hwnd) = MAIN_WINDOW.with(|wnd| *wnd.borrow()) {
+        toast::remove(hwnd);
+    }
+    MenuEvent::set_event_handler(None::<fn(MenuEvent)>);
+    #[cfg(feature = "interception")]
+    interception::stop();
+
+    // A panic unwinding through main is a crash as far as safe mode is concerned
+    if !std::thread::panicking() {
+        safe_mode::clean_exit();
+    }
+}
+
+/// Starts the Interception driver backend if input_driver asks for it; false leaves key
+/// suppression to the keyboard hook
+fn start_interception(hwnd: HWND) -> bool {
+    if settings::current().input_driver != InputDriver::Interception {
+        return false;
+    }
+    #[cfg(feature = "interception")]
+    match interception::start(hwnd, WM_INTERCEPTED_KEY) {
+        Ok(()) => {
+            log::info!("Interception driver installed for key suppression");
+            true
+        }
+        Err(e) => {
+            log::warn!("input_driver = interception, but {}; using the keyboard hook", e);
+            false
+        }
+    }
+    #[cfg(not(feature = "interception"))]
+    {
+        let _ = hwnd;
+        log::warn!("input_driver = interception needs a build with --features interception; using the keyboard hook");
+        false
+    }
+}
+
+/// Every file the configuration is read from: the user's mapping file plus any shared defaults
+/// under it. The file watcher follows this list after each load.
+fn config_files() -> Vec<PathBuf> {
+    let mapping = MAPPING_FILE_PATH.with(|path| path.borrow().clone());
+    let shared = SHARED_MAPPING_PATH.with(|path| path.borrow().clone());
+    mapping.into_iter().chain(shared).collect()
+}
+
+/// Hash of the watched files' contents, so saves that change nothing don't reload
+fn content_hash(files: &[PathBuf]) -> u64 {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for file in files {
+        std::fs::read(file).ok().hash(&mut hasher);
+    }
+    hasher.finish()
 }
 
 fn handle_file_watch_events(rx: Receiver<()>, hwnd: HWND) {
+    let settings = settings::shared();
     while rx.recv().is_ok() {
-        // Debounce: wait a bit to avoid multiple rapid reloads
-        std::thread::sleep(Duration::from_millis(100));
-        
-        // Drain any additional events that came in during the debounce period
-        while rx.try_recv().is_ok() {}
+        // Debounce: a burst of saves reloads once, after the file has been quiet for the window
+        let debounce_ms = settings.read().unwrap_or_else(|p| p.into_inner()).reload_debounce_ms;
+        let window = Duration::from_millis(debounce_ms);
+        let mut events = 1;
+        while rx.recv_timeout(window).is_ok() {
+            events += 1;
+        }
+
+        // A replace-save can leave the file briefly missing; the rename that follows reloads it
+        let files = config_watch::files();
+        if !files.iter().all(|file| file.exists()) {
+            continue;
+        }
+        if content_hash(&files) == LOADED_HASH.load(Ordering::Relaxed) {
+            log::debug!("Mapping file saved without changes, not reloading");
+            continue;
+        }
         
-        log::info!("Mapping file changed, reloading...");
+        log::info!("Mapping file changed ({} file events), reloading...", events);
         unsafe {
             let _ = PostMessageW(hwnd, WM_RELOAD_CONFIG, WPARAM(0), LPARAM(0));
         }
@@ -252,72 +694,296 @@ fn create_system_tray(_exe_dir: &std::path::Path, hwnd: HWND) -> Result<(), Stri
     let menu = Menu::new();
     
     let reload_item = MenuItem::new("Reload Configuration", true, None);
-    let reset_item = MenuItem::new("Reset to Default Configuration", true, None);
+    // One entry per template; the file is rewritten from the one picked
+    let reset_menu = Submenu::new("Reset to Default Configuration", !policy::is_locked());
+    for (index, template) in Template::ALL.iter().enumerate() {
+        let item = MenuItem::with_id(format!("{}{}", TEMPLATE_ID_PREFIX, index), template.describe(), true, None);
+        reset_menu.append(&item).map_err(|e| format!("Menu error: {}", e))?;
+    }
     let separator1 = PredefinedMenuItem::separator();
+    let suspend_item = MenuItem::new(SUSPEND_ITEM_TEXT, true, None);
+    let test_bench_item = MenuItem::new("Mapping Test Bench...", true, None);
+    let capture_item = MenuItem::new(capture_item_text(), true, None);
+    let copy_events_item = MenuItem::new("Copy Recent Events to Clipboard", true, None);
+    let keystrokes_item = CheckMenuItem::new("Show Keystrokes", true, osd::showing_keystrokes(), None);
+    let update_item = MenuItem::new("Check for Updates...", true, None);
+    let restore_menu = Submenu::new("Restore backup", !policy::is_locked());
+    // Enabled while there is a pre-reset copy to go back to
+    let undo_reset_item = MenuItem::new("Undo Reset",
+        !policy::is_locked() && undo_reset_path().is_some_and(|path| path.exists()), None);
+    // Portable mode never writes the Run key, and a machine-wide entry or the scheduled task
+    // isn't the toggle's to change, so then it is shown but disabled
+    let autostart = autostart();
+    let autostart_item = CheckMenuItem::new(autostart.menu_text(), !is_portable() && autostart.is_toggleable(),
+                                            autostart != Autostart::Off, None);
+    let separator2 = PredefinedMenuItem::separator();
     let exit_item = MenuItem::new("Exit", true, None);
 
     menu.append(&reload_item).map_err(|e| format!("Menu error: {}", e))?;
-    menu.append(&reset_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&reset_menu).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&undo_reset_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&restore_menu).map_err(|e| format!("Menu error: {}", e))?;
     menu.append(&separator1).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&suspend_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&test_bench_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&capture_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&copy_events_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&keystrokes_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&update_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&autostart_item).map_err(|e| format!("Menu error: {}", e))?;
+    menu.append(&separator2).map_err(|e| format!("Menu error: {}", e))?;
     menu.append(&exit_item).map_err(|e| format!("Menu error: {}", e))?;
 
     // Build tray icon
-    let _tray_icon = TrayIconBuilder::new()
+    let tray_icon = TrayIconBuilder::new()
         .with_menu(Box::new(menu))
-        .with_tooltip("A1314 Keyboard Daemon")
+        .with_tooltip(TRAY_TOOLTIP)
         .with_icon(icon)
         .build()
         .map_err(|e| format!("Failed to build tray icon: {}", e))?;
 
-    // Pre-clone IDs for the thread to avoid capturing Send-hostile types
+    // Pre-clone IDs for the menu event handler, which must be Send
     let reload_id = reload_item.id().clone();
-    let reset_id = reset_item.id().clone();
+    let undo_reset_id = undo_reset_item.id().clone();
+    let suspend_id = suspend_item.id().clone();
+    let test_bench_id = test_bench_item.id().clone();
+    let capture_id = capture_item.id().clone();
+    let copy_events_id = copy_events_item.id().clone();
+    let keystrokes_id = keystrokes_item.id().clone();
+    let update_id = update_item.id().clone();
+    let autostart_id = autostart_item.id().clone();
     let exit_id = exit_item.id().clone();
 
-    // Handle menu events
+    // Menu clicks are handled on this thread: tray-icon calls the handler from its window
+    // procedure, and each click is posted to the main window, whose message loop runs it like any
+    // other command. There is no thread to die on a channel error or outlive the daemon.
     let hwnd_val = hwnd.0 as usize;
-    std::thread::spawn(move || {
+    MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
         let hwnd = HWND(hwnd_val as *mut c_void);
-        loop {
-            if let Ok(event) = tray_icon::menu::MenuEvent::receiver().recv() {
-                unsafe {
-                    if event.id == reload_id {
-                        let _ = PostMessageW(hwnd, WM_RELOAD_CONFIG, WPARAM(0), LPARAM(0));
-                    } else if event.id == reset_id {
-                        let _ = PostMessageW(hwnd, WM_RESET_CONFIG, WPARAM(0), LPARAM(0));
-                    } else if event.id == exit_id {
-                        let _ = PostMessageW(hwnd, WM_EXIT_APP, WPARAM(0), LPARAM(0));
-                    }
-                }
+        let (message, wparam) = if event.id == reload_id {
+            (WM_RELOAD_CONFIG, 0)
+        } else if event.id == undo_reset_id {
+            (WM_UNDO_RESET, 0)
+        } else if event.id == suspend_id {
+            (WM_SUSPEND, 0)
+        } else if event.id == test_bench_id {
+            (WM_OPEN_TEST_BENCH, 0)
+        } else if event.id == capture_id {
+            (WM_CAPTURE_JOURNAL, 0)
+        } else if event.id == copy_events_id {
+            (WM_COPY_EVENTS, 0)
+        } else if event.id == keystrokes_id {
+            (WM_SHOW_KEYSTROKES, 0)
+        } else if event.id == update_id {
+            (WM_CHECK_UPDATES, 0)
+        } else if event.id == autostart_id {
+            (WM_TOGGLE_AUTOSTART, 0)
+        } else if event.id == exit_id {
+            (WM_EXIT_APP, 0)
+        } else if let Some(index) = event.id.0.strip_prefix(RESTORE_ID_PREFIX)
+            .and_then(|index| index.parse::<usize>().ok()) {
+            (WM_RESTORE_BACKUP, index)
+        } else if let Some(index) = event.id.0.strip_prefix(TEMPLATE_ID_PREFIX)
+            .and_then(|index| index.parse::<usize>().ok()) {
+            (WM_RESET_CONFIG, index)
+        } else {
+            log::debug!("Click on unknown menu item {:?}", event.id);
+            return;
+        };
+        if let Err(e) = unsafe { PostMessageW(hwnd, message, WPARAM(wparam), LPARAM(0)) } {
+            log::error!("Failed to pass on the menu click on {:?}: {}", event.id, e);
+        }
+    }));
+
+    TRAY_ICON.with(|icon| *icon.borrow_mut() = Some(tray_icon));
+
+    // The check mark is refreshed from the registry after each toggle
+    AUTOSTART_ITEM.with(|item| *item.borrow_mut() = Some(autostart_item));
+
+    UNDO_RESET_ITEM.with(|item| *item.borrow_mut() = Some(undo_reset_item));
+    SUSPEND_ITEM.with(|item| *item.borrow_mut() = Some(suspend_item));
+    KEYSTROKES_ITEM.with(|item| *item.borrow_mut() = Some(keystrokes_item));
+    CAPTURE_ITEM.with(|item| *item.borrow_mut() = Some(capture_item));
+    RESTORE_MENU.with(|menu| *menu.borrow_mut() = Some(restore_menu));
+    refresh_restore_menu();
+
+    Ok(())
+}
+
+/// The capture item's text, for the journal_seconds in effect
+fn capture_item_text() -> String {
+    format!("Capture Last {}s of Events", settings::current().journal_seconds)
+}
+
+// Menu ids of the restore submenu entries are this prefix plus an index into RESTORE_CHOICES
+const RESTORE_ID_PREFIX: &str = "restore-backup:";
+const TEMPLATE_ID_PREFIX: &str = "reset-template:";
+
+/// Rebuilds the "Restore backup" submenu from the backups folder
+fn refresh_restore_menu() {
+    let backups = MAPPING_FILE_PATH.with(|path| {
+        path.borrow().as_deref().map(backups::list).unwrap_or_default()
+    });
+
+    RESTORE_MENU.with(|menu| {
+        if let Some(menu) = &*menu.borrow() {
+            while menu.remove_at(0).is_some() {}
+            if backups.is_empty() {
+                let _ = menu.append(&MenuItem::new("(no backups yet)", false, None));
+            }
+            for (index, backup) in backups.iter().take(10).enumerate() {
+                let item = MenuItem::with_id(format!("{}{}", RESTORE_ID_PREFIX, index), backups::label(backup), true, None);
+                let _ = menu.append(&item);
             }
         }
     });
+    RESTORE_CHOICES.with(|choices| *choices.borrow_mut() = backups);
+}
 
-    // Keep tray icon alive by leaking it (it will be cleaned up on program exit)
-    Box::leak(Box::new(_tray_icon));
+fn restore_backup(index: usize) {
+    if refused_by_policy("restore a backup") {
+        return;
+    }
+    let backup = RESTORE_CHOICES.with(|choices| choices.borrow().get(index).cloned());
+    let mapping_path = MAPPING_FILE_PATH.with(|path| path.borrow().clone());
+    if let (Some(backup), Some(mapping_path)) = (backup, mapping_path) {
+        match backups::restore(&mapping_path, &backup) {
+            Ok(()) => reload_configuration(),
+            Err(e) => log::error!("Failed to restore backup {}: {}", backup.display(), e),
+        }
+    }
+}
 
-    Ok(())
+/// Loads the built-in default mappings, for safe mode
+fn load_safe_mode_mappings(mapper: &mut KeyMapper, dir: &std::path::Path) -> error::Result<()> {
+    match safe_mode::default_mapping_file(dir, &Template::UsAnsi.render()) {
+        Ok(path) => mapper.load_mapping_file(path),
+        Err(e) => {
+            log::error!("Failed to write the safe mode mappings, no keys are mapped: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Where the daemon keeps its own state: beside the exe in portable mode, else under
+/// %LOCALAPPDATA%
+fn state_dir(exe_dir: &std::path::Path) -> PathBuf {
+    match std::env::var_os("LOCALAPPDATA") {
+        Some(dir) if !is_portable() => PathBuf::from(dir).join("A1314Daemon"),
+        _ => exe_dir.to_path_buf(),
+    }
+}
+
+/// Loads the mapping file, after checking it against the policy hash when a policy fixes it. A
+/// file that fails the check isn't loaded, so the mappings from the last good load stay.
+fn load_mappings(mapper: &mut KeyMapper, path: &std::path::Path) -> error::Result<()> {
+    match policy::current() {
+        // Parse the bytes that were hashed; reading the file again could pick up a different one
+        Some(policy) => mapper.load_mapping_text(path, policy.read_verified()?),
+        None => mapper.load_mapping_file(path),
+    }
+}
+
+/// Logs and refuses a change to the mapping file while a policy fixes it
+fn refused_by_policy(what: &str) -> bool {
+    if policy::is_locked() {
+        log::warn!("Refusing to {}: the mapping file is fixed by policy ({})", what, policy::POLICY_KEY);
+    }
+    policy::is_locked()
 }
 
 fn reload_configuration() {
+    if safe_mode::active() {
+        log::warn!("Safe mode: not loading the mapping file; exit and start the daemon again to load it");
+        return;
+    }
     MAPPING_FILE_PATH.with(|path| {
         if let Some(mapping_path) = &*path.borrow() {
             GLOBAL_MAPPER.with(|gm| {
                 if let Some(mapper_rc) = &*gm.borrow() {
                     log::info!("Reloading configuration from {}", mapping_path.display());
-                    mapper_rc.borrow_mut().load_mapping_file(mapping_path);
+                    let before = config_diff::Snapshot::of(&mapper_rc.borrow());
+                    let result = load_mappings(&mut mapper_rc.borrow_mut(), mapping_path);
+                    let clean = result.is_ok();
+                    report_reload(&before, &config_diff::Snapshot::of(&mapper_rc.borrow()), clean);
+                    LAST_LOAD_ERROR.with(|error| *error.borrow_mut() = result.err());
+                    apply_log_level();
+                    CAPTURE_ITEM.with(|item| {
+                        if let Some(item) = &*item.borrow() {
+                            item.set_text(capture_item_text());
+                        }
+                    });
+                    LOADED_HASH.store(content_hash(&config_files()), Ordering::Relaxed);
+                    config_watch::watch(config_files());
                     log::info!("Configuration reloaded successfully");
+                    update_pointer_input();
+                    let driver = settings::current().input_driver;
+                    if driver != REQUESTED_DRIVER.with(|requested| requested.get()) {
+                        log::warn!("input_driver = {} takes effect when the daemon restarts", driver.name());
+                    }
+                    test_bench::refresh();
+
+                    // Keep the version this edit replaced, once the new one loads cleanly
+                    if clean {
+                        let current = std::fs::read_to_string(mapping_path).ok();
+                        let previous = LOADED_TEXT.with(|text| text.replace(current.clone()));
+                        if let (Some(previous), Some(current)) = (previous, current) {
+                            if previous != current && backups::save(mapping_path, &previous, "edit").is_some() {
+                                refresh_restore_menu();
+                            }
+                        }
+                    }
                 }
             });
         }
     });
 }
 
-fn reset_configuration() {
+/// Logs what a reload changed, and says so in a notification, so an edit can be seen to take
+fn report_reload(before: &config_diff::Snapshot, after: &config_diff::Snapshot, clean: bool) {
+    let diff = config_diff::diff(before, after);
+    log::info!("Mappings: {}", diff);
+    for line in diff.details(before, after) {
+        log::info!("  {}", line);
+    }
+    let Some(hwnd) = MAIN_WINDOW.with(|wnd| *wnd.borrow()) else {
+        return;
+    };
+    if clean {
+        toast::show(hwnd, WM_TOAST, "Configuration reloaded", &diff.to_string(), false);
+    } else {
+        toast::show(hwnd, WM_TOAST, "Configuration reloaded with errors",
+                    &format!("Some lines were rejected, see the log; {}", diff), true);
+    }
+}
+
+fn reset_configuration(template: Template) {
+    if refused_by_policy("reset the configuration") {
+        return;
+    }
     MAPPING_FILE_PATH.with(|path| {
         if let Some(mapping_path) = &*path.borrow() {
-            log::info!("Resetting configuration to defaults");
-            match create_default_mapping_file(mapping_path) {
+            log::info!("Resetting configuration to the {} template", template.name());
+            if let Ok(previous) = std::fs::read_to_string(mapping_path) {
+                if backups::save(mapping_path, &previous, "reset").is_some() {
+                    refresh_restore_menu();
+                }
+
+                // Kept beside the mapping file for "Undo Reset"
+                if let Some(undo_path) = undo_reset_path() {
+                    match std::fs::write(&undo_path, &previous) {
+                        Ok(()) => set_undo_reset_enabled(true),
+                        Err(e) => log::error!("Failed to save {}: {}", undo_path.display(), e),
+                    }
+                }
+            }
+            // With shared defaults, resetting just clears the user's overrides
+            let result = if template == Template::UsAnsi && SHARED_MAPPING_PATH.with(|shared| shared.borrow().is_some()) {
+                create_user_override_file(mapping_path)
+            } else {
+                create_default_mapping_file(mapping_path, template)
+            };
+            match result {
                 Ok(_) => {
                     log::info!("Default configuration file created");
                     reload_configuration();
@@ -330,228 +996,1225 @@ fn reset_configuration() {
     });
 }
 
-fn create_default_mapping_file(path: &std::path::Path) -> windows::core::Result<()> {
-    let default_content = include_str!("../A1314_mapping.txt");
-    std::fs::write(path, default_content)
-        .map_err(|e| {
-            log::error!("Failed to write default mapping file: {}", e);
-            windows::core::Error::from_win32()
-        })?;
-    log::info!("Created default mapping file at {}", path.display());
-    Ok(())
-}
-
-unsafe fn register_raw_input(hwnd: HWND) -> windows::core::Result<()> {
-    let devices = [
-        RAWINPUTDEVICE {
-            usUsagePage: 0x01,
-            usUsage: 0x06,
-            dwFlags: RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0),
-            hwndTarget: hwnd,
-        },
-        RAWINPUTDEVICE {
-            usUsagePage: 0x0C,
-            usUsage: 0x01,
-            dwFlags: RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0),
-            hwndTarget: hwnd,
-        },
-        RAWINPUTDEVICE {
-            usUsagePage: 0xFF00,
-            usUsage: 0x01,
-            dwFlags: RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0),
-            hwndTarget: hwnd,
-        },
-        RAWINPUTDEVICE {
-            usUsagePage: 0xFF00,
-            usUsage: 0x03, // Explicitly for some Apple Fn key implementations
-            dwFlags: RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0),
-            hwndTarget: hwnd,
-        },
-        RAWINPUTDEVICE {
-            usUsagePage: 0xFF01, // Another vendor usage page sometimes used by Apple
-            usUsage: 0x01,
-            dwFlags: RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0),
-            hwndTarget: hwnd,
-        },
-    ];
-
-    RegisterRawInputDevices(&devices, std::mem::size_of::<RAWINPUTDEVICE>() as u32)?;
-    Ok(())
+/// `A1314_mapping.txt.bak`, the mapping file as it was before the last reset
+fn undo_reset_path() -> Option<PathBuf> {
+    MAPPING_FILE_PATH.with(|path| {
+        path.borrow().as_ref().map(|path| {
+            let mut bak = path.as_os_str().to_owned();
+            bak.push(".bak");
+            PathBuf::from(bak)
+        })
+    })
 }
 
-extern "system" fn wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    unsafe {
-        match msg {
-            WM_INPUT => {
-                handle_raw_input(lparam);
-                LRESULT(0)
-            }
-            WM_RELOAD_CONFIG => {
-                reload_configuration();
-                LRESULT(0)
-            }
-            WM_RESET_CONFIG => {
-                reset_configuration();
-                LRESULT(0)
-            }
-            WM_EXIT_APP => {
-                log::info!("Exit requested from system tray");
-                PostQuitMessage(0);
-                LRESULT(0)
-            }
-            WM_DESTROY => {
-                log::info!("Received WM_DESTROY, shutting down");
-                PostQuitMessage(0);
-                LRESULT(0)
-            }
-            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+fn set_undo_reset_enabled(enabled: bool) {
+    UNDO_RESET_ITEM.with(|item| {
+        if let Some(item) = &*item.borrow() {
+            item.set_enabled(enabled);
         }
-    }
+    });
 }
 
-const RIM_TYPEHID: u32 = 2;
-const RIM_TYPEKEYBOARD: u32 = 1;
-
-unsafe fn handle_raw_input(lparam: LPARAM) {
-    let hrawinput = HRAWINPUT(lparam.0 as *mut c_void);
-    
-    // First call: get the size of the RAWINPUT structure
-    let mut size = 0u32;
-    GetRawInputData(
-        hrawinput,
-        RID_INPUT,
-        None,
-        &mut size,
-        std::mem::size_of::<RAWINPUTHEADER>() as u32,
-    );
-
-    if size == 0 {
+fn undo_reset() {
+    if refused_by_policy("undo the reset") {
         return;
     }
-
-    // Second call: get the actual RAWINPUT data
-    let mut buffer = vec![0u8; size as usize];
-    let res = GetRawInputData(
-        hrawinput,
-        RID_INPUT,
-        Some(buffer.as_mut_ptr() as *mut c_void),
-        &mut size,
-        std::mem::size_of::<RAWINPUTHEADER>() as u32,
-    );
-
-    if res == u32::MAX {
-        log::error!("Failed to get raw input data");
+    let mapping_path = MAPPING_FILE_PATH.with(|path| path.borrow().clone());
+    let (Some(mapping_path), Some(undo_path)) = (mapping_path, undo_reset_path()) else {
         return;
+    };
+
+    match std::fs::rename(&undo_path, &mapping_path) {
+        Ok(()) => {
+            log::info!("Undid reset, restored {}", mapping_path.display());
+            set_undo_reset_enabled(false);
+            reload_configuration();
+        }
+        Err(e) => log::error!("Failed to undo reset from {}: {}", undo_path.display(), e),
     }
+}
 
-    let raw: &RAWINPUT = &*(buffer.as_ptr() as *const RAWINPUT);
+fn handle_ipc_request(request: &mut ipc::IpcRequest) {
+    let command = request.line.split_whitespace().next().unwrap_or_default();
+    request.response = match command {
+        "status" => status_report(),
+        "dump-events" => journal::dump_recent(),
+        "explain" => explain_report(request.line.split_whitespace().nth(1).unwrap_or_default()),
+        "map" => map_request(&request.line),
+        other => format!("ERROR unknown command '{}'\n", other),
+    };
+}
 
-    if raw.header.dwType == RIM_TYPEHID {
-        let hid = raw.data.hid;
-        let report_size = hid.dwSizeHid as usize;
-        let count = hid.dwCount as usize;
-        let data_ptr = hid.bRawData.as_ptr();
+/// Shows the TOGGLE() states and any suspension countdown under the daemon's name in the tray tooltip
+fn update_tray_tooltip() {
+    let mut tooltip = TRAY_TOOLTIP.to_string();
+    if let Some(left) = suspend::remaining() {
+        tooltip.push_str(&format!("\nSuspended, {} left", suspend::countdown(left)));
+    }
+    if let Some(states) = toggles::summary() {
+        tooltip.push_str(&format!("\n{}", states));
+    }
+    if safe_mode::active() {
+        tooltip.push_str("\nSafe mode: default mappings");
+    }
+    if bluetooth::disconnected_for().is_some() {
+        tooltip.push_str(if bluetooth::gave_up() { "\nKeyboard disconnected" } else { "\nKeyboard disconnected, reconnecting" });
+    }
+    TRAY_ICON.with(|icon| {
+        if let Some(icon) = &*icon.borrow() {
+            if let Err(e) = icon.set_tooltip(Some(tooltip)) {
+                log::warn!("Failed to update the tray tooltip: {}", e);
+            }
+        }
+    });
+}
 
-        for i in 0..count {
-            let report = std::slice::from_raw_parts(
-                data_ptr.add(i * report_size),
-                report_size,
-            );
+const SUSPEND_ITEM_TEXT: &str = "Suspend for 5 Minutes";
 
-            let events = hid_parser::parse_a1314_hid_report(report);
+/// Starts or stops the tray countdown as a suspension starts or ends
+fn suspension_changed(hwnd: HWND) {
+    let suspended = suspend::remaining().is_some();
+    unsafe {
+        if suspended {
+            SetTimer(hwnd, SUSPEND_TIMER_ID, 1000, None);
+        } else {
+            let _ = KillTimer(hwnd, SUSPEND_TIMER_ID);
+        }
+    }
+    SUSPEND_ITEM.with(|item| {
+        if let Some(item) = &*item.borrow() {
+            item.set_text(if suspended { "Resume Now" } else { SUSPEND_ITEM_TEXT });
+        }
+    });
+    update_tray_tooltip();
+}
 
-            GLOBAL_MAPPER.with(|gm| {
-                if let Some(mapper_rc) = &*gm.borrow() {
-                    let mut mapper = mapper_rc.borrow_mut();
-                    for (usage_page, usage, value) in events {
-                        mapper.handle_hid_event(usage_page, usage, value);
-                    }
-                }
-            });
+/// Starts or stops reconnecting as the Apple Bluetooth keyboard goes away or comes back, and
+/// tells the user
+fn keyboard_connection_changed(hwnd: HWND, change: bluetooth::Change) {
+    match change {
+        bluetooth::Change::Disconnected => {
+            unsafe {
+                SetTimer(hwnd, RECONNECT_TIMER_ID, bluetooth::RECONNECT_INTERVAL.as_millis() as u32, None);
+            }
+            toast::show(hwnd, WM_TOAST, "Apple keyboard disconnected",
+                        "Press a key to wake it. The daemon keeps trying to reconnect it.", true);
         }
+        bluetooth::Change::Reconnected => {
+            unsafe {
+                let _ = KillTimer(hwnd, RECONNECT_TIMER_ID);
+            }
+            toast::show(hwnd, WM_TOAST, "Apple keyboard reconnected", "Its keys are remapped again.", false);
+        }
+        bluetooth::Change::Unchanged => return,
     }
+    update_tray_tooltip();
 }
 
-unsafe extern "system" fn keyboard_hook_proc(ncode: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    if ncode >= 0 {
-        let kbd = *(lparam.0 as *const KBDLLHOOKSTRUCT);
-        
-        // Skip inputs injected by this daemon to prevent feedback loops
-        if kbd.dwExtraInfo == action_executor::DAEMON_INJECTION_TAG as usize {
-            return CallNextHookEx(None, ncode, wparam, lparam);
+/// Summary printed by `--status`, built on the window thread from live state
+fn status_report() -> String {
+    let mut out = String::new();
+    let uptime = STARTED_AT.with(|started| started.elapsed().as_secs());
+    out.push_str(&format!("{} v{} (PID {})\n", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), std::process::id()));
+    out.push_str(&format!("Uptime:    {}h {:02}m {:02}s\n", uptime / 3600, (uptime / 60) % 60, uptime % 60));
+
+    match devices::list_keyboards() {
+        Ok(devices) => {
+            let keyboard = devices.iter().find(|d| d.is_apple() && d.kind == "keyboard");
+            match keyboard {
+                Some(d) => out.push_str(&format!("Keyboard:  connected ({}, {:04X}:{:04X})\n",
+                                                 d.product.as_deref().unwrap_or("Apple keyboard"), d.vendor_id, d.product_id)),
+                None => match bluetooth::disconnected_for() {
+                    Some(away) => out.push_str(&format!("Keyboard:  Bluetooth keyboard disconnected {}m {:02}s ago{}\n",
+                                                        away.as_secs() / 60, away.as_secs() % 60,
+                                                        if bluetooth::gave_up() { "" } else { ", reconnecting" })),
+                    None => out.push_str("Keyboard:  no Apple keyboard detected\n"),
+                },
+            }
         }
+        Err(e) => out.push_str(&format!("Keyboard:  {}\n", e)),
+    }
 
-        let msg = wparam.0 as u32;
-        let is_up = msg == WM_KEYUP || msg == WM_SYSKEYUP;
-        let vk = kbd.vkCode;
-        
-        // Translate VK to HID Usage (Usage Page 0x07)
-        let usage = match vk {
-            0x41..=0x5A => vk as u16 - 0x41 + 4, // A-Z (0x41='A' -> Usage 0x04)
-            0x30 => 0x27, // '0' -> Usage 0x27
-            0x31..=0x39 => vk as u16 - 0x31 + 0x1E, // 1-9 (0x31='1' -> Usage 0x1E)
-            0x0D => 0x28, // ENTER -> Usage 0x28
-            0x1B => 0x29, // ESCAPE -> Usage 0x29
-            0x08 => 0x2A, // BACKSPACE -> Usage 0x2A
-            0x09 => 0x2B, // TAB -> Usage 0x2B
-            0x20 => 0x2C, // SPACE -> Usage 0x2C
-            0x25 => 0x50, // LEFT -> Usage 0x50
-            0x26 => 0x52, // UP -> Usage 0x52
-            0x27 => 0x4F, // RIGHT -> Usage 0x4F
-            0x28 => 0x51, // DOWN -> Usage 0x51
-            0x2E => 0x4C, // DELETE -> Usage 0x4C (Forward Delete)
-            0x70..=0x7B => vk as u16 - 0x70 + 0x3A, // F1-F12 (0x70=F1 -> Usage 0x3A)
-            _ => 0,
+    MAPPING_FILE_PATH.with(|path| {
+        if let Some(path) = &*path.borrow() {
+            out.push_str(&format!("Profile:   default ({})\n", path.display()));
+        }
+    });
+    if safe_mode::active() {
+        out.push_str("Safe mode: on after repeated crashes; default mappings, no keys held back\n");
+    }
+    if let Some(policy) = policy::current() {
+        let check = if policy.sha256.is_some() { "hash checked on every load" } else { "no hash set" };
+        out.push_str(&format!("Policy:    mapping file fixed by HKLM\\{}, {}\n", policy::POLICY_KEY, check));
+    }
+
+    GLOBAL_MAPPER.with(|gm| {
+        if let Some(mapper) = &*gm.borrow() {
+            let counts = mapper.borrow().mapping_counts();
+            let total: usize = counts.iter().map(|(_, n)| n).sum();
+            let detail: Vec<String> = counts.iter().map(|(layer, n)| format!("{} {}", layer, n)).collect();
+            out.push_str(&format!("Mappings:  {} ({})\n", total, detail.join(", ")));
+            for warning in mapper.borrow().warnings() {
+                out.push_str(&format!("Warning:   line {}: {}\n", warning.line, warning.message));
+            }
+        }
+    });
+    LAST_LOAD_ERROR.with(|error| match &*error.borrow() {
+        Some(e) => out.push_str(&format!("Config:    {}\n", e)),
+        None => out.push_str("Config:    OK\n"),
+    });
+
+    if let Some(left) = suspend::remaining() {
+        out.push_str(&format!("Suspended: {} left\n", suspend::countdown(left)));
+    }
+    if let Some(states) = toggles::summary() {
+        out.push_str(&format!("Toggles:   {}\n", states));
+    }
+    out.push_str(&format!("Launcher:  {}\n", launcher::current().describe()));
+    match scroll::rule() {
+        settings::NaturalScroll::Off => {}
+        settings::NaturalScroll::All => out.push_str("Scrolling: natural on every mouse\n"),
+        settings::NaturalScroll::Devices(devices) => {
+            out.push_str(&format!("Scrolling: natural on {} listed device(s)\n", devices.len()));
+        }
+    }
+    let (requested, driver) = (REQUESTED_DRIVER.with(|d| d.get()), INPUT_DRIVER.with(|d| d.get()));
+    if requested == driver {
+        out.push_str(&format!("Input:     {}\n", driver.name()));
+    } else {
+        out.push_str(&format!("Input:     {} ({} unavailable)\n", driver.name(), requested.name()));
+    }
+    for line in latency::summary() {
+        out.push_str(&format!("Latency:   {}\n", line));
+    }
+
+    out.push_str(&format!("UIAccess:  {}\n", if has_ui_access() { "yes" } else { "no" }));
+    out.push_str(&format!("Portable:  {}\n", if is_portable() { "yes" } else { "no" }));
+    out
+}
+
+/// What the running mapper does with a key, for `--explain`
+fn explain_report(key_name: &str) -> String {
+    GLOBAL_MAPPER.with(|gm| match &*gm.borrow() {
+        Some(mapper) => mapper.borrow().explain(key_name).unwrap_or_else(|e| format!("ERROR {}\n", e)),
+        None => "ERROR no mappings loaded\n".to_string(),
+    })
+}
+
+const MAP_USAGE: &str = "map add \"LHS\" \"ACTION\" [--save] | map remove \"LHS\" [--save]";
+
+/// `map add` and `map remove`: changes a mapping in the running mapper right away. With --save
+/// the mapping file gets the same change, and the reload that follows keeps the two in step.
+fn map_request(line: &str) -> String {
+    if policy::is_locked() {
+        return format!("ERROR the mappings are fixed by policy ({})\n", policy::POLICY_KEY);
+    }
+    let args = match ipc::split_args(line) {
+        Ok(args) => args,
+        Err(e) => return format!("ERROR {}\n", e),
+    };
+    let save = args.iter().any(|arg| arg == "--save");
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|arg| *arg != "--save").collect();
+
+    let result = GLOBAL_MAPPER.with(|gm| {
+        let Some(mapper) = &*gm.borrow() else {
+            return Err("no mappings loaded".to_string());
         };
+        let mut mapper = mapper.borrow_mut();
+        match args.as_slice() {
+            [_, "add", lhs, action] => mapper.add_mapping(&format!("{} = {}", lhs, action)),
+            [_, "remove", lhs] => mapper.remove_mapping(lhs),
+            _ => Err(format!("usage: {}", MAP_USAGE)),
+        }
+    });
+    if let Err(e) = result {
+        return format!("ERROR {}\n", e);
+    }
+    test_bench::refresh();
+    update_pointer_input();
 
-        if usage != 0 {
-            let mut should_suppress = false;
-            GLOBAL_MAPPER.with(|gm| {
-                if let Some(mapper_rc) = &*gm.borrow() {
-                    let mut mapper = mapper_rc.borrow_mut();
-                    
-                    if !is_up {
-                        // Check for mapping and trigger it
-                        if mapper.try_trigger_mapping(0x07, usage, 1) {
-                            SUPPRESSED_KEYS.with(|sk| sk.borrow_mut().insert(vk));
-                            should_suppress = true;
-                        }
-                    } else {
-                        // If it's an UP event, check if we suppressed the corresponding DOWN
-                        let was_suppressed = SUPPRESSED_KEYS.with(|sk| sk.borrow_mut().remove(&vk));
-                        if was_suppressed {
-                            should_suppress = true;
-                        }
-                        // Always update state for modifiers etc.
-                        mapper.handle_hid_event(0x07, usage, 0);
+    if !save {
+        return "OK (until the mapping file is next reloaded)\n".to_string();
+    }
+    let Some(mapping_path) = MAPPING_FILE_PATH.with(|path| path.borrow().clone()) else {
+        return "ERROR changed in memory, but there is no mapping file to save to\n".to_string();
+    };
+    let saved = match args.as_slice() {
+        [_, "add", lhs, action] => append_mapping(&mapping_path, &format!("{} = {}", lhs, action)),
+        [_, _, lhs] => comment_out_mappings(&mapping_path, lhs),
+        _ => unreachable!("checked above"),
+    };
+    match saved {
+        Ok(()) => format!("OK saved to {}\n", mapping_path.display()),
+        Err(e) => format!("ERROR changed in memory, but not saved to {}: {}\n", mapping_path.display(), e),
+    }
+}
+
+/// Adds a mapping line at the end of the file, under a [mappings] header if the file ends in
+/// another section
+fn append_mapping(path: &std::path::Path, line: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let text = std::fs::read_to_string(path)?;
+    if config_json::is_json(&text) {
+        return Err(std::io::Error::other("the mapping file is JSON; edit it or export it again"));
+    }
+    let last_section = text.lines()
+        .map(str::trim)
+        .rfind(|line| line.starts_with('[') && line.ends_with(']'));
+    let mut addition = String::new();
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    if !text.is_empty() && !text.ends_with('\n') {
+        addition.push_str(newline);
+    }
+    if last_section.is_some_and(|section| !section[1..section.len() - 1].trim().eq_ignore_ascii_case("mappings")) {
+        addition.push_str(&format!("{0}[mappings]{0}", newline));
+    }
+    addition.push_str(line);
+    addition.push_str(newline);
+    std::fs::OpenOptions::new().append(true).open(path)?.write_all(addition.as_bytes())
+}
+
+/// Comments out every line of the file that maps what `lhs` names, leaving the text to bring back
+fn comment_out_mappings(path: &std::path::Path, lhs: &str) -> std::io::Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    if config_json::is_json(&text) {
+        return Err(std::io::Error::other("the mapping file is JSON; edit it or export it again"));
+    }
+    // Lines keep their own endings, so a CRLF file stays CRLF
+    let mut found = false;
+    let edited: String = text.split_inclusive('\n').map(|line| {
+        if !line.trim_start().starts_with('#') && config::line_maps(line.trim(), lhs) {
+            found = true;
+            format!("# {}", line)
+        } else {
+            line.to_string()
+        }
+    }).collect();
+    if !found {
+        // Only in the shared defaults, or added without --save
+        return Err(std::io::Error::other(format!("no line for {} in the file", lhs)));
+    }
+    std::fs::write(path, edited)
+}
+
+/// `--map add LHS ACTION [--save]` / `--map remove LHS [--save]`, sent to the running daemon
+fn send_map_command(args: &[String]) {
+    let words: Vec<String> = args.iter()
+        .enumerate()
+        .map(|(i, arg)| if i == 0 || arg == "--save" { arg.clone() } else { ipc::quote(arg) })
+        .collect();
+    match ipc::query(&format!("map {}", words.join(" "))) {
+        Ok(response) => {
+            print!("{}", response);
+            if response.starts_with("ERROR") {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            println!("A1314 Daemon is not running ({}); edit the mapping file instead", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Asks the running daemon to explain a key, or loads the mapping file here if it isn't running
+fn print_explanation(key_name: Option<&str>) {
+    let Some(key_name) = key_name else {
+        println!("--explain takes a key name, e.g. --explain KEY_F1");
+        std::process::exit(1);
+    };
+    let explanation = match ipc::query(&format!("explain {}", key_name)) {
+        Ok(response) => response,
+        Err(_) => {
+            let exe_path = std::env::current_exe().expect("Failed to get executable path");
+            let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+            let (mapping_path, shared_path) = resolve_mapping_paths(exe_dir);
+            let mut mapper = KeyMapper::new();
+            mapper.set_shared_defaults(shared_path);
+            // Lines with errors are skipped, the rest still explain the key
+            if let Err(e) = mapper.load_mapping_file(&mapping_path) {
+                println!("{}\n", e);
+            }
+            mapper.explain(key_name).unwrap_or_else(|e| format!("ERROR {}\n", e))
+        }
+    };
+    print!("{}", explanation);
+    if explanation.starts_with("ERROR") {
+        std::process::exit(1);
+    }
+}
+
+fn print_status() {
+    match ipc::query("status") {
+        Ok(response) => print!("{}", response),
+        Err(e) => {
+            println!("A1314 Daemon is not running ({})", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Prints the mapping file in use (over its shared defaults) as parsed, in the given format
+fn export_config(format: Option<&str>) {
+    if !format.is_some_and(|format| format.eq_ignore_ascii_case("json")) {
+        println!("--export-config takes the output format: --export-config json");
+        std::process::exit(1);
+    }
+    let (mapper, _) = load_for_export();
+    print!("{}", config_json::export(&mapper));
+}
+
+/// Prints the mapping file in use (over its shared defaults) as a cheat sheet, HTML or Markdown
+fn export_cheatsheet(format: Option<&str>) {
+    let Some(format) = format.and_then(cheatsheet::Format::parse) else {
+        println!("--export-cheatsheet takes the output format: --export-cheatsheet html|md");
+        std::process::exit(1);
+    };
+    let (mapper, mapping_path) = load_for_export();
+    let title = format!("A1314 key mappings ({})", mapping_path.display());
+    print!("{}", cheatsheet::export(&mapper, format, &title));
+}
+
+/// A mapper loaded from the mapping file in use over its shared defaults, and that file's path
+fn load_for_export() -> (KeyMapper, PathBuf) {
+    let exe_path = std::env::current_exe().expect("Failed to get executable path");
+    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+    let (mapping_path, shared_path) = resolve_mapping_paths(exe_dir);
+
+    let mut mapper = KeyMapper::new();
+    mapper.set_shared_defaults(shared_path);
+    // Rejected lines are left out of the export; say so where it can't end up in a redirected file
+    if let Err(e) = mapper.load_mapping_file(&mapping_path) {
+        eprintln!("{}", e);
+    }
+    (mapper, mapping_path)
+}
+
+/// Loads a mapping file (the one in use, over its shared defaults, unless one is given) and
+/// prints every error and warning; exits with 1 if any line was rejected
+fn check_mapping_file(path: Option<PathBuf>) {
+    let exe_path = std::env::current_exe().expect("Failed to get executable path");
+    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+    let (mapping_path, shared_path) = match path {
+        Some(path) => (path, None),
+        None => resolve_mapping_paths(exe_dir),
+    };
+
+    let mut mapper = KeyMapper::new();
+    mapper.set_shared_defaults(shared_path);
+    let problems = match mapper.load_mapping_file(&mapping_path) {
+        Ok(()) => Vec::new(),
+        Err(error::A1314Error::Config { problems, .. }) => problems,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", mapping_path.display());
+    for problem in &problems {
+        println!("  {}: error: {}", problem.location(), problem.message);
+    }
+    for warning in mapper.warnings() {
+        println!("  line {}: warning: {}", warning.line, warning.message);
+    }
+    println!("{} error(s), {} warning(s)", problems.len(), mapper.warnings().len());
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+/// Replaces the mapping file in use with a template, keeping a backup; a running daemon picks the
+/// new file up like any other edit. Without a name, lists the templates.
+fn write_template(name: Option<&str>) {
+    let Some(template) = name.and_then(Template::parse) else {
+        if let Some(name) = name {
+            println!("Unknown template '{}'", name);
+        }
+        println!("Templates for --template:");
+        for template in Template::ALL {
+            println!("  {:<14} {}", template.name(), template.describe());
+        }
+        std::process::exit(if name.is_some() { 1 } else { 0 });
+    };
+    if policy::is_locked() {
+        println!("The mapping file is fixed by policy (HKLM\\{}); --template is disabled", policy::POLICY_KEY);
+        std::process::exit(1);
+    }
+    let exe_path = std::env::current_exe().expect("Failed to get executable path");
+    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+    let (mapping_path, _) = resolve_mapping_paths(exe_dir);
+
+    if let Ok(previous) = std::fs::read_to_string(&mapping_path) {
+        if let Some(backup) = backups::save(&mapping_path, &previous, "reset") {
+            println!("Previous mapping file saved as {}", backup.display());
+        }
+    }
+    if let Some(dir) = mapping_path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    match std::fs::write(&mapping_path, template.render()) {
+        Ok(()) => println!("Wrote the {} template to {}", template.name(), mapping_path.display()),
+        Err(e) => {
+            println!("Failed to write {}: {}", mapping_path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Times the parser and mapper on a canned report stream against the mapping file in use
+fn run_bench(iterations: Option<&str>) {
+    let iterations = match iterations.map(str::parse::<usize>) {
+        None => bench::DEFAULT_ITERATIONS,
+        Some(Ok(n)) if n > 0 => n,
+        Some(_) => {
+            println!("--bench takes a positive number of iterations");
+            std::process::exit(1);
+        }
+    };
+    let exe_path = std::env::current_exe().expect("Failed to get executable path");
+    let exe_dir = exe_path.parent().expect("Failed to get executable directory");
+    let (mapping_path, _) = resolve_mapping_paths(exe_dir);
+
+    match bench::run(&mapping_path, iterations) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_recent_events() {
+    match ipc::query("dump-events") {
+        Ok(response) => print!("{}", response),
+        Err(e) => {
+            println!("A1314 Daemon is not running ({})", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Streams the daemon's parsed events to stdout until it exits or the pipe breaks
+fn print_capture(exclusive: bool) {
+    use std::io::Write;
+
+    let result = ipc::stream(exclusive, |line| {
+        println!("{}", line);
+        let _ = std::io::stdout().flush();
+    });
+    if let Err(e) = result {
+        println!("A1314 Daemon is not running ({})", e);
+        std::process::exit(1);
+    }
+}
+
+fn print_devices() {
+    let devices = match devices::list_keyboards() {
+        Ok(devices) => devices,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if devices.is_empty() {
+        println!("No raw input keyboards found");
+        return;
+    }
+
+    println!("{:<9} {:<4} {:<9} {:<9} {:<32} PATH", "KIND", "LINK", "VID:PID", "USAGE", "PRODUCT");
+    for d in devices {
+        println!("{:<9} {:<4} {:04X}:{:04X} {:04X}:{:04X} {:<32} {}{}",
+                 d.kind, d.transport.label(), d.vendor_id, d.product_id, d.usage_page, d.usage,
+                 d.product.as_deref().unwrap_or("-"), d.path,
+                 if d.is_apple() { "  [Apple]" } else { "" });
+    }
+}
+
+fn open_test_bench() {
+    let mapper = GLOBAL_MAPPER.with(|gm| gm.borrow().clone());
+    let mapping_path = MAPPING_FILE_PATH.with(|path| path.borrow().clone());
+    if let (Some(mapper), Some(mapping_path)) = (mapper, mapping_path) {
+        if let Err(e) = test_bench::open(mapper, mapping_path) {
+            log::error!("Failed to open mapping test bench: {}", e);
+        }
+    }
+}
+
+/// Checks for and stages an update on a worker thread so the message loop keeps running
+fn check_for_updates() {
+    std::thread::spawn(|| {
+        use windows::Win32::UI::WindowsAndMessaging::{MessageBoxW, MB_ICONINFORMATION, MB_OK};
+
+        let result = updater::check_and_stage();
+        if let Err(e) = &result {
+            log::error!("Update check failed: {}", e);
+        }
+
+        let text = widestring(&updater::describe(&result));
+        let caption = widestring("A1314 Daemon");
+        unsafe {
+            MessageBoxW(None, PCWSTR(text.as_ptr()), PCWSTR(caption.as_ptr()), MB_OK | MB_ICONINFORMATION);
+        }
+    });
+}
+
+fn toggle_autostart() {
+    let result = match autostart() {
+        Autostart::User => uninstall_service(InstallOptions::default()),
+        Autostart::Off => install_service(InstallOptions::default()),
+        // Adding a per-user entry as well would start two daemons at logon
+        other => {
+            log::warn!("Not changing Windows startup: {}", other.menu_text());
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        log::error!("Failed to change Windows startup setting: {}", e);
+    }
+
+    // Reflect whatever the registry and Task Scheduler actually say now, even if the change failed
+    let autostart = autostart();
+    AUTOSTART_ITEM.with(|item| {
+        if let Some(item) = &*item.borrow() {
+            item.set_checked(autostart != Autostart::Off);
+            item.set_text(autostart.menu_text());
+            item.set_enabled(!is_portable() && autostart.is_toggleable());
+        }
+    });
+}
+
+fn capture_journal() {
+    MAPPING_FILE_PATH.with(|path| {
+        if let Some(mapping_path) = &*path.borrow() {
+            let stamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let journal_path = mapping_path.with_file_name(format!("A1314_journal_{}.txt", stamp));
+
+            match journal::dump_to_file(&journal_path) {
+                Ok(_) => {
+                    log::info!("Event journal written to {}", journal_path.display());
+                    // Open it right away so the user can attach it to a bug report
+                    let file = widestring(&journal_path.to_string_lossy());
+                    let verb = widestring("open");
+                    unsafe {
+                        ShellExecuteW(None, PCWSTR(verb.as_ptr()), PCWSTR(file.as_ptr()), None, None, SW_SHOWNORMAL);
                     }
                 }
-            });
+                Err(e) => {
+                    log::error!("Failed to write event journal to {}: {}", journal_path.display(), e);
+                }
+            }
+        }
+    });
+}
+
+/// Shared defaults under %ProgramData%, written by `--install --all-users`
+fn shared_mapping_file() -> Option<PathBuf> {
+    std::env::var_os("ProgramData")
+        .map(|dir| PathBuf::from(dir).join("A1314Daemon").join("A1314_mapping.txt"))
+}
+
+/// Picks the mapping file to load and watch, plus any shared defaults layered underneath it.
+/// A mapping file next to the exe always wins (per-user and portable installs); otherwise,
+/// when machine-wide defaults exist, each user gets an override file under %APPDATA%.
+fn resolve_mapping_paths(exe_dir: &std::path::Path) -> (PathBuf, Option<PathBuf>) {
+    if let Some(policy) = policy::current() {
+        return (policy.config_path.clone(), None);
+    }
+    let local = exe_dir.join("A1314_mapping.txt");
+    if is_portable() || local.exists() {
+        return (local, None);
+    }
+
+    let shared = shared_mapping_file().filter(|path| path.exists());
+    let user = std::env::var_os("APPDATA")
+        .map(|dir| PathBuf::from(dir).join("A1314Daemon").join("A1314_mapping.txt"));
+    match (shared, user) {
+        (Some(shared), Some(user)) => (user, Some(shared)),
+        _ => (local, None),
+    }
+}
+
+/// Starts a user's override file on top of the shared defaults
+fn create_user_override_file(path: &std::path::Path) -> windows::core::Result<()> {
+    let shared = shared_mapping_file().map(|p| p.display().to_string()).unwrap_or_default();
+    let content = format!(
+        "VERSION={}\n\
+         # Personal A1314 mappings\n\
+         # The shared defaults in {} are loaded first.\n\
+         # Any key mapped here replaces the shared mapping for that key,\n\
+         # and [settings] here override the shared settings.\n",
+        migration::CURRENT_VERSION, shared);
+
+    if let Some(dir) = path.parent() {
+        let _ = std::fs::create_dir_all(dir);
+    }
+    std::fs::write(path, content)
+        .map_err(|e| {
+            log::error!("Failed to write mapping override file: {}", e);
+            windows::core::Error::from_win32()
+        })?;
+    log::info!("Created mapping override file at {}", path.display());
+    Ok(())
+}
+
+fn create_default_mapping_file(path: &std::path::Path, template: Template) -> windows::core::Result<()> {
+    std::fs::write(path, template.render())
+        .map_err(|e| {
+            log::error!("Failed to write default mapping file: {}", e);
+            windows::core::Error::from_win32()
+        })?;
+    log::info!("Created {} mapping file at {}", template.name(), path.display());
+    Ok(())
+}
 
-            if should_suppress {
-                return LRESULT(1); // Suppress the physical key event
+// HID collections the daemon listens to: the keyboard, consumer keys and Apple's vendor pages
+const RAW_INPUT_USAGES: [(u16, u16); 5] = [
+    (0x01, 0x06),
+    (0x0C, 0x01),
+    (0xFF00, 0x01),
+    (0xFF00, 0x03), // Explicitly for some Apple Fn key implementations
+    (0xFF01, 0x01), // Another vendor usage page sometimes used by Apple
+];
+
+// Raw input registered on demand (see set_raw_input): mice and precision touchpads
+const MOUSE_USAGE: (u16, u16) = (0x01, 0x02);
+const TOUCH_PAD_USAGE: (u16, u16) = (gestures::DIGITIZER_PAGE, gestures::TOUCH_PAD);
+
+unsafe fn register_raw_input(hwnd: HWND) -> windows::core::Result<()> {
+    // Keyboards coming and going are reported too (WM_INPUT_DEVICE_CHANGE), for the Bluetooth
+    // keyboard's connection status
+    let devices = RAW_INPUT_USAGES.map(|(usage_page, usage)| RAWINPUTDEVICE {
+        usUsagePage: usage_page,
+        usUsage: usage,
+        dwFlags: if (usage_page, usage) == (0x01, 0x06) { RIDEV_INPUTSINK | RIDEV_DEVNOTIFY } else { RIDEV_INPUTSINK },
+        hwndTarget: hwnd,
+    });
+
+    RegisterRawInputDevices(&devices, std::mem::size_of::<RAWINPUTDEVICE>() as u32)?;
+    Ok(())
+}
+
+unsafe fn unregister_raw_input() -> windows::core::Result<()> {
+    let devices = RAW_INPUT_USAGES.map(|(usage_page, usage)| RAWINPUTDEVICE {
+        usUsagePage: usage_page,
+        usUsage: usage,
+        dwFlags: RIDEV_REMOVE,
+        hwndTarget: HWND(null_mut()),
+    });
+
+    RegisterRawInputDevices(&devices, std::mem::size_of::<RAWINPUTDEVICE>() as u32)?;
+    log::info!("Raw input unregistered");
+    Ok(())
+}
+
+extern "system" fn wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_INPUT => {
+                handle_raw_input(lparam);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == LONG_PRESS_TIMER_ID => {
+                GLOBAL_MAPPER.with(|gm| {
+                    if let Some(mapper_rc) = &*gm.borrow() {
+                        let mut mapper = mapper_rc.borrow_mut();
+                        mapper.fire_long_presses(std::time::Instant::now());
+                        schedule_long_press(&mapper);
+                    }
+                });
+                LRESULT(0)
+            }
+            WM_RELOAD_CONFIG => {
+                reload_configuration();
+                LRESULT(0)
+            }
+            WM_WTSSESSION_CHANGE if matches!(wparam.0 as u32, WTS_SESSION_LOCK | WTS_SESSION_UNLOCK) => {
+                action_executor::forget_physical_keys();
+                LRESULT(0)
+            }
+            WM_RESET_CONFIG => {
+                if let Some(template) = Template::ALL.get(wparam.0) {
+                    reset_configuration(*template);
+                }
+                LRESULT(0)
+            }
+            WM_TOGGLE_AUTOSTART => {
+                toggle_autostart();
+                LRESULT(0)
+            }
+            WM_CAPTURE_JOURNAL => {
+                capture_journal();
+                LRESULT(0)
+            }
+            WM_COPY_EVENTS => {
+                match clipboard::set_text(&journal::dump_recent()) {
+                    Ok(()) => log::info!("Recent events copied to the clipboard"),
+                    Err(e) => log::error!("Failed to copy recent events: {}", e),
+                }
+                LRESULT(0)
+            }
+            WM_TOGGLES_CHANGED => {
+                update_tray_tooltip();
+                LRESULT(0)
+            }
+            WM_SHOW_LEVEL => {
+                osd::show_level();
+                LRESULT(0)
+            }
+            WM_SUSPEND => {
+                if suspend::remaining().is_some() {
+                    suspend::resume();
+                } else {
+                    suspend::suspend(TRAY_SUSPEND_DURATION);
+                }
+                LRESULT(0)
+            }
+            WM_SHOW_KEYSTROKES => {
+                let on = !osd::showing_keystrokes();
+                osd::set_show_keystrokes(on);
+                KEYSTROKES_ITEM.with(|item| {
+                    if let Some(item) = item.borrow().as_ref() {
+                        item.set_checked(on);
+                    }
+                });
+                LRESULT(0)
+            }
+            WM_SUSPEND_CHANGED => {
+                suspension_changed(hwnd);
+                LRESULT(0)
+            }
+            WM_SCROLL_CHANGED => {
+                update_pointer_input();
+                LRESULT(0)
+            }
+            WM_INTERCEPTED_KEY => LRESULT(filter_key(wparam.0 as u32, lparam.0 != 0) as isize),
+            WM_INPUT_DEVICE_CHANGE => {
+                let device = windows::Win32::Foundation::HANDLE(lparam.0 as *mut c_void);
+                let change = match wparam.0 as u32 {
+                    GIDC_ARRIVAL => bluetooth::device_arrived(device),
+                    GIDC_REMOVAL => bluetooth::device_removed(device),
+                    _ => bluetooth::Change::Unchanged,
+                };
+                keyboard_connection_changed(hwnd, change);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == RECONNECT_TIMER_ID => {
+                if !bluetooth::reconnect() {
+                    let _ = KillTimer(hwnd, RECONNECT_TIMER_ID);
+                    toast::show(hwnd, WM_TOAST, "Apple keyboard still disconnected",
+                                "Switch the keyboard on, or connect it again in Bluetooth settings.", true);
+                }
+                update_tray_tooltip();
+                LRESULT(0)
+            }
+            WM_TOAST => {
+                toast::handle_message(hwnd, lparam);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == SUSPEND_TIMER_ID => {
+                if suspend::remaining().is_none() {
+                    let _ = KillTimer(hwnd, SUSPEND_TIMER_ID);
+                }
+                update_tray_tooltip();
+                LRESULT(0)
+            }
+            WM_OPEN_TEST_BENCH => {
+                open_test_bench();
+                LRESULT(0)
+            }
+            WM_CHECK_UPDATES => {
+                check_for_updates();
+                LRESULT(0)
+            }
+            WM_UNDO_RESET => {
+                undo_reset();
+                LRESULT(0)
+            }
+            WM_RESTORE_BACKUP => {
+                restore_backup(wparam.0);
+                LRESULT(0)
+            }
+            WM_IPC_REQUEST => {
+                // Sent (not posted) by the IPC thread, which waits for the response
+                if let Some(request) = (lparam.0 as *mut ipc::IpcRequest).as_mut() {
+                    handle_ipc_request(request);
+                }
+                LRESULT(0)
+            }
+            WM_EXIT_APP => {
+                log::info!("Exit requested from system tray");
+                shutdown();
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            // Windows ends the process after this at logoff or shutdown, which isn't a crash
+            WM_ENDSESSION if wparam.0 != 0 => {
+                log::info!("Windows session ending, shutting down");
+                shutdown();
+                LRESULT(0)
+            }
+            WM_DESTROY => {
+                log::info!("Received WM_DESTROY, shutting down");
+                shutdown();
+                PostQuitMessage(0);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+unsafe fn handle_raw_input(lparam: LPARAM) {
+    let received = std::time::Instant::now();
+    let Some(mut backend) = RawInputBackend::from_wm_input(lparam) else {
+        return;
+    };
+
+    // Events are still parsed and journaled while remapping is paused, just not mapped
+    let routing = routing();
+    let gestures = backend.take_gestures();
+
+    GLOBAL_MAPPER.with(|gm| {
+        if let Some(mapper_rc) = &*gm.borrow() {
+            let mut mapper = mapper_rc.borrow_mut();
+            for key in gestures {
+                journal::record(JournalEvent::Hid { usage_page: key.usage_page, usage: key.usage, value: 1 });
+                ipc::forward(format_args!("hid {:04X}:{:04X} 1", key.usage_page, key.usage));
+                if routing == Routing::Map {
+                    mapper.gesture(key);
+                }
+            }
+            latency::input_received(Some(received));
+            input_backend::dispatch(&mut backend, &mut mapper, routing);
+            latency::input_received(None);
+            schedule_long_press(&mapper);
+        }
+    });
+}
+
+/// Where raw input goes right now: nowhere but the journal while a capture-only client is
+/// attached, remapping is suspended or game mode found a fullscreen game in front, otherwise
+/// as the app lists say
+fn routing() -> Routing {
+    if ipc::capture_exclusive() || suspend::remaining().is_some() || foreground::game_in_front() {
+        return Routing::CaptureOnly;
+    }
+    match foreground::app_mode() {
+        AppMode::Remap => Routing::Map,
+        AppMode::Excluded => Routing::CaptureOnly,
+        AppMode::Passthrough => Routing::Passthrough,
+    }
+}
+
+/// Whether keys should reach Windows untouched right now
+fn remapping_paused() -> bool {
+    routing() != Routing::Map
+}
+
+fn default_log_level() -> log::LevelFilter {
+    if cfg!(debug_assertions) { log::LevelFilter::Debug } else { log::LevelFilter::Info }
+}
+
+/// Applies log_level from [settings], unless RUST_LOG chose the level
+fn apply_log_level() {
+    if LOG_LEVEL_FROM_ENV.load(Ordering::Relaxed) {
+        return;
+    }
+    let level = settings::current().log_level.unwrap_or_else(default_log_level);
+    if log::max_level() != level {
+        log::set_max_level(level);
+        log::info!("Log level: {}", level);
+    }
+}
+
+/// Whether an auto-repeat of mapped key `vk` runs its action again, per repeat_delay_ms and
+/// repeat_interval_ms from [settings]; where those are 0, Windows' delay and rate apply
+fn repeat_due(vk: u32) -> bool {
+    let (delay_ms, interval_ms) = {
+        let settings = settings::current();
+        (settings.repeat_delay_ms, settings.repeat_interval_ms)
+    };
+    let Some(delay_ms) = delay_ms else {
+        return false;
+    };
+    let now = std::time::Instant::now();
+    KEY_REPEATS.with(|repeats| match repeats.borrow_mut().get_mut(&vk) {
+        Some((pressed_at, last_fired)) => {
+            let due = now.duration_since(*pressed_at) >= Duration::from_millis(delay_ms)
+                && now.duration_since(*last_fired) >= Duration::from_millis(interval_ms);
+            if due {
+                *last_fired = now;
+            }
+            due
+        }
+        None => true,
+    })
+}
+
+/// (Re)arms the long press timer for the mapper's next pending long press, or stops it
+fn schedule_long_press(mapper: &KeyMapper) {
+    let Some(hwnd) = MAIN_WINDOW.with(|wnd| *wnd.borrow()) else {
+        return;
+    };
+    unsafe {
+        match mapper.next_long_press() {
+            Some(deadline) => {
+                let delay = deadline.saturating_duration_since(std::time::Instant::now());
+                SetTimer(hwnd, LONG_PRESS_TIMER_ID, (delay.as_millis() as u32).max(1), None);
+            }
+            None => {
+                let _ = KillTimer(hwnd, LONG_PRESS_TIMER_ID);
+            }
+        }
+    }
+}
+
+/// Handles raw input already waiting in the queue, so the report of a press is parsed before the
+/// hook event for it is attributed
+unsafe fn drain_raw_input() {
+    let mut msg = MSG::default();
+    while PeekMessageW(&mut msg, None, WM_INPUT, WM_INPUT, PM_REMOVE).as_bool() {
+        handle_raw_input(msg.lParam);
+    }
+}
+
+unsafe extern "system" fn keyboard_hook_proc(ncode: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if ncode >= 0 {
+        let kbd = *(lparam.0 as *const KBDLLHOOKSTRUCT);
+        
+        // Skip inputs injected by this daemon to prevent feedback loops
+        if action_executor::is_daemon_injection(kbd.dwExtraInfo) {
+            return CallNextHookEx(None, ncode, wparam, lparam);
+        }
+
+        let msg = wparam.0 as u32;
+        let is_up = msg == WM_KEYUP || msg == WM_SYSKEYUP;
+        if filter_key(kbd.vkCode, is_up) {
+            return LRESULT(1); // Suppress the physical key event
+        }
+    }
+    CallNextHookEx(None, ncode, wparam, lparam)
+}
+
+/// Installs the low-level mouse hook while a mapping is for a mouse button or the wheel or some
+/// mouse scrolls naturally, and removes it otherwise, so the hook doesn't see every mouse
+/// movement for nothing. Raw mouse and trackpad input are likewise only read when needed.
+fn update_pointer_input() {
+    let scroll_rule = scroll::rule();
+    set_raw_input(MOUSE_USAGE, matches!(scroll_rule, settings::NaturalScroll::Devices(_)));
+    set_raw_input(TOUCH_PAD_USAGE, GLOBAL_MAPPER.with(|gm| gm.borrow().as_ref().is_some_and(|mapper| mapper.borrow().uses_gestures())));
+    let wanted = scroll_rule != settings::NaturalScroll::Off
+        || GLOBAL_MAPPER.with(|gm| gm.borrow().as_ref().is_some_and(|mapper| mapper.borrow().uses_mouse()));
+    H_MOUSE_HOOK.with(|h| {
+        let mut hook = h.borrow_mut();
+        if wanted && hook.is_none() {
+            let installed = unsafe {
+                windows::Win32::System::LibraryLoader::GetModuleHandleW(None)
+                    .and_then(|hinstance| SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook_proc), hinstance, 0))
+            };
+            match installed {
+                Ok(installed) => {
+                    *hook = Some(installed);
+                    log::info!("Low-level mouse hook installed for mouse mappings");
+                }
+                Err(e) => log::error!("Failed to install the mouse hook, mouse mappings won't work: {}", e),
+            }
+        } else if let Some(installed) = hook.take_if(|_| !wanted) {
+            unsafe {
+                let _ = UnhookWindowsHookEx(installed);
+            }
+            SUPPRESSED_BUTTONS.with(|buttons| buttons.borrow_mut().clear());
+            log::info!("Low-level mouse hook removed, no mapping uses the mouse");
+        }
+    });
+}
+
+/// Registers for raw input from one top-level collection besides the keyboards, or stops
+/// reading it
+fn set_raw_input(usage: (u16, u16), on: bool) {
+    let changed = ON_DEMAND_RAW_INPUT.with(|registered| {
+        let mut registered = registered.borrow_mut();
+        if on { registered.insert(usage) } else { registered.remove(&usage) }
+    });
+    if !changed {
+        return;
+    }
+    let hwnd = MAIN_WINDOW.with(|wnd| *wnd.borrow()).unwrap_or(HWND(null_mut()));
+    let device = RAWINPUTDEVICE {
+        usUsagePage: usage.0,
+        usUsage: usage.1,
+        dwFlags: if on { RAWINPUTDEVICE_FLAGS(RIDEV_INPUTSINK.0) } else { RIDEV_REMOVE },
+        hwndTarget: if on { hwnd } else { HWND(null_mut()) },
+    };
+    match unsafe { RegisterRawInputDevices(&[device], std::mem::size_of::<RAWINPUTDEVICE>() as u32) } {
+        Ok(()) => log::debug!("Raw input {:02X}:{:02X} {}", usage.0, usage.1, if on { "registered" } else { "unregistered" }),
+        Err(e) => log::warn!("Failed to {} raw input {:02X}:{:02X}: {}", if on { "register" } else { "unregister" }, usage.0, usage.1, e),
+    }
+}
+
+unsafe extern "system" fn mouse_hook_proc(ncode: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    if ncode >= 0 {
+        let mouse = *(lparam.0 as *const MSLLHOOKSTRUCT);
+        let message = wparam.0 as u32;
+        if !action_executor::is_daemon_injection(mouse.dwExtraInfo) {
+            if let Some((key, pressed)) = variable_maps::mouse_key(message, mouse.mouseData) {
+                if filter_mouse(key, pressed) {
+                    return LRESULT(1); // Suppress the physical button or wheel event
+                }
+                // Natural scrolling: the wheel event is replaced by one the other way round
+                if key.usage_page == variable_maps::WHEEL_PAGE && !remapping_paused() {
+                    drain_raw_input();
+                    if scroll::reverses_wheel() {
+                        match scroll::send_reversed((mouse.mouseData >> 16) as i16, message == WM_MOUSEHWHEEL) {
+                            Ok(()) => return LRESULT(1),
+                            Err(e) => log::warn!("{}", e),
+                        }
+                    }
+                }
             }
         }
     }
     CallNextHookEx(None, ncode, wparam, lparam)
 }
 
-fn install_service() -> windows::core::Result<()> {
+/// Runs a mouse button or wheel event through the mapper, and returns whether to hold it back
+fn filter_mouse(key: HidKey, pressed: bool) -> bool {
+    let mut should_suppress = false;
+    GLOBAL_MAPPER.with(|gm| {
+        if let Some(mapper_rc) = &*gm.borrow() {
+            let mut mapper = mapper_rc.borrow_mut();
+            if !pressed {
+                should_suppress = SUPPRESSED_BUTTONS.with(|buttons| buttons.borrow_mut().remove(&key.usage));
+                mapper.mouse_event(key, false);
+            } else if !remapping_paused() {
+                should_suppress = mapper.mouse_event(key, true);
+                if key.usage_page == variable_maps::WHEEL_PAGE {
+                    mapper.mouse_event(key, false); // A wheel notch has no release
+                } else if should_suppress {
+                    SUPPRESSED_BUTTONS.with(|buttons| buttons.borrow_mut().insert(key.usage));
+                }
+            }
+            schedule_long_press(&mapper);
+        }
+    });
+    if should_suppress {
+        log::debug!("Mouse {:04X}:{:04X} {} suppressed", key.usage_page, key.usage, if pressed { "down" } else { "up" });
+    }
+    should_suppress
+}
+
+/// Runs a physical key event from the hook or the Interception driver through the mapper, and
+/// returns whether to hold it back
+fn filter_key(vk: u32, is_up: bool) -> bool {
+    // Modifiers aren't remapped here and always reach Windows, so these are the ones the user holds
+    action_executor::note_physical_key(vk, is_up);
+
+    // Translate VK to HID Usage (Usage Page 0x07)
+    if let Some(usage) = variable_maps::hook_usage(vk) {
+        // Only keys attributed to a keyboard whose reports we parse are remapped; a repeat or
+        // release belongs to the keyboard its key-down was attributed to
+        let suppressed_from = SUPPRESSED_KEYS.with(|sk| sk.borrow().get(&vk).copied());
+        let attributed = match suppressed_from {
+            None if !is_up => {
+                unsafe { drain_raw_input() };
+                attribution::take(0x07, usage)
+            }
+            _ => None,
+        };
+        let device = suppressed_from.or(attributed.map(|press| press.device));
+
+        let mut should_suppress = false;
+        GLOBAL_MAPPER.with(|gm| {
+            if let Some(mapper_rc) = &*gm.borrow() {
+                let mut mapper = mapper_rc.borrow_mut();
+                
+                if !is_up {
+                    // Windows auto-repeats a held key; a mapped key's repeats may be held back
+                    let repeat = suppressed_from.is_some();
+                    if repeat && !repeat_due(vk) {
+                        should_suppress = true;
+                    } else if !remapping_paused() {
+                        // Check for mapping and trigger it (unless remapping is paused), once per press
+                        let suppress = match (device, attributed.and_then(|press| press.mapped)) {
+                            // The report already ran this press through the mapper
+                            (Some(_), Some(suppress)) => suppress,
+                            (Some(device), None) => mapper.try_trigger_mapping(device, 0x07, usage, 1),
+                            (None, _) => {
+                                log::debug!("VK 0x{:02X} matched no keyboard report, passing it through", vk);
+                                false
+                            }
+                        };
+                        if let (true, Some(device)) = (suppress, device) {
+                            SUPPRESSED_KEYS.with(|sk| sk.borrow_mut().insert(vk, device));
+                            if !repeat {
+                                let now = std::time::Instant::now();
+                                KEY_REPEATS.with(|repeats| repeats.borrow_mut().insert(vk, (now, now)));
+                            }
+                            should_suppress = true;
+                        }
+                    }
+                } else {
+                    // If it's an UP event, check if we suppressed the corresponding DOWN
+                    let was_suppressed = SUPPRESSED_KEYS.with(|sk| sk.borrow_mut().remove(&vk)).is_some();
+                    KEY_REPEATS.with(|repeats| repeats.borrow_mut().remove(&vk));
+                    if was_suppressed {
+                        should_suppress = true;
+                    }
+                    // Always update state for modifiers etc.
+                    mapper.handle_hid_event(device.unwrap_or_default(), 0x07, usage, 0);
+                }
+                schedule_long_press(&mapper);
+            }
+        });
+
+        // Safe mode never keeps a key from Windows; actions still run from the keyboard's reports
+        let should_suppress = should_suppress && !safe_mode::active();
+        journal::record(JournalEvent::Hook { vk, is_up, device, suppressed: should_suppress });
+        ipc::forward(format_args!("hook {:02X} {}{}", vk, if is_up { "up" } else { "down" },
+                                  if should_suppress { " suppressed" } else { "" }));
+
+        return should_suppress;
+    }
+    false
+}
+
+fn install_service(options: InstallOptions) -> windows::core::Result<()> {
     use windows::Win32::System::Registry::*;
     use windows::core::HSTRING;
 
-    log::info!("Installing A1314 Daemon to start with Windows...");
+    refuse_in_portable_mode("add a startup entry")?;
+
+    log::info!("Installing A1314 Daemon to start with Windows{}...", options.scope_label());
+
+    if options.all_users {
+        require_elevation("--install --all-users")?;
+
+        // Seed the shared defaults that every user's override file is layered on
+        if let Some(shared) = shared_mapping_file() {
+            if !shared.exists() {
+                if let Some(dir) = shared.parent() {
+                    let _ = std::fs::create_dir_all(dir);
+                }
+                create_default_mapping_file(&shared, Template::UsAnsi)?;
+            }
+        }
+    }
 
     let exe_path = std::env::current_exe()
         .expect("Failed to get executable path");
     
-    let key_path = HSTRING::from("Software\\Microsoft\\Windows\\CurrentVersion\\Run");
-    let value_name = HSTRING::from("A1314Daemon");
+    let key_path = HSTRING::from(RUN_KEY_PATH);
+    let value_name = HSTRING::from(RUN_VALUE_NAME);
 
     unsafe {
         let mut hkey = HKEY::default();
         let result = RegOpenKeyExW(
-            HKEY_CURRENT_USER,
+            options.root_key(),
             &key_path,
             0,
             KEY_SET_VALUE,
@@ -560,7 +2223,7 @@ fn install_service() -> windows::core::Result<()> {
 
         if result.is_err() {
             log::error!("Failed to open registry key: {:?}", result);
-            println!("Failed to install. Run as administrator if needed.");
+            say!("Failed to install. Run as administrator if needed.");
             return result.ok();
         }
 
@@ -579,31 +2242,39 @@ fn install_service() -> windows::core::Result<()> {
 
         if result.is_ok() {
             log::info!("Successfully installed A1314 Daemon to start with Windows");
-            println!("âœ“ A1314 Daemon installed successfully!");
-            println!("  The daemon will now start automatically when you log in.");
-            println!("  To uninstall, run: {} --uninstall", exe_path.file_name().unwrap().to_string_lossy());
+            say!("âœ“ A1314 Daemon installed successfully!");
+            say!("  The daemon will now start automatically when {} log in.",
+                 if options.all_users { "users" } else { "you" });
+            say!("  To uninstall, run: {} --uninstall{}", exe_path.file_name().unwrap().to_string_lossy(),
+                 if options.all_users { " --all-users" } else { "" });
         } else {
             log::error!("Failed to set registry value: {:?}", result);
-            println!("Failed to install. Run as administrator if needed.");
+            say!("Failed to install. Run as administrator if needed.");
         }
 
         result.ok()
     }
 }
 
-fn uninstall_service() -> windows::core::Result<()> {
+fn uninstall_service(options: InstallOptions) -> windows::core::Result<()> {
     use windows::Win32::System::Registry::*;
     use windows::core::HSTRING;
 
-    log::info!("Uninstalling A1314 Daemon from Windows startup...");
+    refuse_in_portable_mode("remove the startup entry")?;
 
-    let key_path = HSTRING::from("Software\\Microsoft\\Windows\\CurrentVersion\\Run");
-    let value_name = HSTRING::from("A1314Daemon");
+    log::info!("Uninstalling A1314 Daemon from Windows startup{}...", options.scope_label());
+
+    if options.all_users {
+        require_elevation("--uninstall --all-users")?;
+    }
+
+    let key_path = HSTRING::from(RUN_KEY_PATH);
+    let value_name = HSTRING::from(RUN_VALUE_NAME);
 
     unsafe {
         let mut hkey = HKEY::default();
         let result = RegOpenKeyExW(
-            HKEY_CURRENT_USER,
+            options.root_key(),
             &key_path,
             0,
             KEY_SET_VALUE,
@@ -612,7 +2283,7 @@ fn uninstall_service() -> windows::core::Result<()> {
 
         if result.is_err() {
             log::error!("Failed to open registry key: {:?}", result);
-            println!("Failed to uninstall. The daemon may not be installed.");
+            say!("Failed to uninstall. The daemon may not be installed.");
             return result.ok();
         }
 
@@ -621,17 +2292,293 @@ fn uninstall_service() -> windows::core::Result<()> {
 
         if result.is_ok() {
             log::info!("Successfully uninstalled A1314 Daemon from Windows startup");
-            println!("âœ“ A1314 Daemon uninstalled successfully!");
-            println!("  The daemon will no longer start automatically.");
+            say!("âœ“ A1314 Daemon uninstalled successfully!");
+            say!("  The daemon will no longer start automatically.");
         } else {
             log::error!("Failed to delete registry value: {:?}", result);
-            println!("Failed to uninstall. The daemon may not be installed.");
+            say!("Failed to uninstall. The daemon may not be installed.");
         }
 
         result.ok()
     }
 }
 
+/// Registers a Scheduled Task that starts the daemon at logon with highest privileges,
+/// so injected keys also reach elevated windows. Requires an elevated prompt.
+fn install_task() -> windows::core::Result<()> {
+    use windows::Win32::Foundation::E_FAIL;
+
+    refuse_in_portable_mode("create a scheduled task")?;
+
+    log::info!("Installing A1314 Daemon as a scheduled logon task...");
+
+    let exe_path = std::env::current_exe()
+        .expect("Failed to get executable path");
+
+    // The task must run in the user's interactive session to own the tray icon and hook
+    let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(name)) => format!("{}\\{}", domain, name),
+        (_, Ok(name)) => name,
+        _ => {
+            say!("Failed to install task: could not determine the current user.");
+            return Err(windows::core::Error::new(E_FAIL, "USERNAME is not set"));
+        }
+    };
+
+    let output = std::process::Command::new("schtasks.exe")
+        .args(["/Create", "/F", "/TN", TASK_NAME, "/SC", "ONLOGON", "/RL", "HIGHEST", "/IT", "/RU", &user])
+        .arg("/TR")
+        .arg(format!("\"{}\"", exe_path.display()))
+        .output();
+
+    match output {
+        Ok(out) if out.status.success() => {
+            log::info!("Scheduled task '{}' created for {}", TASK_NAME, user);
+
+            // Starting from both the Run key and the task would launch two daemons
+            if is_autostart_enabled() {
+                log::info!("Removing Run key entry in favor of the scheduled task");
+                let _ = uninstall_service(InstallOptions::default());
+            }
+
+            say!("âœ“ A1314 Daemon scheduled task installed successfully!");
+            say!("  The daemon will start with highest privileges when you log in.");
+            say!("  To uninstall, run: {} --uninstall-task", exe_path.file_name().unwrap().to_string_lossy());
+            Ok(())
+        }
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            log::error!("schtasks failed ({}): {}", out.status, stderr.trim());
+            say!("Failed to install task. Run from an elevated (administrator) prompt.");
+            Err(windows::core::Error::new(E_FAIL, stderr.trim()))
+        }
+        Err(e) => {
+            log::error!("Failed to run schtasks.exe: {}", e);
+            say!("Failed to install task: {}", e);
+            Err(windows::core::Error::new(E_FAIL, e.to_string()))
+        }
+    }
+}
+
+fn uninstall_task() -> windows::core::Result<()> {
+    use windows::Win32::Foundation::E_FAIL;
+
+    refuse_in_portable_mode("remove the scheduled task")?;
+
+    log::info!("Removing A1314 Daemon scheduled task...");
+
+    let output = std::process::Command::new("schtasks.exe")
+        .args(["/Delete", "/F", "/TN", TASK_NAME])
+        .output();
+
+    match output {
+        Ok(out) if out.status.success() => {
+            log::info!("Scheduled task '{}' removed", TASK_NAME);
+            say!("âœ“ A1314 Daemon scheduled task uninstalled successfully!");
+            say!("  The daemon will no longer start automatically.");
+            Ok(())
+        }
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            log::error!("schtasks failed ({}): {}", out.status, stderr.trim());
+            say!("Failed to uninstall task. It may not be installed, or an elevated prompt is required.");
+            Err(windows::core::Error::new(E_FAIL, stderr.trim()))
+        }
+        Err(e) => {
+            log::error!("Failed to run schtasks.exe: {}", e);
+            say!("Failed to uninstall task: {}", e);
+            Err(windows::core::Error::new(E_FAIL, e.to_string()))
+        }
+    }
+}
+
+/// What starts the daemon at logon
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Autostart {
+    Off,
+    User,     // The HKCU Run key, which the tray toggle adds and removes
+    Machine,  // The HKLM Run key, from --install --all-users or the MSI
+    Task,     // The scheduled task of --install-task
+}
+
+impl Autostart {
+    /// The tray item's text, saying what controls startup when the toggle can't
+    fn menu_text(self) -> &'static str {
+        match self {
+            Autostart::Off | Autostart::User => "Start with Windows",
+            Autostart::Machine => "Start with Windows (set for all users; use --uninstall --all-users)",
+            Autostart::Task => "Start with Windows (scheduled task; use --uninstall-task)",
+        }
+    }
+
+    fn is_toggleable(self) -> bool {
+        matches!(self, Autostart::Off | Autostart::User)
+    }
+}
+
+/// What starts the daemon at logon; the machine-wide entries win, as the toggle leaves them be
+fn autostart() -> Autostart {
+    use windows::Win32::System::Registry::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    if is_task_installed() {
+        Autostart::Task
+    } else if is_run_key_set(HKEY_LOCAL_MACHINE) {
+        Autostart::Machine
+    } else if is_run_key_set(HKEY_CURRENT_USER) {
+        Autostart::User
+    } else {
+        Autostart::Off
+    }
+}
+
+/// Returns true if the --install-task scheduled task exists
+fn is_task_installed() -> bool {
+    use std::os::windows::process::CommandExt;
+    use windows::Win32::System::Threading::CREATE_NO_WINDOW;
+
+    std::process::Command::new("schtasks.exe")
+        .args(["/Query", "/TN", TASK_NAME])
+        .creation_flags(CREATE_NO_WINDOW.0)
+        .output()
+        .is_ok_and(|out| out.status.success())
+}
+
+/// Returns true if the per-user Run key points at this executable
+fn is_autostart_enabled() -> bool {
+    is_run_key_set(windows::Win32::System::Registry::HKEY_CURRENT_USER)
+}
+
+/// Returns true if the Run key under `root` points at this executable
+fn is_run_key_set(root: windows::Win32::System::Registry::HKEY) -> bool {
+    use windows::Win32::System::Registry::*;
+    use windows::core::HSTRING;
+
+    let key_path = HSTRING::from(RUN_KEY_PATH);
+    let value_name = HSTRING::from(RUN_VALUE_NAME);
+
+    let mut buffer = [0u16; 1024];
+    let mut size = std::mem::size_of_val(&buffer) as u32;
+    let result = unsafe {
+        RegGetValueW(
+            root,
+            &key_path,
+            &value_name,
+            RRF_RT_REG_SZ,
+            None,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            Some(&mut size),
+        )
+    };
+
+    if result.is_err() {
+        return false;
+    }
+
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    let registered = String::from_utf16_lossy(&buffer[..len]);
+    match std::env::current_exe() {
+        Ok(exe) => registered.trim_matches('"').eq_ignore_ascii_case(&exe.to_string_lossy()),
+        Err(_) => true,
+    }
+}
+
+/// Fails with a clear message when a machine-wide change is attempted from a normal prompt
+fn require_elevation(command: &str) -> windows::core::Result<()> {
+    use windows::Win32::Foundation::E_ACCESSDENIED;
+
+    if is_elevated() {
+        return Ok(());
+    }
+    log::error!("{} requires administrator rights", command);
+    say!("{} changes settings for every user and needs administrator rights.", command);
+    say!("  Right-click your terminal, choose \"Run as administrator\", and try again.");
+    Err(windows::core::Error::new(E_ACCESSDENIED, "Administrator rights required"))
+}
+
+/// Returns true if the process token is elevated (UAC "Run as administrator")
+fn is_elevated() -> bool {
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_err() {
+            return false;
+        }
+
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut returned: u32 = 0;
+        let result = GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut TOKEN_ELEVATION as *mut c_void),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut returned,
+        );
+        let _ = CloseHandle(token);
+        result.is_ok() && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Connects stdout/stderr to a console. The parent's console (e.g. the terminal a CLI command was
+/// typed in) is used if there is one; otherwise a new window is opened only when `allocate` is set.
+/// Returns true if output is now visible.
+fn attach_console(attach: bool, allocate: bool) -> bool {
+    use windows::Win32::System::Console::{AllocConsole, AttachConsole, ATTACH_PARENT_PROCESS};
+
+    if cfg!(feature = "console") {
+        return true;
+    }
+    if !attach {
+        return false;
+    }
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS).is_ok() || (allocate && AllocConsole().is_ok()) }
+}
+
+fn is_portable() -> bool {
+    PORTABLE.load(Ordering::Relaxed)
+}
+
+/// Startup entries live in the registry or Task Scheduler, which portable mode must not touch.
+/// Fails (so the command exits non-zero) instead of silently doing nothing.
+fn refuse_in_portable_mode(what: &str) -> windows::core::Result<()> {
+    use windows::Win32::Foundation::E_ACCESSDENIED;
+
+    if !is_portable() {
+        return Ok(());
+    }
+    log::warn!("Portable mode: refusing to {}", what);
+    say!("Running in portable mode (--portable or portable.txt); not going to {}.", what);
+    Err(windows::core::Error::new(E_ACCESSDENIED, "Not available in portable mode"))
+}
+
+/// Returns true if this process token has UIAccess (granted from a signed uiaccess build)
+fn has_ui_access() -> bool {
+    use windows::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows::Win32::Security::{GetTokenInformation, TokenUIAccess, TOKEN_QUERY};
+    use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    unsafe {
+        let mut token = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_err() {
+            return false;
+        }
+
+        let mut ui_access: u32 = 0;
+        let mut returned: u32 = 0;
+        let result = GetTokenInformation(
+            token,
+            TokenUIAccess,
+            Some(&mut ui_access as *mut u32 as *mut c_void),
+            std::mem::size_of::<u32>() as u32,
+            &mut returned,
+        );
+        let _ = CloseHandle(token);
+        result.is_ok() && ui_access != 0
+    }
+}
+
 fn print_help() {
     println!("{} v{} - Apple Wireless Keyboard Mapper for Windows", 
              env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
@@ -640,9 +2587,41 @@ fn print_help() {
     println!("  a1314_daemon.exe [OPTIONS]");
     println!();
     println!("OPTIONS:");
-    println!("  --install      Install daemon to start with Windows");
-    println!("  --uninstall    Remove daemon from Windows startup");
-    println!("  --help, -h     Show this help message");
+    println!("  --install          Install daemon to start with Windows");
+    println!("  --uninstall        Remove daemon from Windows startup");
+    println!("    --all-users      (with --install/--uninstall) Use the machine-wide Run key and");
+    println!("                     shared defaults in %ProgramData%; needs an elevated prompt");
+    println!("    --silent         (with any install command) Print nothing; check the exit code");
+    println!("  --install-task     Start at logon via Task Scheduler with highest privileges");
+    println!("                     (run from an elevated prompt; works with elevated windows)");
+    println!("  --uninstall-task   Remove the scheduled task");
+    println!("  --status           Show the running daemon's state (keyboard, mappings, uptime)");
+    println!("  --explain KEY      Show which mapping fires for KEY under each modifier state, and");
+    println!("                     which lines for it are duplicated or overridden");
+    println!("  --map add LHS ACTION  Map LHS (e.g. FN+KEY_T) to ACTION in the running daemon;");
+    println!("  --map remove LHS   with --save also in the mapping file, otherwise until a reload");
+    println!("  --export-config json  Print the parsed mappings and settings as JSON; a mapping file");
+    println!("                     holding such a document (edited or not) is read like any other");
+    println!("  --export-cheatsheet html|md  Print the mappings in use as a printable cheat sheet,");
+    println!("                     one section per layer");
+    println!("  --check [FILE]     Load the mapping file (or FILE) and list its errors and warnings,");
+    println!("                     such as keys mapped twice or mappings that can never fire");
+    println!("  --template [NAME]  Replace the mapping file with a template (us-ansi, iso, mac,");
+    println!("                     windows-fkeys), keeping a backup; without NAME, list them");
+    println!("  --bench [N]        Time the parser and mapper on a canned report stream (N passes)");
+    println!("  --dump-events      Print the running daemon's last 500 input events and actions");
+    println!("  --list-devices     List raw input keyboards with VID/PID and product strings");
+    println!("  --list-keys        List the key names usable on the left of a mapping");
+    println!("  --list-actions     List the action names and syntax usable on the right of a mapping");
+    println!("    --json           (with --list-keys/--list-actions) Print JSON instead of a table");
+    println!("  --update           Download and verify the latest release; installed on next start");
+    println!("  --capture          Stream the running daemon's parsed HID events to stdout");
+    println!("  --capture-only     Same, but the daemon stops applying mappings while attached");
+    println!("  --portable         Keep all state (config, logs, updates) next to the exe and never");
+    println!("                     write the registry; same as placing portable.txt beside the exe");
+    println!("  --console          Show the daemon's log in a console window (the terminal it was");
+    println!("                     started from, or a new window) for troubleshooting");
+    println!("  --help, -h         Show this help message");
     println!();
     println!("NORMAL OPERATION:");
     println!("  Run without arguments to start the daemon.");
@@ -0,0 +1,188 @@
+// --- START OF FILE src/migration.rs ---
+use std::fs;
+use std::path::Path;
+
+use crate::{config_json, policy};
+
+/// Format version written into generated mapping files as a `VERSION=` header.
+/// Files without a header predate versioning and are treated as version 1.
+pub const CURRENT_VERSION: u32 = 2;
+
+// One entry per format change: STEPS[n] upgrades a line from version n + 1 to n + 2.
+// When the format changes, bump CURRENT_VERSION and append a step here.
+const STEPS: &[fn(&str) -> String] = &[
+    quote_run_paths,
+];
+
+/// Returns the version from a `VERSION=n` line, or None if `line` isn't a version header
+pub fn version_header(line: &str) -> Option<u32> {
+    let (name, value) = line.split_once('=')?;
+    if !name.trim().eq_ignore_ascii_case("VERSION") {
+        return None;
+    }
+    value.trim().parse().ok()
+}
+
+/// Format version of a mapping file's contents. There is no version 0: the loader reports a
+/// `VERSION=0` header and the file is read as version 1.
+pub fn file_version(text: &str) -> u32 {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .find_map(version_header)
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// Upgrades older mapping text to the current format for writing back to disk, with a
+/// `VERSION=` header and the file's own line endings. Returns None if it is already current
+/// (or newer, in which case it is left alone for the newer daemon that wrote it), or if no line
+/// needed upgrading, so an old file is never rewritten just to add the header.
+pub fn migrate(text: &str) -> Option<String> {
+    let upgraded = upgrade_in_memory(text);
+    if upgraded == text {
+        return None;
+    }
+
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut migrated = format!("VERSION={}{}", CURRENT_VERSION, newline);
+    for line in upgraded.split_inclusive('\n') {
+        if version_header(line.trim()).is_none() {
+            migrated.push_str(line);
+        }
+    }
+    Some(migrated)
+}
+
+/// Upgrades older mapping text line by line without adding a header, so every line keeps its
+/// number and errors still point at the file as written. For text that is never written back.
+pub fn upgrade_in_memory(text: &str) -> String {
+    let from = file_version(text);
+    if from >= CURRENT_VERSION {
+        return text.to_string();
+    }
+
+    let steps = &STEPS[(from - 1) as usize..];
+    text.split_inclusive('\n')
+        .map(|line| {
+            let body = line.trim_end_matches(['\r', '\n']);
+            let ending = &line[body.len()..];
+            if version_header(body.trim()).is_some() {
+                return line.to_string();
+            }
+            steps.iter().fold(body.to_string(), |body, step| step(&body)) + ending
+        })
+        .collect()
+}
+
+/// Upgrades `text`, just read from the mapping file at `path`, rewriting the file first if it was
+/// written by an older version. The original is kept as `<file>.v<N>.bak` so a bad migration
+/// never loses a hand-tuned config. A file fixed by policy is only upgraded in memory: rewriting
+/// it would change the hash the policy checks.
+pub fn upgrade(path: &Path, text: String) -> std::io::Result<String> {
+    // An exported JSON configuration is turned into mapping lines and upgraded in memory only
+    if config_json::is_json(&text) {
+        let text = config_json::to_mapping_text(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        return Ok(upgrade_in_memory(&text));
+    }
+    if policy::is_locked() {
+        return Ok(upgrade_in_memory(&text));
+    }
+    let migrated = match migrate(&text) {
+        Some(migrated) => migrated,
+        None => {
+            let version = file_version(&text);
+            if version > CURRENT_VERSION {
+                log::warn!("{} is format version {}, newer than this daemon understands ({}); some lines may not load",
+                           path.display(), version, CURRENT_VERSION);
+            }
+            return Ok(text);
+        }
+    };
+
+    let version = file_version(&text);
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", version));
+    if let Err(e) = fs::write(&backup, &text) {
+        // Without a backup, use the migrated text for this session but leave the file untouched
+        log::error!("Failed to back up {} before migrating: {}", path.display(), e);
+        return Ok(migrated);
+    }
+
+    match fs::write(path, &migrated) {
+        Ok(()) => log::info!("Migrated {} from format version {} to {} (backup: {})",
+                             path.display(), version, CURRENT_VERSION, Path::new(&backup).display()),
+        Err(e) => log::error!("Failed to write migrated {}: {}", path.display(), e),
+    }
+    Ok(migrated)
+}
+
+/// Version 1 accepted `RUN(program.exe)` without quotes but never ran it; quote the path
+fn quote_run_paths(line: &str) -> String {
+    let (lhs, rhs) = match line.split_once('=') {
+        Some(parts) => parts,
+        None => return line.to_string(),
+    };
+    let action = rhs.trim();
+    if line.trim_start().starts_with('#') || action.starts_with("RUN(\"") {
+        return line.to_string();
+    }
+
+    match action.strip_prefix("RUN(").and_then(|rest| rest.strip_suffix(')')) {
+        Some(path) if !path.trim().is_empty() => {
+            format!("{}= RUN(\"{}\")", lhs, path.trim().trim_matches('"'))
+        }
+        _ => line.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_comes_from_the_first_header() {
+        assert_eq!(file_version("# header\nVERSION=2\nF1 = MUTE\n"), 2);
+        assert_eq!(file_version("version = 3\n"), 3);
+
+        // Unversioned files predate the header
+        assert_eq!(file_version("F1 = MUTE\n"), 1);
+        assert_eq!(file_version(""), 1);
+        assert_eq!(version_header("F1 = MUTE"), None);
+
+        // Version 0 doesn't exist, and is read (and upgraded) as version 1
+        assert_eq!(file_version("VERSION=0\nEJECT+KEY_1 = RUN(calc.exe)\n"), 1);
+        assert_eq!(upgrade_in_memory("version = 0\nEJECT+KEY_1 = RUN(calc.exe)\n"),
+                   "version = 0\nEJECT+KEY_1 = RUN(\"calc.exe\")\n");
+    }
+
+    #[test]
+    fn unquoted_run_paths_get_quoted() {
+        assert_eq!(quote_run_paths("EJECT+KEY_1 = RUN(calc.exe)"), "EJECT+KEY_1 = RUN(\"calc.exe\")");
+        assert_eq!(quote_run_paths("EJECT+KEY_1 = RUN(\"calc.exe\")"), "EJECT+KEY_1 = RUN(\"calc.exe\")");
+        assert_eq!(quote_run_paths("F1 = BRIGHTNESS_DOWN"), "F1 = BRIGHTNESS_DOWN");
+        assert_eq!(quote_run_paths("# EJECT+KEY_1 = RUN(calc.exe)"), "# EJECT+KEY_1 = RUN(calc.exe)");
+    }
+
+    #[test]
+    fn files_that_need_no_change_are_not_rewritten() {
+        assert_eq!(migrate("F1 = BRIGHTNESS_DOWN\nEJECT+KEY_1 = RUN(\"calc.exe\")\n"), None);
+        assert_eq!(migrate("VERSION=2\nEJECT+KEY_1 = RUN(calc.exe)\n"), None);
+        assert_eq!(migrate("VERSION=9\nEJECT+KEY_1 = RUN(calc.exe)\n"), None);
+    }
+
+    #[test]
+    fn migration_keeps_crlf_line_endings() {
+        let migrated = migrate("# mine\r\nVERSION=1\r\nEJECT+KEY_1 = RUN(calc.exe)\r\nF1 = MUTE").unwrap();
+        assert_eq!(migrated, "VERSION=2\r\n# mine\r\nEJECT+KEY_1 = RUN(\"calc.exe\")\r\nF1 = MUTE");
+    }
+
+    #[test]
+    fn in_memory_upgrade_keeps_line_numbers() {
+        let text = "# shared\nEJECT+KEY_1 = RUN(calc.exe)\nF1 = MUTE\n";
+        let upgraded = upgrade_in_memory(text);
+        assert_eq!(upgraded.lines().count(), text.lines().count());
+        assert_eq!(upgraded.lines().nth(1), Some("EJECT+KEY_1 = RUN(\"calc.exe\")"));
+    }
+}
@@ -0,0 +1,192 @@
+// --- START OF FILE src/name_list.rs ---
+// --list-keys / --list-actions output, generated from the tables the parser uses
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::action_executor::{Action, KeyStep};
+use crate::config::ACTION_SYNTAX;
+use crate::key_mapper::HidKey;
+use crate::launcher;
+use crate::variable_maps::{HID_USAGE_NAMES, STRING_TO_ACTION, STRING_TO_HID_KEY};
+
+/// Every LHS key name with its usage page, usage and aliases, sorted by name
+pub fn keys(json: bool) -> String {
+    // Aliases from the usage table, grouped under the key they name
+    let mut aliases: HashMap<HidKey, Vec<&str>> = HashMap::new();
+    for &(name, usage_page, usage, canonical) in HID_USAGE_NAMES {
+        if !canonical {
+            aliases.entry(HidKey { usage_page, usage }).or_default().push(name);
+        }
+    }
+
+    let mut keys: Vec<(&str, HidKey)> = STRING_TO_HID_KEY.iter()
+        .filter(|(name, key)| !aliases.get(key).is_some_and(|names| names.contains(name)))
+        .map(|(name, key)| (*name, *key))
+        .collect();
+    keys.sort_by_key(|(name, _)| *name);
+    let aliases_of = |key: &HidKey| aliases.get(key).map(Vec::as_slice).unwrap_or_default();
+
+    let mut out = String::new();
+    if json {
+        let entries: Vec<String> = keys.iter()
+            .map(|(name, key)| {
+                let aliases: Vec<String> = aliases_of(key).iter().map(|alias| json_string(alias)).collect();
+                format!("    {{\"name\": {}, \"usage_page\": {}, \"usage\": {}, \"aliases\": [{}]}}",
+                        json_string(name), key.usage_page, key.usage, aliases.join(", "))
+            })
+            .collect();
+        let _ = writeln!(out, "{{\n  \"keys\": [\n{}\n  ]\n}}", entries.join(",\n"));
+        return out;
+    }
+
+    let _ = writeln!(out, "{:<34} {:<9} ALIASES", "KEY", "USAGE");
+    for (name, key) in &keys {
+        let _ = writeln!(out, "{:<34} {:04X}:{:04X} {}", name, key.usage_page, key.usage, aliases_of(key).join(", "));
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "Prefixes: FN+, EJECT+, LEFT_SHIFT+, RIGHT_SHIFT+, HOLD+, LONGHOLD+");
+    let _ = writeln!(out, "Long press: LONGPRESS(KEY) or LONGPRESS(KEY, 800ms)");
+    let _ = writeln!(out, "Pass-through: PASSTHRU KEY = ACTION also lets the original key through");
+    let _ = writeln!(out, "Held modifiers: CLEARMODS KEY = ACTION lets go of them while it runs, KEEPMODS keeps them");
+    let _ = writeln!(out, "Keys without a name: HID(usage_page,usage), e.g. HID(0xFF00,0x0005)");
+    out
+}
+
+/// Every named RHS action and the action syntax forms, names sorted
+pub fn actions(json: bool) -> String {
+    let mut actions: Vec<(&str, String)> = STRING_TO_ACTION.iter()
+        .map(|(name, action)| (*name, describe(action)))
+        .collect();
+    actions.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut out = String::new();
+    if json {
+        let entries: Vec<String> = actions.iter()
+            .map(|(name, action)| format!("    {{\"name\": {}, \"action\": {}}}",
+                                          json_string(name), json_string(action)))
+            .collect();
+        let syntax: Vec<String> = ACTION_SYNTAX.iter()
+            .map(|(syntax, description)| format!("    {{\"syntax\": {}, \"description\": {}}}",
+                                                 json_string(syntax), json_string(description)))
+            .collect();
+        let _ = writeln!(out, "{{\n  \"actions\": [\n{}\n  ],\n  \"syntax\": [\n{}\n  ]\n}}",
+                         entries.join(",\n"), syntax.join(",\n"));
+        return out;
+    }
+
+    let _ = writeln!(out, "{:<20} ACTION", "NAME");
+    for (name, action) in &actions {
+        let _ = writeln!(out, "{:<20} {}", name, action);
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(out, "{:<24} DESCRIPTION", "SYNTAX");
+    for (syntax, description) in ACTION_SYNTAX {
+        let _ = writeln!(out, "{:<24} {}", syntax, description);
+    }
+    out
+}
+
+/// What an action does, in a few words
+pub fn describe(action: &Action) -> String {
+    match action {
+        Action::KeyCombo(combo) => format!("keys {}", combo),
+        Action::Run(path) => format!("run {}", path),
+        Action::RunForeground(path) => format!("run {} and bring it to the front", path),
+        Action::Open(target) => format!("open {}", target),
+        Action::Explore(folder) => format!("explore {}", folder),
+        Action::LayoutNext => "next input language".to_string(),
+        Action::Layout(locale) => format!("input language {}", locale),
+        Action::ImeToggle => "IME on/off".to_string(),
+        Action::FocusAssist(on) => format!("Focus Assist {}", match on {
+            Some(true) => "on",
+            Some(false) => "off",
+            None => "on/off",
+        }),
+        Action::Launcher => format!("open {}", launcher::current().describe()),
+        Action::AppWindowNext => "next window of the app".to_string(),
+        Action::AppWindowPrev => "previous window of the app".to_string(),
+        Action::CloseWindow => "close window".to_string(),
+        Action::Magnifier(zoom) => zoom.describe().to_string(),
+        Action::Sound(file) => format!("play {}", file),
+        Action::WithSound { sound, action } => format!("{} (plays {})", describe(action), sound),
+        Action::QuitApp { confirm } => format!("quit app{}", if *confirm { " (asks first)" } else { "" }),
+        Action::ForceKillApp { confirm } => format!("force quit app{}", if *confirm { " (asks first)" } else { "" }),
+        Action::AppCommand(command) => format!("app command {}", command),
+        Action::AltCode(digits) => format!("Alt+{} on the numpad", digits),
+        Action::Text(text) => format!("type \"{}\"", text),
+        Action::Paste(text) => format!("paste \"{}\"", text),
+        Action::Backlight(step) => format!("backlight {:+}%", step),
+        Action::CapsLed(on) => format!("Caps Lock LED {}", if *on { "on" } else { "off" }),
+        Action::SendHid(report) => format!("output report {:02X?}", report),
+        Action::Disable => "nothing, the key is blocked".to_string(),
+        Action::Toggle(toggle) => format!("toggle {}", toggle.name()),
+        Action::Keys(steps) => {
+            let steps: Vec<String> = steps.iter()
+                .map(|step| match step {
+                    KeyStep::Press(key) => format!("{} down", key),
+                    KeyStep::Release(key) => format!("{} up", key),
+                    KeyStep::Tap(key) => key.clone(),
+                    KeyStep::Wait(wait) => format!("wait {}ms", wait.as_millis()),
+                })
+                .collect();
+            format!("key events {}", steps.join(", "))
+        }
+        Action::Sequence(steps) => {
+            let steps: Vec<String> = steps.iter().map(describe).collect();
+            format!("in turn: {}", steps.join("; "))
+        }
+        Action::WaitWindow { app, timeout } => format!("wait up to {}ms for a {} window", timeout.as_millis(), app),
+        Action::NaturalScrollToggle => "natural scrolling on/off".to_string(),
+        Action::Suspend(duration) => format!("suspend remapping for {}", crate::suspend::countdown(*duration)),
+        Action::IfApp { app, then, otherwise } => {
+            format!("if {} is in front: {}, otherwise {}", app, describe(then), describe(otherwise))
+        }
+        Action::LiftModifiers { action, .. } => describe(action),
+    }
+}
+
+/// The canonical name of `key` for the left of a mapping, or `HID(0x..,0x..)` if it has none
+pub fn key_name(key: HidKey) -> String {
+    HID_USAGE_NAMES.iter()
+        .find(|&&(_, usage_page, usage, canonical)| canonical && HidKey { usage_page, usage } == key)
+        .map(|(name, ..)| name.to_string())
+        .or_else(|| STRING_TO_HID_KEY.iter().find(|(_, k)| **k == key).map(|(name, _)| name.to_string()))
+        .unwrap_or_else(|| format!("HID(0x{:04X},0x{:04X})", key.usage_page, key.usage))
+}
+
+/// `s` as a quoted JSON string
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listings_come_from_the_tables() {
+        let keys = keys(false);
+        assert!(keys.find("KEY_A").unwrap() < keys.find("KEY_B").unwrap());
+        let escape = keys.lines().find(|line| line.starts_with("ESCAPE ")).unwrap();
+        assert!(escape.ends_with("0007:0029 ESC"));
+        assert!(!keys.lines().any(|line| line.starts_with("ESC ")));
+
+        let json = actions(true);
+        assert!(json.contains("{\"name\": \"BACKLIGHT_UP\", \"action\": \"backlight +10%\"}"));
+        assert!(json.contains("{\"name\": \"\\\"\", \"action\": \"keys SHIFT+APOSTROPHE\"}"));
+        assert!(json.contains("\"syntax\": \"RUN(\\\"path\\\")\""));
+    }
+}
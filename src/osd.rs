@@ -0,0 +1,287 @@
+// --- START OF FILE src/osd.rs ---
+// On-screen bubble near the bottom of the screen that shows a line of text for a moment, and
+// "Show Keystrokes" (a tray toggle), which puts each key pressed on the keyboard and what its
+// mapping did there, for screencasts, teaching and checking mappings by eye. The bubble never
+// takes focus and clicks go through it.
+//
+// The bubble also shows a level meter when the daemon changes a level itself rather than by a
+// media key (TOGGLE(MUTE), the keyboard backlight), as Windows' own volume flyout would only
+// show for the key.
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{COLORREF, HINSTANCE, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BeginPaint, CreateFontW, CreateRoundRectRgn, CreateSolidBrush, DeleteObject, DrawTextW, EndPaint,
+    FillRect, GetDC, InvalidateRect, ReleaseDC, SelectObject, SetBkMode, SetTextColor, SetWindowRgn,
+    DT_CALCRECT, DT_CENTER, DT_SINGLELINE, DT_VCENTER, FW_SEMIBOLD, HDC, HFONT, PAINTSTRUCT, TRANSPARENT,
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, GetClientRect, KillTimer, PostMessageW, RegisterClassW,
+    SetLayeredWindowAttributes, SetTimer, SetWindowPos, ShowWindow, SystemParametersInfoW,
+    HWND_TOPMOST, LWA_ALPHA, SPI_GETWORKAREA, SWP_NOACTIVATE, SWP_SHOWWINDOW, SW_HIDE,
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS, WM_PAINT, WM_TIMER, WNDCLASSW, WS_EX_LAYERED,
+    WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP,
+};
+
+// How long a keystroke stays up once the keys stop
+const KEYSTROKE_DURATION: Duration = Duration::from_millis(1500);
+// How long a level meter stays up, about as long as Windows' volume flyout
+const LEVEL_DURATION: Duration = Duration::from_secs(2);
+
+// Look of the bubble: padding around the text, corner radius, text height, opacity (of 255),
+// and how far above the bottom of the work area it sits
+const PADDING: i32 = 16;
+const RADIUS: i32 = 16;
+const FONT_HEIGHT: i32 = 32;
+const OPACITY: u8 = 220;
+const BOTTOM_MARGIN: i32 = 80;
+const BACKGROUND: COLORREF = COLORREF(0x00202020);
+const FOREGROUND: COLORREF = COLORREF(0x00FFFFFF);
+// The level meter: a bar under the text, its unfilled part in TRACK
+const LEVEL_WIDTH: i32 = 240;
+const LEVEL_HEIGHT: i32 = 6;
+const LEVEL_GAP: i32 = 12;
+const TRACK: COLORREF = COLORREF(0x00606060);
+
+const HIDE_TIMER_ID: usize = 1;
+
+// Whether Show Keystrokes is on
+static SHOW_KEYSTROKES: AtomicBool = AtomicBool::new(false);
+
+// Window posted NOTIFY_MESSAGE when a level changes on another thread, so the bubble (which
+// belongs to the window thread) can show it
+static NOTIFY_WINDOW: AtomicIsize = AtomicIsize::new(0);
+static NOTIFY_MESSAGE: AtomicU32 = AtomicU32::new(0);
+// The latest level to show, as its label and percentage
+static PENDING_LEVEL: Mutex<Option<(String, u32)>> = Mutex::new(None);
+
+struct Bubble {
+    hwnd: HWND,
+    font: HFONT,
+    text: Vec<u16>,
+    level: Option<u32>, // Percentage shown as a bar under the text
+}
+
+// The bubble is made the first time it is shown, on the main window thread
+thread_local! {
+    static BUBBLE: RefCell<Option<Bubble>> = const { RefCell::new(None) };
+    // The key most recently shown by Show Keystrokes, and the combo it was shown as
+    static LAST_KEYSTROKE: RefCell<(String, String)> = const { RefCell::new((String::new(), String::new())) };
+}
+
+/// Shows `text` in the bubble for `duration`, replacing whatever it showed
+pub fn show(text: &str, duration: Duration) {
+    display(text, None, duration);
+}
+
+/// Posts `message` to `hwnd` when a level is set, for the window thread to call show_level
+pub fn set_notify(hwnd: HWND, message: u32) {
+    NOTIFY_WINDOW.store(hwnd.0 as isize, Ordering::Relaxed);
+    NOTIFY_MESSAGE.store(message, Ordering::Relaxed);
+}
+
+/// Shows `label` over a meter at `percent`, from any thread
+pub fn level(label: &str, percent: u32) {
+    *PENDING_LEVEL.lock().unwrap_or_else(|p| p.into_inner()) = Some((label.to_string(), percent.min(100)));
+    let hwnd = NOTIFY_WINDOW.load(Ordering::Relaxed);
+    if hwnd != 0 {
+        unsafe {
+            let _ = PostMessageW(HWND(hwnd as *mut _), NOTIFY_MESSAGE.load(Ordering::Relaxed), WPARAM(0), LPARAM(0));
+        }
+    }
+}
+
+/// Shows the level last set with `level`, on the window thread
+pub fn show_level() {
+    if let Some((label, percent)) = PENDING_LEVEL.lock().unwrap_or_else(|p| p.into_inner()).take() {
+        display(&label, Some(percent), LEVEL_DURATION);
+    }
+}
+
+fn display(text: &str, level: Option<u32>, duration: Duration) {
+    let Some(hwnd) = bubble() else {
+        return;
+    };
+    let mut wide: Vec<u16> = text.encode_utf16().collect();
+    unsafe {
+        // Size the bubble to the text, centred at the bottom of the primary monitor's work area
+        let mut text_rect = RECT::default();
+        let dc = GetDC(hwnd);
+        let previous_font = BUBBLE.with(|b| b.borrow().as_ref().map(|bubble| SelectObject(dc, bubble.font)));
+        DrawTextW(dc, &mut wide, &mut text_rect, DT_CALCRECT | DT_SINGLELINE);
+        if let Some(font) = previous_font {
+            SelectObject(dc, font);
+        }
+        ReleaseDC(hwnd, dc);
+
+        let (width, height) = match level {
+            Some(_) => (text_rect.right.max(LEVEL_WIDTH), text_rect.bottom + LEVEL_GAP + LEVEL_HEIGHT),
+            None => (text_rect.right, text_rect.bottom),
+        };
+        let (width, height) = (width + 2 * PADDING, height + 2 * PADDING);
+        let mut work_area = RECT::default();
+        let _ = SystemParametersInfoW(SPI_GETWORKAREA, 0, Some(&mut work_area as *mut RECT as *mut c_void),
+                                      SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0));
+        let x = work_area.left + (work_area.right - work_area.left - width) / 2;
+        let y = work_area.bottom - BOTTOM_MARGIN - height;
+
+        BUBBLE.with(|b| {
+            if let Some(bubble) = b.borrow_mut().as_mut() {
+                bubble.text = wide;
+                bubble.level = level;
+            }
+        });
+        SetWindowRgn(hwnd, CreateRoundRectRgn(0, 0, width + 1, height + 1, RADIUS, RADIUS), true);
+        let _ = SetWindowPos(hwnd, HWND_TOPMOST, x, y, width, height, SWP_NOACTIVATE | SWP_SHOWWINDOW);
+        let _ = InvalidateRect(hwnd, None, true);
+        SetTimer(hwnd, HIDE_TIMER_ID, duration.as_millis() as u32, None);
+    }
+}
+
+/// Turns Show Keystrokes on or off
+pub fn set_show_keystrokes(on: bool) {
+    SHOW_KEYSTROKES.store(on, Ordering::Relaxed);
+    log::info!("Show keystrokes {}", if on { "on" } else { "off" });
+}
+
+pub fn showing_keystrokes() -> bool {
+    SHOW_KEYSTROKES.load(Ordering::Relaxed)
+}
+
+/// Show Keystrokes: a key was pressed, as `combo` (the key named `key` with the modifiers held)
+pub fn keystroke(key: &str, combo: String) {
+    show(&combo, KEYSTROKE_DURATION);
+    LAST_KEYSTROKE.with(|last| *last.borrow_mut() = (key.to_string(), combo));
+}
+
+/// Show Keystrokes: the mapping of the key named `key` ran an action, described as `action`
+pub fn keystroke_action(key: &str, action: &str) {
+    let combo = LAST_KEYSTROKE.with(|last| {
+        let last = last.borrow();
+        if last.0 == key { last.1.clone() } else { key.to_string() }
+    });
+    show(&format!("{}  \u{2192}  {}", combo, action), KEYSTROKE_DURATION);
+}
+
+/// The bubble's window, made on first use
+fn bubble() -> Option<HWND> {
+    if let Some(hwnd) = BUBBLE.with(|b| b.borrow().as_ref().map(|bubble| bubble.hwnd)) {
+        return Some(hwnd);
+    }
+    let created = unsafe { create() };
+    match created {
+        Ok(bubble) => {
+            let hwnd = bubble.hwnd;
+            BUBBLE.with(|b| *b.borrow_mut() = Some(bubble));
+            Some(hwnd)
+        }
+        Err(e) => {
+            log::warn!("Failed to create the on-screen display: {}", e);
+            None
+        }
+    }
+}
+
+unsafe fn create() -> windows::core::Result<Bubble> {
+    let hinstance: HINSTANCE = GetModuleHandleW(None)?.into();
+    let class_name = w!("A1314OsdClass");
+    let wc = WNDCLASSW {
+        lpfnWndProc: Some(bubble_wnd_proc),
+        hInstance: hinstance,
+        lpszClassName: class_name,
+        ..Default::default()
+    };
+    RegisterClassW(&wc);
+
+    let hwnd = CreateWindowExW(
+        WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+        class_name,
+        PCWSTR::null(),
+        WS_POPUP,
+        0, 0, 0, 0,
+        None,
+        None,
+        hinstance,
+        None,
+    )?;
+    SetLayeredWindowAttributes(hwnd, COLORREF(0), OPACITY, LWA_ALPHA)?;
+    let font = CreateFontW(FONT_HEIGHT, 0, 0, 0, FW_SEMIBOLD.0 as i32, 0, 0, 0, 0, 0, 0, 0, 0, w!("Segoe UI"));
+    Ok(Bubble { hwnd, font, text: Vec::new(), level: None })
+}
+
+extern "system" fn bubble_wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_PAINT => {
+                let mut paint = PAINTSTRUCT::default();
+                let dc = BeginPaint(hwnd, &mut paint);
+                let mut client = RECT::default();
+                let _ = GetClientRect(hwnd, &mut client);
+                fill(dc, &client, BACKGROUND);
+                BUBBLE.with(|b| {
+                    if let Some(bubble) = b.borrow_mut().as_mut() {
+                        // A level meter takes the bottom of the bubble, and the text the rest
+                        let mut text_rect = client;
+                        if let Some(percent) = bubble.level {
+                            let (track, filled) = level_bar(&client, percent);
+                            fill(dc, &track, TRACK);
+                            fill(dc, &filled, FOREGROUND);
+                            text_rect.bottom = track.top - LEVEL_GAP + PADDING;
+                        }
+                        let previous_font = SelectObject(dc, bubble.font);
+                        SetBkMode(dc, TRANSPARENT);
+                        SetTextColor(dc, FOREGROUND);
+                        DrawTextW(dc, &mut bubble.text, &mut text_rect, DT_CENTER | DT_VCENTER | DT_SINGLELINE);
+                        SelectObject(dc, previous_font);
+                    }
+                });
+                let _ = EndPaint(hwnd, &paint);
+                LRESULT(0)
+            }
+            WM_TIMER if wparam.0 == HIDE_TIMER_ID => {
+                let _ = KillTimer(hwnd, HIDE_TIMER_ID);
+                let _ = ShowWindow(hwnd, SW_HIDE);
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+/// The meter's track and its filled part, along the bottom of the bubble's client area
+fn level_bar(client: &RECT, percent: u32) -> (RECT, RECT) {
+    let track = RECT {
+        left: client.left + PADDING,
+        top: client.bottom - PADDING - LEVEL_HEIGHT,
+        right: client.right - PADDING,
+        bottom: client.bottom - PADDING,
+    };
+    let filled = RECT { right: track.left + (track.right - track.left) * percent.min(100) as i32 / 100, ..track };
+    (track, filled)
+}
+
+unsafe fn fill(dc: HDC, rect: &RECT, color: COLORREF) {
+    let brush = CreateSolidBrush(color);
+    FillRect(dc, rect, brush);
+    let _ = DeleteObject(brush);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn level_meter_fills_its_share_of_the_track() {
+        let client = RECT { left: 0, top: 0, right: 272, bottom: 100 };
+        let (track, filled) = level_bar(&client, 25);
+        assert_eq!((track.left, track.top, track.right, track.bottom), (16, 78, 256, 84));
+        assert_eq!((filled.left, filled.right), (16, 76));
+        assert_eq!(level_bar(&client, 250).1.right, track.right);
+    }
+}
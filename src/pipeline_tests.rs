@@ -0,0 +1,91 @@
+// --- START OF FILE src/pipeline_tests.rs ---
+// End-to-end tests: recorded A1314 reports → hid_parser → KeyMapper → RecordingSink,
+// using the default mapping file shipped with the daemon
+use crate::action_executor::{Action, RecordingSink};
+use crate::input_backend::{dispatch, ReplayBackend, Routing};
+use crate::hid_parser;
+use crate::key_mapper::KeyMapper;
+
+const KEYS_UP: &[u8] = &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+const F1_DOWN: &[u8] = &[0x01, 0x00, 0x00, 0x3A, 0x00, 0x00, 0x00, 0x00];
+const KEY_1_DOWN: &[u8] = &[0x01, 0x00, 0x00, 0x1E, 0x00, 0x00, 0x00, 0x00];
+const BACKSPACE_DOWN: &[u8] = &[0x01, 0x00, 0x00, 0x2A, 0x00, 0x00, 0x00, 0x00];
+const KEY_A_DOWN: &[u8] = &[0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00];
+
+// USB: Fn in vendor report 0x05, Eject as consumer usage 0x00B8
+const USB_FN_DOWN: &[u8] = &[0x05, 0x01];
+const USB_FN_UP: &[u8] = &[0x05, 0x00];
+const USB_EJECT_DOWN: &[u8] = &[0x02, 0xB8, 0x00];
+const USB_EJECT_UP: &[u8] = &[0x02, 0x00, 0x00];
+
+// Bluetooth: Fn (0x10) and Eject (0x08) share vendor report 0x11
+const BT_FN_DOWN: &[u8] = &[0x11, 0x10];
+const BT_EJECT_DOWN: &[u8] = &[0x11, 0x08];
+const BT_VENDOR_UP: &[u8] = &[0x11, 0x00];
+
+fn replay(reports: &[&[u8]]) -> Vec<Action> {
+    let _parser = hid_parser::lock_for_test();
+
+    let sink = RecordingSink::default();
+    let mut mapper = KeyMapper::with_sink(Box::new(sink.clone()));
+    mapper.load_mapping_file(concat!(env!("CARGO_MANIFEST_DIR"), "/A1314_mapping.txt"))
+        .expect("default mapping file should load cleanly");
+
+    dispatch(&mut ReplayBackend::new(reports), &mut mapper, Routing::Map);
+    sink.take()
+}
+
+fn key_combo(combo: &str) -> Action {
+    Action::KeyCombo(combo.to_string())
+}
+
+#[test]
+fn usb_plain_f1_sends_brightness_down() {
+    assert_eq!(replay(&[F1_DOWN, KEYS_UP]), vec![key_combo("BRIGHTNESS_DOWN")]);
+}
+
+#[test]
+fn usb_fn_f1_sends_f1() {
+    assert_eq!(replay(&[USB_FN_DOWN, F1_DOWN, KEYS_UP, USB_FN_UP]), vec![key_combo("F1")]);
+}
+
+#[test]
+fn usb_fn_backspace_sends_delete() {
+    assert_eq!(replay(&[USB_FN_DOWN, BACKSPACE_DOWN, KEYS_UP, USB_FN_UP]), vec![key_combo("DELETE")]);
+}
+
+#[test]
+fn usb_eject_layer_runs_program() {
+    assert_eq!(replay(&[USB_EJECT_DOWN, KEY_1_DOWN, KEYS_UP, USB_EJECT_UP]),
+               vec![Action::Run("calc.exe".to_string())]);
+}
+
+#[test]
+fn bluetooth_fn_f1_sends_f1() {
+    assert_eq!(replay(&[BT_FN_DOWN, F1_DOWN, KEYS_UP, BT_VENDOR_UP]), vec![key_combo("F1")]);
+}
+
+#[test]
+fn bluetooth_eject_layer_runs_program() {
+    assert_eq!(replay(&[BT_EJECT_DOWN, KEY_1_DOWN, KEYS_UP, BT_VENDOR_UP]),
+               vec![Action::Run("calc.exe".to_string())]);
+}
+
+#[test]
+fn layer_ends_when_fn_is_released() {
+    assert_eq!(replay(&[BT_FN_DOWN, BT_VENDOR_UP, F1_DOWN, KEYS_UP]), vec![key_combo("BRIGHTNESS_DOWN")]);
+}
+
+#[test]
+fn unmapped_keys_emit_nothing() {
+    assert_eq!(replay(&[KEY_A_DOWN, KEYS_UP]), Vec::new());
+}
+
+#[test]
+fn bench_replays_the_canned_stream() {
+    let _parser = hid_parser::lock_for_test();
+
+    let report = crate::bench::run(concat!(env!("CARGO_MANIFEST_DIR"), "/A1314_mapping.txt").as_ref(), 2)
+        .expect("default mapping file should load cleanly");
+    assert!(report.contains("Replayed 40 reports (2 x 20), 10 actions resolved"), "{}", report);
+}
@@ -0,0 +1,97 @@
+// --- START OF FILE src/policy.rs ---
+// Managed deployments: a ConfigPath value under HKLM\SOFTWARE\Policies\A1314Daemon fixes the
+// mapping file. The daemon then loads that file only while its SHA-256 matches ConfigSha256,
+// and the tray reset/restore entries, the test bench's Add to Config, `--template` and `map` edits
+// are turned off.
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::error::{A1314Error, ConfigProblem, Result};
+use crate::updater;
+
+pub const POLICY_KEY: &str = r"SOFTWARE\Policies\A1314Daemon";
+const CONFIG_PATH_VALUE: &str = "ConfigPath";
+const CONFIG_HASH_VALUE: &str = "ConfigSha256";
+
+/// The mapping file an administrator fixed, and the hash it must have
+pub struct Policy {
+    pub config_path: PathBuf,
+    pub sha256: Option<String>,
+}
+
+// Read once: changing the policy takes a restart, like any other machine policy
+static POLICY: OnceLock<Option<Policy>> = OnceLock::new();
+
+/// The policy in force, if an administrator set one
+pub fn current() -> Option<&'static Policy> {
+    POLICY.get_or_init(read).as_ref()
+}
+
+/// True when the mapping file is fixed by policy and can't be changed from the daemon
+pub fn is_locked() -> bool {
+    current().is_some()
+}
+
+impl Policy {
+    /// Reads the mapping file and checks it against ConfigSha256. The text returned is exactly
+    /// the bytes that were hashed, so parse it rather than reading the file again. A mismatch is
+    /// logged as tampering and the file is not loaded.
+    pub fn read_verified(&self) -> Result<String> {
+        let fail = |message: String| A1314Error::Config {
+            path: self.config_path.clone(),
+            problems: vec![ConfigProblem { line: 0, column: 0, message }],
+        };
+        let data = std::fs::read(&self.config_path)
+            .map_err(|e| fail(format!("Failed to read file: {}", e)))?;
+        match &self.sha256 {
+            Some(expected) => {
+                let actual = updater::sha256_hex(&data).map_err(fail)?;
+                if !actual.eq_ignore_ascii_case(expected.trim()) {
+                    log::error!("TAMPERING: {} has SHA-256 {}, but the policy expects {}; not loading it",
+                                self.config_path.display(), actual, expected.trim());
+                    return Err(fail("File does not match the SHA-256 set by policy; it was not loaded".to_string()));
+                }
+                log::info!("{} matches the policy hash", self.config_path.display());
+            }
+            None => log::warn!("{}\\{} is not set; loading {} unverified",
+                               POLICY_KEY, CONFIG_HASH_VALUE, self.config_path.display()),
+        }
+        String::from_utf8(data).map_err(|e| fail(format!("Failed to read file: {}", e)))
+    }
+}
+
+fn read() -> Option<Policy> {
+    let config_path = read_string(CONFIG_PATH_VALUE).filter(|path| !path.trim().is_empty())?;
+    let policy = Policy {
+        config_path: PathBuf::from(config_path.trim()),
+        sha256: read_string(CONFIG_HASH_VALUE).filter(|hash| !hash.trim().is_empty()),
+    };
+    log::info!("Mapping file fixed by policy: {}", policy.config_path.display());
+    Some(policy)
+}
+
+/// A REG_SZ (or expanded REG_EXPAND_SZ) value under the policy key
+fn read_string(value_name: &str) -> Option<String> {
+    use std::ffi::c_void;
+    use windows::core::HSTRING;
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+
+    let mut buffer = [0u16; 1024];
+    let mut size = std::mem::size_of_val(&buffer) as u32;
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &HSTRING::from(POLICY_KEY),
+            &HSTRING::from(value_name),
+            RRF_RT_REG_SZ,
+            None,
+            Some(buffer.as_mut_ptr() as *mut c_void),
+            Some(&mut size),
+        )
+    };
+    if result.is_err() {
+        return None;
+    }
+    let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+    Some(String::from_utf16_lossy(&buffer[..len]))
+}
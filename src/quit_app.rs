@@ -0,0 +1,89 @@
+// --- START OF FILE src/quit_app.rs ---
+// CLOSE_WINDOW, QUIT_APP and FORCE_KILL_APP: Cmd+W and Cmd+Q for the application in front.
+// Windows has no "quit" message, so quitting closes every window the application has open.
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{CloseHandle, HWND, LPARAM, WPARAM};
+use windows::Win32::System::Threading::{OpenProcess, TerminateProcess, PROCESS_TERMINATE};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, GetShellWindow, GetWindowThreadProcessId, MessageBoxW, PostMessageW, IDYES,
+    MB_ICONQUESTION, MB_SETFOREGROUND, MB_TOPMOST, MB_YESNO, WM_CLOSE,
+};
+
+use crate::error::{A1314Error, Result};
+use crate::foreground;
+
+/// Asks the foreground window to close, as if its close button was clicked
+pub fn close_window() -> Result<()> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd.0.is_null() || hwnd == unsafe { GetShellWindow() } {
+        return Ok(());
+    }
+    post_close(hwnd)
+}
+
+/// Closes every window of the foreground application, after a Yes/No prompt if `confirm`
+pub fn quit_app(confirm: bool) -> Result<()> {
+    let (pid, name) = foreground_app()?;
+    when_confirmed(confirm, format!("Quit {}?", name), move || {
+        let windows = foreground::process_windows(pid);
+        log::info!("Quitting {}: closing {} window(s)", name, windows.len());
+        windows.into_iter().try_for_each(post_close)
+    })
+}
+
+/// Ends the foreground application's process without letting it save anything
+pub fn force_kill_app(confirm: bool) -> Result<()> {
+    let (pid, name) = foreground_app()?;
+    when_confirmed(confirm, format!("Force {} to quit? Unsaved work will be lost.", name), move || unsafe {
+        let process = OpenProcess(PROCESS_TERMINATE, false, pid)
+            .map_err(|e| A1314Error::win32(format!("open {} to end it", name), e))?;
+        let result = TerminateProcess(process, 1);
+        let _ = CloseHandle(process);
+        result.map_err(|e| A1314Error::win32(format!("end {}", name), e))?;
+        log::info!("Force quit {} (process {})", name, pid);
+        Ok(())
+    })
+}
+
+/// Process ID and executable name of the application in front, refusing the Windows shell
+/// (taskbar and desktop) and the daemon itself
+fn foreground_app() -> Result<(u32, String)> {
+    let pid = foreground::foreground_process_id()
+        .ok_or_else(|| A1314Error::Injection("No application in front to quit".to_string()))?;
+    let name = foreground::process_name().unwrap_or_else(|| format!("process {}", pid));
+    let mut shell_pid = 0u32;
+    unsafe {
+        GetWindowThreadProcessId(GetShellWindow(), Some(&mut shell_pid));
+    }
+    if pid == shell_pid || pid == std::process::id() {
+        return Err(A1314Error::Injection(format!("Not quitting {}, it runs the desktop or this daemon", name)));
+    }
+    Ok((pid, name))
+}
+
+/// Runs `action` now, or on its own thread once the user answers Yes, so the prompt can't hold
+/// up the keyboard hook
+fn when_confirmed(confirm: bool, question: String, action: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    if !confirm {
+        return action();
+    }
+    std::thread::spawn(move || {
+        let answer = unsafe {
+            MessageBoxW(None, &HSTRING::from(question), &HSTRING::from("A1314 Daemon"),
+                        MB_YESNO | MB_ICONQUESTION | MB_SETFOREGROUND | MB_TOPMOST)
+        };
+        if answer == IDYES {
+            if let Err(e) = action() {
+                log::error!("{}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn post_close(hwnd: HWND) -> Result<()> {
+    unsafe {
+        PostMessageW(hwnd, WM_CLOSE, WPARAM(0), LPARAM(0))
+            .map_err(|e| A1314Error::win32("ask the window to close", e))
+    }
+}
@@ -0,0 +1,255 @@
+// --- START OF FILE src/report_profiles.rs ---
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use windows::Win32::Foundation::HANDLE;
+
+use crate::devices::{self, Transport, APPLE_VENDOR_ID};
+use crate::hid_parser;
+
+// Reports looked at before giving up on detection and assuming a report ID prefix
+const MAX_DETECTION_REPORTS: u32 = 16;
+
+// BLE firmware uses the Bluetooth vendor report layout
+const BLE_VENDOR_REPORT_ID: u8 = 0x11;
+
+/// Which kind of report a HID collection delivers, i.e. the report ID to imply when it has none
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Collection {
+    Keyboard,
+    Consumer,
+    Vendor,
+}
+
+impl Collection {
+    fn from_usage_page(usage_page: u16) -> Self {
+        match usage_page {
+            0x0C => Collection::Consumer,
+            page if page >= 0xFF00 => Collection::Vendor,
+            _ => Collection::Keyboard,
+        }
+    }
+
+    fn implied_report_id(self) -> u8 {
+        match self {
+            Collection::Keyboard => 0x01,
+            Collection::Consumer => 0x02,
+            Collection::Vendor => BLE_VENDOR_REPORT_ID,
+        }
+    }
+}
+
+/// Whether a device's reports start with a report ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Prefixed,
+    Unprefixed,
+}
+
+/// Per-device parsing profile: USB and classic Bluetooth always send report IDs, while some
+/// BLE (HOGP) stacks strip them, which is detected from the first reports
+#[derive(Debug, Clone)]
+pub struct ReportProfile {
+    collection: Collection,
+    framing: Option<Framing>, // None while still detecting
+    reports_seen: u32,
+}
+
+impl ReportProfile {
+    pub fn new(transport: Transport, usage_page: u16) -> Self {
+        let framing = match transport {
+            Transport::BluetoothLe => None,
+            _ => Some(Framing::Prefixed),
+        };
+        Self { collection: Collection::from_usage_page(usage_page), framing, reports_seen: 0 }
+    }
+
+    /// Writes the report to `out` in the prefixed form the parser expects
+    pub fn frame(&mut self, report: &[u8], out: &mut Vec<u8>) {
+        let framing = match self.framing {
+            Some(framing) => framing,
+            None => {
+                self.reports_seen += 1;
+                match self.detect(report) {
+                    Some(framing) => {
+                        log::info!("BLE {:?} reports are {:?} (after {} report(s))",
+                                   self.collection, framing, self.reports_seen);
+                        self.framing = Some(framing);
+                        framing
+                    }
+                    None if self.reports_seen >= MAX_DETECTION_REPORTS => {
+                        log::warn!("Couldn't tell whether BLE {:?} reports carry a report ID, assuming they do",
+                                   self.collection);
+                        self.framing = Some(Framing::Prefixed);
+                        Framing::Prefixed
+                    }
+                    // Ambiguous so far: read it the usual way
+                    None => Framing::Prefixed,
+                }
+            }
+        };
+
+        out.clear();
+        if framing == Framing::Unprefixed {
+            out.push(self.collection.implied_report_id());
+        }
+        out.extend_from_slice(report);
+    }
+
+    /// Decides the framing from one report, or None if it reads sensibly either way
+    fn detect(&self, report: &[u8]) -> Option<Framing> {
+        if report.iter().all(|&b| b == 0) {
+            return None;
+        }
+
+        let (prefixed, unprefixed) = match self.collection {
+            // [id, modifiers, reserved, keys...] vs [modifiers, reserved, keys...]
+            Collection::Keyboard => (
+                report.first() == Some(&0x01) && report.get(2) == Some(&0),
+                report.get(1) == Some(&0),
+            ),
+            // [id, usage lo, usage hi, ...] vs an even number of usage bytes
+            Collection::Consumer => (
+                matches!(report.first(), Some(0x02 | 0x03)) && !report.len().is_multiple_of(2),
+                report.len().is_multiple_of(2),
+            ),
+            // [id, bits] vs [bits]
+            Collection::Vendor => (
+                report.len() >= 2 && hid_parser::is_vendor_report(report[0]),
+                report.len() == 1,
+            ),
+        };
+
+        match (prefixed, unprefixed) {
+            (true, false) => Some(Framing::Prefixed),
+            (false, true) => Some(Framing::Unprefixed),
+            // Neither: not a layout we know, so leave the report alone
+            (false, false) => Some(Framing::Prefixed),
+            (true, true) => None,
+        }
+    }
+}
+
+/// A device seen on WM_INPUT
+struct KnownDevice {
+    vendor_id: u16,
+    product_id: u16,
+    profile: ReportProfile,
+}
+
+// Devices seen so far, keyed by raw input device handle
+static DEVICES: Mutex<Option<HashMap<isize, KnownDevice>>> = Mutex::new(None);
+
+// Vendor IDs whose reports are parsed (the [settings] vendor_ids list); None allows any device
+static ALLOWED_VENDORS: Mutex<Option<Cow<'static, [u16]>>> = Mutex::new(Some(Cow::Borrowed(&[APPLE_VENDOR_ID])));
+
+pub fn set_allowed_vendors(vendor_ids: Option<&[u16]>) {
+    let mut lock = ALLOWED_VENDORS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *lock = vendor_ids.map(|ids| Cow::Owned(ids.to_vec()));
+}
+
+fn vendor_allowed(allowed: Option<&[u16]>, vendor_id: u16) -> bool {
+    allowed.is_none_or(|ids| ids.contains(&vendor_id))
+}
+
+/// Frames a report from `device` into `out`, creating its profile from the device info on first
+/// sight. False if the device isn't on the vendor allowlist, so other HID devices (mice, macro
+/// pads) aren't read with A1314 heuristics.
+pub fn frame(device: HANDLE, report: &[u8], out: &mut Vec<u8>) -> bool {
+    let mut lock = DEVICES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let known = lock.get_or_insert_with(HashMap::new)
+        .entry(device.0 as isize)
+        .or_insert_with(|| match unsafe { devices::describe(device) } {
+            Some(info) => {
+                log::debug!("New {} {} device {:04X}:{:04X} ({:04X}:{:04X})", info.transport.label(), info.kind,
+                            info.vendor_id, info.product_id, info.usage_page, info.usage);
+                KnownDevice {
+                    vendor_id: info.vendor_id,
+                    product_id: info.product_id,
+                    profile: ReportProfile::new(info.transport, info.usage_page),
+                }
+            }
+            None => KnownDevice { vendor_id: 0, product_id: 0, profile: ReportProfile::new(Transport::Unknown, 0) },
+        });
+
+    let allowed = ALLOWED_VENDORS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if !vendor_allowed(allowed.as_deref(), known.vendor_id) {
+        log::trace!("Ignoring report from {:04X}:{:04X} (not in vendor_ids)", known.vendor_id, known.product_id);
+        return false;
+    }
+    known.profile.frame(report, out);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn framed(profile: &mut ReportProfile, report: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        profile.frame(report, &mut out);
+        out
+    }
+
+    #[test]
+    fn usb_and_bluetooth_are_left_alone() {
+        for transport in [Transport::Usb, Transport::Bluetooth, Transport::Unknown] {
+            let mut profile = ReportProfile::new(transport, 0xFF00);
+            assert_eq!(framed(&mut profile, &[0x10]), vec![0x10]);
+        }
+    }
+
+    #[test]
+    fn ble_vendor_reports_without_id_get_one() {
+        let mut profile = ReportProfile::new(Transport::BluetoothLe, 0xFF00);
+        assert_eq!(framed(&mut profile, &[0x10]), vec![0x11, 0x10]);
+        assert_eq!(framed(&mut profile, &[0x00]), vec![0x11, 0x00]);
+        assert_eq!(profile.framing, Some(Framing::Unprefixed));
+    }
+
+    #[test]
+    fn ble_consumer_reports_with_id_stay_prefixed() {
+        let mut profile = ReportProfile::new(Transport::BluetoothLe, 0x0C);
+        assert_eq!(framed(&mut profile, &[0x02, 0xE9, 0x00]), vec![0x02, 0xE9, 0x00]);
+        assert_eq!(profile.framing, Some(Framing::Prefixed));
+    }
+
+    #[test]
+    fn ble_consumer_reports_without_id_get_one() {
+        let mut profile = ReportProfile::new(Transport::BluetoothLe, 0x0C);
+        assert_eq!(framed(&mut profile, &[0xE9, 0x00]), vec![0x02, 0xE9, 0x00]);
+    }
+
+    #[test]
+    fn ble_keyboard_detection_waits_for_an_unambiguous_report() {
+        let mut profile = ReportProfile::new(Transport::BluetoothLe, 0x01);
+        // Empty reports and Left Ctrl + nothing read fine either way
+        framed(&mut profile, &[0; 8]);
+        framed(&mut profile, &[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(profile.framing, None);
+        // 'A' at byte 2 can only be the key array of an unprefixed report
+        assert_eq!(framed(&mut profile, &[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]),
+                   vec![0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(profile.framing, Some(Framing::Unprefixed));
+    }
+
+    #[test]
+    fn ble_detection_gives_up_as_prefixed() {
+        let mut profile = ReportProfile::new(Transport::BluetoothLe, 0x01);
+        for _ in 0..MAX_DETECTION_REPORTS {
+            framed(&mut profile, &[0; 8]);
+        }
+        assert_eq!(profile.framing, Some(Framing::Prefixed));
+    }
+
+    #[test]
+    fn only_allowed_vendors_are_parsed() {
+        let allowed: &[u16] = &[APPLE_VENDOR_ID, 0x046D];
+        assert!(vendor_allowed(Some(allowed), APPLE_VENDOR_ID));
+        assert!(vendor_allowed(Some(allowed), 0x046D));
+        assert!(!vendor_allowed(Some(allowed), 0x0FD9)); // Stream Deck
+        assert!(!vendor_allowed(Some(allowed), 0));
+        assert!(vendor_allowed(None, 0x0FD9));
+    }
+}
@@ -0,0 +1,154 @@
+// --- START OF FILE src/safe_mode.rs ---
+// Safe mode: a mapping or action that crashes the daemon, started with Windows and restarted by
+// the user, could leave the keyboard unusable every time. Each run marks a state file as running
+// and clears the mark when it shuts down cleanly, so a run that finds the mark left behind knows
+// the last one crashed. After CRASHES_FOR_SAFE_MODE crashes within CRASH_WINDOW the daemon starts
+// in safe mode: the built-in default mappings instead of the mapping file, and no key is ever
+// held back from Windows, until it is exited and started again. Only the first daemon using a
+// state file owns it (a named mutex decides), so a second copy starting isn't taken for a crash
+// and its exit doesn't clear the first one's mark.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const STATE_FILE: &str = "A1314_run_state.txt";
+// Mappings loaded in safe mode, written next to the state file
+const SAFE_MAPPING_FILE: &str = "A1314_safe_mode_mapping.txt";
+
+pub const CRASH_WINDOW: Duration = Duration::from_secs(10 * 60);
+pub const CRASHES_FOR_SAFE_MODE: usize = 2;
+
+/// Why a run is in safe mode, e.g. "2 crashes within 10 minutes", for the log and the toast
+pub fn trigger() -> String {
+    format!("{} crashes within {} minutes", CRASHES_FOR_SAFE_MODE, CRASH_WINDOW.as_secs() / 60)
+}
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// The state file of this run, once started
+static STATE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Marks this run as started in the state file in `dir`, and returns whether it should run in
+/// safe mode because earlier runs crashed
+pub fn start(dir: &Path) -> bool {
+    let path = dir.join(STATE_FILE);
+    if !claim(&path) {
+        log::warn!("Another daemon is already running from {}; not tracking crashes in this one", dir.display());
+        return false;
+    }
+    let (running, crashes) = std::fs::read_to_string(&path).map(|text| parse(&text)).unwrap_or_default();
+    let (crashes, safe) = decide(running, crashes, now());
+    if running {
+        log::warn!("The daemon didn't shut down cleanly last time ({} crash(es) in the last {} minutes)",
+                   crashes.len(), CRASH_WINDOW.as_secs() / 60);
+    }
+
+    let _ = std::fs::create_dir_all(dir);
+    if let Err(e) = std::fs::write(&path, render(true, &crashes)) {
+        log::warn!("Failed to write {}, crashes won't be noticed: {}", path.display(), e);
+    }
+    *STATE_PATH.lock().unwrap_or_else(|p| p.into_inner()) = Some(path);
+    ACTIVE.store(safe, Ordering::Relaxed);
+    safe
+}
+
+/// Takes the named mutex for the state file at `path`, held until this process ends (however it
+/// ends). False if another running daemon already holds it.
+fn claim(path: &Path) -> bool {
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::{GetLastError, ERROR_ALREADY_EXISTS};
+    use windows::Win32::System::Threading::CreateMutexW;
+
+    // Mutex names can't contain backslashes, and paths differ only by case on Windows
+    let name = format!("Local\\A1314Daemon_{}", path.display().to_string().replace('\\', "/").to_lowercase());
+    match unsafe { CreateMutexW(None, false, &HSTRING::from(name)) } {
+        // The handle is deliberately never closed
+        Ok(_) => unsafe { GetLastError() != ERROR_ALREADY_EXISTS },
+        Err(e) => {
+            log::warn!("Failed to create the run state mutex: {}", e);
+            true
+        }
+    }
+}
+
+/// Clears the running mark (and with it the crashes so far), on a clean shutdown
+pub fn clean_exit() {
+    if let Some(path) = STATE_PATH.lock().unwrap_or_else(|p| p.into_inner()).take() {
+        if let Err(e) = std::fs::write(&path, render(false, &[])) {
+            log::warn!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Whether this run is in safe mode
+pub fn active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Writes the built-in default mappings next to the state file in `dir` for safe mode to load
+pub fn default_mapping_file(dir: &Path, text: &str) -> std::io::Result<PathBuf> {
+    let path = dir.join(SAFE_MAPPING_FILE);
+    std::fs::write(&path, text)?;
+    Ok(path)
+}
+
+/// The crashes to remember (those within the window, plus the last run's if it crashed) and
+/// whether there were enough of them for safe mode
+fn decide(running: bool, mut crashes: Vec<u64>, now: u64) -> (Vec<u64>, bool) {
+    if running {
+        crashes.push(now);
+    }
+    crashes.retain(|&at| now.saturating_sub(at) < CRASH_WINDOW.as_secs());
+    let safe = crashes.len() >= CRASHES_FOR_SAFE_MODE;
+    (crashes, safe)
+}
+
+/// The state file: "running" while a run is up, then a "crash <unix time>" line per crash
+fn parse(text: &str) -> (bool, Vec<u64>) {
+    let running = text.lines().any(|line| line.trim() == "running");
+    let crashes = text.lines()
+        .filter_map(|line| line.trim().strip_prefix("crash "))
+        .filter_map(|at| at.trim().parse().ok())
+        .collect();
+    (running, crashes)
+}
+
+fn render(running: bool, crashes: &[u64]) -> String {
+    let mut out = String::new();
+    if running {
+        out.push_str("running\n");
+    }
+    for at in crashes {
+        out.push_str(&format!("crash {}\n", at));
+    }
+    out
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_crashes_in_the_window_start_safe_mode() {
+        let (running, crashes) = parse(&render(true, &[]));
+        let (crashes, safe) = decide(running, crashes, 1_000);
+        assert_eq!((crashes.clone(), safe), (vec![1_000], false));
+
+        // Crashed again five minutes later
+        let (running, crashes) = parse(&render(true, &crashes));
+        assert_eq!(decide(running, crashes.clone(), 1_300), (vec![1_000, 1_300], true));
+
+        // ...or an hour later, when the first one no longer counts
+        assert_eq!(decide(running, crashes, 4_600), (vec![4_600], false));
+
+        // A clean shutdown clears the mark and the crashes
+        let (running, crashes) = parse(&render(false, &[]));
+        assert_eq!(decide(running, crashes, 1_300), (vec![], false));
+
+        assert_eq!(trigger(), "2 crashes within 10 minutes");
+    }
+}
@@ -0,0 +1,134 @@
+// --- START OF FILE src/scroll.rs ---
+// natural_scroll in [settings] and NATURAL_SCROLL_TOGGLE: macOS-style scrolling, where the
+// content follows the fingers. A low-level hook can't change an event, so the mouse hook holds
+// each wheel event back and sends it again the other way round. Which mouse turned the wheel
+// comes from raw mouse input, only read while the setting names devices.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use windows::Win32::Foundation::{HANDLE, HWND, LPARAM, WPARAM};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput, INPUT, INPUT_0, INPUT_MOUSE, MOUSEEVENTF_HWHEEL, MOUSEEVENTF_WHEEL, MOUSEINPUT,
+};
+use windows::Win32::UI::WindowsAndMessaging::PostMessageW;
+
+use crate::action_executor;
+use crate::devices;
+use crate::error::{A1314Error, Result};
+use crate::settings::NaturalScroll;
+
+// How long after a raw wheel event the hook's wheel event is still put down to its mouse
+const MATCH_WINDOW: Duration = Duration::from_millis(250);
+
+// natural_scroll from the last load, and whether NATURAL_SCROLL_TOGGLE has flipped it since
+static CONFIGURED: Mutex<NaturalScroll> = Mutex::new(NaturalScroll::Off);
+static TOGGLED: AtomicBool = AtomicBool::new(false);
+// Window posted NOTIFY_MESSAGE whenever the rule changes, so the hooks can follow it
+static NOTIFY_WINDOW: AtomicIsize = AtomicIsize::new(0);
+static NOTIFY_MESSAGE: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    // The mouse whose wheel last turned, from raw input, and when
+    static LAST_WHEEL: Cell<Option<(isize, Instant)>> = const { Cell::new(None) };
+    // Vendor and product IDs of the mice seen, by raw input device handle
+    static DEVICE_IDS: RefCell<HashMap<isize, Option<(u16, u16)>>> = RefCell::new(HashMap::new());
+}
+
+/// Posts `message` to `hwnd` whenever which mice scroll naturally changes
+pub fn set_notify(hwnd: HWND, message: u32) {
+    NOTIFY_WINDOW.store(hwnd.0 as isize, Ordering::Relaxed);
+    NOTIFY_MESSAGE.store(message, Ordering::Relaxed);
+}
+
+/// Takes natural_scroll from a (re)load; a toggle made since stays in effect
+pub fn configure(rule: &NaturalScroll) {
+    let changed = {
+        let mut configured = CONFIGURED.lock().unwrap_or_else(|p| p.into_inner());
+        std::mem::replace(&mut *configured, rule.clone()) != *rule
+    };
+    if changed {
+        notify();
+    }
+}
+
+/// NATURAL_SCROLL_TOGGLE: turns natural scrolling off where it is on, or on for every mouse
+pub fn toggle() {
+    TOGGLED.fetch_xor(true, Ordering::SeqCst);
+    log::info!("Natural scrolling {}", if rule() == NaturalScroll::Off { "off" } else { "on" });
+    notify();
+}
+
+/// Which mice scroll naturally right now
+pub fn rule() -> NaturalScroll {
+    let configured = CONFIGURED.lock().unwrap_or_else(|p| p.into_inner()).clone();
+    match (TOGGLED.load(Ordering::SeqCst), configured) {
+        (false, configured) => configured,
+        (true, NaturalScroll::Off) => NaturalScroll::All,
+        (true, _) => NaturalScroll::Off,
+    }
+}
+
+/// Notes a wheel turn seen in raw mouse input, so the hook event that follows it can be put
+/// down to that mouse
+pub fn wheel_turned(device: HANDLE) {
+    LAST_WHEEL.with(|last| last.set(Some((device.0 as isize, Instant::now()))));
+}
+
+/// Whether a hooked wheel event should be reversed, judged by the mouse that turned it
+pub fn reverses_wheel() -> bool {
+    match rule() {
+        NaturalScroll::Off => false,
+        NaturalScroll::All => true,
+        NaturalScroll::Devices(devices) => {
+            let Some((device, at)) = LAST_WHEEL.with(Cell::get) else {
+                return false;
+            };
+            if at.elapsed() > MATCH_WINDOW {
+                return false;
+            }
+            let ids = DEVICE_IDS.with(|ids| {
+                *ids.borrow_mut().entry(device)
+                    .or_insert_with(|| unsafe { devices::vid_pid(HANDLE(device as *mut _)) })
+            });
+            ids.is_some_and(|(vendor_id, product_id)| matches_device(&devices, vendor_id, product_id))
+        }
+    }
+}
+
+/// Sends a wheel event of `delta` notches (120 each) the other way round, tagged as the daemon's
+pub fn send_reversed(delta: i16, horizontal: bool) -> Result<()> {
+    let input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                mouseData: (-(delta as i32)) as u32,
+                dwFlags: if horizontal { MOUSEEVENTF_HWHEEL } else { MOUSEEVENTF_WHEEL },
+                dwExtraInfo: action_executor::injection_tag(),
+                ..Default::default()
+            },
+        },
+    };
+    unsafe {
+        if SendInput(&[input], std::mem::size_of::<INPUT>() as i32) == 0 {
+            return Err(A1314Error::Injection(format!("SendInput rejected a wheel event: {}", windows::core::Error::from_win32())));
+        }
+    }
+    Ok(())
+}
+
+/// Whether a natural_scroll device list names the mouse with these IDs
+pub fn matches_device(devices: &[(u16, Option<u16>)], vendor_id: u16, product_id: u16) -> bool {
+    devices.iter().any(|&(vendor, product)| vendor == vendor_id && product.is_none_or(|product| product == product_id))
+}
+
+fn notify() {
+    let hwnd = NOTIFY_WINDOW.load(Ordering::Relaxed);
+    if hwnd != 0 {
+        unsafe {
+            let _ = PostMessageW(HWND(hwnd as *mut _), NOTIFY_MESSAGE.load(Ordering::Relaxed), WPARAM(0), LPARAM(0));
+        }
+    }
+}
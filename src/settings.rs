@@ -0,0 +1,355 @@
+// --- START OF FILE src/settings.rs ---
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::Duration;
+
+use crate::devices::APPLE_VENDOR_ID;
+use crate::foreground::AppLists;
+use crate::hid_parser::{GhostPolicy, VendorReport, DEFAULT_VENDOR_REPORTS};
+use crate::launcher::Launcher;
+
+// Default hold thresholds for tap/hold/long-hold mappings (in milliseconds)
+const DEFAULT_HOLD_MEDIUM_MS: u64 = 500;
+const DEFAULT_HOLD_LONG_MS: u64 = 2000;
+
+// Default threshold for LONGPRESS(KEY) mappings without a duration (in milliseconds)
+const DEFAULT_LONG_PRESS_MS: u64 = 800;
+
+// Longest a modifier can be held alone for its tap action to still fire (in milliseconds)
+const DEFAULT_MODIFIER_TAP_MS: u64 = 500;
+
+// How far back the in-memory event journal reaches (in seconds)
+const DEFAULT_JOURNAL_SECONDS: u64 = 30;
+
+// Quiet period after the last file change before hot reload runs (in milliseconds)
+const DEFAULT_RELOAD_DEBOUNCE_MS: u64 = 100;
+
+// Pause between the key events of a combo; 0 sends the whole combo at once (in milliseconds)
+const DEFAULT_KEY_DELAY_MS: u64 = 0;
+
+// Longest key_delay_ms allowed. Spaced-out combos go to the sequence worker, off the keyboard
+// hook's thread, but anything longer makes a combo too slow to feel like one.
+const MAX_KEY_DELAY_MS: u64 = 50;
+
+// Auto-repeat of a held mapped key: 0 leaves the delay and rate to Windows (in milliseconds)
+const DEFAULT_REPEAT_DELAY_MS: u64 = 0;
+const DEFAULT_REPEAT_INTERVAL_MS: u64 = 0;
+
+// Simultaneous non-modifier keys the A1314 matrix can reliably report
+const DEFAULT_MAX_CHORD_KEYS: usize = 3;
+
+/// What happens to a key with no mapping in the active modifier layer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallthrough {
+    None,   // Blocked
+    Normal, // Uses the key's mapping without modifiers, or is sent as-is if it has none
+    System, // Sent as-is
+}
+
+impl Fallthrough {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "none" => Some(Fallthrough::None),
+            "normal" => Some(Fallthrough::Normal),
+            "system" => Some(Fallthrough::System),
+            _ => None,
+        }
+    }
+}
+
+/// Where the daemon intercepts keys it may hold back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputDriver {
+    Hook,         // The low-level keyboard hook, above the Win32 injection layer
+    Interception, // The Interception filter driver, below it (needs the `interception` build feature)
+}
+
+impl InputDriver {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "hook" => Some(InputDriver::Hook),
+            "interception" => Some(InputDriver::Interception),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InputDriver::Hook => "hook",
+            InputDriver::Interception => "interception",
+        }
+    }
+}
+
+/// Which mice scroll the macOS way, with the content following the fingers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NaturalScroll {
+    Off,
+    All,
+    Devices(Vec<(u16, Option<u16>)>), // Vendor ID, and the product ID if only that product
+}
+
+impl NaturalScroll {
+    /// Parses off, on, or a list of vendor IDs with optional product IDs, e.g. `0x05AC:0x030D, 046D`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value.trim().to_lowercase().as_str() {
+            "off" | "false" | "no" => return Ok(NaturalScroll::Off),
+            "on" | "true" | "yes" | "any" => return Ok(NaturalScroll::All),
+            _ => {}
+        }
+        let hex = |id: &str| {
+            let id = id.trim();
+            u16::from_str_radix(id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(id), 16)
+        };
+        value.split(',')
+            .map(|device| match device.split_once(':') {
+                Some((vendor, product)) => hex(vendor).and_then(|vendor| Ok((vendor, Some(hex(product)?)))),
+                None => hex(device).map(|vendor| (vendor, None)),
+            }
+            .map_err(|_| format!("Invalid natural_scroll device '{}', expected on, off or hex IDs like 0x05AC or 05AC:030D", device.trim())))
+            .collect::<Result<Vec<_>, String>>()
+            .map(NaturalScroll::Devices)
+    }
+}
+
+/// Global options read from the `[settings]` and `[vendor_reports]` sections of the mapping file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub hold_medium_ms: u64,
+    pub hold_long_ms: u64,
+    pub long_press_ms: u64,
+    pub modifier_tap_ms: u64, // 0 lets a modifier's tap fire however long it was held
+    pub journal_seconds: u64,
+    pub ghost_policy: GhostPolicy,
+    pub max_chord_keys: usize,
+    pub reload_debounce_ms: u64,
+    pub key_delay_ms: u64,
+    pub log_level: Option<log::LevelFilter>, // None keeps the build's default (or RUST_LOG)
+    pub repeat_delay_ms: Option<u64>, // Hold before a mapped key's action repeats; None never repeats
+    pub repeat_interval_ms: u64, // Least time between repeats of a mapped key's action
+    pub game_mode: bool, // Pause remapping while a fullscreen game is in front
+    pub apps: AppLists, // exclude_apps, include_apps and passthrough_apps
+    pub launcher: Launcher, // What the LAUNCHER action opens
+    pub layer_sound: Option<String>, // Played whenever Fn, Shift or Eject switches the layer
+    pub fn_fallthrough: Fallthrough,
+    pub shift_fallthrough: Fallthrough,
+    pub eject_fallthrough: Fallthrough,
+    pub eject_fn_fallthrough: Fallthrough,
+    pub vendor_reports: Vec<VendorReport>,
+    pub vendor_ids: Option<Vec<u16>>, // Devices whose HID reports are parsed; None for any device
+    pub input_driver: InputDriver, // Read at startup only
+    pub natural_scroll: NaturalScroll,
+    pub mac_navigation: bool, // The built-in macOS navigation mappings, under the file's own
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            hold_medium_ms: DEFAULT_HOLD_MEDIUM_MS,
+            hold_long_ms: DEFAULT_HOLD_LONG_MS,
+            long_press_ms: DEFAULT_LONG_PRESS_MS,
+            modifier_tap_ms: DEFAULT_MODIFIER_TAP_MS,
+            journal_seconds: DEFAULT_JOURNAL_SECONDS,
+            ghost_policy: GhostPolicy::Off,
+            max_chord_keys: DEFAULT_MAX_CHORD_KEYS,
+            reload_debounce_ms: DEFAULT_RELOAD_DEBOUNCE_MS,
+            key_delay_ms: DEFAULT_KEY_DELAY_MS,
+            log_level: None,
+            repeat_delay_ms: Some(DEFAULT_REPEAT_DELAY_MS),
+            repeat_interval_ms: DEFAULT_REPEAT_INTERVAL_MS,
+            game_mode: true,
+            apps: AppLists::new(),
+            launcher: Launcher::Search,
+            layer_sound: None,
+            fn_fallthrough: Fallthrough::System,
+            shift_fallthrough: Fallthrough::System,
+            eject_fallthrough: Fallthrough::System,
+            eject_fn_fallthrough: Fallthrough::System,
+            vendor_reports: DEFAULT_VENDOR_REPORTS.to_vec(),
+            vendor_ids: Some(vec![APPLE_VENDOR_ID]),
+            input_driver: InputDriver::Hook,
+            natural_scroll: NaturalScroll::Off,
+            mac_navigation: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Applies a single `name = value` line from the `[settings]` section
+    pub fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name.to_lowercase().as_str() {
+            "hold_medium_ms" => self.hold_medium_ms = parse_ms(value)?,
+            "hold_long_ms" => self.hold_long_ms = parse_ms(value)?,
+            "long_press_ms" => self.long_press_ms = parse_ms(value)?,
+            "modifier_tap_ms" => self.modifier_tap_ms = parse_ms(value)?,
+            "journal_seconds" => {
+                self.journal_seconds = value.trim().parse()
+                    .map_err(|_| format!("Invalid journal_seconds '{}', expected a whole number", value))?;
+            }
+            "ghost_policy" => {
+                self.ghost_policy = GhostPolicy::parse(value)
+                    .ok_or_else(|| format!("Invalid ghost_policy '{}', expected off, ignore or prefer_earlier", value))?;
+            }
+            "max_chord_keys" => {
+                self.max_chord_keys = value.trim().parse()
+                    .map_err(|_| format!("Invalid max_chord_keys '{}', expected a whole number", value))?;
+            }
+            "reload_debounce_ms" => self.reload_debounce_ms = parse_ms(value)?,
+            "key_delay_ms" => self.key_delay_ms = parse_ms(value)?,
+            "log_level" => {
+                self.log_level = match value.trim().to_lowercase().as_str() {
+                    "default" => None,
+                    level => Some(level.parse().map_err(|_| {
+                        format!("Invalid log_level '{}', expected off, error, warn, info, debug, trace or default", value)
+                    })?),
+                };
+            }
+            "repeat_delay_ms" => {
+                self.repeat_delay_ms = match value.trim().to_lowercase().as_str() {
+                    "off" => None,
+                    _ => Some(parse_ms(value)?),
+                };
+            }
+            "repeat_interval_ms" => self.repeat_interval_ms = parse_ms(value)?,
+            "game_mode" => self.game_mode = parse_switch(name, value)?,
+            "exclude_apps" => self.apps.exclude = parse_app_list(value),
+            "include_apps" => self.apps.include = parse_app_list(value),
+            "passthrough_apps" => self.apps.passthrough = parse_app_list(value),
+            "launcher" => {
+                self.launcher = Launcher::parse(value)
+                    .ok_or_else(|| format!("Invalid launcher '{}', expected search, powertoys, everything or RUN(\"command\")", value))?;
+            }
+            "layer_sound" => {
+                let file = value.trim().trim_matches('"');
+                self.layer_sound = (!file.is_empty() && !file.eq_ignore_ascii_case("off")).then(|| file.to_string());
+            }
+            "fn_fallthrough" => self.fn_fallthrough = parse_fallthrough(name, value)?,
+            "shift_fallthrough" => self.shift_fallthrough = parse_fallthrough(name, value)?,
+            "eject_fallthrough" => self.eject_fallthrough = parse_fallthrough(name, value)?,
+            "eject_fn_fallthrough" => self.eject_fn_fallthrough = parse_fallthrough(name, value)?,
+            "vendor_ids" => self.vendor_ids = parse_vendor_ids(value)?,
+            "natural_scroll" => self.natural_scroll = NaturalScroll::parse(value)?,
+            "mac_navigation" => self.mac_navigation = parse_switch(name, value)?,
+            "input_driver" => {
+                self.input_driver = InputDriver::parse(value)
+                    .ok_or_else(|| format!("Invalid input_driver '{}', expected hook or interception", value))?;
+            }
+            _ => return Err(format!("Unknown setting '{}'", name)),
+        }
+        Ok(())
+    }
+
+    /// Applies a single `report_id = FN:<bits>, EJECT:<bits>` line from the `[vendor_reports]` section,
+    /// replacing the layout for that report ID
+    pub fn apply_vendor_report(&mut self, report_id: &str, masks: &str) -> Result<(), String> {
+        let vendor = VendorReport::parse(report_id, masks)?;
+        self.vendor_reports.retain(|existing| existing.report_id != vendor.report_id);
+        self.vendor_reports.push(vendor);
+        Ok(())
+    }
+
+    /// Called once the whole file has been read to catch inconsistent values
+    pub fn validate(&mut self) {
+        if self.hold_long_ms <= self.hold_medium_ms {
+            log::warn!("hold_long_ms ({}) must be greater than hold_medium_ms ({}), using defaults",
+                       self.hold_long_ms, self.hold_medium_ms);
+            self.hold_medium_ms = DEFAULT_HOLD_MEDIUM_MS;
+            self.hold_long_ms = DEFAULT_HOLD_LONG_MS;
+        }
+
+        if self.max_chord_keys == 0 {
+            log::warn!("max_chord_keys must be at least 1, using {}", DEFAULT_MAX_CHORD_KEYS);
+            self.max_chord_keys = DEFAULT_MAX_CHORD_KEYS;
+        }
+
+        if self.key_delay_ms > MAX_KEY_DELAY_MS {
+            log::warn!("key_delay_ms ({}) can be at most {}, using {}", self.key_delay_ms, MAX_KEY_DELAY_MS, MAX_KEY_DELAY_MS);
+            self.key_delay_ms = MAX_KEY_DELAY_MS;
+        }
+    }
+}
+
+lazy_static::lazy_static! {
+    // The settings from the last load, for the threads and modules that don't own the mapper
+    static ref SHARED: Arc<RwLock<Settings>> = Arc::new(RwLock::new(Settings::default()));
+}
+
+/// Makes `settings` the ones every reader of `current()` sees from now on
+pub fn publish(settings: &Settings) {
+    *SHARED.write().unwrap_or_else(|p| p.into_inner()) = settings.clone();
+}
+
+/// The settings from the last load; don't hold on to the guard
+pub fn current() -> RwLockReadGuard<'static, Settings> {
+    SHARED.read().unwrap_or_else(|p| p.into_inner())
+}
+
+/// A handle on the settings from the last load, for a thread that keeps reading them
+pub fn shared() -> Arc<RwLock<Settings>> {
+    SHARED.clone()
+}
+
+/// Parses a duration in milliseconds, accepting an optional "ms" suffix (e.g. "500" or "500ms")
+pub fn parse_ms(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let digits = value.strip_suffix("ms").unwrap_or(value).trim();
+    digits.parse::<u64>()
+        .map_err(|_| format!("Invalid duration '{}', expected milliseconds like 500 or 500ms", value))
+}
+
+/// Parses a duration in seconds, or with an "ms", "s" or "m" suffix (e.g. "30", "30s" or "5m")
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim().to_lowercase();
+    let error = || format!("Invalid duration '{}', expected e.g. 30s, 5m or 500ms", value);
+    let (digits, unit_ms) = if let Some(digits) = value.strip_suffix("ms") {
+        (digits, 1)
+    } else if let Some(digits) = value.strip_suffix('s') {
+        (digits, 1000)
+    } else if let Some(digits) = value.strip_suffix('m') {
+        (digits, 60_000)
+    } else {
+        (value.as_str(), 1000)
+    };
+    let count: u64 = digits.trim().parse().map_err(|_| error())?;
+    count.checked_mul(unit_ms).map(Duration::from_millis).ok_or_else(error)
+}
+
+/// Parses on/off (also true/false, yes/no)
+fn parse_switch(name: &str, value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "on" | "true" | "yes" => Ok(true),
+        "off" | "false" | "no" => Ok(false),
+        _ => Err(format!("Invalid {} '{}', expected on or off", name, value)),
+    }
+}
+
+/// Parses a list of executable names, e.g. `["vmware.exe", "mstsc.exe"]` or `vmware.exe, mstsc`
+fn parse_app_list(value: &str) -> Vec<String> {
+    let value = value.trim();
+    let value = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+    value.split(',')
+        .map(|app| app.trim().trim_matches('"').trim())
+        .filter(|app| !app.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn parse_fallthrough(name: &str, value: &str) -> Result<Fallthrough, String> {
+    Fallthrough::parse(value)
+        .ok_or_else(|| format!("Invalid {} '{}', expected none, normal or system", name, value))
+}
+
+/// Parses a comma separated list of USB vendor IDs (e.g. "0x05AC, 0x046D"), or "any"
+fn parse_vendor_ids(value: &str) -> Result<Option<Vec<u16>>, String> {
+    if value.trim().eq_ignore_ascii_case("any") {
+        return Ok(None);
+    }
+    value.split(',')
+        .map(|id| {
+            let id = id.trim();
+            let hex = id.strip_prefix("0x").or_else(|| id.strip_prefix("0X")).unwrap_or(id);
+            u16::from_str_radix(hex, 16)
+                .map_err(|_| format!("Invalid vendor ID '{}', expected hex like 0x05AC or 'any'", id))
+        })
+        .collect::<Result<Vec<u16>, String>>()
+        .map(Some)
+}
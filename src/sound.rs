@@ -0,0 +1,35 @@
+// --- START OF FILE src/sound.rs ---
+// SOUND("click.wav"): audio confirmation that a macro fired or a layer switched, for setups
+// without anything on screen. Relative paths are looked up next to the mapping file.
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use windows::core::HSTRING;
+use windows::Win32::Media::Audio::{PlaySoundW, SND_ASYNC, SND_FILENAME, SND_NODEFAULT};
+
+use crate::error::{A1314Error, Result};
+use crate::expand;
+
+// Folder of the mapping file, which relative sound paths are relative to
+static BASE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_base_dir(dir: Option<&Path>) {
+    *BASE_DIR.lock().unwrap_or_else(|p| p.into_inner()) = dir.map(Path::to_path_buf);
+}
+
+/// Starts playing a .wav file and returns without waiting for it; a new sound cuts off the last
+pub fn play(file: &str) -> Result<()> {
+    let path = PathBuf::from(expand::expand(file));
+    let path = match BASE_DIR.lock().unwrap_or_else(|p| p.into_inner()).as_ref() {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path,
+    };
+    if !path.is_file() {
+        return Err(A1314Error::Injection(format!("Sound file '{}' not found", path.display())));
+    }
+    let played = unsafe { PlaySoundW(&HSTRING::from(path.as_os_str()), None, SND_FILENAME | SND_ASYNC | SND_NODEFAULT) };
+    if !played.as_bool() {
+        return Err(A1314Error::Injection(format!("Could not play '{}'", path.display())));
+    }
+    Ok(())
+}
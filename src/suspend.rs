@@ -0,0 +1,68 @@
+// --- START OF FILE src/suspend.rs ---
+// SUSPEND(...) and the tray's "Suspend for 5 Minutes": keys pass through untouched until a
+// deadline, then remapping resumes by itself
+use std::sync::atomic::{AtomicIsize, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::UI::WindowsAndMessaging::PostMessageW;
+
+static SUSPENDED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+// Window posted NOTIFY_MESSAGE when a suspension starts or ends, so the tray can count down
+static NOTIFY_WINDOW: AtomicIsize = AtomicIsize::new(0);
+static NOTIFY_MESSAGE: AtomicU32 = AtomicU32::new(0);
+
+/// Posts `message` to `hwnd` whenever a suspension starts or ends
+pub fn set_notify(hwnd: HWND, message: u32) {
+    NOTIFY_WINDOW.store(hwnd.0 as isize, Ordering::Relaxed);
+    NOTIFY_MESSAGE.store(message, Ordering::Relaxed);
+}
+
+/// Suspends remapping for `duration` from now, replacing any earlier deadline
+pub fn suspend(duration: Duration) {
+    *SUSPENDED_UNTIL.lock().unwrap_or_else(|p| p.into_inner()) = Some(Instant::now() + duration);
+    log::info!("Remapping suspended for {}", countdown(duration));
+    notify();
+}
+
+/// Ends a suspension early
+pub fn resume() {
+    if SUSPENDED_UNTIL.lock().unwrap_or_else(|p| p.into_inner()).take().is_some() {
+        log::info!("Remapping resumed");
+        notify();
+    }
+}
+
+/// Time left until remapping resumes, or None if it isn't suspended
+pub fn remaining() -> Option<Duration> {
+    let left = {
+        let mut until = SUSPENDED_UNTIL.lock().unwrap_or_else(|p| p.into_inner());
+        let left = until.as_ref()?.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            *until = None;
+        }
+        left
+    };
+    if left.is_zero() {
+        log::info!("Suspension over, remapping resumed");
+        notify();
+        return None;
+    }
+    Some(left)
+}
+
+/// Time left as minutes and seconds, rounded up, e.g. "4:59"
+pub fn countdown(left: Duration) -> String {
+    let seconds = left.as_millis().div_ceil(1000);
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+fn notify() {
+    let hwnd = NOTIFY_WINDOW.load(Ordering::Relaxed);
+    if hwnd != 0 {
+        unsafe {
+            let _ = PostMessageW(HWND(hwnd as *mut _), NOTIFY_MESSAGE.load(Ordering::Relaxed), WPARAM(0), LPARAM(0));
+        }
+    }
+}
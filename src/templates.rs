@@ -0,0 +1,134 @@
+// --- START OF FILE src/templates.rs ---
+// Starting points for a new mapping file, picked with `--template NAME` or the tray's "Reset to
+// Default Configuration" submenu. Each is the bundled A1314_mapping.txt with the part that
+// depends on the keyboard layout or habits changed, so all of them keep its comments.
+
+const BASE: &str = include_str!("../A1314_mapping.txt");
+
+// The function row and the Fn layer's F-keys in BASE run from this line up to FN_ROW_END
+const FN_ROW_START: &str = "# Function row";
+const FN_ROW_END: &str = "# Fn + Backspace";
+
+const WINDOWS_FN_ROW: &str = "\
+# Function row - Plain F1-F12 reach Windows as function keys (Windows-style);
+# hold Fn for the media/system functions printed on the keys
+###############################################################################
+
+###############################################################################
+# FN-modified mappings - Media/system functions and Home/End/PgUp/PgDn
+###############################################################################
+
+# Fn + F1-F12 → Brightness, Task View, search, dictation, notifications, media, volume
+FN+F1 = BRIGHTNESS_DOWN
+FN+F2 = BRIGHTNESS_UP
+FN+F3 = WIN+TAB
+FN+F4 = WIN+S
+FN+F5 = WIN+H
+FN+F6 = WIN+A
+FN+F7 = MEDIA_PREV
+FN+F8 = MEDIA_PLAY_PAUSE
+FN+F9 = MEDIA_NEXT
+FN+F10 = MUTE
+FN+F11 = VOLUME_DOWN
+FN+F12 = VOLUME_UP
+
+";
+
+const ISO_SECTION: &str = "
+###############################################################################
+# ISO layout - Apple's ISO keyboards swap two keys compared to a PC ISO keyboard:
+# the § key left of 1 sends the key Windows expects beside Left Shift, and the
+# ` key beside Left Shift sends the one Windows expects left of 1
+###############################################################################
+SECTION = GRAVE
+GRAVE = SECTION
+";
+
+// Cmd is the Windows key, so it is released before the Ctrl shortcut is sent; otherwise Windows
+// would see Win+Ctrl+key (a new virtual desktop for D, the sound output picker for V, ...)
+const MAC_SECTION: &str = "
+###############################################################################
+# macOS-like modifiers - Cmd (the Windows key) with a letter does what it does on
+# a Mac. Windows sees Cmd released once such a shortcut fires, so hold it again
+# for Win+ shortcuts; Cmd with any key not listed here is still Win+key.
+###############################################################################
+CMD+KEY_C = KEYS(CTRL_DOWN, WIN_UP, C, CTRL_UP)
+CMD+KEY_V = KEYS(CTRL_DOWN, WIN_UP, V, CTRL_UP)
+CMD+KEY_X = KEYS(CTRL_DOWN, WIN_UP, X, CTRL_UP)
+CMD+KEY_Z = KEYS(CTRL_DOWN, WIN_UP, Z, CTRL_UP)
+CMD+SHIFT+KEY_Z = KEYS(CTRL_DOWN, WIN_UP, Y, CTRL_UP)
+CMD+KEY_A = KEYS(CTRL_DOWN, WIN_UP, A, CTRL_UP)
+CMD+KEY_F = KEYS(CTRL_DOWN, WIN_UP, F, CTRL_UP)
+CMD+KEY_S = KEYS(CTRL_DOWN, WIN_UP, S, CTRL_UP)
+CMD+KEY_O = KEYS(CTRL_DOWN, WIN_UP, O, CTRL_UP)
+CMD+KEY_N = KEYS(CTRL_DOWN, WIN_UP, N, CTRL_UP)
+CMD+KEY_T = KEYS(CTRL_DOWN, WIN_UP, T, CTRL_UP)
+CMD+KEY_P = KEYS(CTRL_DOWN, WIN_UP, P, CTRL_UP)
+CMD+KEY_W = CLOSE_WINDOW
+CMD+KEY_Q = QUIT_APP
+";
+
+/// `mac_navigation = on` in [settings]: the text navigation keys of a Mac, loaded underneath the
+/// mapping files so any of them can be mapped differently there. CLEARMODS lets go of the
+/// modifiers a mapping is for while its keys are sent, so Cmd+Left reaches Windows as Home, not
+/// Win+Home.
+pub const MAC_NAVIGATION: &str = "\
+CLEARMODS FN+BACKSPACE = DELETE
+CLEARMODS CMD+LEFT_ARROW = HOME
+CLEARMODS CMD+RIGHT_ARROW = END
+CLEARMODS CMD+UP_ARROW = CTRL+HOME
+CLEARMODS CMD+DOWN_ARROW = CTRL+END
+CLEARMODS OPTION+LEFT_ARROW = CTRL+LEFT_ARROW
+CLEARMODS OPTION+RIGHT_ARROW = CTRL+RIGHT_ARROW
+";
+
+/// A mapping file to start from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Template {
+    UsAnsi,       // The bundled file: media keys on the function row
+    Iso,          // Same, with Apple's swapped § and ` keys put back for ISO layouts
+    MacModifiers, // Same, plus Cmd+C/V/X/Z/... doing what they do on a Mac
+    WindowsFKeys, // Plain F1-F12, with the media keys moved to Fn
+}
+
+impl Template {
+    pub const ALL: [Template; 4] = [Template::UsAnsi, Template::Iso, Template::MacModifiers, Template::WindowsFKeys];
+
+    /// Name for `--template`
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::UsAnsi => "us-ansi",
+            Template::Iso => "iso",
+            Template::MacModifiers => "mac",
+            Template::WindowsFKeys => "windows-fkeys",
+        }
+    }
+
+    /// Menu text and `--template` listing
+    pub fn describe(self) -> &'static str {
+        match self {
+            Template::UsAnsi => "US ANSI (media keys on F1-F12)",
+            Template::Iso => "ISO (fixes the swapped \u{a7} and ` keys)",
+            Template::MacModifiers => "macOS-like modifiers (Cmd+C copies)",
+            Template::WindowsFKeys => "Windows-native F-keys (media keys on Fn)",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Template::ALL.into_iter().find(|template| template.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// The mapping file text for this template
+    pub fn render(self) -> String {
+        match self {
+            Template::UsAnsi => BASE.to_string(),
+            Template::Iso => format!("{}{}", BASE, ISO_SECTION),
+            Template::MacModifiers => format!("{}{}", BASE, MAC_SECTION),
+            Template::WindowsFKeys => {
+                let start = BASE.find(FN_ROW_START).expect("A1314_mapping.txt has a function row section");
+                let end = BASE.find(FN_ROW_END).expect("A1314_mapping.txt has an Fn + Backspace section");
+                format!("{}{}{}", &BASE[..start], WINDOWS_FN_ROW, &BASE[end..])
+            }
+        }
+    }
+}
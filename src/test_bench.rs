@@ -0,0 +1,336 @@
+// --- START OF FILE src/test_bench.rs ---
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::io::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HINSTANCE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Gdi::{GetStockObject, COLOR_BTNFACE, DEFAULT_GUI_FONT, HBRUSH};
+use windows::Win32::UI::WindowsAndMessaging::{
+    CreateWindowExW, DefWindowProcW, DestroyWindow, GetWindowTextW, LoadCursorW, RegisterClassW,
+    SendMessageW, SetForegroundWindow, SetWindowTextW, ShowWindow, BS_DEFPUSHBUTTON, BS_PUSHBUTTON,
+    CW_USEDEFAULT, ES_AUTOHSCROLL, HMENU, IDC_ARROW, LBN_DBLCLK, LBS_NOINTEGRALHEIGHT, LBS_NOTIFY,
+    LB_ADDSTRING, LB_ERR, LB_GETCURSEL, LB_RESETCONTENT, SW_SHOW, WINDOW_EX_STYLE, WINDOW_STYLE,
+    WM_CLOSE, WM_COMMAND, WM_DESTROY, WM_SETFONT, WNDCLASSW, WS_BORDER, WS_CAPTION, WS_CHILD,
+    WS_DISABLED, WS_EX_CLIENTEDGE, WS_MINIMIZEBOX, WS_OVERLAPPED, WS_SYSMENU, WS_TABSTOP, WS_VISIBLE, WS_VSCROLL,
+};
+
+use crate::action_executor::{execute_action, Action};
+use crate::config::parse_mapping;
+use crate::key_mapper::KeyMapper;
+use crate::policy;
+
+// Control IDs
+const ID_MAPPINGS: i32 = 100;
+const ID_TEST: i32 = 101;
+const ID_DRY_RUN: i32 = 102;
+const ID_REFRESH: i32 = 103;
+const ID_CANDIDATE: i32 = 104;
+const ID_VALIDATE: i32 = 105;
+const ID_SAVE: i32 = 106;
+const ID_STATUS: i32 = 107;
+
+struct TestBench {
+    hwnd: HWND,
+    list: HWND,
+    candidate: HWND,
+    status: HWND,
+    mapper: Rc<RefCell<KeyMapper>>,
+    mapping_path: PathBuf,
+    entries: Vec<(String, Action)>,  // Snapshot shown in the list, indexed by list position
+}
+
+// The bench lives on the main window thread, next to the mapper it tests
+thread_local! {
+    static BENCH: RefCell<Option<TestBench>> = const { RefCell::new(None) };
+}
+
+/// Opens the test bench window, or brings it to the front if it is already open
+pub fn open(mapper: Rc<RefCell<KeyMapper>>, mapping_path: PathBuf) -> windows::core::Result<()> {
+    if let Some(hwnd) = BENCH.with(|b| b.borrow().as_ref().map(|bench| bench.hwnd)) {
+        unsafe {
+            let _ = ShowWindow(hwnd, SW_SHOW);
+            let _ = SetForegroundWindow(hwnd);
+        }
+        return Ok(());
+    }
+
+    unsafe {
+        let hinstance: HINSTANCE = windows::Win32::System::LibraryLoader::GetModuleHandleW(None)?.into();
+
+        let class_name = widestring("A1314TestBenchClass");
+        let window_name = widestring("A1314 Mapping Test Bench");
+        let wc = WNDCLASSW {
+            lpfnWndProc: Some(bench_wnd_proc),
+            hInstance: hinstance,
+            hCursor: LoadCursorW(None, IDC_ARROW)?,
+            hbrBackground: HBRUSH((COLOR_BTNFACE.0 + 1) as isize as *mut c_void),
+            lpszClassName: PCWSTR(class_name.as_ptr()),
+            ..Default::default()
+        };
+
+        // Fails harmlessly if the class is still registered from an earlier open
+        RegisterClassW(&wc);
+
+        let hwnd = CreateWindowExW(
+            WINDOW_EX_STYLE::default(),
+            PCWSTR(class_name.as_ptr()),
+            PCWSTR(window_name.as_ptr()),
+            WS_OVERLAPPED | WS_CAPTION | WS_SYSMENU | WS_MINIMIZEBOX,
+            CW_USEDEFAULT,
+            CW_USEDEFAULT,
+            600,
+            440,
+            None,
+            None,
+            hinstance,
+            None,
+        )?;
+
+        let list = create_control(hwnd, "LISTBOX", "",
+            WS_VSCROLL | WS_TABSTOP | WINDOW_STYLE((LBS_NOTIFY | LBS_NOINTEGRALHEIGHT) as u32),
+            WS_EX_CLIENTEDGE, (10, 10, 460, 260), ID_MAPPINGS)?;
+        create_control(hwnd, "BUTTON", "Test", WS_TABSTOP | WINDOW_STYLE(BS_DEFPUSHBUTTON as u32),
+            WINDOW_EX_STYLE::default(), (480, 10, 95, 28), ID_TEST)?;
+        create_control(hwnd, "BUTTON", "Dry Run", WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32),
+            WINDOW_EX_STYLE::default(), (480, 44, 95, 28), ID_DRY_RUN)?;
+        create_control(hwnd, "BUTTON", "Refresh", WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32),
+            WINDOW_EX_STYLE::default(), (480, 78, 95, 28), ID_REFRESH)?;
+        create_control(hwnd, "STATIC", "Candidate line (e.g. FN+F1 = BRIGHTNESS_DOWN):", WINDOW_STYLE(0),
+            WINDOW_EX_STYLE::default(), (10, 282, 460, 18), 0)?;
+        let candidate = create_control(hwnd, "EDIT", "",
+            WS_BORDER | WS_TABSTOP | WINDOW_STYLE(ES_AUTOHSCROLL as u32),
+            WINDOW_EX_STYLE::default(), (10, 302, 460, 24), ID_CANDIDATE)?;
+        create_control(hwnd, "BUTTON", "Validate", WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32),
+            WINDOW_EX_STYLE::default(), (480, 300, 95, 28), ID_VALIDATE)?;
+        // A mapping file fixed by policy can't be edited from here
+        let save_state = if policy::is_locked() { WS_DISABLED } else { WINDOW_STYLE(0) };
+        create_control(hwnd, "BUTTON", "Add to Config", WS_TABSTOP | WINDOW_STYLE(BS_PUSHBUTTON as u32) | save_state,
+            WINDOW_EX_STYLE::default(), (480, 334, 95, 28), ID_SAVE)?;
+        let status = create_control(hwnd, "STATIC", "Select a mapping and click Test, or type a line to validate.",
+            WINDOW_STYLE(0), WINDOW_EX_STYLE::default(), (10, 340, 460, 50), ID_STATUS)?;
+
+        BENCH.with(|b| {
+            *b.borrow_mut() = Some(TestBench {
+                hwnd,
+                list,
+                candidate,
+                status,
+                mapper,
+                mapping_path,
+                entries: Vec::new(),
+            });
+        });
+        refresh();
+
+        let _ = ShowWindow(hwnd, SW_SHOW);
+        let _ = SetForegroundWindow(hwnd);
+    }
+
+    log::info!("Mapping test bench opened");
+    Ok(())
+}
+
+/// Re-reads the loaded mappings into the list; called after every config reload
+pub fn refresh() {
+    let snapshot = BENCH.with(|b| {
+        let mut bench = b.borrow_mut();
+        let bench = bench.as_mut()?;
+        bench.entries = bench.mapper.borrow().mappings();
+        Some((bench.list, bench.entries.iter().map(|(line, _)| line.clone()).collect::<Vec<_>>()))
+    });
+
+    // Window calls are made outside the borrow so re-entrant messages can't panic
+    if let Some((list, lines)) = snapshot {
+        let count = lines.len();
+        unsafe {
+            SendMessageW(list, LB_RESETCONTENT, WPARAM(0), LPARAM(0));
+            for line in lines {
+                let wide = widestring(&line);
+                SendMessageW(list, LB_ADDSTRING, WPARAM(0), LPARAM(wide.as_ptr() as isize));
+            }
+        }
+        if policy::is_locked() {
+            set_status(&format!("{} mappings loaded. Add to Config is off: the mapping file is fixed by policy.", count));
+        } else {
+            set_status(&format!("{} mappings loaded", count));
+        }
+    }
+}
+
+// System control classes don't need our module handle
+unsafe fn create_control(parent: HWND, class: &str, text: &str, style: WINDOW_STYLE,
+                         ex_style: WINDOW_EX_STYLE, rect: (i32, i32, i32, i32), id: i32) -> windows::core::Result<HWND> {
+    let class = widestring(class);
+    let text = widestring(text);
+    let (x, y, w, h) = rect;
+    let hwnd = CreateWindowExW(
+        ex_style,
+        PCWSTR(class.as_ptr()),
+        PCWSTR(text.as_ptr()),
+        WS_CHILD | WS_VISIBLE | style,
+        x, y, w, h,
+        parent,
+        HMENU(id as isize as *mut c_void),
+        None,
+        None,
+    )?;
+    SendMessageW(hwnd, WM_SETFONT, WPARAM(GetStockObject(DEFAULT_GUI_FONT).0 as usize), LPARAM(1));
+    Ok(hwnd)
+}
+
+/// Runs (or just describes) the mapping currently selected in the list
+fn test_selected(dry_run: bool) {
+    let selected = BENCH.with(|b| {
+        let bench = b.borrow();
+        let bench = bench.as_ref()?;
+        let index = unsafe { SendMessageW(bench.list, LB_GETCURSEL, WPARAM(0), LPARAM(0)).0 };
+        if index == LB_ERR as isize {
+            return None;
+        }
+        bench.entries.get(index as usize).cloned()
+    });
+
+    let (line, action) = match selected {
+        Some(entry) => entry,
+        None => {
+            set_status("Select a mapping first");
+            return;
+        }
+    };
+
+    if dry_run {
+        log::info!("Test bench dry run: {} -> {:?}", line, action);
+        set_status(&format!("Dry run: {}\nWould execute {:?}", line, action));
+    } else {
+        log::info!("Test bench executing: {} -> {:?}", line, action);
+        match execute_action(&action) {
+            Ok(()) => set_status(&format!("Executed: {}\n{:?}", line, action)),
+            Err(e) => {
+                log::error!("{}", e);
+                set_status(&format!("Failed: {}\n{}", line, e));
+            }
+        }
+    }
+}
+
+/// Checks the candidate line against the mapping parser, returning it if valid
+fn validate_candidate() -> Option<String> {
+    let line = read_text(BENCH.with(|b| b.borrow().as_ref().map(|bench| bench.candidate))?);
+    let line = line.trim().to_string();
+    if line.is_empty() {
+        set_status("Type a mapping line first, e.g. FN+F1 = BRIGHTNESS_DOWN");
+        return None;
+    }
+
+    match parse_mapping(&line, 0) {
+        Ok(mapping) => {
+            set_status(&format!("Valid: {} layer, key {:04X}:{:04X} -> {:?}",
+                                mapping.modifiers, mapping.key.usage_page, mapping.key.usage, mapping.action));
+            Some(line)
+        }
+        Err(e) => {
+            set_status(&format!("Column {}: {}\n{}", e.column, e.message, e.hint));
+            None
+        }
+    }
+}
+
+/// Appends a validated candidate to the mapping file; the file watcher then reloads it
+fn save_candidate() {
+    if crate::refused_by_policy("add a mapping from the test bench") {
+        set_status("The mapping file is fixed by policy and can't be changed here.");
+        return;
+    }
+    let line = match validate_candidate() {
+        Some(line) => line,
+        None => return,
+    };
+
+    let path = match BENCH.with(|b| b.borrow().as_ref().map(|bench| bench.mapping_path.clone())) {
+        Some(path) => path,
+        None => return,
+    };
+
+    let needs_newline = std::fs::read_to_string(&path)
+        .map(|text| !text.is_empty() && !text.ends_with('\n'))
+        .unwrap_or(false);
+
+    let result = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| {
+            if needs_newline {
+                writeln!(file)?;
+            }
+            writeln!(file, "{}", line)
+        });
+
+    match result {
+        Ok(()) => {
+            log::info!("Test bench appended mapping to {}: {}", path.display(), line);
+            set_status(&format!("Added to config: {}", line));
+        }
+        Err(e) => {
+            log::error!("Failed to append mapping to {}: {}", path.display(), e);
+            set_status(&format!("Failed to write {}: {}", path.display(), e));
+        }
+    }
+}
+
+fn set_status(text: &str) {
+    if let Some(status) = BENCH.with(|b| b.borrow().as_ref().map(|bench| bench.status)) {
+        // Static controls need CRLF for line breaks
+        let wide = widestring(&text.replace('\n', "\r\n"));
+        unsafe {
+            let _ = SetWindowTextW(status, PCWSTR(wide.as_ptr()));
+        }
+    }
+}
+
+fn read_text(hwnd: HWND) -> String {
+    let mut buf = [0u16; 512];
+    let len = unsafe { GetWindowTextW(hwnd, &mut buf) };
+    String::from_utf16_lossy(&buf[..len.max(0) as usize])
+}
+
+extern "system" fn bench_wnd_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    unsafe {
+        match msg {
+            WM_COMMAND => {
+                let id = (wparam.0 & 0xFFFF) as i32;
+                let notification = ((wparam.0 >> 16) & 0xFFFF) as u32;
+                match id {
+                    ID_MAPPINGS if notification == LBN_DBLCLK => test_selected(false),
+                    ID_TEST => test_selected(false),
+                    ID_DRY_RUN => test_selected(true),
+                    ID_REFRESH => refresh(),
+                    ID_VALIDATE => {
+                        validate_candidate();
+                    }
+                    ID_SAVE => save_candidate(),
+                    _ => {}
+                }
+                LRESULT(0)
+            }
+            WM_CLOSE => {
+                let _ = DestroyWindow(hwnd);
+                LRESULT(0)
+            }
+            WM_DESTROY => {
+                // Only the bench closes here; the daemon keeps running
+                BENCH.with(|b| *b.borrow_mut() = None);
+                log::info!("Mapping test bench closed");
+                LRESULT(0)
+            }
+            _ => DefWindowProcW(hwnd, msg, wparam, lparam),
+        }
+    }
+}
+
+fn widestring(s: &str) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    std::ffi::OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+}
@@ -0,0 +1,76 @@
+// --- START OF FILE src/toast.rs ---
+// Windows notifications from the daemon. tray-icon can't show one, so a second notification area
+// icon is added for as long as a notification is up, and removed once it is clicked or times out.
+use std::cell::Cell;
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HWND, LPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Shell::{
+    Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_INFO, NIIF_WARNING, NIM_ADD,
+    NIM_DELETE, NIM_MODIFY, NIN_BALLOONTIMEOUT, NIN_BALLOONUSERCLICK, NOTIFYICONDATAW,
+};
+use windows::Win32::UI::WindowsAndMessaging::LoadIconW;
+
+// The notification icon's ID among the main window's icons; tray-icon uses its own window
+const TOAST_ICON_ID: u32 = 0xA131;
+
+thread_local! {
+    // Whether the notification icon is in the notification area
+    static SHOWING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Shows a notification, replacing any still up. `message` is posted to `hwnd` as it goes away;
+/// pass it on to `handle_message`.
+pub fn show(hwnd: HWND, message: u32, title: &str, text: &str, warning: bool) {
+    let mut data = icon_data(hwnd);
+    data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP | NIF_INFO;
+    data.uCallbackMessage = message;
+    data.hIcon = unsafe {
+        // The exe's icon (resource 1), as in the tray
+        GetModuleHandleW(None).and_then(|module| LoadIconW(module, PCWSTR(std::ptr::without_provenance(1)))).unwrap_or_default()
+    };
+    data.dwInfoFlags = if warning { NIIF_WARNING } else { NIIF_INFO };
+    copy_truncated(&mut data.szTip, title);
+    copy_truncated(&mut data.szInfoTitle, title);
+    copy_truncated(&mut data.szInfo, text);
+
+    let command = if SHOWING.with(Cell::get) { NIM_MODIFY } else { NIM_ADD };
+    if unsafe { Shell_NotifyIconW(command, &data) }.as_bool() {
+        SHOWING.with(|showing| showing.set(true));
+    } else {
+        log::warn!("Failed to show the notification '{}': {}", title, text);
+    }
+}
+
+/// Takes the notification icon away once its notification has been clicked or has timed out
+pub fn handle_message(hwnd: HWND, lparam: LPARAM) {
+    if matches!(lparam.0 as u32 & 0xFFFF, NIN_BALLOONTIMEOUT | NIN_BALLOONUSERCLICK) {
+        remove(hwnd);
+    }
+}
+
+/// Takes the notification icon away, with any notification still up
+pub fn remove(hwnd: HWND) {
+    if SHOWING.with(|showing| showing.replace(false)) {
+        unsafe {
+            let _ = Shell_NotifyIconW(NIM_DELETE, &icon_data(hwnd));
+        }
+    }
+}
+
+fn icon_data(hwnd: HWND) -> NOTIFYICONDATAW {
+    NOTIFYICONDATAW {
+        cbSize: std::mem::size_of::<NOTIFYICONDATAW>() as u32,
+        hWnd: hwnd,
+        uID: TOAST_ICON_ID,
+        ..Default::default()
+    }
+}
+
+/// Copies `text` into a fixed-size UTF-16 field, cut short to leave room for the terminator
+fn copy_truncated(field: &mut [u16], text: &str) {
+    let wide: Vec<u16> = text.encode_utf16().take(field.len() - 1).collect();
+    field[..wide.len()].copy_from_slice(&wide);
+    field[wide.len()] = 0;
+}
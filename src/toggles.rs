@@ -0,0 +1,150 @@
+// --- START OF FILE src/toggles.rs ---
+// TOGGLE(...) actions: on/off system states that are read back from Windows before flipping,
+// so the daemon's idea of the state can't drift from the real one the way a blind key can
+use std::sync::atomic::{AtomicIsize, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::Media::Audio::Endpoints::IAudioEndpointVolume;
+use windows::Win32::Media::Audio::{eCapture, eConsole, eRender, EDataFlow, IMMDeviceEnumerator, MMDeviceEnumerator};
+use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_APARTMENTTHREADED};
+use windows::Win32::UI::WindowsAndMessaging::PostMessageW;
+
+use crate::error::{A1314Error, Result};
+use crate::osd;
+use crate::variable_maps;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Toggle {
+    Mute,    // Default playback device
+    MicMute, // Default recording device
+}
+
+impl Toggle {
+    pub const ALL: [Toggle; 2] = [Toggle::Mute, Toggle::MicMute];
+
+    /// The toggle named in TOGGLE(name)
+    pub fn parse(name: &str) -> Option<Toggle> {
+        match variable_maps::normalize_name(name).as_str() {
+            "MUTE" | "SPEAKER_MUTE" => Some(Toggle::Mute),
+            "MIC_MUTE" | "MICROPHONE_MUTE" => Some(Toggle::MicMute),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Toggle::Mute => "MUTE",
+            Toggle::MicMute => "MIC_MUTE",
+        }
+    }
+
+    /// What the state is shown as, e.g. "Microphone muted"
+    pub fn describe(self, on: bool) -> &'static str {
+        match (self, on) {
+            (Toggle::Mute, true) => "Sound muted",
+            (Toggle::Mute, false) => "Sound on",
+            (Toggle::MicMute, true) => "Microphone muted",
+            (Toggle::MicMute, false) => "Microphone on",
+        }
+    }
+
+    fn flow(self) -> EDataFlow {
+        match self {
+            Toggle::Mute => eRender,
+            Toggle::MicMute => eCapture,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+// Last state read from or set in Windows, None until it could be read
+static STATES: Mutex<[Option<bool>; 2]> = Mutex::new([None; 2]);
+// Window posted NOTIFY_MESSAGE whenever a state changes, so the tray can show it
+static NOTIFY_WINDOW: AtomicIsize = AtomicIsize::new(0);
+static NOTIFY_MESSAGE: AtomicU32 = AtomicU32::new(0);
+
+/// Posts `message` to `hwnd` after every state change
+pub fn set_notify(hwnd: HWND, message: u32) {
+    NOTIFY_WINDOW.store(hwnd.0 as isize, Ordering::Relaxed);
+    NOTIFY_MESSAGE.store(message, Ordering::Relaxed);
+}
+
+/// Flips `toggle` from its current state in Windows and returns the new state
+pub fn toggle(toggle: Toggle) -> Result<bool> {
+    let endpoint = endpoint_volume(toggle)?;
+    let on = unsafe {
+        let muted = endpoint.GetMute()
+            .map_err(|e| A1314Error::win32("read the mute state", e))?
+            .as_bool();
+        endpoint.SetMute(!muted, std::ptr::null())
+            .map_err(|e| A1314Error::win32("set the mute state", e))?;
+        // Windows shows its volume flyout for the mute key but not for this, so show our own
+        match endpoint.GetMasterVolumeLevelScalar() {
+            Ok(volume) => osd::level(toggle.describe(!muted), (volume * 100.0).round() as u32),
+            Err(e) => log::debug!("Could not read the volume of {}: {}", toggle.name(), e),
+        }
+        !muted
+    };
+    log::info!("{}", toggle.describe(on));
+    store(toggle, Some(on));
+    Ok(on)
+}
+
+/// Reads every toggle's state from Windows, e.g. at startup or after the devices changed
+pub fn refresh() {
+    for toggle in Toggle::ALL {
+        let state = endpoint_volume(toggle)
+            .and_then(|endpoint| unsafe {
+                endpoint.GetMute().map_err(|e| A1314Error::win32("read the mute state", e))
+            })
+            .map(|muted| muted.as_bool());
+        if let Err(e) = &state {
+            log::debug!("Could not read {}: {}", toggle.name(), e);
+        }
+        store(toggle, state.ok());
+    }
+}
+
+/// The last known state of `toggle`
+pub fn state(toggle: Toggle) -> Option<bool> {
+    STATES.lock().unwrap_or_else(|p| p.into_inner())[toggle.index()]
+}
+
+/// The known states, e.g. "Sound on, Microphone muted", or None if none could be read
+pub fn summary() -> Option<String> {
+    let states: Vec<&str> = Toggle::ALL.iter()
+        .filter_map(|&toggle| state(toggle).map(|on| toggle.describe(on)))
+        .collect();
+    (!states.is_empty()).then(|| states.join(", "))
+}
+
+fn store(toggle: Toggle, state: Option<bool>) {
+    let changed = {
+        let mut states = STATES.lock().unwrap_or_else(|p| p.into_inner());
+        std::mem::replace(&mut states[toggle.index()], state) != state
+    };
+    let hwnd = NOTIFY_WINDOW.load(Ordering::Relaxed);
+    if changed && hwnd != 0 {
+        unsafe {
+            let _ = PostMessageW(HWND(hwnd as *mut _), NOTIFY_MESSAGE.load(Ordering::Relaxed), WPARAM(0), LPARAM(0));
+        }
+    }
+}
+
+/// Volume control of the default device `toggle` acts on
+fn endpoint_volume(toggle: Toggle) -> Result<IAudioEndpointVolume> {
+    unsafe {
+        // Already initialized on this thread is fine; the result only matters for the calls below
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        let enumerator: IMMDeviceEnumerator = CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)
+            .map_err(|e| A1314Error::win32("create the audio device enumerator", e))?;
+        let device = enumerator.GetDefaultAudioEndpoint(toggle.flow(), eConsole)
+            .map_err(|e| A1314Error::win32("find the default audio device", e))?;
+        device.Activate::<IAudioEndpointVolume>(CLSCTX_ALL, None)
+            .map_err(|e| A1314Error::win32("open the audio device's volume control", e))
+    }
+}
@@ -0,0 +1,362 @@
+// --- START OF FILE src/updater.rs ---
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+
+use windows::core::PCWSTR;
+use windows::Win32::Foundation::{HANDLE, HWND};
+use windows::Win32::Networking::WinHttp::{
+    WinHttpCloseHandle, WinHttpConnect, WinHttpOpen, WinHttpOpenRequest, WinHttpQueryDataAvailable,
+    WinHttpQueryHeaders, WinHttpReadData, WinHttpReceiveResponse, WinHttpSendRequest,
+    INTERNET_DEFAULT_HTTPS_PORT, WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY, WINHTTP_FLAG_SECURE,
+    WINHTTP_QUERY_FLAG_NUMBER, WINHTTP_QUERY_STATUS_CODE,
+};
+use windows::Win32::Security::Cryptography::{BCryptHash, CertGetNameStringW, BCRYPT_SHA256_ALG_HANDLE,
+                                             CERT_NAME_SIMPLE_DISPLAY_TYPE};
+use windows::Win32::Security::WinTrust::{
+    WTHelperGetProvSignerFromChain, WTHelperProvDataFromStateData, WinVerifyTrust, WINTRUST_ACTION_GENERIC_VERIFY_V2,
+    WINTRUST_DATA, WINTRUST_DATA_0, WINTRUST_FILE_INFO, WTD_CHOICE_FILE, WTD_REVOKE_WHOLECHAIN,
+    WTD_STATEACTION_CLOSE, WTD_STATEACTION_VERIFY, WTD_UI_NONE,
+};
+
+// Releases are published on GitHub with the exe (Authenticode-signed) and a matching .sha256 file
+const RELEASES_URL: &str = "https://api.github.com/repos/ArtisticMusician/Apple1314KybrdWinMap/releases/latest";
+const ASSET_NAME: &str = "a1314_daemon.exe";
+
+/// A newer release that has been downloaded and verified
+pub struct StagedUpdate {
+    pub version: String,
+}
+
+/// Checks GitHub for a newer release and, if found, downloads and verifies it next to the exe.
+/// Returns Ok(None) when already up to date. The swap happens on next start (`apply_staged_update`).
+pub fn check_and_stage() -> Result<Option<StagedUpdate>, String> {
+    let release = String::from_utf8_lossy(&http_get(RELEASES_URL)?).to_string();
+
+    let tag = json_string(&release, "tag_name").ok_or("Release has no tag_name")?;
+    let current = env!("CARGO_PKG_VERSION");
+    if !is_newer(&tag, current) {
+        log::info!("No update available (latest {}, running {})", tag, current);
+        return Ok(None);
+    }
+
+    let urls = json_strings(&release, "browser_download_url");
+    let exe_url = urls.iter().find(|u| u.ends_with(&format!("/{}", ASSET_NAME)))
+        .ok_or_else(|| format!("Release {} has no {} asset", tag, ASSET_NAME))?;
+    let hash_url = urls.iter().find(|u| u.ends_with(&format!("/{}.sha256", ASSET_NAME)))
+        .ok_or_else(|| format!("Release {} has no {}.sha256 asset, refusing to install unverified", tag, ASSET_NAME))?;
+
+    log::info!("Downloading {} from {}", tag, exe_url);
+    let exe = http_get(exe_url)?;
+    let expected = String::from_utf8_lossy(&http_get(hash_url)?)
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+
+    let actual = sha256_hex(&exe)?;
+    if actual != expected {
+        return Err(format!("Hash mismatch for {} (expected {}, got {})", tag, expected, actual));
+    }
+
+    // The .sha256 comes from the same release as the exe, so it only catches a corrupt download.
+    // Trust comes from the signature: the update must be signed by whoever signed this exe.
+    let path = staged_path()?;
+    let download = path.with_extension("download");
+    std::fs::write(&download, &exe).map_err(|e| format!("Failed to write {}: {}", download.display(), e))?;
+    if let Err(e) = verify_same_signer(&download) {
+        let _ = std::fs::remove_file(&download);
+        return Err(format!("Refusing to install {}: {}", tag, e));
+    }
+    std::fs::rename(&download, &path).map_err(|e| format!("Failed to stage {}: {}", path.display(), e))?;
+    log::info!("Update {} verified and staged at {}", tag, path.display());
+    Ok(Some(StagedUpdate { version: tag }))
+}
+
+/// Swaps in a staged update before anything else starts. Returns true if the new exe was
+/// launched and this process should exit.
+pub fn apply_staged_update() -> bool {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return false,
+    };
+    let old = exe.with_extension("exe.old");
+
+    // Left over from the previous swap; may still be locked if that process is exiting
+    let _ = std::fs::remove_file(&old);
+
+    let staged = match staged_path() {
+        Ok(path) if path.exists() => path,
+        _ => return false,
+    };
+
+    // Checked again here: the file sat next to the exe since it was staged
+    if let Err(e) = verify_same_signer(&staged) {
+        log::error!("Discarding staged update {}: {}", staged.display(), e);
+        let _ = std::fs::remove_file(&staged);
+        return false;
+    }
+
+    // A running exe can be renamed but not overwritten
+    if let Err(e) = std::fs::rename(&exe, &old) {
+        log::error!("Failed to move current exe aside for update: {}", e);
+        return false;
+    }
+    if let Err(e) = std::fs::rename(&staged, &exe) {
+        log::error!("Failed to install staged update: {}", e);
+        let _ = std::fs::rename(&old, &exe);
+        return false;
+    }
+
+    log::info!("Installed staged update, restarting");
+    match std::process::Command::new(&exe).args(std::env::args().skip(1)).spawn() {
+        Ok(_) => true,
+        Err(e) => {
+            log::error!("Failed to start updated exe: {}", e);
+            false
+        }
+    }
+}
+
+fn staged_path() -> Result<PathBuf, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path: {}", e))?;
+    Ok(exe.with_extension("exe.update"))
+}
+
+/// The leaf certificate of an Authenticode signature
+#[derive(Debug)]
+struct Signer {
+    // For messages only; any certificate can carry the same name
+    name: String,
+    public_key: Vec<u8>,
+}
+
+/// Checks that `update` carries a valid Authenticode signature made with the same key as the running exe
+fn verify_same_signer(update: &Path) -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to get executable path: {}", e))?;
+    let ours = authenticode_signer(&exe)
+        .map_err(|e| format!("the running exe is not signed ({}), so there is no publisher to match", e))?;
+    let theirs = authenticode_signer(update).map_err(|e| format!("the download is not validly signed ({})", e))?;
+    if theirs.public_key != ours.public_key {
+        return Err(format!("the download is signed by \"{}\" with a different key than \"{}\"", theirs.name, ours.name));
+    }
+    Ok(())
+}
+
+/// Verifies the Authenticode signature on `path` (including revocation) and returns its signer
+fn authenticode_signer(path: &Path) -> Result<Signer, String> {
+    let wide_path = widestring(&path.to_string_lossy());
+    let mut file = WINTRUST_FILE_INFO {
+        cbStruct: std::mem::size_of::<WINTRUST_FILE_INFO>() as u32,
+        pcwszFilePath: PCWSTR(wide_path.as_ptr()),
+        ..Default::default()
+    };
+    let mut data = WINTRUST_DATA {
+        cbStruct: std::mem::size_of::<WINTRUST_DATA>() as u32,
+        dwUIChoice: WTD_UI_NONE,
+        fdwRevocationChecks: WTD_REVOKE_WHOLECHAIN,
+        dwUnionChoice: WTD_CHOICE_FILE,
+        Anonymous: WINTRUST_DATA_0 { pFile: &mut file },
+        dwStateAction: WTD_STATEACTION_VERIFY,
+        ..Default::default()
+    };
+    let mut action = WINTRUST_ACTION_GENERIC_VERIFY_V2;
+    // INVALID_HANDLE_VALUE: no UI, no interactive user required
+    let no_ui = HWND(-1isize as *mut c_void);
+
+    unsafe {
+        let status = WinVerifyTrust(no_ui, &mut action, &mut data as *mut _ as *mut c_void);
+        let signer = if status != 0 {
+            Err(format!("WinVerifyTrust returned 0x{:08X}", status as u32))
+        } else {
+            signer_certificate(data.hWVTStateData)
+        };
+
+        // Release the state WinVerifyTrust kept for the signer lookup
+        data.dwStateAction = WTD_STATEACTION_CLOSE;
+        WinVerifyTrust(no_ui, &mut action, &mut data as *mut _ as *mut c_void);
+        signer
+    }
+}
+
+/// Name and public key of the leaf certificate of the first signer in verified trust state
+unsafe fn signer_certificate(state: HANDLE) -> Result<Signer, String> {
+    let provider = WTHelperProvDataFromStateData(state);
+    if provider.is_null() {
+        return Err("no provider data".to_string());
+    }
+    let signer = WTHelperGetProvSignerFromChain(provider, 0, false, 0);
+    if signer.is_null() || (*signer).csCertChain == 0 || (*signer).pasCertChain.is_null() {
+        return Err("no signer certificate".to_string());
+    }
+    let cert = (*(*signer).pasCertChain).pCert;
+    if cert.is_null() || (*cert).pCertInfo.is_null() {
+        return Err("no signer certificate".to_string());
+    }
+    let key = &(*(*cert).pCertInfo).SubjectPublicKeyInfo.PublicKey;
+    if key.pbData.is_null() || key.cbData == 0 {
+        return Err("signer certificate has no public key".to_string());
+    }
+    let public_key = std::slice::from_raw_parts(key.pbData, key.cbData as usize).to_vec();
+
+    let mut name = [0u16; 256];
+    let len = CertGetNameStringW(cert, CERT_NAME_SIMPLE_DISPLAY_TYPE, 0, None, Some(&mut name));
+    // len counts the terminating null; 1 means an empty name
+    if len <= 1 {
+        return Err("signer certificate has no name".to_string());
+    }
+    Ok(Signer { name: String::from_utf16_lossy(&name[..len as usize - 1]), public_key })
+}
+
+/// True if `tag` (e.g. "v1.2.0") is a higher version than `current` (e.g. "1.0.0")
+pub fn is_newer(tag: &str, current: &str) -> bool {
+    fn parse(v: &str) -> Vec<u32> {
+        v.trim().trim_start_matches(['v', 'V'])
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    }
+    parse(tag) > parse(current)
+}
+
+/// Extracts the first `"key": "value"` string from a JSON document
+fn json_string(json: &str, key: &str) -> Option<String> {
+    json_strings(json, key).into_iter().next()
+}
+
+/// Extracts every `"key": "value"` string from a JSON document. Good enough for the
+/// GitHub releases API, whose values we need never contain escaped quotes.
+fn json_strings(json: &str, key: &str) -> Vec<String> {
+    let needle = format!("\"{}\"", key);
+    let mut values = Vec::new();
+    let mut rest = json;
+    while let Some(pos) = rest.find(&needle) {
+        rest = &rest[pos + needle.len()..];
+        let value = rest.trim_start().strip_prefix(':').map(str::trim_start);
+        if let Some(value) = value.and_then(|v| v.strip_prefix('"')) {
+            if let Some(end) = value.find('"') {
+                values.push(value[..end].to_string());
+            }
+        }
+    }
+    values
+}
+
+pub fn sha256_hex(data: &[u8]) -> Result<String, String> {
+    let mut digest = [0u8; 32];
+    let status = unsafe { BCryptHash(BCRYPT_SHA256_ALG_HANDLE, None, data, &mut digest) };
+    if status.is_err() {
+        return Err(format!("SHA-256 failed: 0x{:08X}", status.0));
+    }
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Closes a WinHTTP handle when dropped
+struct HttpHandle(*mut c_void);
+
+impl Drop for HttpHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            unsafe {
+                let _ = WinHttpCloseHandle(self.0);
+            }
+        }
+    }
+}
+
+/// Downloads an https:// URL (following redirects) into memory
+fn http_get(url: &str) -> Result<Vec<u8>, String> {
+    let rest = url.strip_prefix("https://").ok_or_else(|| format!("Only https URLs are supported: {}", url))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+
+    let agent = widestring(&format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")));
+    let host = widestring(host);
+    let path = widestring(path);
+    let verb = widestring("GET");
+    // GitHub's API rejects requests without a User-Agent, which WinHttpOpen already sets
+    let headers: Vec<u16> = "Accept: application/vnd.github+json\r\n".encode_utf16().collect();
+
+    unsafe {
+        let session = HttpHandle(WinHttpOpen(PCWSTR(agent.as_ptr()), WINHTTP_ACCESS_TYPE_AUTOMATIC_PROXY,
+                                             PCWSTR::null(), PCWSTR::null(), 0));
+        if session.0.is_null() {
+            return Err(format!("WinHttpOpen failed: {}", windows::core::Error::from_win32()));
+        }
+
+        let connection = HttpHandle(WinHttpConnect(session.0, PCWSTR(host.as_ptr()), INTERNET_DEFAULT_HTTPS_PORT, 0));
+        if connection.0.is_null() {
+            return Err(format!("Failed to connect: {}", windows::core::Error::from_win32()));
+        }
+
+        let request = HttpHandle(WinHttpOpenRequest(connection.0, PCWSTR(verb.as_ptr()), PCWSTR(path.as_ptr()),
+                                                    PCWSTR::null(), PCWSTR::null(), std::ptr::null(), WINHTTP_FLAG_SECURE));
+        if request.0.is_null() {
+            return Err(format!("Failed to open request: {}", windows::core::Error::from_win32()));
+        }
+
+        WinHttpSendRequest(request.0, Some(&headers), None, 0, 0, 0)
+            .map_err(|e| format!("Request to {} failed: {}", url, e))?;
+        WinHttpReceiveResponse(request.0, std::ptr::null_mut())
+            .map_err(|e| format!("No response from {}: {}", url, e))?;
+
+        let mut status: u32 = 0;
+        let mut status_len = std::mem::size_of::<u32>() as u32;
+        WinHttpQueryHeaders(request.0, WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER, PCWSTR::null(),
+                            Some(&mut status as *mut u32 as *mut c_void), &mut status_len, std::ptr::null_mut())
+            .map_err(|e| format!("Failed to read status: {}", e))?;
+        if status != 200 {
+            return Err(format!("{} returned HTTP {}", url, status));
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let mut available: u32 = 0;
+            WinHttpQueryDataAvailable(request.0, &mut available)
+                .map_err(|e| format!("Download failed: {}", e))?;
+            if available == 0 {
+                break;
+            }
+
+            let start = body.len();
+            body.resize(start + available as usize, 0);
+            let mut read: u32 = 0;
+            WinHttpReadData(request.0, body[start..].as_mut_ptr() as *mut c_void, available, &mut read)
+                .map_err(|e| format!("Download failed: {}", e))?;
+            body.truncate(start + read as usize);
+        }
+        Ok(body)
+    }
+}
+
+/// Short result for the user, used by both `--update` and the tray item
+pub fn describe(result: &Result<Option<StagedUpdate>, String>) -> String {
+    match result {
+        Ok(Some(update)) => format!("Update {} downloaded and verified.\nIt will be installed the next time the daemon starts.",
+                                    update.version),
+        Ok(None) => format!("You're running the latest version ({}).", env!("CARGO_PKG_VERSION")),
+        Err(e) => format!("Update check failed: {}", e),
+    }
+}
+
+fn widestring(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_tags_compare_numerically() {
+        assert!(is_newer("v1.1.0", "1.0.0"));
+        assert!(is_newer("v1.10.0", "1.9.3"));
+        assert!(is_newer("2.0", "1.9.9"));
+        assert!(!is_newer("v1.0.0", "1.0.0"));
+        assert!(!is_newer("v0.9.0", "1.0.0"));
+
+        // Pre-release suffixes are ignored, so a release candidate of the running version isn't newer
+        assert!(!is_newer("v1.0.0-rc1", "1.0.0"));
+    }
+}
@@ -2,125 +2,190 @@
 use std::collections::HashMap;
 use crate::key_mapper::HidKey;
 use crate::action_executor::Action;
+use crate::magnifier::Zoom;
 
-// --- Hardcoded mappings from friendly string names to HID keys ---
+// HID_USAGE_NAMES: (name, usage page, usage, canonical), generated by build.rs from hid_usages.txt
+include!(concat!(env!("OUT_DIR"), "/hid_usages.rs"));
+
+// --- Friendly key names to HID keys: the generated usage names plus the Apple vendor keys ---
 lazy_static::lazy_static! {
     pub static ref STRING_TO_HID_KEY: HashMap<&'static str, HidKey> = {
-        let mut m = HashMap::new();
-        // Normal keys
-        m.insert("KEY_A", HidKey { usage_page: 0x07, usage: 0x0004 });
-        m.insert("KEY_B", HidKey { usage_page: 0x07, usage: 0x0005 });
-        m.insert("KEY_C", HidKey { usage_page: 0x07, usage: 0x0006 });
-        m.insert("KEY_D", HidKey { usage_page: 0x07, usage: 0x0007 });
-        m.insert("KEY_E", HidKey { usage_page: 0x07, usage: 0x0008 });
-        m.insert("KEY_F", HidKey { usage_page: 0x07, usage: 0x0009 });
-        m.insert("KEY_G", HidKey { usage_page: 0x07, usage: 0x000A });
-        m.insert("KEY_H", HidKey { usage_page: 0x07, usage: 0x000B });
-        m.insert("KEY_I", HidKey { usage_page: 0x07, usage: 0x000C });
-        m.insert("KEY_J", HidKey { usage_page: 0x07, usage: 0x000D });
-        m.insert("KEY_K", HidKey { usage_page: 0x07, usage: 0x000E });
-        m.insert("KEY_L", HidKey { usage_page: 0x07, usage: 0x000F });
-        m.insert("KEY_M", HidKey { usage_page: 0x07, usage: 0x0010 });
-        m.insert("KEY_N", HidKey { usage_page: 0x07, usage: 0x0011 });
-        m.insert("KEY_O", HidKey { usage_page: 0x07, usage: 0x0012 });
-        m.insert("KEY_P", HidKey { usage_page: 0x07, usage: 0x0013 });
-        m.insert("KEY_Q", HidKey { usage_page: 0x07, usage: 0x0014 });
-        m.insert("KEY_R", HidKey { usage_page: 0x07, usage: 0x0015 });
-        m.insert("KEY_S", HidKey { usage_page: 0x07, usage: 0x0016 });
-        m.insert("KEY_T", HidKey { usage_page: 0x07, usage: 0x0017 });
-        m.insert("KEY_U", HidKey { usage_page: 0x07, usage: 0x0018 });
-        m.insert("KEY_V", HidKey { usage_page: 0x07, usage: 0x0019 });
-        m.insert("KEY_W", HidKey { usage_page: 0x07, usage: 0x001A });
-        m.insert("KEY_X", HidKey { usage_page: 0x07, usage: 0x001B });
-        m.insert("KEY_Y", HidKey { usage_page: 0x07, usage: 0x001C });
-        m.insert("KEY_Z", HidKey { usage_page: 0x07, usage: 0x001D });
-
-        // Number row
-        m.insert("KEY_1", HidKey { usage_page: 0x07, usage: 0x001E });
-        m.insert("KEY_2", HidKey { usage_page: 0x07, usage: 0x001F });
-        m.insert("KEY_3", HidKey { usage_page: 0x07, usage: 0x0020 });
-        m.insert("KEY_4", HidKey { usage_page: 0x07, usage: 0x0021 });
-        m.insert("KEY_5", HidKey { usage_page: 0x07, usage: 0x0022 });
-        m.insert("KEY_6", HidKey { usage_page: 0x07, usage: 0x0023 });
-        m.insert("KEY_7", HidKey { usage_page: 0x07, usage: 0x0024 });
-        m.insert("KEY_8", HidKey { usage_page: 0x07, usage: 0x0025 });
-        m.insert("KEY_9", HidKey { usage_page: 0x07, usage: 0x0026 });
-        m.insert("KEY_0", HidKey { usage_page: 0x07, usage: 0x0027 });
-
-        // Basic controls
-        m.insert("ENTER", HidKey { usage_page: 0x07, usage: 0x0028 });
-        m.insert("ESCAPE", HidKey { usage_page: 0x07, usage: 0x0029 });
-        m.insert("BACKSPACE", HidKey { usage_page: 0x07, usage: 0x002A });
-        m.insert("TAB", HidKey { usage_page: 0x07, usage: 0x002B });
-        m.insert("SPACE", HidKey { usage_page: 0x07, usage: 0x002C });
-
-        // Symbols and punctuation (US keyboard layout)
-        m.insert("MINUS", HidKey { usage_page: 0x07, usage: 0x002D });           // - and _
-        m.insert("EQUALS", HidKey { usage_page: 0x07, usage: 0x002E });          // = and +
-        m.insert("LEFT_BRACKET", HidKey { usage_page: 0x07, usage: 0x002F });    // [ and {
-        m.insert("RIGHT_BRACKET", HidKey { usage_page: 0x07, usage: 0x0030 });   // ] and }
-        m.insert("BACKSLASH", HidKey { usage_page: 0x07, usage: 0x0031 });       // \ and |
-        m.insert("SEMICOLON", HidKey { usage_page: 0x07, usage: 0x0033 });       // ; and :
-        m.insert("APOSTROPHE", HidKey { usage_page: 0x07, usage: 0x0034 });      // ' and "
-        m.insert("GRAVE", HidKey { usage_page: 0x07, usage: 0x0035 });           // ` and ~
-        m.insert("COMMA", HidKey { usage_page: 0x07, usage: 0x0036 });           // , and <
-        m.insert("PERIOD", HidKey { usage_page: 0x07, usage: 0x0037 });          // . and >
-        m.insert("SLASH", HidKey { usage_page: 0x07, usage: 0x0038 });           // / and ?
-
-        // Function keys
-        m.insert("F1", HidKey { usage_page: 0x07, usage: 0x003A });
-        m.insert("F2", HidKey { usage_page: 0x07, usage: 0x003B });
-        m.insert("F3", HidKey { usage_page: 0x07, usage: 0x003C });
-        m.insert("F4", HidKey { usage_page: 0x07, usage: 0x003D });
-        m.insert("F5", HidKey { usage_page: 0x07, usage: 0x003E });
-        m.insert("F6", HidKey { usage_page: 0x07, usage: 0x003F });
-        m.insert("F7", HidKey { usage_page: 0x07, usage: 0x0040 });
-        m.insert("F8", HidKey { usage_page: 0x07, usage: 0x0041 });
-        m.insert("F9", HidKey { usage_page: 0x07, usage: 0x0042 });
-        m.insert("F10", HidKey { usage_page: 0x07, usage: 0x0043 });
-        m.insert("F11", HidKey { usage_page: 0x07, usage: 0x0044 });
-        m.insert("F12", HidKey { usage_page: 0x07, usage: 0x0045 });
-
-        // Arrows
-        m.insert("RIGHT_ARROW", HidKey { usage_page: 0x07, usage: 0x004F });
-        m.insert("LEFT_ARROW", HidKey { usage_page: 0x07, usage: 0x0050 });
-        m.insert("DOWN_ARROW", HidKey { usage_page: 0x07, usage: 0x0051 });
-        m.insert("UP_ARROW", HidKey { usage_page: 0x07, usage: 0x0052 });
-
-        // Navigation keys
-        m.insert("DELETE", HidKey { usage_page: 0x07, usage: 0x004C });
-        m.insert("HOME", HidKey { usage_page: 0x07, usage: 0x004A });
-        m.insert("END", HidKey { usage_page: 0x07, usage: 0x004D });
-        m.insert("PAGE_UP", HidKey { usage_page: 0x07, usage: 0x004B });
-        m.insert("PAGE_DOWN", HidKey { usage_page: 0x07, usage: 0x004E });
-
-        // Modifiers (These are used internally by the Raw Input Handler, not typically mapped by user directly)
-        m.insert("LEFT_CTRL", HidKey { usage_page: 0x07, usage: 0x00E0 });
-        m.insert("LEFT_SHIFT", HidKey { usage_page: 0x07, usage: 0x00E1 });
-        m.insert("LEFT_ALT", HidKey { usage_page: 0x07, usage: 0x00E2 });
-        m.insert("LEFT_GUI", HidKey { usage_page: 0x07, usage: 0x00E3 });
-        m.insert("RIGHT_CTRL", HidKey { usage_page: 0x07, usage: 0x00E4 });
-        m.insert("RIGHT_SHIFT", HidKey { usage_page: 0x07, usage: 0x00E5 });
-        m.insert("RIGHT_ALT", HidKey { usage_page: 0x07, usage: 0x00E6 });
-        m.insert("RIGHT_GUI", HidKey { usage_page: 0x07, usage: 0x00E7 });
-
-        // Consumer/media keys
-        m.insert("BRIGHTNESS_DOWN", HidKey { usage_page: 0x0C, usage: 0x006F });
-        m.insert("BRIGHTNESS_UP", HidKey { usage_page: 0x0C, usage: 0x0070 });
-        m.insert("MEDIA_NEXT", HidKey { usage_page: 0x0C, usage: 0x00B3 });
-        m.insert("MEDIA_PREV", HidKey { usage_page: 0x0C, usage: 0x00B4 });
-        m.insert("EJECT", HidKey { usage_page: 0x0C, usage: 0x00B8 }); // EJECT key
-        m.insert("MEDIA_PLAY_PAUSE", HidKey { usage_page: 0x0C, usage: 0x00CD });
-        m.insert("MUTE", HidKey { usage_page: 0x0C, usage: 0x00E2 });
-        m.insert("VOLUME_UP", HidKey { usage_page: 0x0C, usage: 0x00E9 });
-        m.insert("VOLUME_DOWN", HidKey { usage_page: 0x0C, usage: 0x00EA });
+        let mut m: HashMap<&'static str, HidKey> = HID_USAGE_NAMES.iter()
+            .map(|&(name, usage_page, usage, _)| (name, HidKey { usage_page, usage }))
+            .collect();
 
         // Fn state (Apple vendor page)
         m.insert("FN_STATE", HidKey { usage_page: 0xFF00, usage: 0x0003 });
+
+        // Mouse wheel notches
+        m.insert("WHEEL_UP", HidKey { usage_page: WHEEL_PAGE, usage: 0x0001 });
+        m.insert("WHEEL_DOWN", HidKey { usage_page: WHEEL_PAGE, usage: 0x0002 });
+        m.insert("WHEEL_LEFT", HidKey { usage_page: WHEEL_PAGE, usage: 0x0003 });
+        m.insert("WHEEL_RIGHT", HidKey { usage_page: WHEEL_PAGE, usage: 0x0004 });
+
+        // Trackpad gestures
+        m.insert("GESTURE_SWIPE_LEFT", HidKey { usage_page: GESTURE_PAGE, usage: 0x0001 });
+        m.insert("GESTURE_SWIPE_RIGHT", HidKey { usage_page: GESTURE_PAGE, usage: 0x0002 });
         m
     };
 }
 
+// --- Mouse buttons (HID Button page) and wheel notches, seen by the low-level mouse hook ---
+pub const BUTTON_PAGE: u16 = 0x09;
+// A wheel notch has no HID usage of its own, so the daemon keeps its directions on a vendor page
+pub const WHEEL_PAGE: u16 = 0xFFF0;
+// Likewise for the gestures the daemon spots in trackpad reports (two-finger swipes)
+pub const GESTURE_PAGE: u16 = 0xFFF1;
+
+/// The mouse key for a low-level mouse hook message and its `mouseData`, and whether it is a
+/// press; a wheel notch is a press with no release
+pub fn mouse_key(message: u32, mouse_data: u32) -> Option<(HidKey, bool)> {
+    let high_word = (mouse_data >> 16) as u16;
+    let (usage_page, usage, pressed) = match message {
+        0x0201 | 0x0202 => (BUTTON_PAGE, 1, message == 0x0201), // WM_LBUTTONDOWN / UP
+        0x0204 | 0x0205 => (BUTTON_PAGE, 2, message == 0x0204), // WM_RBUTTONDOWN / UP
+        0x0207 | 0x0208 => (BUTTON_PAGE, 3, message == 0x0207), // WM_MBUTTONDOWN / UP
+        // WM_XBUTTONDOWN / UP: XBUTTON1 (back) is button 4, XBUTTON2 (forward) is button 5
+        0x020B | 0x020C => match high_word {
+            1 => (BUTTON_PAGE, 4, message == 0x020B),
+            2 => (BUTTON_PAGE, 5, message == 0x020B),
+            _ => return None,
+        },
+        // WM_MOUSEWHEEL / WM_MOUSEHWHEEL: a positive delta turns away from the user, or right
+        0x020A => (WHEEL_PAGE, if (high_word as i16) > 0 { 1 } else { 2 }, true),
+        0x020E => (WHEEL_PAGE, if (high_word as i16) > 0 { 4 } else { 3 }, true),
+        _ => return None,
+    };
+    Some((HidKey { usage_page, usage }, pressed))
+}
+
+/// Whether `key` is a mouse button or wheel notch
+pub fn is_mouse_key(key: HidKey) -> bool {
+    key.usage_page == BUTTON_PAGE || key.usage_page == WHEEL_PAGE
+}
+
+/// Whether `key` is a trackpad gesture
+pub fn is_gesture_key(key: HidKey) -> bool {
+    key.usage_page == GESTURE_PAGE
+}
+
+// --- Keys the low-level keyboard hook handles: virtual-key code to keyboard usage (page 0x07) ---
+pub fn hook_usage(vk: u32) -> Option<u16> {
+    let usage = match vk {
+        0x41..=0x5A => vk as u16 - 0x41 + 4, // A-Z (0x41='A' -> Usage 0x04)
+        0x30 => 0x27, // '0' -> Usage 0x27
+        0x31..=0x39 => vk as u16 - 0x31 + 0x1E, // 1-9 (0x31='1' -> Usage 0x1E)
+        0x0D => 0x28, // ENTER -> Usage 0x28
+        0x1B => 0x29, // ESCAPE -> Usage 0x29
+        0x08 => 0x2A, // BACKSPACE -> Usage 0x2A
+        0x09 => 0x2B, // TAB -> Usage 0x2B
+        0x14 => 0x39, // CAPS LOCK -> Usage 0x39
+        0x20 => 0x2C, // SPACE -> Usage 0x2C
+        0x25 => 0x50, // LEFT -> Usage 0x50
+        0x26 => 0x52, // UP -> Usage 0x52
+        0x27 => 0x4F, // RIGHT -> Usage 0x4F
+        0x28 => 0x51, // DOWN -> Usage 0x51
+        0x2E => 0x4C, // DELETE -> Usage 0x4C (Forward Delete)
+        0x70..=0x7B => vk as u16 - 0x70 + 0x3A, // F1-F12 (0x70=F1 -> Usage 0x3A)
+        _ => return None,
+    };
+    Some(usage)
+}
+
+/// Whether the keyboard hook ever sees `key`; others (consumer keys, Fn, Eject) only come as HID reports
+pub fn hook_sees(key: HidKey) -> bool {
+    key.usage_page == 0x07 && (0..=0xFF).any(|vk| hook_usage(vk) == Some(key.usage))
+}
+
+// --- Alternative spellings, accepted in any case wherever a key or modifier name is ---
+const NAME_ALIASES: &[(&str, &str)] = &[
+    ("ESC", "ESCAPE"),
+    ("RETURN", "ENTER"),
+    ("DEL", "DELETE"),
+    ("PGUP", "PAGE_UP"),
+    ("PAGEUP", "PAGE_UP"),
+    ("PGDN", "PAGE_DOWN"),
+    ("PAGEDOWN", "PAGE_DOWN"),
+    ("LEFT", "LEFT_ARROW"),
+    ("RIGHT", "RIGHT_ARROW"),
+    ("UP", "UP_ARROW"),
+    ("DOWN", "DOWN_ARROW"),
+    ("CMD", "WIN"),
+    ("COMMAND", "WIN"),
+    ("GUI", "WIN"),
+    ("SUPER", "WIN"),
+    ("OPT", "ALT"),
+    ("OPTION", "ALT"),
+    ("CONTROL", "CTRL"),
+];
+
+/// Upper-cases a single key or modifier name and resolves NAME_ALIASES, e.g. `esc` -> `ESCAPE`
+pub fn normalize_name(name: &str) -> String {
+    let upper = name.trim().to_ascii_uppercase();
+    match NAME_ALIASES.iter().find(|(alias, _)| *alias == upper) {
+        Some((_, canonical)) => canonical.to_string(),
+        None => upper,
+    }
+}
+
+/// Normalizes every part of a `MOD+KEY` combo, e.g. `cmd+opt+esc` -> `WIN+ALT+ESCAPE`.
+/// Combos with an empty part (a literal `+` key) are only upper-cased.
+pub fn normalize_combo(combo: &str) -> String {
+    if combo.split('+').any(|part| part.trim().is_empty()) {
+        return combo.trim().to_ascii_uppercase();
+    }
+    combo.split('+').map(normalize_name).collect::<Vec<_>>().join("+")
+}
+
+/// The closest names to a misspelled one by edit distance (at most three, ties sorted),
+/// or nothing if none is close enough to be a likely typo
+pub fn closest_names<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let name = normalize_name(name);
+    let max_distance = (name.len() / 3).max(1);
+
+    let mut best: Vec<&str> = Vec::new();
+    let mut best_distance = usize::MAX;
+    for candidate in candidates {
+        let distance = edit_distance(&name, candidate);
+        if distance > max_distance || distance > best_distance {
+            continue;
+        }
+        if distance < best_distance {
+            best_distance = distance;
+            best.clear();
+        }
+        if !best.contains(&candidate) {
+            best.push(candidate);
+        }
+    }
+    best.sort_unstable();
+    best.truncate(3);
+    best
+}
+
+/// closest_names as " (did you mean X or Y?)" for an error message, or an empty string
+pub fn did_you_mean<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> String {
+    match closest_names(name, candidates).as_slice() {
+        [] => String::new(),
+        names => format!(" (did you mean {}?)", names.join(" or ")),
+    }
+}
+
+/// Levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
 // --- Hardcoded mappings from friendly string names to Actions for RHS ---
 lazy_static::lazy_static! {
     pub static ref STRING_TO_ACTION: HashMap<&'static str, Action> = {
@@ -143,6 +208,26 @@ lazy_static::lazy_static! {
         m.insert("MEDIA_PLAY_PAUSE", Action::KeyCombo("MEDIA_PLAY_PAUSE".to_string()));
         m.insert("VOLUME_UP", Action::KeyCombo("VOLUME_UP".to_string()));
         m.insert("VOLUME_DOWN", Action::KeyCombo("VOLUME_DOWN".to_string()));
+        m.insert("BACKLIGHT_UP", Action::Backlight(10));
+        m.insert("BACKLIGHT_DOWN", Action::Backlight(-10));
+        m.insert("CAPS_LED_ON", Action::CapsLed(true));
+        m.insert("CAPS_LED_OFF", Action::CapsLed(false));
+        m.insert("NONE", Action::Disable);
+        m.insert("DISABLE", Action::Disable);
+        m.insert("LAYOUT_NEXT", Action::LayoutNext);
+        m.insert("IME_TOGGLE", Action::ImeToggle);
+        m.insert("LAUNCHER", Action::Launcher);
+        m.insert("APP_WINDOW_NEXT", Action::AppWindowNext);
+        m.insert("APP_WINDOW_PREV", Action::AppWindowPrev);
+        m.insert("CLOSE_WINDOW", Action::CloseWindow);
+        m.insert("QUIT_APP", Action::QuitApp { confirm: false });
+        m.insert("FORCE_KILL_APP", Action::ForceKillApp { confirm: false });
+        m.insert("MAGNIFIER_ZOOM_IN", Action::Magnifier(Zoom::In));
+        m.insert("MAGNIFIER_ZOOM_OUT", Action::Magnifier(Zoom::Out));
+        m.insert("MAGNIFIER_TOGGLE", Action::Magnifier(Zoom::Toggle));
+        m.insert("NATURAL_SCROLL_TOGGLE", Action::NaturalScrollToggle);
+        m.insert("SHUTDOWN_MENU", Action::Run(
+            "powershell.exe -NoProfile -WindowStyle Hidden -Command (New-Object -ComObject Shell.Application).ShutdownWindows()".to_string()));
         
         // Add all single character/number/symbol keys if they can appear on RHS
         // This is important if you want to map `FN+KEY_1 = A` for instance.
@@ -213,6 +298,7 @@ lazy_static::lazy_static! {
         m.insert("SEMICOLON", Action::KeyCombo("SEMICOLON".to_string()));
         m.insert("APOSTROPHE", Action::KeyCombo("APOSTROPHE".to_string()));
         m.insert("GRAVE", Action::KeyCombo("GRAVE".to_string()));
+        m.insert("NON_US_BACKSLASH", Action::KeyCombo("NON_US_BACKSLASH".to_string()));
         m.insert("COMMA", Action::KeyCombo("COMMA".to_string()));
         m.insert("PERIOD", Action::KeyCombo("PERIOD".to_string()));
         m.insert("SLASH", Action::KeyCombo("SLASH".to_string()));
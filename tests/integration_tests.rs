@@ -200,59 +200,6 @@ KEY_F = F # This is a comment
         assert_eq!(get_priority(&state5), 5); // EJECT+FN
     }
 
-    #[test]
-    fn test_action_parsing() {
-        // Test parsing different action formats
-        let test_cases = vec![
-            ("WIN+TAB", "KeyCombo"),
-            ("RUN(\"calc.exe\")", "Run"),
-            ("APPCOMMAND(8)", "AppCommand"),
-            ("F1", "KeyCombo"),
-            ("CTRL+SHIFT+ESC", "KeyCombo"),
-        ];
-        
-        for (input, expected_type) in test_cases {
-            if input.starts_with("RUN(\"") && input.ends_with("\")") {
-                assert_eq!(expected_type, "Run");
-            } else if input.starts_with("APPCOMMAND(") && input.ends_with(")") {
-                assert_eq!(expected_type, "AppCommand");
-            } else {
-                assert_eq!(expected_type, "KeyCombo");
-            }
-        }
-    }
-
-    #[test]
-    fn test_key_combo_parsing() {
-        // Test parsing key combinations
-        fn parse_combo(combo: &str) -> (Vec<&str>, Option<&str>) {
-            let parts: Vec<&str> = combo.split('+').map(|s| s.trim()).collect();
-            let mut modifiers = Vec::new();
-            let mut main_key = None;
-            
-            for part in &parts {
-                match part.to_uppercase().as_str() {
-                    "CTRL" | "SHIFT" | "ALT" | "WIN" => modifiers.push(*part),
-                    _ => main_key = Some(*part),
-                }
-            }
-            
-            (modifiers, main_key)
-        }
-        
-        let (mods, key) = parse_combo("CTRL+SHIFT+ESC");
-        assert_eq!(mods.len(), 2);
-        assert_eq!(key, Some("ESC"));
-        
-        let (mods, key) = parse_combo("WIN+TAB");
-        assert_eq!(mods.len(), 1);
-        assert_eq!(key, Some("TAB"));
-        
-        let (mods, key) = parse_combo("F1");
-        assert_eq!(mods.len(), 0);
-        assert_eq!(key, Some("F1"));
-    }
-
     #[test]
     fn test_hid_report_constants() {
         // Test HID report constants
@@ -337,70 +284,6 @@ KEY_F = F # This is a comment
         assert_eq!(parse_vk_code("UNKNOWN"), 0);
     }
 
-    #[test]
-    fn test_run_command_extraction() {
-        // Test extracting program path from RUN() command
-        fn extract_run_path(action: &str) -> Option<String> {
-            if let Some(rest) = action.strip_prefix("RUN(\"") {
-                if let Some(end) = rest.rfind("\")") {
-                    return Some(rest[..end].to_string());
-                }
-            }
-            None
-        }
-        
-        assert_eq!(
-            extract_run_path("RUN(\"calc.exe\")"),
-            Some("calc.exe".to_string())
-        );
-        assert_eq!(
-            extract_run_path("RUN(\"C:\\Windows\\System32\\notepad.exe\")"),
-            Some("C:\\Windows\\System32\\notepad.exe".to_string())
-        );
-        assert_eq!(extract_run_path("RUN(calc.exe)"), None); // Missing quotes
-        assert_eq!(extract_run_path("WIN+TAB"), None); // Not a RUN command
-    }
-
-    #[test]
-    fn test_appcommand_extraction() {
-        // Test extracting command number from APPCOMMAND()
-        fn extract_appcommand(action: &str) -> Option<u32> {
-            if let Some(rest) = action.strip_prefix("APPCOMMAND(") {
-                if let Some(end) = rest.find(')') {
-                    return rest[..end].parse::<u32>().ok();
-                }
-            }
-            None
-        }
-        
-        assert_eq!(extract_appcommand("APPCOMMAND(8)"), Some(8));
-        assert_eq!(extract_appcommand("APPCOMMAND(46)"), Some(46));
-        assert_eq!(extract_appcommand("APPCOMMAND(abc)"), None); // Not a number
-        assert_eq!(extract_appcommand("APPCOMMAND("), None); // Malformed
-        assert_eq!(extract_appcommand("WIN+TAB"), None); // Not an APPCOMMAND
-    }
-
-    #[test]
-    fn test_mapping_line_variants() {
-        // Test various mapping line formats
-        let valid_lines = vec![
-            "KEY_A = A",
-            "KEY_A=A",
-            "  KEY_A  =  A  ",
-            "FN+KEY_A = F1",
-            "LEFT_SHIFT+KEY_1 = !",
-            "EJECT+FN+KEY_1 = RUN(\"calc.exe\")",
-        ];
-        
-        for line in valid_lines {
-            let trimmed = line.trim();
-            let parts: Vec<&str> = trimmed.split('=').map(|s| s.trim()).collect();
-            assert_eq!(parts.len(), 2, "Failed for line: {}", line);
-            assert!(!parts[0].is_empty(), "LHS empty for line: {}", line);
-            assert!(!parts[1].is_empty(), "RHS empty for line: {}", line);
-        }
-    }
-
     #[test]
     fn test_comment_filtering() {
         // Test that comments are properly filtered
@@ -424,25 +307,6 @@ KEY_F = F # This is a comment
         assert_eq!(valid_lines[1], "KEY_B = B");
     }
 
-    #[test]
-    fn test_modifier_detection() {
-        // Test detecting modifiers in mapping keys
-        fn has_modifier(key: &str, modifier: &str) -> bool {
-            key.starts_with(modifier)
-        }
-        
-        assert!(has_modifier("FN+KEY_A", "FN+"));
-        assert!(has_modifier("LEFT_SHIFT+KEY_1", "LEFT_SHIFT+"));
-        assert!(has_modifier("EJECT+KEY_M", "EJECT+"));
-        assert!(!has_modifier("KEY_A", "FN+"));
-        
-        // Test combined modifiers
-        let key = "EJECT+FN+KEY_1";
-        let has_eject = key.contains("EJECT+");
-        let has_fn = key.contains("FN+");
-        assert!(has_eject && has_fn);
-    }
-
     #[test]
     fn test_empty_mapping_file() {
         let test_dir = setup_test_dir();
@@ -553,4 +553,4 @@ mod logging_tests {
         assert!(formatted.contains(level));
         assert!(formatted.contains(message));
     }
-}
\ No newline at end of file
+}